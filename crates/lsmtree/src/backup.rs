@@ -0,0 +1,676 @@
+//! An incremental backup engine built on top of checkpoints.
+//!
+//! The layout of a backup directory:
+//!
+//! ```text
+//!     <backup dir>/
+//!         meta/<backup id>                        the files and checksums of a backup
+//!         private/<backup id>/MANIFEST-xxxxxx     files owned by a single backup
+//!         private/<backup id>/CURRENT
+//!         shared/<number>_<crc>_<size>.sst        sst files shared by backups
+//!         shared/<number>_<crc>_<size>.blob       blob files shared by backups
+//! ```
+//!
+//! An sst file or a blob file is never changed once it's written, so the same file appearing
+//! in several backups is stored only once and identified by its file number, crc32 checksum
+//! and size.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    db_impl::template_impl::TemplateDB,
+    error::{TemplateKVError, TemplateResult},
+    manager::filename::{FileType, generate_filename, parse_filename},
+    storage::{File, Storage, copy_file, do_write_string_to_file, file_checksum},
+    util::{
+        collection::{HashMap, HashSet},
+        comparator::Comparator,
+    },
+};
+
+const META_DIR: &str = "meta";
+const PRIVATE_DIR: &str = "private";
+const SHARED_DIR: &str = "shared";
+
+/// The summary of a backup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub id: u32,
+    /// Seconds since UNIX epoch when the backup is created
+    pub timestamp: u64,
+    /// The total size of all the files in the backup, including the shared ones
+    pub size: u64,
+    pub num_files: usize,
+}
+
+// A file in the backup directory
+#[derive(Debug, Clone)]
+struct BackupFile {
+    // path relative to the backup directory
+    path: String,
+    crc: u32,
+    size: u64,
+}
+
+#[derive(Debug, Clone)]
+struct BackupMeta {
+    timestamp: u64,
+    // CURRENT is always the last one so that a restored db is openable only if it's complete
+    files: Vec<BackupFile>,
+}
+
+impl BackupMeta {
+    // Format:
+    //   <timestamp>
+    //   <number of files>
+    //   <path> <crc> <size>
+    //   ...
+    fn encode(&self) -> String {
+        let mut s = format!("{}\n{}\n", self.timestamp, self.files.len());
+        for f in self.files.iter() {
+            s.push_str(&format!("{} {} {}\n", f.path, f.crc, f.size));
+        }
+        s
+    }
+
+    fn decode(src: &str) -> Option<Self> {
+        let mut lines = src.lines();
+        let timestamp = lines.next()?.parse().ok()?;
+        let count: usize = lines.next()?.parse().ok()?;
+        let mut files = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut parts = lines.next()?.split(' ');
+            files.push(BackupFile {
+                path: parts.next()?.to_owned(),
+                crc: parts.next()?.parse().ok()?,
+                size: parts.next()?.parse().ok()?,
+            });
+        }
+        Some(Self { timestamp, files })
+    }
+}
+
+/// `BackupEngine` keeps numbered backups of dbs in a `Storage`.
+pub struct BackupEngine<T: Storage> {
+    storage: T,
+    backup_dir: PathBuf,
+    backups: BTreeMap<u32, BackupMeta>,
+}
+
+impl<T: Storage> BackupEngine<T> {
+    /// Opens the backup engine on `backup_dir` in `storage` and loads the existing backups.
+    pub fn open<P: AsRef<Path>>(storage: T, backup_dir: P) -> TemplateResult<Self> {
+        let backup_dir = backup_dir.as_ref().to_owned();
+        for d in [META_DIR, PRIVATE_DIR, SHARED_DIR] {
+            storage.mkdir_all(backup_dir.join(d))?;
+        }
+        let mut backups = BTreeMap::new();
+        for path in storage.list(backup_dir.join(META_DIR))? {
+            // skip the unfinished meta files
+            let Some(id) = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            let mut f = storage.open_read_only(&path)?;
+            let mut buf = vec![];
+            f.read_all(&mut buf)?;
+            let meta = String::from_utf8(buf)
+                .ok()
+                .and_then(|s| BackupMeta::decode(&s))
+                .ok_or_else(|| {
+                    TemplateKVError::Corruption(format!("invalid backup meta file {}", id))
+                        .with_path(&path)
+                })?;
+            backups.insert(id, meta);
+        }
+        Ok(Self {
+            storage,
+            backup_dir,
+            backups,
+        })
+    }
+
+    /// Returns the information of all the backups ordered by backup id
+    pub fn get_backup_info(&self) -> Vec<BackupInfo> {
+        self.backups
+            .iter()
+            .map(|(id, meta)| BackupInfo {
+                id: *id,
+                timestamp: meta.timestamp,
+                size: meta.files.iter().map(|f| f.size).sum(),
+                num_files: meta.files.len(),
+            })
+            .collect()
+    }
+
+    /// Takes a new backup of `db` and returns the backup id.
+    /// The sst files and blob files which are already in the backup directory are not copied
+    /// again.
+    pub fn create_new_backup<S: Storage + Clone, C: Comparator + 'static>(
+        &mut self,
+        db: &TemplateDB<S, C>,
+    ) -> TemplateResult<u32> {
+        let id = self.backups.keys().next_back().map_or(1, |id| id + 1);
+        let env = &db.inner.env;
+        let checkpoint_dir = format!("{}.backup-{}.tmp", &db.inner.db_path, id);
+        if env.exists(&checkpoint_dir) {
+            env.remove_dir(&checkpoint_dir, true)?;
+        }
+        // The sst files and blob files are immutable so the checksums recorded in the current
+        // MANIFEST stay valid for the ones still in the checkpoint
+        let checksums = {
+            let current = db.inner.versions.lock().unwrap().current();
+            current
+                .files
+                .iter()
+                .flatten()
+                .filter_map(|f| f.file_checksum.map(|c| (f.number, c)))
+                .chain(
+                    current
+                        .blob_files
+                        .values()
+                        .map(|b| (b.number, b.file_checksum)),
+                )
+                .collect::<HashMap<_, _>>()
+        };
+        db.create_checkpoint(&checkpoint_dir)?;
+        let res = self.backup_checkpoint(env, &checkpoint_dir, id, &checksums);
+        let _ = env.remove_dir(&checkpoint_dir, true);
+        if res.is_err() {
+            let _ = self.storage.remove_dir(self.private_dir(id), true);
+        }
+        let meta = res?;
+        info!(
+            "Created backup {} with {} files in {:?}",
+            id,
+            meta.files.len(),
+            &self.backup_dir
+        );
+        self.backups.insert(id, meta);
+        Ok(id)
+    }
+
+    /// Restores the backup `backup_id` as a db at `db_dir` in `env`.
+    /// The `db_dir` must not exist or be empty.
+    pub fn restore_db_from_backup<S: Storage, P: AsRef<Path>>(
+        &self,
+        backup_id: u32,
+        env: &S,
+        db_dir: P,
+    ) -> TemplateResult<()> {
+        let meta = self.backup_meta(backup_id)?;
+        let db_dir = db_dir.as_ref();
+        let db_path = db_dir.to_str().ok_or_else(|| {
+            TemplateKVError::InvalidArgument(format!(
+                "restore target {:?} is not valid UTF-8",
+                db_dir
+            ))
+        })?;
+        if env.exists(db_dir) && !env.list(db_dir)?.is_empty() {
+            return Err(TemplateKVError::InvalidArgument(format!(
+                "restore target {:?} is not empty",
+                db_dir
+            )));
+        }
+        env.mkdir_all(db_dir)?;
+        let res = (|| {
+            for f in meta.files.iter() {
+                let src = self.backup_dir.join(&f.path);
+                let dst = match shared_file(&f.path) {
+                    Some((file_type, number)) => {
+                        PathBuf::from(generate_filename(db_path, file_type, number))
+                    }
+                    None => db_dir.join(Path::new(&f.path).file_name().unwrap()),
+                };
+                let (crc, size) = copy_file(&self.storage, &src, env, &dst, None)?;
+                check_file(f, crc, size)?;
+            }
+            env.sync_dir(db_dir)
+        })();
+        if res.is_err() {
+            let _ = env.remove_dir(db_dir, true);
+        }
+        res
+    }
+
+    /// Restores the newest backup as a db at `db_dir` in `env`
+    pub fn restore_db_from_latest_backup<S: Storage, P: AsRef<Path>>(
+        &self,
+        env: &S,
+        db_dir: P,
+    ) -> TemplateResult<()> {
+        match self.backups.keys().next_back() {
+            Some(id) => self.restore_db_from_backup(*id, env, db_dir),
+            None => Err(TemplateKVError::InvalidArgument("no backup".to_owned())),
+        }
+    }
+
+    /// Checks the size and crc32 checksum of every file in the backup `backup_id`
+    pub fn verify_backup(&self, backup_id: u32) -> TemplateResult<()> {
+        let meta = self.backup_meta(backup_id)?;
+        for f in meta.files.iter() {
+            let (crc, size) = file_checksum(&self.storage, self.backup_dir.join(&f.path))?;
+            check_file(f, crc, size)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the backup `backup_id` and the shared files no longer used by any backup
+    pub fn delete_backup(&mut self, backup_id: u32) -> TemplateResult<()> {
+        self.backup_meta(backup_id)?;
+        // Remove the meta file first so a crash leaves garbage files instead of a broken
+        // backup
+        self.storage
+            .remove(self.backup_dir.join(META_DIR).join(backup_id.to_string()))?;
+        self.backups.remove(&backup_id);
+        self.garbage_collect()
+    }
+
+    /// Deletes all the backups except the newest `num_to_keep` ones
+    pub fn purge_old_backups(&mut self, num_to_keep: usize) -> TemplateResult<()> {
+        let count = self.backups.len().saturating_sub(num_to_keep);
+        let to_delete = self.backups.keys().take(count).copied().collect::<Vec<_>>();
+        for id in to_delete {
+            self.delete_backup(id)?;
+        }
+        Ok(())
+    }
+
+    // Removes the shared files and private directories not referred by any backup
+    fn garbage_collect(&self) -> TemplateResult<()> {
+        let mut referred = HashSet::default();
+        for meta in self.backups.values() {
+            for f in meta.files.iter() {
+                referred.insert(self.backup_dir.join(&f.path));
+            }
+        }
+        for path in self.storage.list(self.backup_dir.join(SHARED_DIR))? {
+            let path = self
+                .backup_dir
+                .join(SHARED_DIR)
+                .join(path.file_name().unwrap());
+            if !referred.contains(&path) {
+                info!("Delete unused backup file {:?}", &path);
+                self.storage.remove(&path)?;
+            }
+        }
+        for path in self.storage.list(self.backup_dir.join(PRIVATE_DIR))? {
+            let Some(id) = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            if !self.backups.contains_key(&id) && self.storage.exists(self.private_dir(id)) {
+                self.storage.remove_dir(self.private_dir(id), true)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Copies the files of a checkpoint into the backup directory and writes the meta file
+    fn backup_checkpoint<S: Storage>(
+        &self,
+        env: &S,
+        checkpoint_dir: &str,
+        id: u32,
+        checksums: &HashMap<u64, u32>,
+    ) -> TemplateResult<BackupMeta> {
+        self.storage.mkdir_all(self.private_dir(id))?;
+        let mut files = vec![];
+        let mut current = None;
+        for src in env.list(checkpoint_dir)? {
+            let file_name = src
+                .file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| {
+                    TemplateKVError::InvalidArgument(format!(
+                        "checkpoint file {:?} is not valid UTF-8",
+                        &src
+                    ))
+                })?
+                .to_owned();
+            let file = match parse_filename(&src) {
+                Some((file_type @ (FileType::Table | FileType::Blob), number)) => {
+                    let (crc, size) = file_checksum(env, &src)?;
+                    if let Some(expected) = checksums.get(&number) {
+                        if *expected != crc {
+                            return Err(TemplateKVError::Corruption(format!(
+                                "file checksum {:#010x} does not match {:#010x} in MANIFEST",
+                                crc, expected
+                            ))
+                            .with_file_number(number)
+                            .with_path(&src));
+                        }
+                    }
+                    let ext = if file_type == FileType::Table {
+                        "sst"
+                    } else {
+                        "blob"
+                    };
+                    let path = format!("{}/{:06}_{}_{}.{}", SHARED_DIR, number, crc, size, ext);
+                    let dst = self.backup_dir.join(&path);
+                    if !self.storage.exists(&dst) {
+                        // Copy to a temporary file first so that a crash never leaves a
+                        // partial shared file which looks valid
+                        let tmp = self.backup_dir.join(format!("{}.tmp", &path));
+                        let (copied_crc, copied_size) =
+                            copy_file(env, &src, &self.storage, &tmp, None)?;
+                        if copied_crc != crc || copied_size != size {
+                            return Err(TemplateKVError::Corruption(format!(
+                                "file {} is changed during backup",
+                                number
+                            )));
+                        }
+                        self.storage.rename(&tmp, &dst)?;
+                        self.storage.sync_dir(self.backup_dir.join(SHARED_DIR))?;
+                    }
+                    BackupFile { path, crc, size }
+                }
+                _ => {
+                    let path = format!("{}/{}/{}", PRIVATE_DIR, id, &file_name);
+                    let (crc, size) =
+                        copy_file(env, &src, &self.storage, self.backup_dir.join(&path), None)?;
+                    BackupFile { path, crc, size }
+                }
+            };
+            if file_name == "CURRENT" {
+                current = Some(file);
+            } else {
+                files.push(file);
+            }
+        }
+        match current {
+            Some(f) => files.push(f),
+            None => {
+                return Err(TemplateKVError::Corruption(
+                    "no CURRENT file in checkpoint".to_owned(),
+                ));
+            }
+        }
+        // The private files must be durable before the meta file referring to them
+        self.storage.sync_dir(self.private_dir(id))?;
+        self.storage.sync_dir(self.backup_dir.join(PRIVATE_DIR))?;
+        let meta = BackupMeta {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            files,
+        };
+        let meta_file = self.backup_dir.join(META_DIR).join(id.to_string());
+        let tmp = self.backup_dir.join(META_DIR).join(format!("{}.tmp", id));
+        do_write_string_to_file(&self.storage, meta.encode(), &tmp, true)?;
+        self.storage.rename(&tmp, &meta_file)?;
+        self.storage.sync_dir(self.backup_dir.join(META_DIR))?;
+        Ok(meta)
+    }
+
+    fn backup_meta(&self, backup_id: u32) -> TemplateResult<&BackupMeta> {
+        self.backups.get(&backup_id).ok_or_else(|| {
+            TemplateKVError::InvalidArgument(format!("backup {} not found", backup_id))
+        })
+    }
+
+    fn private_dir(&self, id: u32) -> PathBuf {
+        self.backup_dir.join(PRIVATE_DIR).join(id.to_string())
+    }
+}
+
+// Returns the type and the original file number of a shared file named
+// `<number>_<crc>_<size>.sst` or `<number>_<crc>_<size>.blob`
+fn shared_file(path: &str) -> Option<(FileType, u64)> {
+    let name = path.strip_prefix(SHARED_DIR)?.strip_prefix('/')?;
+    let file_type = if name.ends_with(".blob") {
+        FileType::Blob
+    } else {
+        FileType::Table
+    };
+    Some((file_type, name.split('_').next()?.parse().ok()?))
+}
+
+fn check_file(f: &BackupFile, crc: u32, size: u64) -> TemplateResult<()> {
+    if f.size != size {
+        return Err(TemplateKVError::Corruption(format!(
+            "backup file size mismatch: expect {} but got {}",
+            f.size, size
+        ))
+        .with_path(&f.path));
+    }
+    if f.crc != crc {
+        return Err(TemplateKVError::Corruption(format!(
+            "backup file checksum mismatch: expect {} but got {}",
+            f.crc, crc
+        ))
+        .with_path(&f.path));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{
+        db_trait::DB,
+        options::{Options, ReadOptions, WriteOptions},
+        storage::mem::MemStorage,
+        util::comparator::BytewiseComparator,
+    };
+
+    fn get(db: &TemplateDB<MemStorage, BytewiseComparator>, key: &str) -> Option<String> {
+        db.get(ReadOptions::default(), key.as_bytes())
+            .unwrap()
+            .map(|v| String::from_utf8(v).unwrap())
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let store = MemStorage::default();
+        let backup_store = MemStorage::default();
+        let opts = Options::<BytewiseComparator>::default();
+        let db = TemplateDB::open_db(opts.clone(), "db", store.clone()).unwrap();
+        let mut engine = BackupEngine::open(backup_store.clone(), "backup").unwrap();
+
+        db.put(WriteOptions::default(), b"k1", b"v1").unwrap();
+        assert_eq!(engine.create_new_backup(&db).unwrap(), 1);
+        let shared_files = backup_store.list("backup/shared").unwrap().len();
+        assert_eq!(shared_files, 1);
+
+        db.put(WriteOptions::default(), b"k2", b"v2").unwrap();
+        assert_eq!(engine.create_new_backup(&db).unwrap(), 2);
+        // The sst file containing k1 is shared
+        assert_eq!(backup_store.list("backup/shared").unwrap().len(), 2);
+        engine.verify_backup(1).unwrap();
+        engine.verify_backup(2).unwrap();
+
+        // Reload the backups
+        let mut engine = BackupEngine::open(backup_store.clone(), "backup").unwrap();
+        let infos = engine.get_backup_info();
+        assert_eq!(infos.iter().map(|i| i.id).collect::<Vec<_>>(), vec![1, 2]);
+
+        engine
+            .restore_db_from_backup(1, &store, "restore1")
+            .unwrap();
+        let restored = TemplateDB::open_db(opts.clone(), "restore1", store.clone()).unwrap();
+        assert_eq!(get(&restored, "k1"), Some("v1".to_owned()));
+        assert_eq!(get(&restored, "k2"), None);
+        assert!(
+            engine
+                .restore_db_from_backup(2, &store, "restore1")
+                .is_err()
+        );
+
+        engine.purge_old_backups(1).unwrap();
+        assert_eq!(engine.get_backup_info().len(), 1);
+        assert!(engine.verify_backup(1).is_err());
+        engine.verify_backup(2).unwrap();
+        engine
+            .restore_db_from_latest_backup(&store, "restore2")
+            .unwrap();
+        let restored = TemplateDB::open_db(opts, "restore2", store.clone()).unwrap();
+        assert_eq!(get(&restored, "k1"), Some("v1".to_owned()));
+        assert_eq!(get(&restored, "k2"), Some("v2".to_owned()));
+    }
+
+    #[test]
+    fn test_backup_blob_files() {
+        let store = MemStorage::default();
+        let backup_store = MemStorage::default();
+        let mut opts = Options::<BytewiseComparator>::default();
+        opts.enable_blob_files = true;
+        opts.min_blob_size = 100;
+        let db = TemplateDB::open_db(opts.clone(), "db", store.clone()).unwrap();
+        let mut engine = BackupEngine::open(backup_store.clone(), "backup").unwrap();
+        let value = "v".repeat(200);
+        let shared_blob_files = || {
+            backup_store
+                .list("backup/shared")
+                .unwrap()
+                .iter()
+                .filter(|p| p.extension().is_some_and(|e| e == "blob"))
+                .count()
+        };
+
+        db.put(WriteOptions::default(), b"k1", value.as_bytes())
+            .unwrap();
+        engine.create_new_backup(&db).unwrap();
+        assert_eq!(shared_blob_files(), 1);
+        db.put(WriteOptions::default(), b"k2", value.as_bytes())
+            .unwrap();
+        let id = engine.create_new_backup(&db).unwrap();
+        // The blob file containing the value of k1 is shared
+        assert_eq!(shared_blob_files(), 2);
+        let private = backup_store.list(format!("backup/private/{}", id)).unwrap();
+        assert!(
+            private
+                .iter()
+                .all(|p| !matches!(parse_filename(p), Some((FileType::Blob, _))))
+        );
+        engine.verify_backup(id).unwrap();
+
+        engine
+            .restore_db_from_backup(id, &store, "restore")
+            .unwrap();
+        let restored = TemplateDB::open_db(opts, "restore", store.clone()).unwrap();
+        assert_eq!(get(&restored, "k1"), Some(value.clone()));
+        assert_eq!(get(&restored, "k2"), Some(value));
+    }
+
+    #[test]
+    fn test_verify_corrupted_backup() {
+        let store = MemStorage::default();
+        let backup_store = MemStorage::default();
+        let db =
+            TemplateDB::open_db(Options::<BytewiseComparator>::default(), "db", store).unwrap();
+        db.put(WriteOptions::default(), b"k1", b"v1").unwrap();
+        let mut engine = BackupEngine::open(backup_store.clone(), "backup").unwrap();
+        let id = engine.create_new_backup(&db).unwrap();
+        engine.verify_backup(id).unwrap();
+
+        let shared = backup_store.list("backup/shared").unwrap();
+        let mut f = backup_store.create(&shared[0]).unwrap();
+        f.write(b"garbage").unwrap();
+        let e = engine.verify_backup(id).unwrap_err();
+        assert!(e.is_corruption(), "{}", e);
+        assert!(
+            engine
+                .restore_db_from_backup(id, &backup_store, "restore")
+                .is_err()
+        );
+        assert!(!backup_store.exists("restore"));
+    }
+
+    // A storage recording the directories synced by `sync_dir`
+    #[derive(Clone, Default)]
+    struct SyncRecorder {
+        inner: MemStorage,
+        synced: Arc<Mutex<Vec<PathBuf>>>,
+    }
+
+    impl Storage for SyncRecorder {
+        type F = <MemStorage as Storage>::F;
+        fn create<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+            self.inner.create(name)
+        }
+        fn open<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+            self.inner.open(name)
+        }
+        fn remove<P: AsRef<Path>>(&self, name: P) -> TemplateResult<()> {
+            self.inner.remove(name)
+        }
+        fn remove_dir<P: AsRef<Path>>(&self, dir: P, recursively: bool) -> TemplateResult<()> {
+            self.inner.remove_dir(dir, recursively)
+        }
+        fn exists<P: AsRef<Path>>(&self, name: P) -> bool {
+            self.inner.exists(name)
+        }
+        fn rename<P: AsRef<Path>>(&self, old: P, new: P) -> TemplateResult<()> {
+            self.inner.rename(old, new)
+        }
+        fn mkdir_all<P: AsRef<Path>>(&self, dir: P) -> TemplateResult<()> {
+            self.inner.mkdir_all(dir)
+        }
+        fn list<P: AsRef<Path>>(&self, dir: P) -> TemplateResult<Vec<PathBuf>> {
+            self.inner.list(dir)
+        }
+        fn sync_dir<P: AsRef<Path>>(&self, dir: P) -> TemplateResult<()> {
+            self.synced.lock().unwrap().push(dir.as_ref().to_owned());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sync_backup_dirs() {
+        let store = MemStorage::default();
+        let backup_store = SyncRecorder::default();
+        let db =
+            TemplateDB::open_db(Options::<BytewiseComparator>::default(), "db", store).unwrap();
+        db.put(WriteOptions::default(), b"k1", b"v1").unwrap();
+        let mut engine = BackupEngine::open(backup_store.clone(), "backup").unwrap();
+        let id = engine.create_new_backup(&db).unwrap();
+        // The renamed shared file and meta file survive a crash once the backup is created
+        let synced = backup_store.synced.lock().unwrap().clone();
+        for dir in ["backup/shared", "backup/private/1", "backup/meta"] {
+            assert!(synced.contains(&PathBuf::from(dir)), "{:?}", synced);
+        }
+
+        backup_store.synced.lock().unwrap().clear();
+        engine
+            .restore_db_from_backup(id, &backup_store, "restore")
+            .unwrap();
+        assert_eq!(*backup_store.synced.lock().unwrap(), vec![PathBuf::from(
+            "restore"
+        )]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restore_to_non_utf8_path() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let store = MemStorage::default();
+        let db = TemplateDB::open_db(
+            Options::<BytewiseComparator>::default(),
+            "db",
+            store.clone(),
+        )
+        .unwrap();
+        db.put(WriteOptions::default(), b"k1", b"v1").unwrap();
+        let mut engine = BackupEngine::open(MemStorage::default(), "backup").unwrap();
+        let id = engine.create_new_backup(&db).unwrap();
+        let db_dir = Path::new(OsStr::from_bytes(b"restore\xff"));
+        assert!(matches!(
+            engine.restore_db_from_backup(id, &store, db_dir),
+            Err(TemplateKVError::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,221 @@
+//! Command line tool for inspecting and maintaining a db on the local file system.
+//!
+//! Usage: `ldb <command> [args]`
+
+use std::{env, process};
+
+use log::LevelFilter;
+use lsmtree::{
+    db_impl::{repair::repair_db, template_impl::TemplateDB},
+    db_trait::DB,
+    error::TemplateResult,
+    iterator::Iterator,
+    manager::version_edit::VersionEdit,
+    memtable::{batch::WriteBatch, key_format::ParsedInternalKey, value_format::ValueType},
+    options::{Options, ReadOptions},
+    sstable::sst_dump::SstFileDumper,
+    storage::{Storage, file::FileStorage},
+    util::{comparator::BytewiseComparator, reporter::LogReporter},
+    wal::{wal_record_format::HEADER_SIZE, wal_record_reader::Reader},
+};
+
+const USAGE: &str = "Usage: ldb <command> [args]
+
+Commands:
+    get <db_path> <key>                 Print the value of the key
+    scan <db_path> [<from> [<to>]]      Print the key/value pairs in [from, to)
+    dump <db_path>                      Print all the key/value pairs and their count
+    compact <db_path> [<from> [<to>]]   Compact the key range [from, to]
+    verify <db_path>                    Verify the checksums of all the blocks in the live sst files
+    repair <db_path>                    Rebuild the MANIFEST of a db from the files left in the directory
+    manifest_dump <manifest_file>       Print the VersionEdits in a MANIFEST file
+    wal_dump <log_file>                 Print the WriteBatches in a log file
+    sst_dump <sst_file> [--verify]      Print the blocks and properties of a sst file,
+                                        verifying the checksums of all the blocks if --verify is given";
+
+type Database = TemplateDB<FileStorage, BytewiseComparator>;
+
+fn options() -> Options<BytewiseComparator> {
+    Options {
+        logger_level: LevelFilter::Off,
+        create_if_missing: false,
+        ..Default::default()
+    }
+}
+
+// Escapes the non-printable bytes
+fn escape(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|b| std::ascii::escape_default(*b))
+        .map(char::from)
+        .collect()
+}
+
+// Formats an internal key as `user_key @ seq : type`
+fn format_internal_key(key: &[u8]) -> String {
+    match ParsedInternalKey::decode_from(key) {
+        Some(k) => format!("{} @ {} : {:?}", escape(k.user_key), k.seq, k.value_type),
+        None => format!("(bad){}", escape(key)),
+    }
+}
+
+fn scan(db_path: &str, from: Option<&str>, to: Option<&str>) -> TemplateResult<usize> {
+    let db = Database::open_read_only(options(), db_path, FileStorage)?;
+    let mut iter = db.iter(ReadOptions::default())?;
+    match from {
+        Some(k) => iter.seek(k.as_bytes()),
+        None => iter.seek_to_first(),
+    }
+    let mut count = 0;
+    while iter.valid() {
+        if to.is_some_and(|to| iter.key() >= to.as_bytes()) {
+            break;
+        }
+        println!("{} => {}", escape(iter.key()), escape(iter.value()));
+        count += 1;
+        iter.next();
+    }
+    iter.status()?;
+    Ok(count)
+}
+
+fn manifest_dump(file_name: &str) -> TemplateResult<()> {
+    let file = FileStorage.open_read_only(file_name)?;
+    let reporter = LogReporter::new();
+    let mut reader = Reader::new(file, Some(Box::new(reporter.clone())), true, 0);
+    let mut record = vec![];
+    while reader.read_record(&mut record) {
+        reporter.result()?;
+        let mut edit = VersionEdit::new(options().max_levels);
+        edit.decoded_from(&record)?;
+        println!("--- offset {} ---", reader.last_record_offset());
+        println!("{:?}", edit);
+    }
+    reporter.result()
+}
+
+fn wal_dump(file_name: &str) -> TemplateResult<()> {
+    let file = FileStorage.open_read_only(file_name)?;
+    let reporter = LogReporter::new();
+    let mut reader = Reader::new(file, Some(Box::new(reporter.clone())), true, 0);
+    let mut record = vec![];
+    let mut batch = WriteBatch::default();
+    while reader.read_record(&mut record) {
+        reporter.result()?;
+        let offset = reader.last_record_offset();
+        if record.len() < HEADER_SIZE {
+            println!("--- offset {}: record too small ---", offset);
+            continue;
+        }
+        batch.set_contents(&mut record);
+        println!(
+            "--- offset {}: seq {}, count {} ---",
+            offset,
+            batch.get_sequence(),
+            batch.get_count()
+        );
+        batch.for_each(|seq, value_type, key, value| match value_type {
+            ValueType::Deletion => println!("  Delete({}) @ {}", escape(key), seq),
+            _ => println!("  Put({}, {}) @ {}", escape(key), escape(value), seq),
+        })?;
+    }
+    reporter.result()
+}
+
+fn sst_dump(file_name: &str, verify: bool) -> TemplateResult<()> {
+    let dumper = SstFileDumper::open(FileStorage.open_read_only(file_name)?, verify)?;
+    println!("Footer:\n  {:?}", dumper.footer());
+    println!("Meta index block:");
+    for (key, handle) in dumper.meta_index()? {
+        println!("  {} => {:?}", key, handle);
+    }
+    if !dumper.index_partitions().is_empty() {
+        println!("Index partitions:");
+        for handle in dumper.index_partitions() {
+            println!("  {:?}", handle);
+        }
+    }
+    println!("Index block:");
+    for (key, handle) in dumper.index()? {
+        println!("  {} => {:?}", format_internal_key(key.data()), handle);
+    }
+    println!("Filter blocks:");
+    for filter in dumper.filters()? {
+        println!(
+            "  {} => {:?}, {} filters, base lg {}",
+            filter.policy, filter.handle, filter.num_filters, filter.base_lg
+        );
+    }
+    println!("Properties:");
+    for line in dumper.properties()?.to_string().lines() {
+        println!("  {}", line);
+    }
+    println!("Entries:");
+    dumper.for_each_entry(|key, value| {
+        println!("  {} => {}", format_internal_key(key), escape(value));
+        Ok(())
+    })?;
+    if verify {
+        dumper.verify_checksums()?;
+        println!("Checksums verified");
+    }
+    Ok(())
+}
+
+fn run(args: &[&str]) -> TemplateResult<()> {
+    match *args {
+        ["get", db_path, key] => {
+            let db = Database::open_read_only(options(), db_path, FileStorage)?;
+            match db.get(ReadOptions::default(), key.as_bytes())? {
+                Some(v) => println!("{}", escape(&v)),
+                None => println!("Not found"),
+            }
+        }
+        ["scan", db_path, ref range @ ..] if range.len() <= 2 => {
+            scan(db_path, range.first().copied(), range.get(1).copied())?;
+        }
+        ["dump", db_path] => {
+            let count = scan(db_path, None, None)?;
+            println!("Keys in range: {}", count);
+        }
+        ["compact", db_path, ref range @ ..] if range.len() <= 2 => {
+            let mut db = Database::open_db(options(), db_path, FileStorage)?;
+            db.compact_range(
+                range.first().map(|k| k.as_bytes()),
+                range.get(1).map(|k| k.as_bytes()),
+            )?;
+            db.close()?;
+        }
+        ["verify", db_path] => {
+            let db = Database::open_read_only(options(), db_path, FileStorage)?;
+            let report = db.verify_checksums()?;
+            println!("{}", report);
+            if !report.is_ok() {
+                process::exit(1);
+            }
+        }
+        ["repair", db_path] => {
+            repair_db(db_path, options(), FileStorage)?;
+            println!("Repaired {}", db_path);
+        }
+        ["manifest_dump", file_name] => manifest_dump(file_name)?,
+        ["wal_dump", file_name] => wal_dump(file_name)?,
+        ["sst_dump", file_name] => sst_dump(file_name, false)?,
+        ["sst_dump", file_name, "--verify"] => sst_dump(file_name, true)?,
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,255 @@
+use super::blob_index::BlobIndex;
+use crate::{
+    error::{TemplateKVError, TemplateResult},
+    manager::version_edit::BlobFileMeta,
+    storage::File,
+    util::{
+        coding::{decode_fixed_32, put_fixed_32},
+        crc32::{extend, hash, mask, unmask},
+    },
+};
+
+/// The size of the header of a blob record
+///
+/// Blob file format:
+///
+/// ```text
+/// +------------------------+------------------------+-------+
+/// | value length (fixed32) | masked crc32 (fixed32) | value |  record 1
+/// +------------------------+------------------------+-------+
+/// | ...                                                     |  record 2..n
+/// +---------------------------------------------------------+
+/// ```
+///
+/// The records are appended one by one so the file can be scanned without any index.
+pub const BLOB_RECORD_HEADER_SIZE: usize = 8;
+
+/// `BlobFileBuilder` appends the separated values into a blob file
+pub struct BlobFileBuilder<F: File> {
+    file: F,
+    number: u64,
+    offset: u64,
+    count: u64,
+    bytes: u64,
+    // crc32 of the whole file written so far
+    checksum: u32,
+}
+
+impl<F: File> BlobFileBuilder<F> {
+    pub fn new(file: F, number: u64) -> Self {
+        Self {
+            file,
+            number,
+            offset: 0,
+            count: 0,
+            bytes: 0,
+            checksum: 0,
+        }
+    }
+
+    /// Appends `value` and returns the `BlobIndex` referring to it
+    pub fn add(&mut self, value: &[u8]) -> TemplateResult<BlobIndex> {
+        let mut header = Vec::with_capacity(BLOB_RECORD_HEADER_SIZE);
+        put_fixed_32(&mut header, value.len() as u32);
+        put_fixed_32(&mut header, mask(hash(value)));
+        self.file.write(&header)?;
+        self.file.write(value)?;
+        let index = BlobIndex::new(self.number, self.offset, value.len() as u64);
+        self.offset += (BLOB_RECORD_HEADER_SIZE + value.len()) as u64;
+        self.count += 1;
+        self.bytes += value.len() as u64;
+        self.checksum = extend(extend(self.checksum, &header), value);
+        Ok(index)
+    }
+
+    /// Returns the size of the file generated so far
+    #[inline]
+    pub fn file_size(&self) -> u64 {
+        self.offset
+    }
+
+    /// Finishes the file and returns its metadata
+    pub fn finish(&mut self, sync: bool) -> TemplateResult<BlobFileMeta> {
+        if sync {
+            self.file.flush()?;
+            self.file.close()?;
+        }
+        Ok(BlobFileMeta {
+            number: self.number,
+            total_count: self.count,
+            total_bytes: self.bytes,
+            file_size: self.offset,
+            file_checksum: self.checksum,
+            ..Default::default()
+        })
+    }
+
+    /// Abandons the file. The caller should delete it.
+    pub fn close(&mut self) {
+        let _ = self.file.close();
+    }
+}
+
+/// `BlobFileReader` reads the values from a blob file
+pub struct BlobFileReader<F: File> {
+    file: F,
+    file_size: u64,
+}
+
+impl<F: File> BlobFileReader<F> {
+    pub fn open(file: F) -> TemplateResult<Self> {
+        let file_size = file.len()?;
+        Ok(Self { file, file_size })
+    }
+
+    /// Reads the value referred by `index`
+    pub fn get(&self, index: &BlobIndex) -> TemplateResult<Vec<u8>> {
+        let record_size = BLOB_RECORD_HEADER_SIZE as u64 + index.size;
+        if index
+            .offset
+            .checked_add(record_size)
+            .is_none_or(|end| end > self.file_size)
+        {
+            return Err(TemplateKVError::Corruption(format!(
+                "blob record [{}, +{}) exceeds the file size {}",
+                index.offset, record_size, self.file_size
+            )));
+        }
+        let mut record = vec![0; record_size as usize];
+        self.file.read_exact_at(&mut record, index.offset)?;
+        let value = decode_record(&record, index.offset)?;
+        if value.len() as u64 != index.size {
+            return Err(TemplateKVError::Corruption(format!(
+                "blob record at {} has {} bytes but {} bytes are expected",
+                index.offset,
+                value.len(),
+                index.size
+            )));
+        }
+        record.drain(..BLOB_RECORD_HEADER_SIZE);
+        Ok(record)
+    }
+
+    /// Reads all the records in the file in order and calls `f` with the index of each one.
+    /// Stops at the first corrupted record and returns the error.
+    pub fn scan<FN: FnMut(BlobIndex)>(&self, number: u64, mut f: FN) -> TemplateResult<()> {
+        let mut offset = 0;
+        let mut header = [0; BLOB_RECORD_HEADER_SIZE];
+        while offset < self.file_size {
+            if offset + BLOB_RECORD_HEADER_SIZE as u64 > self.file_size {
+                return Err(truncated(offset));
+            }
+            self.file.read_exact_at(&mut header, offset)?;
+            let size = decode_fixed_32(&header) as u64;
+            let index = BlobIndex::new(number, offset, size);
+            if offset + BLOB_RECORD_HEADER_SIZE as u64 + size > self.file_size {
+                return Err(truncated(offset));
+            }
+            self.get(&index)?;
+            f(index);
+            offset += BLOB_RECORD_HEADER_SIZE as u64 + size;
+        }
+        Ok(())
+    }
+}
+
+// Returns the value in the given record after verifying its checksum
+fn decode_record(record: &[u8], offset: u64) -> TemplateResult<&[u8]> {
+    let size = decode_fixed_32(record) as usize;
+    let value = &record[BLOB_RECORD_HEADER_SIZE..];
+    if size != value.len() {
+        return Err(TemplateKVError::Corruption(format!(
+            "bad blob record length {} at {}",
+            size, offset
+        )));
+    }
+    let crc = unmask(decode_fixed_32(&record[4..]));
+    if crc != hash(value) {
+        return Err(TemplateKVError::Corruption(format!(
+            "blob record checksum mismatch at {}",
+            offset
+        )));
+    }
+    Ok(value)
+}
+
+fn truncated(offset: u64) -> TemplateKVError {
+    TemplateKVError::Corruption(format!("truncated blob record at {}", offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{Storage, mem::MemStorage};
+
+    fn build(store: &MemStorage, values: &[Vec<u8>]) -> (Vec<BlobIndex>, BlobFileMeta) {
+        let mut builder = BlobFileBuilder::new(store.create("000001.blob").unwrap(), 1);
+        let indexes = values
+            .iter()
+            .map(|v| builder.add(v).unwrap())
+            .collect::<Vec<_>>();
+        let meta = builder.finish(true).unwrap();
+        (indexes, meta)
+    }
+
+    #[test]
+    fn test_build_and_read() {
+        let store = MemStorage::default();
+        let values = (0..100).map(|i| vec![i as u8; i * 10]).collect::<Vec<_>>();
+        let (indexes, meta) = build(&store, &values);
+        assert_eq!(meta.number, 1);
+        assert_eq!(meta.total_count, 100);
+        assert_eq!(
+            meta.total_bytes,
+            values.iter().map(|v| v.len() as u64).sum::<u64>()
+        );
+        let reader = BlobFileReader::open(store.open("000001.blob").unwrap()).unwrap();
+        assert_eq!(
+            meta.file_size,
+            store.open("000001.blob").unwrap().len().unwrap()
+        );
+        for (index, value) in indexes.iter().zip(values.iter()) {
+            assert_eq!(&reader.get(index).unwrap(), value);
+        }
+        let mut scanned = vec![];
+        reader.scan(1, |index| scanned.push(index)).unwrap();
+        assert_eq!(scanned, indexes);
+
+        // Out of the file
+        let mut bad = *indexes.last().unwrap();
+        bad.size += 1;
+        assert!(reader.get(&bad).unwrap_err().is_corruption());
+        // Not the start of a record
+        let mut bad = indexes[10];
+        bad.offset += 1;
+        assert!(reader.get(&bad).unwrap_err().is_corruption());
+    }
+
+    #[test]
+    fn test_corrupted_record() {
+        let store = MemStorage::default();
+        let values = (0..10).map(|i| vec![b'x'; 100 + i]).collect::<Vec<_>>();
+        let (indexes, _) = build(&store, &values);
+        let mut contents = vec![];
+        store
+            .open("000001.blob")
+            .unwrap()
+            .read_all(&mut contents)
+            .unwrap();
+        // Flip a byte in the value of the 6th record
+        contents[indexes[5].offset as usize + BLOB_RECORD_HEADER_SIZE + 1] ^= 0xff;
+        store.remove("000001.blob").unwrap();
+        store
+            .create("000001.blob")
+            .unwrap()
+            .write(&contents)
+            .unwrap();
+        let reader = BlobFileReader::open(store.open("000001.blob").unwrap()).unwrap();
+        assert_eq!(reader.get(&indexes[4]).unwrap(), values[4]);
+        assert!(reader.get(&indexes[5]).unwrap_err().is_corruption());
+        let mut scanned = 0;
+        let e = reader.scan(1, |_| scanned += 1).unwrap_err();
+        assert!(e.is_corruption());
+        assert_eq!(scanned, 5);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{
+    error::{TemplateKVError, TemplateResult},
+    util::varint::VarintU64,
+};
+
+/// `BlobIndex` refers to a value separated into a blob file. It's stored as the value of a
+/// `ValueType::BlobIndex` entry in the sst files.
+///
+/// Encoding:
+///
+/// ```text
+/// +-------------------------+------------------+----------------+
+/// | file number (varint64)  | offset (varint64)| size (varint64)|
+/// +-------------------------+------------------+----------------+
+/// ```
+///
+/// `offset` is the offset of the blob record in the file and `size` is the size of the value.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct BlobIndex {
+    pub file_number: u64,
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl BlobIndex {
+    pub fn new(file_number: u64, offset: u64, size: u64) -> Self {
+        Self {
+            file_number,
+            offset,
+            size,
+        }
+    }
+
+    /// Encodes the index into bytes
+    pub fn encode(&self) -> Vec<u8> {
+        let mut dst = Vec::with_capacity(30);
+        VarintU64::put_varint(&mut dst, self.file_number);
+        VarintU64::put_varint(&mut dst, self.offset);
+        VarintU64::put_varint(&mut dst, self.size);
+        dst
+    }
+
+    /// Decodes the index from the value of a `ValueType::BlobIndex` entry
+    pub fn decode_from(src: &[u8]) -> TemplateResult<Self> {
+        let mut s = src;
+        if let Some(file_number) = VarintU64::drain_read(&mut s) {
+            if let Some(offset) = VarintU64::drain_read(&mut s) {
+                if let Some(size) = VarintU64::drain_read(&mut s) {
+                    if s.is_empty() {
+                        return Ok(Self::new(file_number, offset, size));
+                    }
+                }
+            }
+        }
+        Err(TemplateKVError::Corruption(format!(
+            "bad blob index {:?}",
+            src
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        for index in [
+            BlobIndex::new(0, 0, 0),
+            BlobIndex::new(7, 4096, 100),
+            BlobIndex::new(u64::MAX, u64::MAX, u64::MAX),
+        ] {
+            assert_eq!(BlobIndex::decode_from(&index.encode()).unwrap(), index);
+        }
+        let encoded = BlobIndex::new(1, 2, 300).encode();
+        for i in 0..encoded.len() {
+            assert!(BlobIndex::decode_from(&encoded[..i]).is_err());
+        }
+        let mut trailing = encoded;
+        trailing.push(0);
+        assert!(BlobIndex::decode_from(&trailing).is_err());
+    }
+}
@@ -0,0 +1,2 @@
+pub mod blob_file;
+pub mod blob_index;
@@ -0,0 +1,77 @@
+use std::sync::Arc;
+
+use super::lru_cache::LRUCache;
+use crate::{
+    blob::{blob_file::BlobFileReader, blob_index::BlobIndex},
+    cache::CacheSync,
+    error::{Operation, TemplateResult},
+    manager::filename::{FileType, generate_filename},
+    storage::Storage,
+};
+
+/// A `BlobFileCache` is the cache for the opened blob files
+pub struct BlobFileCache<S: Storage + Clone> {
+    storage: S,
+    db_path: String,
+    // the key is the file number
+    cache: Arc<dyn CacheSync<u64, Arc<BlobFileReader<S::F>>>>,
+}
+
+impl<S: Storage + Clone> BlobFileCache<S> {
+    pub fn new(db_path: String, size: usize, storage: S) -> Self {
+        let cache = Arc::new(LRUCache::<u64, Arc<BlobFileReader<S::F>>>::new(size));
+        Self {
+            storage,
+            db_path,
+            cache,
+        }
+    }
+
+    /// Try to find the blob file from cache. If not found, open the file from storage and
+    /// insert it into the cache
+    pub fn find_blob_file(&self, file_number: u64) -> TemplateResult<Arc<BlobFileReader<S::F>>> {
+        if let Some(v) = self.cache.get(&file_number) {
+            Ok(v)
+        } else {
+            let filename = generate_filename(&self.db_path, FileType::Blob, file_number);
+            // The blob files are never written once built
+            let reader = self
+                .storage
+                .open_read_only(&filename)
+                .and_then(BlobFileReader::open)
+                .map_err(|e| {
+                    e.with_operation(Operation::OpenFile)
+                        .with_file_number(file_number)
+                        .with_path(&filename)
+                })?;
+            let value = Arc::new(reader);
+            let _ = self.cache.insert(file_number, value.clone(), 1);
+            Ok(value)
+        }
+    }
+
+    /// Evict any entry for the specified file number
+    pub fn evict(&self, file_number: u64) {
+        self.cache.erase(&file_number);
+    }
+
+    /// Reads the value referred by `index`
+    pub fn get(&self, index: &BlobIndex) -> TemplateResult<Vec<u8>> {
+        let reader = self.find_blob_file(index.file_number)?;
+        reader.get(index).map_err(|e| {
+            e.with_operation(Operation::ReadBlob)
+                .with_file_number(index.file_number)
+                .with_offset(index.offset)
+        })
+    }
+}
+
+impl<S: Storage + Clone> Clone for BlobFileCache<S> {
+    fn clone(&self) -> Self {
+        BlobFileCache {
+            storage: self.storage.clone(),
+            db_path: self.db_path.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::sync::Arc;
+
+use super::FilterPolicy;
+use crate::util::hash::{hash, mix64};
+
+// The size of a cache line in bytes. All the probes of a key are in the same line.
+const CACHE_LINE_SIZE: usize = 64;
+const CACHE_LINE_BITS: u32 = (CACHE_LINE_SIZE * 8) as u32;
+
+/// `BlockedBloomFilter` is a bloom filter splitting the bits into cache lines. A key is hashed
+/// into one line and all its probes are in that line, so a lookup touches one cache line
+/// at most at the cost of a slightly higher false positive rate than `BloomFilter` with the
+/// same bits per key.
+///
+/// Filter format:
+///
+/// ```text
+/// +-----------------------+-----------------------+-----+-------------------+
+/// | cache line 1 (64B)    | cache line 2 (64B)    | ... | num_probes (1B)   |
+/// +-----------------------+-----------------------+-----+-------------------+
+/// ```
+pub struct BlockedBloomFilter {
+    // the number of probes for a key
+    k: usize,
+    bits_per_key: usize,
+}
+
+impl BlockedBloomFilter {
+    #[must_use]
+    pub fn new(bits_per_key: usize) -> Self {
+        // The probes in a line collide more often than the ones over the whole filter, so
+        // fewer probes than the classic bloom filter are optimal
+        let k = (bits_per_key as f32 * 0.6).clamp(1f32, 24f32);
+        Self {
+            k: k as usize,
+            bits_per_key,
+        }
+    }
+
+    // Returns the index of the cache line for the key and the hash for the probes in the line
+    #[inline]
+    fn locate(key: &[u8], lines: usize) -> (usize, u32) {
+        let h = mix64(u64::from(hash(key, 0xc6a4_a793)));
+        let line = ((h >> 32) * lines as u64) >> 32;
+        (line as usize, h as u32)
+    }
+}
+
+impl FilterPolicy for BlockedBloomFilter {
+    fn name(&self) -> &str {
+        "TemplateDB.BlockedBloomFilter"
+    }
+
+    fn may_contain(&self, filter: &[u8], key: &[u8]) -> bool {
+        if filter.len() <= 1 {
+            return false;
+        }
+        let n = filter.len() - 1; // exclude the k
+        if n % CACHE_LINE_SIZE != 0 {
+            // Not generated by this policy. Consider it a match.
+            return true;
+        }
+        let k = filter[n];
+        let lines = n / CACHE_LINE_SIZE;
+        let (line, mut h) = Self::locate(key, lines);
+        let line = &filter[line * CACHE_LINE_SIZE..(line + 1) * CACHE_LINE_SIZE];
+        for _ in 0..k {
+            // the highest 9 bits address a bit in the line
+            let bit_pos = h >> (32 - CACHE_LINE_BITS.trailing_zeros());
+            if line[(bit_pos / 8) as usize] & (1 << (bit_pos % 8)) == 0 {
+                return false;
+            }
+            h = h.wrapping_mul(0x9e37_79b9);
+        }
+        true
+    }
+
+    fn create_filter(&self, keys: &[Vec<u8>]) -> Vec<u8> {
+        let bits = (keys.len() * self.bits_per_key).max(CACHE_LINE_BITS as usize);
+        let lines = bits.div_ceil(CACHE_LINE_BITS as usize);
+        let bytes = lines * CACHE_LINE_SIZE;
+        let mut dst = vec![0; bytes + 1];
+        dst[bytes] = self.k as u8;
+        for key in keys {
+            let (line, mut h) = Self::locate(key, lines);
+            let line = &mut dst[line * CACHE_LINE_SIZE..(line + 1) * CACHE_LINE_SIZE];
+            for _ in 0..self.k {
+                let bit_pos = h >> (32 - CACHE_LINE_BITS.trailing_zeros());
+                line[(bit_pos / 8) as usize] |= 1 << (bit_pos % 8);
+                h = h.wrapping_mul(0x9e37_79b9);
+            }
+        }
+        dst
+    }
+
+    fn with_bits_per_key(&self, bits_per_key: f64) -> Option<Arc<dyn FilterPolicy>> {
+        Some(Arc::new(Self::new(bits_per_key.round().max(1.0) as usize)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::coding::encode_fixed_32;
+
+    fn num_key(i: u32) -> Vec<u8> {
+        let mut k = vec![0; 4];
+        encode_fixed_32(&mut k, i);
+        k
+    }
+
+    // Builds a filter over `n` keys and returns the false positive rate of 10000 other keys
+    fn false_positive_rate(policy: &dyn FilterPolicy, n: u32) -> f64 {
+        let keys = (0..n).map(num_key).collect::<Vec<_>>();
+        let filter = policy.create_filter(&keys);
+        for key in keys.iter() {
+            assert!(policy.may_contain(&filter, key));
+        }
+        let false_positives = (0..10000)
+            .filter(|i| policy.may_contain(&filter, &num_key(i + 1_000_000_000)))
+            .count();
+        false_positives as f64 / 10000.0
+    }
+
+    #[test]
+    fn test_blocked_bloom_filter_empty() {
+        let policy = BlockedBloomFilter::new(10);
+        let filter = policy.create_filter(&[]);
+        assert!(!policy.may_contain(&filter, b"hello"));
+        assert!(!policy.may_contain(&[], b"hello"));
+    }
+
+    #[test]
+    fn test_blocked_bloom_filter_small() {
+        let policy = BlockedBloomFilter::new(10);
+        let filter = policy.create_filter(&[b"hello".to_vec(), b"world".to_vec()]);
+        assert_eq!(filter.len(), CACHE_LINE_SIZE + 1);
+        assert!(policy.may_contain(&filter, b"hello"));
+        assert!(policy.may_contain(&filter, b"world"));
+        assert!(!policy.may_contain(&filter, b"x"));
+        assert!(!policy.may_contain(&filter, b"foo"));
+        // Unknown formats are considered as matches
+        assert!(policy.may_contain(&filter[..filter.len() - 2], b"foo"));
+    }
+
+    #[test]
+    fn test_blocked_bloom_filter_false_positive_rate() {
+        for (bits_per_key, max_rate) in [(6, 0.08), (10, 0.02), (16, 0.003)] {
+            let policy = BlockedBloomFilter::new(bits_per_key);
+            for n in [100, 1000, 10000] {
+                let rate = false_positive_rate(&policy, n);
+                assert!(
+                    rate <= max_rate,
+                    "bits per key {}, {} keys: {}",
+                    bits_per_key,
+                    n,
+                    rate
+                );
+            }
+        }
+    }
+}
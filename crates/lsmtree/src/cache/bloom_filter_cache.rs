@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use super::FilterPolicy;
 use crate::util::hash::hash;
 
@@ -85,6 +87,10 @@ impl FilterPolicy for BloomFilter {
         }
         dst
     }
+
+    fn with_bits_per_key(&self, bits_per_key: f64) -> Option<Arc<dyn FilterPolicy>> {
+        Some(Arc::new(Self::new(bits_per_key.round().max(1.0) as usize)))
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,491 @@
+use std::{hash::Hash, sync::Mutex};
+
+use crate::{
+    cache::{CachePriority, CacheSync, EvictionListener, linked_slab::LinkedSlab},
+    util::collection::HashMap,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    Hot,
+    Cold,
+    // a non-resident cold entry in its test period, which only remembers the key
+    Test,
+}
+
+struct Entry<K, V> {
+    key: K,
+    // `None` for a test entry
+    value: Option<V>,
+    charge: usize,
+    status: Status,
+    referenced: bool,
+    high_pri: bool,
+}
+
+struct ClockProInner<K, V> {
+    capacity: usize,
+    high_pri_pool: usize,
+    // all the entries in a single clock
+    ring: LinkedSlab<Entry<K, V>>,
+    table: HashMap<K, usize>,
+    // the next entries to examine by the hands, `None` iff the clock is empty
+    hand_hot: Option<usize>,
+    hand_cold: Option<usize>,
+    hand_test: Option<usize>,
+    size_hot: usize,
+    size_cold: usize,
+    size_test: usize,
+    // the charge of the hot entries inserted with the high priority
+    high_pri_hot: usize,
+    // the adaptive target of the charge of the resident cold entries
+    cold_target: usize,
+    // the entries evicted to pass to the eviction listener once the cache is unlocked, `None`
+    // if there is no listener
+    evicted: Option<Vec<(K, V)>>,
+}
+
+/// `ClockProCache` is a scan-resistant cache with the CLOCK-Pro replacement policy.
+///
+/// A new entry starts cold, and is promoted to hot only if it's accessed again before the
+/// cold hand reaches it, so a scan that touches each block once can't evict the hot
+/// entries. An evicted cold entry stays in the clock as a test entry for a while, and
+/// reinserting it in the test period grows the share of the cold entries since the
+/// working set doesn't fit in the hot ones.
+///
+/// The entries inserted with `CachePriority::High` start hot, and the hot hand doesn't
+/// demote them while their charge is within the high priority pool.
+pub struct ClockProCache<K, V> {
+    inner: Mutex<ClockProInner<K, V>>,
+    eviction_listener: Option<EvictionListener<K, V>>,
+}
+
+impl<K: Clone + Hash + Eq, V> ClockProCache<K, V> {
+    /// Creates a `ClockProCache` whose high priority pool is half of `capacity`
+    pub fn new(capacity: usize) -> Self {
+        Self::with_high_pri_pool_ratio(capacity, 0.5)
+    }
+
+    /// Creates a `ClockProCache` whose high priority pool is `ratio` of `capacity`
+    pub fn with_high_pri_pool_ratio(capacity: usize, ratio: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&ratio),
+            "invalid high priority pool ratio {ratio}"
+        );
+        Self {
+            inner: Mutex::new(ClockProInner {
+                capacity,
+                high_pri_pool: (capacity as f64 * ratio) as usize,
+                ring: LinkedSlab::new(),
+                table: HashMap::default(),
+                hand_hot: None,
+                hand_cold: None,
+                hand_test: None,
+                size_hot: 0,
+                size_cold: 0,
+                size_test: 0,
+                high_pri_hot: 0,
+                cold_target: capacity / 2,
+                evicted: None,
+            }),
+            eviction_listener: None,
+        }
+    }
+
+    /// Sets the listener called with the evicted entries
+    #[must_use]
+    pub fn with_eviction_listener(mut self, listener: EvictionListener<K, V>) -> Self {
+        self.inner.get_mut().unwrap().evicted = Some(vec![]);
+        self.eviction_listener = Some(listener);
+        self
+    }
+}
+
+impl<K: Clone + Hash + Eq, V> ClockProInner<K, V> {
+    // Links a new resident entry right behind the hot hand, which is the head of the clock
+    fn add(&mut self, key: K, value: V, charge: usize, status: Status, high_pri: bool) -> usize {
+        let i = self.ring.insert(Entry {
+            key: key.clone(),
+            value: Some(value),
+            charge,
+            status,
+            referenced: false,
+            high_pri,
+        });
+        match self.hand_hot {
+            Some(h) => self.ring.link_before(i, h),
+            None => {
+                self.hand_hot = Some(i);
+                self.hand_cold = Some(i);
+                self.hand_test = Some(i);
+            }
+        }
+        self.table.insert(key, i);
+        match status {
+            Status::Hot => {
+                self.size_hot += charge;
+                if high_pri {
+                    self.high_pri_hot += charge;
+                }
+            }
+            Status::Cold => self.size_cold += charge,
+            Status::Test => unreachable!("add a test entry"),
+        }
+        i
+    }
+
+    fn remove(&mut self, i: usize) -> Entry<K, V> {
+        let next = if self.ring.is_alone(i) {
+            None
+        } else {
+            Some(self.ring.next(i))
+        };
+        for hand in [&mut self.hand_hot, &mut self.hand_cold, &mut self.hand_test] {
+            if *hand == Some(i) {
+                *hand = next;
+            }
+        }
+        let e = self.ring.remove(i);
+        self.table.remove(&e.key);
+        match e.status {
+            Status::Hot => {
+                self.size_hot -= e.charge;
+                if e.high_pri {
+                    self.high_pri_hot -= e.charge;
+                }
+            }
+            Status::Cold => self.size_cold -= e.charge,
+            Status::Test => self.size_test -= e.charge,
+        }
+        e
+    }
+
+    fn set_hot(&mut self, i: usize) {
+        let e = self.ring.get_mut(i);
+        debug_assert_eq!(e.status, Status::Cold);
+        e.status = Status::Hot;
+        e.referenced = false;
+        let (charge, high_pri) = (e.charge, e.high_pri);
+        self.size_cold -= charge;
+        self.size_hot += charge;
+        if high_pri {
+            self.high_pri_hot += charge;
+        }
+    }
+
+    fn set_cold(&mut self, i: usize) {
+        let e = self.ring.get_mut(i);
+        debug_assert_eq!(e.status, Status::Hot);
+        e.status = Status::Cold;
+        e.referenced = false;
+        let (charge, high_pri) = (e.charge, e.high_pri);
+        self.size_hot -= charge;
+        self.size_cold += charge;
+        if high_pri {
+            self.high_pri_hot -= charge;
+        }
+    }
+
+    #[inline]
+    fn resident(&self) -> usize {
+        self.size_hot + self.size_cold
+    }
+
+    // Evicts the resident entries until an entry with `charge` fits
+    fn evict(&mut self, charge: usize) {
+        while self.resident() > 0 && self.resident() + charge > self.capacity {
+            if self.size_cold == 0 {
+                self.force_demote();
+            } else {
+                self.run_hand_cold();
+            }
+        }
+    }
+
+    // Examines the entry under the cold hand: a referenced cold entry is promoted to hot,
+    // and an unreferenced one is evicted and starts its test period.
+    fn run_hand_cold(&mut self) {
+        let i = self.hand_cold.unwrap();
+        self.hand_cold = Some(self.ring.next(i));
+        let e = self.ring.get_mut(i);
+        if e.status != Status::Cold {
+            return;
+        }
+        if e.referenced {
+            self.set_hot(i);
+            self.balance_hot();
+        } else {
+            e.status = Status::Test;
+            let value = e.value.take().unwrap();
+            if let Some(evicted) = &mut self.evicted {
+                evicted.push((e.key.clone(), value));
+            }
+            let charge = e.charge;
+            self.size_cold -= charge;
+            self.size_test += charge;
+            while self.size_test > self.capacity {
+                self.run_hand_test();
+            }
+        }
+    }
+
+    // Demotes the hot entries until the cold entries can reach their target
+    fn balance_hot(&mut self) {
+        while self.size_hot > self.capacity - self.cold_target {
+            self.run_hand_hot();
+        }
+    }
+
+    // Examines the entry under the hot hand: an unreferenced hot entry is demoted to cold
+    // unless it's protected by the high priority pool, and a test entry ends its test
+    // period.
+    fn run_hand_hot(&mut self) {
+        let i = self.hand_hot.unwrap();
+        self.hand_hot = Some(self.ring.next(i));
+        // The high priority entries are protected only if they alone fit in the hot ones,
+        // otherwise `balance_hot` can't finish.
+        let protected_pool = self.high_pri_pool.min(self.capacity - self.cold_target);
+        let high_pri_hot = self.high_pri_hot;
+        let e = self.ring.get_mut(i);
+        match e.status {
+            Status::Hot => {
+                if e.referenced {
+                    e.referenced = false;
+                } else if !(e.high_pri && high_pri_hot <= protected_pool) {
+                    self.set_cold(i);
+                }
+            }
+            Status::Test => self.end_test(i),
+            Status::Cold => {}
+        }
+    }
+
+    fn run_hand_test(&mut self) {
+        let i = self.hand_test.unwrap();
+        self.hand_test = Some(self.ring.next(i));
+        if self.ring.get(i).status == Status::Test {
+            self.end_test(i);
+        }
+    }
+
+    // Removes the test entry `i` which isn't reinserted in its test period, so the cold
+    // entries shrink.
+    fn end_test(&mut self, i: usize) {
+        let e = self.remove(i);
+        self.cold_target = self.cold_target.saturating_sub(e.charge);
+    }
+
+    // Demotes the next hot entry regardless of its reference and priority, when the
+    // resident entries are all hot.
+    fn force_demote(&mut self) {
+        loop {
+            let i = self.hand_hot.unwrap();
+            self.hand_hot = Some(self.ring.next(i));
+            if self.ring.get(i).status == Status::Hot {
+                self.set_cold(i);
+                return;
+            }
+        }
+    }
+}
+
+impl<K, V> CacheSync<K, V> for ClockProCache<K, V>
+where
+    K: Send + Sync + Clone + Hash + Eq,
+    V: Send + Sync + Clone,
+{
+    fn insert(&self, key: K, value: V, charge: usize) -> Option<V> {
+        self.insert_with_priority(key, value, charge, CachePriority::Low)
+    }
+
+    fn insert_with_priority(
+        &self,
+        key: K,
+        value: V,
+        charge: usize,
+        priority: CachePriority,
+    ) -> Option<V> {
+        let mut l = self.inner.lock().unwrap();
+        let high_pri = priority == CachePriority::High;
+        let mut status = if high_pri { Status::Hot } else { Status::Cold };
+        let mut referenced = false;
+        let mut old = None;
+        if let Some(&i) = l.table.get(&key) {
+            let e = l.remove(i);
+            match e.status {
+                Status::Hot => status = Status::Hot,
+                Status::Cold => referenced = true,
+                Status::Test => {
+                    // The entry is reinserted in its test period, so the cold entries
+                    // are too few to keep it.
+                    l.cold_target = (l.cold_target + e.charge).min(l.capacity);
+                    status = Status::Hot;
+                }
+            }
+            old = e.value;
+        }
+        if charge > l.capacity || l.capacity == 0 {
+            return old;
+        }
+        l.evict(charge);
+        let i = l.add(key, value, charge, status, high_pri);
+        l.ring.get_mut(i).referenced = referenced;
+        if status == Status::Hot {
+            l.balance_hot();
+        }
+        let evicted = l.evicted.as_mut().map(std::mem::take);
+        drop(l);
+        if let (Some(listener), Some(evicted)) = (&self.eviction_listener, evicted) {
+            for (k, v) in evicted {
+                listener(&k, &v);
+            }
+        }
+        old
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        let mut l = self.inner.lock().unwrap();
+        let i = *l.table.get(key)?;
+        let e = l.ring.get_mut(i);
+        let v = e.value.clone()?;
+        e.referenced = true;
+        Some(v)
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        let l = self.inner.lock().unwrap();
+        // The test entries have no value
+        l.table
+            .get(key)
+            .is_some_and(|&i| l.ring.get(i).value.is_some())
+    }
+
+    fn erase(&self, key: &K) {
+        let mut l = self.inner.lock().unwrap();
+        if let Some(&i) = l.table.get(key) {
+            l.remove(i);
+        }
+    }
+
+    fn total_charge(&self) -> usize {
+        self.inner.lock().unwrap().resident()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_hit_and_miss() {
+        let cache = ClockProCache::new(100);
+        assert_eq!(cache.get(&100), None);
+        assert_eq!(cache.insert(100, 101, 1), None);
+        assert_eq!(cache.get(&100), Some(101));
+        assert_eq!(cache.insert(200, 201, 1), None);
+        assert_eq!(cache.insert(100, 102, 1), Some(101));
+        assert_eq!(cache.get(&100), Some(102));
+        assert_eq!(cache.get(&200), Some(201));
+        assert_eq!(cache.total_charge(), 2);
+        cache.erase(&200);
+        assert_eq!(cache.get(&200), None);
+        cache.erase(&300);
+        assert_eq!(cache.total_charge(), 1);
+    }
+
+    #[test]
+    fn test_contains() {
+        let cache = ClockProCache::new(100);
+        assert!(!cache.contains(&100));
+        cache.insert(100, 101, 1);
+        let referenced = |cache: &ClockProCache<u32, u32>| {
+            let l = cache.inner.lock().unwrap();
+            l.ring.get(l.table[&100]).referenced
+        };
+        // Unlike `get`, the entry is not referenced
+        assert!(cache.contains(&100));
+        assert!(!referenced(&cache));
+        cache.get(&100);
+        assert!(referenced(&cache));
+    }
+
+    #[test]
+    fn test_charge() {
+        let cache = ClockProCache::new(100);
+        for i in 0..100 {
+            cache.insert(i, i, 10);
+            assert!(cache.total_charge() <= 100);
+        }
+        // Too heavy to cache
+        assert_eq!(cache.insert(1000, 1000, 101), None);
+        assert_eq!(cache.get(&1000), None);
+        assert!(cache.total_charge() <= 100);
+
+        let cache = ClockProCache::new(0);
+        cache.insert(1, 1, 0);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.total_charge(), 0);
+    }
+
+    #[test]
+    fn test_scan_resistance() {
+        let cache = ClockProCache::new(100);
+        // The hot entries are accessed again before the cold hand reaches them
+        for _ in 0..3 {
+            for i in 0..50 {
+                if cache.get(&i).is_none() {
+                    cache.insert(i, i, 1);
+                }
+            }
+        }
+        for i in 1000..2000 {
+            cache.insert(i, i, 1);
+        }
+        let hits = (0..50).filter(|i| cache.get(i).is_some()).count();
+        assert_eq!(hits, 50);
+        assert!(cache.total_charge() <= 100);
+    }
+
+    #[test]
+    fn test_eviction_listener() {
+        let evicted = Arc::new(Mutex::new(vec![]));
+        let cloned = evicted.clone();
+        let cache = ClockProCache::new(10).with_eviction_listener(Box::new(move |k, v| {
+            cloned.lock().unwrap().push((*k, *v));
+        }));
+        for i in 0..20 {
+            cache.insert(i, i * 10, 1);
+        }
+        cache.erase(&19);
+        // The erased entry is not evicted
+        let evicted = evicted.lock().unwrap().clone();
+        assert_eq!(evicted, (0..10).map(|i| (i, i * 10)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_high_priority() {
+        let cache = ClockProCache::with_high_pri_pool_ratio(100, 0.3);
+        for i in 0..30 {
+            cache.insert_with_priority(i, i, 1, CachePriority::High);
+        }
+        // The high priority entries are never accessed but survive the scans
+        for _ in 0..3 {
+            for i in 1000..1500 {
+                if cache.get(&i).is_none() {
+                    cache.insert(i, i, 1);
+                }
+            }
+        }
+        assert!((0..30).all(|i| cache.get(&i).is_some()));
+
+        // Beyond the pool they are evicted like the others
+        for i in 100..1000 {
+            cache.insert_with_priority(i, i, 1, CachePriority::High);
+            assert!(cache.total_charge() <= 100);
+        }
+        assert_eq!(cache.get(&999), Some(999));
+        assert!((100..1000).filter(|i| cache.get(i).is_some()).count() <= 100);
+    }
+}
@@ -0,0 +1,160 @@
+// A slab of entries linked into circular doubly linked lists by their indexes, which the
+// caches other than `LRUCache` build their eviction orders on without raw pointers.
+//
+// An entry is a circular list of its own until it's linked into another list. A list is
+// either a ring of entries addressed by any of them, or has a sentinel created by
+// `new_sentinel` as its head.
+pub(crate) struct LinkedSlab<T> {
+    slots: Vec<Slot<T>>,
+    // the indexes of the removed slots to reuse
+    free: Vec<usize>,
+}
+
+struct Slot<T> {
+    // `None` for a sentinel or a removed slot
+    value: Option<T>,
+    prev: usize,
+    next: usize,
+}
+
+impl<T> LinkedSlab<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            slots: vec![],
+            free: vec![],
+        }
+    }
+
+    // Allocates a slot linked to itself
+    fn alloc(&mut self, value: Option<T>) -> usize {
+        match self.free.pop() {
+            Some(i) => {
+                self.slots[i] = Slot {
+                    value,
+                    prev: i,
+                    next: i,
+                };
+                i
+            }
+            None => {
+                let i = self.slots.len();
+                self.slots.push(Slot {
+                    value,
+                    prev: i,
+                    next: i,
+                });
+                i
+            }
+        }
+    }
+
+    // Inserts `value` as an entry not linked to any list and returns its index
+    pub(crate) fn insert(&mut self, value: T) -> usize {
+        self.alloc(Some(value))
+    }
+
+    // Creates the sentinel of an empty list
+    pub(crate) fn new_sentinel(&mut self) -> usize {
+        self.alloc(None)
+    }
+
+    // Unlinks and removes the entry at `i`
+    pub(crate) fn remove(&mut self, i: usize) -> T {
+        self.unlink(i);
+        self.free.push(i);
+        self.slots[i].value.take().expect("remove a vacant slot")
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, i: usize) -> &T {
+        self.slots[i].value.as_ref().expect("get a vacant slot")
+    }
+
+    #[inline]
+    pub(crate) fn get_mut(&mut self, i: usize) -> &mut T {
+        self.slots[i].value.as_mut().expect("get a vacant slot")
+    }
+
+    #[inline]
+    pub(crate) fn next(&self, i: usize) -> usize {
+        self.slots[i].next
+    }
+
+    #[inline]
+    pub(crate) fn prev(&self, i: usize) -> usize {
+        self.slots[i].prev
+    }
+
+    // Returns true if `i` is a sentinel whose list is empty, or an entry linked to nothing
+    #[inline]
+    pub(crate) fn is_alone(&self, i: usize) -> bool {
+        self.slots[i].next == i
+    }
+
+    // Unlinks `i` from its list, leaving it linked to itself
+    pub(crate) fn unlink(&mut self, i: usize) {
+        let Slot { prev, next, .. } = self.slots[i];
+        self.slots[prev].next = next;
+        self.slots[next].prev = prev;
+        self.slots[i].prev = i;
+        self.slots[i].next = i;
+    }
+
+    // Links the unlinked `i` right before `at`
+    pub(crate) fn link_before(&mut self, i: usize, at: usize) {
+        let prev = self.slots[at].prev;
+        self.slots[i].prev = prev;
+        self.slots[i].next = at;
+        self.slots[prev].next = i;
+        self.slots[at].prev = i;
+    }
+
+    // Links the unlinked `i` right after `at`
+    pub(crate) fn link_after(&mut self, i: usize, at: usize) {
+        let next = self.slots[at].next;
+        self.link_before(i, next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(slab: &LinkedSlab<u32>, sentinel: usize) -> Vec<u32> {
+        let mut values = vec![];
+        let mut i = slab.next(sentinel);
+        while i != sentinel {
+            values.push(*slab.get(i));
+            i = slab.next(i);
+        }
+        values
+    }
+
+    #[test]
+    fn test_linked_slab() {
+        let mut slab = LinkedSlab::new();
+        let list = slab.new_sentinel();
+        assert!(slab.is_alone(list));
+        let a = slab.insert(1);
+        let b = slab.insert(2);
+        let c = slab.insert(3);
+        slab.link_after(a, list);
+        slab.link_before(b, list);
+        slab.link_after(c, a);
+        assert_eq!(collect(&slab, list), vec![1, 3, 2]);
+        assert_eq!(slab.prev(list), b);
+
+        slab.unlink(c);
+        assert!(slab.is_alone(c));
+        slab.link_after(c, list);
+        assert_eq!(collect(&slab, list), vec![3, 1, 2]);
+        *slab.get_mut(a) = 10;
+        assert_eq!(slab.remove(a), 10);
+        assert_eq!(collect(&slab, list), vec![3, 2]);
+        // The slot is reused
+        let d = slab.insert(4);
+        assert_eq!(d, a);
+        slab.link_before(d, b);
+        assert_eq!(collect(&slab, list), vec![3, 4, 2]);
+    }
+}
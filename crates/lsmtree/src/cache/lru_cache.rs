@@ -9,7 +9,10 @@ use std::{
     },
 };
 
-use crate::{cache::CacheSync, util::collection::HashMap};
+use crate::{
+    cache::{CacheSync, EvictionListener},
+    util::collection::HashMap,
+};
 
 #[derive(Copy, Clone)]
 struct Key<K> {
@@ -76,6 +79,7 @@ pub struct LRUCache<K, V: Clone> {
     usage: Arc<AtomicUsize>,
     // Only for tests
     evict_hook: EvictHook<K, V>,
+    eviction_listener: Option<EvictionListener<K, V>>,
 }
 
 struct LRUInner<K, V> {
@@ -121,8 +125,16 @@ impl<K: Hash + Eq, V: Clone> LRUCache<K, V> {
             capacity: cap,
             inner: Arc::new(Mutex::new(l)),
             evict_hook: None,
+            eviction_listener: None,
         }
     }
+
+    /// Sets the listener called with the evicted entries
+    #[must_use]
+    pub fn with_eviction_listener(mut self, listener: EvictionListener<K, V>) -> Self {
+        self.eviction_listener = Some(listener);
+        self
+    }
 }
 
 impl<K, V> CacheSync<K, V> for LRUCache<K, V>
@@ -132,7 +144,9 @@ where
 {
     fn insert(&self, key: K, mut value: V, charge: usize) -> Option<V> {
         let mut l = self.inner.lock().unwrap();
-        if self.capacity > 0 {
+        // The entry passed to the eviction listener once the cache is unlocked
+        let mut evicted = None;
+        let old = if self.capacity > 0 {
             match l.table.get_mut(&Key {
                 k: &key as *const K,
             }) {
@@ -160,9 +174,15 @@ where
                                     hk(&(*n.key.as_ptr()), &(*n.value.as_ptr()));
                                 }
                             }
-                            unsafe {
-                                ptr::drop_in_place(n.key.as_mut_ptr());
-                                ptr::drop_in_place(n.value.as_mut_ptr());
+                            if self.eviction_listener.is_some() {
+                                evicted = unsafe {
+                                    Some((ptr::read(n.key.as_ptr()), ptr::read(n.value.as_ptr())))
+                                };
+                            } else {
+                                unsafe {
+                                    ptr::drop_in_place(n.key.as_mut_ptr());
+                                    ptr::drop_in_place(n.value.as_mut_ptr());
+                                }
                             }
                             n.key = MaybeUninit::new(key);
                             n.value = MaybeUninit::new(value);
@@ -185,7 +205,12 @@ where
             }
         } else {
             None
+        };
+        drop(l);
+        if let (Some(listener), Some((k, v))) = (&self.eviction_listener, evicted) {
+            listener(&k, &v);
         }
+        old
     }
 
     fn get(&self, key: &K) -> Option<V> {
@@ -201,6 +226,11 @@ where
         }
     }
 
+    fn contains(&self, key: &K) -> bool {
+        let k = Key { k: key as *const K };
+        self.inner.lock().unwrap().table.contains_key(&k)
+    }
+
     fn erase(&self, key: &K) {
         let k = Key { k: key as *const K };
         let mut l = self.inner.lock().unwrap();
@@ -240,7 +270,7 @@ unsafe impl<K: Sync, V: Sync + Clone> Sync for LRUCache<K, V> {}
 
 #[cfg(test)]
 mod tests {
-    use std::{cell::RefCell, rc::Rc};
+    use std::{cell::RefCell, rc::Rc, sync::OnceLock};
 
     use super::*;
 
@@ -375,6 +405,20 @@ mod tests {
         assert_eq!(None, cache.get(300));
     }
 
+    #[test]
+    fn test_contains() {
+        let cache = CacheTest::new(CACHE_SIZE);
+        for i in 0..CACHE_SIZE as u32 {
+            cache.insert(i, i);
+        }
+        // Unlike `get`, the entry is not moved to the front
+        assert!(cache.cache.contains(&0));
+        cache.insert(1000, 1000);
+        assert!(!cache.cache.contains(&0));
+        assert_eq!(None, cache.get(0));
+        assert!(cache.cache.contains(&1));
+    }
+
     #[test]
     fn test_use_exceeds_cache_size() {
         let cache = CacheTest::new(CACHE_SIZE);
@@ -425,4 +469,22 @@ mod tests {
         cache.insert(100, 101);
         assert_eq!(None, cache.get(100));
     }
+
+    #[test]
+    fn test_eviction_listener_without_lock() {
+        let cache = Arc::new(OnceLock::<LRUCache<u32, u32>>::new());
+        let evicted = Arc::new(Mutex::new(vec![]));
+        let (c, e) = (cache.clone(), evicted.clone());
+        let listener: EvictionListener<u32, u32> = Box::new(move |k, v| {
+            // The cache is accessible in the listener
+            assert!(c.get().unwrap().get(k).is_none());
+            e.lock().unwrap().push((*k, *v));
+        });
+        let _ = cache.set(LRUCache::new(2).with_eviction_listener(listener));
+        let cache = cache.get().unwrap();
+        for i in 0..4 {
+            cache.insert(i, i * 10, 1);
+        }
+        assert_eq!(*evicted.lock().unwrap(), vec![(0, 0), (1, 10)]);
+    }
 }
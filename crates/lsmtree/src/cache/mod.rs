@@ -1,8 +1,39 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::{error::TemplateResult, sstable::block::Block};
+
+pub mod blob_file_cache;
+pub mod blocked_bloom_filter;
 pub mod bloom_filter_cache;
+pub mod clock_pro_cache;
+mod linked_slab;
 pub mod lru_cache;
 pub mod new_lru_cache;
+pub mod persistent_cache;
+pub mod ribbon_filter;
 pub mod sharded_cache;
 pub mod table_cache;
+pub mod tiny_lfu_cache;
+
+/// The key of a block in the block cache, which is the file number of the table along with
+/// the offset of the block in it. It's fixed-size so that a lookup allocates nothing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BlockCacheKey {
+    pub file_number: u64,
+    pub offset: u64,
+}
+
+impl BlockCacheKey {
+    #[inline]
+    pub fn new(file_number: u64, offset: u64) -> Self {
+        Self {
+            file_number,
+            offset,
+        }
+    }
+}
 
 /// A `Cache` is an interface that maps keys to values.
 /// It has internal synchronization and may be safely accessed concurrently from
@@ -26,9 +57,26 @@ where
     /// the specified charge against the total cache capacity.
     fn insert(&self, key: K, value: V, charge: usize) -> Option<V>;
 
+    /// Same as `insert` but the entry is inserted with `priority`. The caches with
+    /// per-priority pools keep the high priority entries, like the index and the filter
+    /// blocks, over the low priority ones. The others ignore `priority`.
+    fn insert_with_priority(
+        &self,
+        key: K,
+        value: V,
+        charge: usize,
+        _priority: CachePriority,
+    ) -> Option<V> {
+        self.insert(key, value, charge)
+    }
+
     /// If the cache has no mapping for `key`, returns `None`.
     fn get(&self, key: &K) -> Option<V>;
 
+    /// Returns true if the cache has a mapping for `key`. Unlike `get`, it's not an access
+    /// to the entry, so it changes neither the eviction order nor the frequencies.
+    fn contains(&self, key: &K) -> bool;
+
     /// If the cache contains entry for key, erase it.
     fn erase(&self, key: &K);
 
@@ -37,6 +85,58 @@ where
     fn total_charge(&self) -> usize;
 }
 
+/// The priority of an entry inserted by `CacheSync::insert_with_priority`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CachePriority {
+    High,
+    Low,
+}
+
+/// A callback with the entries a cache evicts to make room for the others, but not the
+/// ones erased or replaced. It's called after the cache is unlocked, so it can be as slow
+/// as writing the entries to a file without blocking the other accesses.
+pub type EvictionListener<K, V> = Box<dyn Fn(&K, &V) + Send + Sync>;
+
+/// A `SecondaryCache` is the tier behind the block cache, e.g. on a local SSD, which keeps
+/// the blocks evicted from the block cache. The blocks missing in the block cache are looked
+/// up in it before being read from the table files.
+///
+/// The keys are the file numbers of the tables, so a secondary cache that outlives a DB
+/// belongs to it alone, which is told by `bind_db`.
+pub trait SecondaryCache: Send + Sync {
+    /// Inserts the contents of a block evicted from the block cache. It's best-effort and
+    /// may drop the block.
+    fn insert(&self, key: BlockCacheKey, data: &[u8]);
+
+    /// Returns the contents of the block if it's cached
+    fn lookup(&self, key: &BlockCacheKey) -> Option<Bytes>;
+
+    /// Erases all the blocks of the table file `file_number`, which is deleted
+    fn erase_file(&self, file_number: u64);
+
+    /// Called by a DB opened with the cache with the unique id of the DB. The blocks cached
+    /// for another DB must be dropped since the same file numbers are used by every DB.
+    fn bind_db(&self, _db_id: &str) -> TemplateResult<()> {
+        Ok(())
+    }
+
+    /// Called by a DB bound by `bind_db` when it's closed
+    fn unbind_db(&self) {}
+}
+
+/// Returns an `EvictionListener` for a block cache that spills the evicted blocks to
+/// `secondary_cache`
+pub fn spill_to(
+    secondary_cache: Arc<dyn SecondaryCache>,
+) -> EvictionListener<BlockCacheKey, Arc<Block>> {
+    Box::new(move |key, block| {
+        // The dummy entries of the write buffer managers are empty
+        if !block.data().is_empty() {
+            secondary_cache.insert(*key, block.data());
+        }
+    })
+}
+
 #[async_trait::async_trait]
 pub trait CacheAsync<K, V>: Sync + Send
 where
@@ -85,6 +185,13 @@ pub trait FilterPolicy: Send + Sync {
     /// Creates a filter based on given keys
     // TODO: use another type instead of &[Vec<u8>]
     fn create_filter(&self, keys: &[Vec<u8>]) -> Vec<u8>;
+
+    /// Returns a policy of the same name building the filters with about `bits_per_key` bits
+    /// for each key. The filters must be readable by any policy of the name since the bits
+    /// per key is not recorded anywhere else. Returns `None` if the policy can't be tuned.
+    fn with_bits_per_key(&self, _bits_per_key: f64) -> Option<Arc<dyn FilterPolicy>> {
+        None
+    }
 }
 
 #[cfg(test)]
@@ -97,11 +204,14 @@ mod tests {
         thread,
     };
 
+    use rand::{Rng, SeedableRng, rngs::StdRng};
     use tests::sharded_cache::ShardedCache;
 
     // use lru::*;
     use super::*;
-    use crate::cache::lru_cache::LRUCache;
+    use crate::cache::{
+        clock_pro_cache::ClockProCache, lru_cache::LRUCache, tiny_lfu_cache::TinyLfuCache,
+    };
 
     fn new_test_lru_shards(n: usize) -> Vec<LRUCache<String, String>> {
         (0..n).fold(vec![], |mut acc, _| {
@@ -147,4 +257,41 @@ mod tests {
             assert_eq!(cache.get(&k), Some(v));
         }
     }
+
+    // Looks up the blocks like a table reader, which inserts a block after a miss: the
+    // random reads of a hot set interleaved with scans of 3 times the capacity. Returns the
+    // hit ratio of the reads.
+    fn hot_set_with_scans_hit_ratio(cache: &dyn CacheSync<u64, u64>) -> f64 {
+        let mut rng = StdRng::seed_from_u64(0);
+        let access = |key: u64| {
+            let hit = cache.get(&key).is_some();
+            if !hit {
+                cache.insert(key, key, 1);
+            }
+            hit
+        };
+        let (mut reads, mut hits) = (0, 0);
+        let mut scanned = 1 << 32;
+        for _ in 0..20 {
+            for _ in 0..5000 {
+                reads += 1;
+                hits += access(rng.gen_range(0..800)) as usize;
+            }
+            for _ in 0..3000 {
+                access(scanned);
+                scanned += 1;
+            }
+        }
+        hits as f64 / reads as f64
+    }
+
+    #[test]
+    fn test_scan_resistant_caches() {
+        let lru = hot_set_with_scans_hit_ratio(&LRUCache::new(1000));
+        let clock_pro = hot_set_with_scans_hit_ratio(&ClockProCache::new(1000));
+        let tiny_lfu = hot_set_with_scans_hit_ratio(&TinyLfuCache::new(1000));
+        // The scans flush the hot set out of the LRU cache, but not the others
+        assert!(clock_pro > lru + 0.1);
+        assert!(tiny_lfu > lru + 0.1);
+    }
 }
@@ -0,0 +1,588 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+
+use crate::{
+    cache::{BlockCacheKey, SecondaryCache},
+    error::{TemplateKVError, TemplateResult},
+    storage::{File, Storage, do_write_string_to_file},
+    util::{
+        coding::{decode_fixed_32, decode_fixed_64, put_fixed_32, put_fixed_64},
+        collection::HashMap,
+        crc32::{hash, mask, unmask},
+    },
+};
+
+// The header of a record: the masked crc32 of the rest of the record (4 bytes), the length
+// of the block (4 bytes), the file number (8 bytes) and the offset (8 bytes) of the block
+const RECORD_HEADER_SIZE: usize = 24;
+
+const SEGMENT_FILE_SUFFIX: &str = ".pcache";
+
+// The offset in a record erasing all the blocks of the file cached before it
+const ERASED_FILE_OFFSET: u64 = u64::MAX;
+
+// The file holding the id of the DB the cached blocks belong to
+const DB_ID_FILE_NAME: &str = "DB_ID";
+
+// The number of segments a cache is split into. The oldest segment is dropped as a whole
+// once the cache is full.
+const SEGMENTS: usize = 8;
+
+// Where the contents of a block are
+#[derive(Clone, Copy)]
+struct Location {
+    segment: u64,
+    // the offset of the record
+    offset: u64,
+    len: usize,
+}
+
+struct Segment<F> {
+    number: u64,
+    file: Arc<F>,
+    size: usize,
+}
+
+struct PersistentCacheInner<F> {
+    index: HashMap<BlockCacheKey, Location>,
+    // the segments from the oldest, whose last one is being written
+    segments: VecDeque<Segment<F>>,
+    writer: F,
+    // the total size of the segments
+    usage: usize,
+    // the id of the DB the blocks in the segments belong to
+    db_id: Option<String>,
+    // the number of the opened instances of the DB bound by `bind_db`. The blocks are neither
+    // inserted nor looked up while it's 0.
+    bound: usize,
+}
+
+/// `PersistentCache` is a `SecondaryCache` storing the blocks in the files of a directory,
+/// typically on a local SSD, which survives restarts.
+///
+/// The blocks are appended to the segment files as the records checked by crc32, and looked
+/// up through an index in memory which is rebuilt from the segments on opening. Once the
+/// segments exceed the capacity, the oldest one is deleted with all its blocks.
+///
+/// The blocks are keyed by the file numbers of the tables, so a cache directory serves only
+/// one DB. The id of the DB bound by `SecondaryCache::bind_db` is kept in the directory, and
+/// all the blocks are dropped if another DB is bound, e.g. the DB is removed and created
+/// again. Binding another DB fails while the cache is still bound.
+///
+/// The erasure of the blocks of a deleted table is appended to the segments as well, so the
+/// blocks are never recovered for another table given the same file number later, e.g. after
+/// a crash or by `repair_db`.
+pub struct PersistentCache<S: Storage> {
+    storage: S,
+    dir: PathBuf,
+    capacity: usize,
+    segment_size: usize,
+    inner: Mutex<PersistentCacheInner<S::F>>,
+}
+
+fn segment_file_name(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:06}{}", number, SEGMENT_FILE_SUFFIX))
+}
+
+fn parse_segment_file_name(name: &Path) -> Option<u64> {
+    name.file_name()?
+        .to_str()?
+        .strip_suffix(SEGMENT_FILE_SUFFIX)?
+        .parse()
+        .ok()
+}
+
+// Returns the length of the block if the record at the start of `buf` is intact
+fn decode_record_header(buf: &[u8]) -> Option<(BlockCacheKey, usize)> {
+    if buf.len() < RECORD_HEADER_SIZE {
+        return None;
+    }
+    let len = decode_fixed_32(&buf[4..]) as usize;
+    let end = RECORD_HEADER_SIZE.checked_add(len)?;
+    if buf.len() < end || unmask(decode_fixed_32(buf)) != hash(&buf[4..end]) {
+        return None;
+    }
+    let key = BlockCacheKey::new(decode_fixed_64(&buf[8..]), decode_fixed_64(&buf[16..]));
+    Some((key, len))
+}
+
+impl<S: Storage> PersistentCache<S> {
+    /// Opens the cache in `dir` with `capacity` bytes, recovering the blocks cached before.
+    /// The records broken by a crash are skipped with the rest of their segments.
+    pub fn open<P: AsRef<Path>>(storage: S, dir: P, capacity: usize) -> TemplateResult<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        storage.mkdir_all(&dir)?;
+        let mut numbers = storage
+            .list(&dir)?
+            .iter()
+            .filter_map(|name| parse_segment_file_name(name))
+            .collect::<Vec<_>>();
+        numbers.sort_unstable();
+
+        let mut index: HashMap<BlockCacheKey, Location> = HashMap::default();
+        let mut segments = VecDeque::new();
+        let mut usage = 0;
+        for number in numbers {
+            let name = segment_file_name(&dir, number);
+            let mut file = storage.open(&name).map_err(|e| e.with_path(&name))?;
+            let mut buf = vec![];
+            file.read_all(&mut buf).map_err(|e| e.with_path(&name))?;
+            // The segment left empty by the last run
+            if buf.is_empty() {
+                drop(file);
+                storage.remove(&name)?;
+                continue;
+            }
+            let mut offset = 0;
+            while let Some((key, len)) = decode_record_header(&buf[offset..]) {
+                if key.offset == ERASED_FILE_OFFSET {
+                    index.retain(|k, _| k.file_number != key.file_number);
+                } else {
+                    index.insert(key, Location {
+                        segment: number,
+                        offset: offset as u64,
+                        len,
+                    });
+                }
+                offset += RECORD_HEADER_SIZE + len;
+            }
+            if offset < buf.len() {
+                warn!(
+                    "Skip {} bytes of broken records in persistent cache segment {:?}",
+                    buf.len() - offset,
+                    name
+                );
+            }
+            usage += buf.len();
+            segments.push_back(Segment {
+                number,
+                file: Arc::new(file),
+                size: buf.len(),
+            });
+        }
+        let next_number = segments.back().map_or(0, |s| s.number + 1);
+        let (writer, segment) = Self::create_segment(&storage, &dir, next_number)?;
+        segments.push_back(segment);
+        let db_id_file = dir.join(DB_ID_FILE_NAME);
+        let db_id = if storage.exists(&db_id_file) {
+            let mut buf = vec![];
+            storage
+                .open(&db_id_file)
+                .and_then(|mut f| f.read_all(&mut buf))
+                .map_err(|e| e.with_path(&db_id_file))?;
+            Some(String::from_utf8_lossy(&buf).into_owned())
+        } else {
+            None
+        };
+        let cache = Self {
+            storage,
+            dir,
+            capacity,
+            segment_size: (capacity / SEGMENTS).max(1),
+            inner: Mutex::new(PersistentCacheInner {
+                index,
+                segments,
+                writer,
+                usage,
+                db_id,
+                bound: 0,
+            }),
+        };
+        {
+            let mut inner = cache.inner.lock().unwrap();
+            cache.drop_old_segments(&mut inner);
+        }
+        Ok(cache)
+    }
+
+    fn create_segment(
+        storage: &S,
+        dir: &Path,
+        number: u64,
+    ) -> TemplateResult<(S::F, Segment<S::F>)> {
+        let name = segment_file_name(dir, number);
+        let on_err = |e: TemplateKVError| e.with_path(&name);
+        let writer = storage.create(&name).map_err(on_err)?;
+        let reader = storage.open(&name).map_err(on_err)?;
+        Ok((writer, Segment {
+            number,
+            file: Arc::new(reader),
+            size: 0,
+        }))
+    }
+
+    // Deletes the oldest segments but the one being written until the cache fits
+    fn drop_old_segments(&self, inner: &mut PersistentCacheInner<S::F>) {
+        while inner.usage > self.capacity && inner.segments.len() > 1 {
+            let segment = inner.segments.pop_front().unwrap();
+            inner.usage -= segment.size;
+            inner.index.retain(|_, loc| loc.segment != segment.number);
+            let name = segment_file_name(&self.dir, segment.number);
+            if let Err(e) = self.storage.remove(&name) {
+                warn!("Fail to delete persistent cache segment {:?}: {}", name, e);
+            }
+        }
+    }
+
+    // Deletes all the segments and starts a new one
+    fn clear(&self, inner: &mut PersistentCacheInner<S::F>) -> TemplateResult<()> {
+        let next_number = inner.segments.back().map_or(0, |s| s.number + 1);
+        for segment in inner.segments.drain(..) {
+            self.storage
+                .remove(segment_file_name(&self.dir, segment.number))?;
+        }
+        inner.index.clear();
+        inner.usage = 0;
+        let (writer, segment) = Self::create_segment(&self.storage, &self.dir, next_number)?;
+        inner.writer = writer;
+        inner.segments.push_back(segment);
+        Ok(())
+    }
+
+    fn append(
+        &self,
+        inner: &mut PersistentCacheInner<S::F>,
+        key: BlockCacheKey,
+        data: &[u8],
+    ) -> TemplateResult<()> {
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + data.len());
+        put_fixed_32(&mut record, 0);
+        put_fixed_32(&mut record, data.len() as u32);
+        put_fixed_64(&mut record, key.file_number);
+        put_fixed_64(&mut record, key.offset);
+        record.extend_from_slice(data);
+        let crc = mask(hash(&record[4..]));
+        record[..4].copy_from_slice(&crc.to_le_bytes());
+
+        let mut written = 0;
+        while written < record.len() {
+            written += inner.writer.write(&record[written..])?;
+        }
+        inner.writer.flush()?;
+        let segment = inner.segments.back_mut().unwrap();
+        let location = Location {
+            segment: segment.number,
+            offset: segment.size as u64,
+            len: data.len(),
+        };
+        segment.size += record.len();
+        let full = segment.size >= self.segment_size;
+        inner.usage += record.len();
+        inner.index.insert(key, location);
+        if full {
+            let next_number = location.segment + 1;
+            let (writer, segment) = Self::create_segment(&self.storage, &self.dir, next_number)?;
+            inner.writer = writer;
+            inner.segments.push_back(segment);
+        }
+        self.drop_old_segments(inner);
+        Ok(())
+    }
+
+    /// Returns the number of the cached blocks
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().index.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the total size of the segment files
+    pub fn usage(&self) -> usize {
+        self.inner.lock().unwrap().usage
+    }
+}
+
+impl<S: Storage> SecondaryCache for PersistentCache<S> {
+    fn insert(&self, key: BlockCacheKey, data: &[u8]) {
+        if RECORD_HEADER_SIZE + data.len() > self.capacity || data.len() > u32::MAX as usize {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        // The blocks never change
+        if inner.bound == 0 || inner.index.contains_key(&key) {
+            return;
+        }
+        if let Err(e) = self.append(&mut inner, key, data) {
+            warn!(
+                "Fail to insert block {:?} into persistent cache: {}",
+                key, e
+            );
+        }
+    }
+
+    fn lookup(&self, key: &BlockCacheKey) -> Option<Bytes> {
+        let (location, file) = {
+            let inner = self.inner.lock().unwrap();
+            if inner.bound == 0 {
+                return None;
+            }
+            let location = *inner.index.get(key)?;
+            let segment = inner
+                .segments
+                .iter()
+                .find(|s| s.number == location.segment)?;
+            (location, segment.file.clone())
+        };
+        let mut buf = vec![0; RECORD_HEADER_SIZE + location.len];
+        if let Err(e) = file.read_exact_at(&mut buf, location.offset) {
+            // The segment may have just been dropped
+            debug!("Fail to read block {:?} from persistent cache: {}", key, e);
+            return None;
+        }
+        match decode_record_header(&buf) {
+            Some((k, len)) if k == *key && len == location.len => {
+                Some(Bytes::from(buf).slice(RECORD_HEADER_SIZE..))
+            }
+            _ => {
+                warn!("Corrupted block {:?} in persistent cache", key);
+                None
+            }
+        }
+    }
+
+    fn erase_file(&self, file_number: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.bound == 0 || !inner.index.keys().any(|k| k.file_number == file_number) {
+            return;
+        }
+        // Persist the erasure so the blocks are not recovered after a restart
+        let erasure = BlockCacheKey::new(file_number, ERASED_FILE_OFFSET);
+        if let Err(e) = self.append(&mut inner, erasure, &[]) {
+            warn!(
+                "Fail to erase file {} in persistent cache, drop all the blocks: {}",
+                file_number, e
+            );
+            if let Err(e) = self.clear(&mut inner) {
+                warn!("Fail to clear persistent cache {:?}: {}", &self.dir, e);
+            }
+        }
+        inner.index.retain(|k, _| k.file_number != file_number);
+    }
+
+    fn bind_db(&self, db_id: &str) -> TemplateResult<()> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.bound > 0 {
+            // The blocks of the DB in use can't be dropped
+            if inner.db_id.as_deref() != Some(db_id) {
+                return Err(TemplateKVError::InvalidArgument(format!(
+                    "persistent cache {:?} is used by another db {:?}",
+                    &self.dir, &inner.db_id
+                )));
+            }
+        } else if inner.db_id.as_deref() != Some(db_id) {
+            // The blocks are dropped before the id is changed, so they're never recovered
+            // for the new DB after a crash
+            if inner.usage > 0 {
+                info!(
+                    "Drop persistent cache {:?} of another db {:?}",
+                    &self.dir, &inner.db_id
+                );
+                self.clear(&mut inner)?;
+            }
+            let db_id_file = self.dir.join(DB_ID_FILE_NAME);
+            if inner.db_id.is_some() {
+                self.storage.remove(&db_id_file)?;
+            }
+            do_write_string_to_file(&self.storage, db_id.to_owned(), &db_id_file, true)
+                .map_err(|e| e.with_path(&db_id_file))?;
+            inner.db_id = Some(db_id.to_owned());
+        }
+        inner.bound += 1;
+        Ok(())
+    }
+
+    fn unbind_db(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.bound = inner.bound.saturating_sub(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::mem::MemStorage;
+
+    fn block(i: u64, len: usize) -> Vec<u8> {
+        (0..len).map(|j| (i as usize + j) as u8).collect()
+    }
+
+    #[test]
+    fn test_insert_and_lookup() {
+        let s = MemStorage::default();
+        let cache = PersistentCache::open(s.clone(), "cache", 1 << 20).unwrap();
+        cache.bind_db("db").unwrap();
+        assert!(cache.is_empty());
+        for i in 0..100 {
+            cache.insert(
+                BlockCacheKey::new(i % 3, i * 100),
+                &block(i, 100 + i as usize),
+            );
+        }
+        assert_eq!(cache.len(), 100);
+        for i in 0..100 {
+            let data = cache.lookup(&BlockCacheKey::new(i % 3, i * 100)).unwrap();
+            assert_eq!(&data[..], &block(i, 100 + i as usize)[..]);
+        }
+        assert!(cache.lookup(&BlockCacheKey::new(3, 0)).is_none());
+
+        cache.erase_file(1);
+        for i in 0..100 {
+            let data = cache.lookup(&BlockCacheKey::new(i % 3, i * 100));
+            assert_eq!(data.is_some(), i % 3 != 1);
+        }
+    }
+
+    #[test]
+    fn test_recover() {
+        let s = MemStorage::default();
+        let cache = PersistentCache::open(s.clone(), "cache", 1 << 20).unwrap();
+        cache.bind_db("db").unwrap();
+        for i in 0..100 {
+            cache.insert(BlockCacheKey::new(1, i * 100), &block(i, 1000));
+        }
+        let usage = cache.usage();
+        drop(cache);
+
+        // A crash leaves a broken record at the end
+        let name = segment_file_name(Path::new("cache"), 0);
+        let mut f = s.open(&name).unwrap();
+        f.seek(std::io::SeekFrom::End(0)).unwrap();
+        f.write(&[1; 100]).unwrap();
+        drop(f);
+
+        let cache = PersistentCache::open(s.clone(), "cache", 1 << 20).unwrap();
+
+        cache.bind_db("db").unwrap();
+        assert_eq!(cache.len(), 100);
+        assert_eq!(cache.usage(), usage + 100);
+        for i in 0..100 {
+            let data = cache.lookup(&BlockCacheKey::new(1, i * 100)).unwrap();
+            assert_eq!(&data[..], &block(i, 1000)[..]);
+        }
+        cache.insert(BlockCacheKey::new(2, 0), &block(0, 10));
+        drop(cache);
+        let cache = PersistentCache::open(s, "cache", 1 << 20).unwrap();
+        cache.bind_db("db").unwrap();
+        assert_eq!(cache.len(), 101);
+        assert_eq!(
+            &cache.lookup(&BlockCacheKey::new(2, 0)).unwrap()[..],
+            &block(0, 10)[..]
+        );
+    }
+
+    #[test]
+    fn test_recover_erasures() {
+        let s = MemStorage::default();
+        let cache = PersistentCache::open(s.clone(), "cache", 1 << 20).unwrap();
+        cache.bind_db("db").unwrap();
+        for i in 0..10 {
+            cache.insert(BlockCacheKey::new(1, i * 100), &block(i, 100));
+            cache.insert(BlockCacheKey::new(2, i * 100), &block(i, 100));
+        }
+        cache.erase_file(1);
+        drop(cache);
+
+        // The file number is given to another table, e.g. after a crash
+        let cache = PersistentCache::open(s.clone(), "cache", 1 << 20).unwrap();
+        cache.bind_db("db").unwrap();
+        assert_eq!(cache.len(), 10);
+        assert!(cache.lookup(&BlockCacheKey::new(1, 0)).is_none());
+        assert!(cache.lookup(&BlockCacheKey::new(2, 0)).is_some());
+        cache.insert(BlockCacheKey::new(1, 0), &block(100, 100));
+        drop(cache);
+        let cache = PersistentCache::open(s, "cache", 1 << 20).unwrap();
+        cache.bind_db("db").unwrap();
+        assert_eq!(cache.len(), 11);
+        assert_eq!(
+            &cache.lookup(&BlockCacheKey::new(1, 0)).unwrap()[..],
+            &block(100, 100)[..]
+        );
+    }
+
+    #[test]
+    fn test_capacity() {
+        let s = MemStorage::default();
+        let capacity = 100 << 10;
+        let cache = PersistentCache::open(s.clone(), "cache", capacity).unwrap();
+        cache.bind_db("db").unwrap();
+        for i in 0..1000 {
+            cache.insert(BlockCacheKey::new(1, i), &block(i, 1000));
+            assert!(cache.usage() <= capacity + capacity / SEGMENTS);
+        }
+        // The oldest blocks are dropped with their segments
+        assert!(cache.lookup(&BlockCacheKey::new(1, 0)).is_none());
+        assert!(cache.lookup(&BlockCacheKey::new(1, 999)).is_some());
+        assert!(cache.len() < 100);
+        let segments = s
+            .list("cache")
+            .unwrap()
+            .iter()
+            .filter_map(|name| parse_segment_file_name(name))
+            .count();
+        assert!(segments <= SEGMENTS + 1, "{}", segments);
+
+        // Too large to cache
+        cache.insert(BlockCacheKey::new(2, 0), &block(0, capacity));
+        assert!(cache.lookup(&BlockCacheKey::new(2, 0)).is_none());
+    }
+
+    #[test]
+    fn test_bind_db() {
+        let s = MemStorage::default();
+        let cache = PersistentCache::open(s.clone(), "cache", 1 << 20).unwrap();
+        // Unused until a DB is bound
+        cache.insert(BlockCacheKey::new(1, 0), &block(0, 100));
+        assert!(cache.is_empty());
+        cache.bind_db("a").unwrap();
+        for i in 0..10 {
+            cache.insert(BlockCacheKey::new(1, i * 100), &block(i, 100));
+        }
+        drop(cache);
+
+        let cache = PersistentCache::open(s.clone(), "cache", 1 << 20).unwrap();
+        assert!(cache.lookup(&BlockCacheKey::new(1, 0)).is_none());
+        cache.bind_db("a").unwrap();
+        assert_eq!(cache.len(), 10);
+        assert_eq!(
+            &cache.lookup(&BlockCacheKey::new(1, 0)).unwrap()[..],
+            &block(0, 100)[..]
+        );
+        drop(cache);
+
+        // Another DB with the same file numbers, e.g. the DB created again
+        let cache = PersistentCache::open(s.clone(), "cache", 1 << 20).unwrap();
+        cache.bind_db("b").unwrap();
+        assert!(cache.is_empty());
+        assert_eq!(cache.usage(), 0);
+        assert!(cache.lookup(&BlockCacheKey::new(1, 0)).is_none());
+        cache.insert(BlockCacheKey::new(1, 0), &block(1, 100));
+        drop(cache);
+        let cache = PersistentCache::open(s.clone(), "cache", 1 << 20).unwrap();
+        cache.bind_db("b").unwrap();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(
+            &cache.lookup(&BlockCacheKey::new(1, 0)).unwrap()[..],
+            &block(1, 100)[..]
+        );
+
+        // The blocks are kept until all the instances of the bound DB are closed
+        cache.bind_db("b").unwrap();
+        cache.unbind_db();
+        assert!(matches!(
+            cache.bind_db("c"),
+            Err(TemplateKVError::InvalidArgument(_))
+        ));
+        assert_eq!(cache.len(), 1);
+        cache.unbind_db();
+        cache.bind_db("c").unwrap();
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,280 @@
+use std::sync::Arc;
+
+use super::FilterPolicy;
+use crate::util::{
+    coding::{decode_fixed_32, put_fixed_32},
+    hash::{hash, mix64},
+};
+
+// The number of the coefficient bits of a key, which is also the number of slots a key spans
+const RIBBON_WIDTH: usize = 64;
+// The fraction of the extra slots over the keys, which keeps the banding mostly successful
+const SLOT_OVERHEAD: f64 = 0.08;
+// num_blocks (4B) + seed (1B) + result bits (1B)
+const RIBBON_TRAILER_SIZE: usize = 6;
+// Grow the slots after this many seeds failed
+const ATTEMPTS_PER_SIZE: usize = 4;
+
+/// `RibbonFilter` is a Standard Ribbon filter, which saves about 30% space of a bloom filter
+/// for the same false positive rate at the cost of more CPU to build.
+///
+/// Each key is hashed into a `r`-bit fingerprint, a start slot and a 64-bit coefficient row
+/// covering the 64 slots from the start. Building the filter solves the linear system over
+/// GF(2) so that the XOR of the solution values of the slots selected by the coefficients
+/// equals the fingerprint for every key. A key not in the set matches with a probability of
+/// `2^-r`.
+///
+/// Filter format:
+///
+/// ```text
+/// +-----------------------------+------------------------+-----------+-----------------+
+/// | solution words (8B * r * n) | num_blocks n (fixed32) | seed (1B) | result bits r   |
+/// +-----------------------------+------------------------+-----------+-----------------+
+/// ```
+///
+/// The solution is stored by blocks of 64 slots. The i-th word of a block holds the i-th
+/// result bits of its 64 slots.
+pub struct RibbonFilter {
+    // the number of the fingerprint bits of a key
+    result_bits: u32,
+}
+
+impl RibbonFilter {
+    #[must_use]
+    pub fn new(bits_per_key: usize) -> Self {
+        let result_bits = (bits_per_key as f64 / (1.0 + SLOT_OVERHEAD)).round() as u32;
+        Self {
+            result_bits: result_bits.clamp(1, 32),
+        }
+    }
+
+    #[inline]
+    fn key_hash(key: &[u8]) -> u64 {
+        (u64::from(hash(key, 0xc6a4_a793)) << 32) | u64::from(hash(key, 0x7a4a_b1c5))
+    }
+
+    // Returns the start slot, the coefficients and the fingerprint of a key
+    #[inline]
+    fn derive(h: u64, seed: u8, positions: usize, result_bits: u32) -> (usize, u64, u32) {
+        let a = mix64(h ^ (u64::from(seed) + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let start = ((u128::from(a) * positions as u128) >> 64) as usize;
+        let b = mix64(a);
+        let fingerprint = (mix64(b) >> (64 - result_bits)) as u32;
+        (start, b | 1, fingerprint)
+    }
+
+    // Tries to solve the system for the given seed and number of blocks. Returns the solution
+    // words or `None` if the keys are not separable.
+    fn solve(&self, hashes: &[u64], seed: u8, num_blocks: usize) -> Option<Vec<u64>> {
+        let slots = num_blocks * RIBBON_WIDTH;
+        let positions = slots - RIBBON_WIDTH + 1;
+        let mut coeffs = vec![0u64; slots];
+        let mut results = vec![0u32; slots];
+        for h in hashes {
+            let (mut start, mut c, mut r) = Self::derive(*h, seed, positions, self.result_bits);
+            // Gaussian elimination on the band
+            loop {
+                if coeffs[start] == 0 {
+                    coeffs[start] = c;
+                    results[start] = r;
+                    break;
+                }
+                c ^= coeffs[start];
+                r ^= results[start];
+                if c == 0 {
+                    // The same row as a key added before, which is fine only for duplicated keys
+                    if r != 0 {
+                        return None;
+                    }
+                    break;
+                }
+                let tz = c.trailing_zeros();
+                start += tz as usize;
+                c >>= tz;
+            }
+        }
+        // Back substitution
+        let mut solution = vec![0u32; slots];
+        for i in (0..slots).rev() {
+            let c = coeffs[i];
+            if c == 0 {
+                continue;
+            }
+            let mut v = results[i];
+            let mut rest = c >> 1;
+            while rest != 0 {
+                v ^= solution[i + 1 + rest.trailing_zeros() as usize];
+                rest &= rest - 1;
+            }
+            solution[i] = v;
+        }
+        let r = self.result_bits as usize;
+        let mut words = vec![0u64; num_blocks * r];
+        for (slot, v) in solution.iter().enumerate() {
+            let (block, offset) = (slot / RIBBON_WIDTH, slot % RIBBON_WIDTH);
+            for bit in 0..r {
+                words[block * r + bit] |= u64::from((v >> bit) & 1) << offset;
+            }
+        }
+        Some(words)
+    }
+}
+
+impl FilterPolicy for RibbonFilter {
+    fn name(&self) -> &str {
+        "TemplateDB.RibbonFilter"
+    }
+
+    fn may_contain(&self, filter: &[u8], key: &[u8]) -> bool {
+        let n = filter.len();
+        if n < RIBBON_TRAILER_SIZE {
+            return false;
+        }
+        let num_blocks = decode_fixed_32(&filter[n - RIBBON_TRAILER_SIZE..]) as usize;
+        let seed = filter[n - 2];
+        // Use the encoded result bits so that the filters built with other bits per key
+        // are readable
+        let result_bits = u32::from(filter[n - 1]);
+        if result_bits == 0
+            || result_bits > 32
+            || num_blocks * result_bits as usize * 8 != n - RIBBON_TRAILER_SIZE
+        {
+            // Not generated by this policy. Consider it a match.
+            return true;
+        }
+        if num_blocks == 0 {
+            return false;
+        }
+        let positions = num_blocks * RIBBON_WIDTH - RIBBON_WIDTH + 1;
+        let (start, c, fingerprint) =
+            Self::derive(Self::key_hash(key), seed, positions, result_bits);
+        let (block, offset) = (start / RIBBON_WIDTH, start % RIBBON_WIDTH);
+        let r = result_bits as usize;
+        let word = |i: usize| {
+            let b = &filter[i * 8..(i + 1) * 8];
+            u64::from_le_bytes(b.try_into().unwrap())
+        };
+        let mut result = 0u32;
+        for bit in 0..r {
+            let mut v = word(block * r + bit) >> offset;
+            if offset > 0 {
+                v |= word((block + 1) * r + bit) << (RIBBON_WIDTH - offset);
+            }
+            result |= ((v & c).count_ones() & 1) << bit;
+        }
+        result == fingerprint
+    }
+
+    fn create_filter(&self, keys: &[Vec<u8>]) -> Vec<u8> {
+        let mut dst = vec![];
+        if keys.is_empty() {
+            put_fixed_32(&mut dst, 0);
+            dst.push(0);
+            dst.push(self.result_bits as u8);
+            return dst;
+        }
+        let hashes = keys.iter().map(|k| Self::key_hash(k)).collect::<Vec<_>>();
+        let slots = (keys.len() as f64 * (1.0 + SLOT_OVERHEAD)).ceil() as usize + RIBBON_WIDTH - 1;
+        let mut num_blocks = slots.div_ceil(RIBBON_WIDTH);
+        let mut attempt = 0;
+        let (words, seed) = loop {
+            let seed = (attempt % 256) as u8;
+            if let Some(words) = self.solve(&hashes, seed, num_blocks) {
+                break (words, seed);
+            }
+            attempt += 1;
+            // More slots make the banding more likely to succeed
+            if attempt % ATTEMPTS_PER_SIZE == 0 {
+                num_blocks += num_blocks / 16 + 1;
+            }
+        };
+        dst.reserve(words.len() * 8 + RIBBON_TRAILER_SIZE);
+        for w in words {
+            dst.extend_from_slice(&w.to_le_bytes());
+        }
+        put_fixed_32(&mut dst, num_blocks as u32);
+        dst.push(seed);
+        dst.push(self.result_bits as u8);
+        dst
+    }
+
+    fn with_bits_per_key(&self, bits_per_key: f64) -> Option<Arc<dyn FilterPolicy>> {
+        Some(Arc::new(Self::new(bits_per_key.round().max(1.0) as usize)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::coding::encode_fixed_32;
+
+    fn num_key(i: u32) -> Vec<u8> {
+        let mut k = vec![0; 4];
+        encode_fixed_32(&mut k, i);
+        k
+    }
+
+    // Builds a filter over `n` keys and returns the false positive rate of 10000 other keys
+    fn false_positive_rate(policy: &dyn FilterPolicy, n: u32) -> f64 {
+        let keys = (0..n).map(num_key).collect::<Vec<_>>();
+        let filter = policy.create_filter(&keys);
+        for key in keys.iter() {
+            assert!(policy.may_contain(&filter, key));
+        }
+        let false_positives = (0..10000)
+            .filter(|i| policy.may_contain(&filter, &num_key(i + 1_000_000_000)))
+            .count();
+        false_positives as f64 / 10000.0
+    }
+
+    #[test]
+    fn test_ribbon_filter_empty() {
+        let policy = RibbonFilter::new(10);
+        let filter = policy.create_filter(&[]);
+        assert_eq!(filter.len(), RIBBON_TRAILER_SIZE);
+        assert!(!policy.may_contain(&filter, b"hello"));
+        assert!(!policy.may_contain(&[], b"hello"));
+    }
+
+    #[test]
+    fn test_ribbon_filter_small() {
+        let policy = RibbonFilter::new(10);
+        let keys = vec![b"hello".to_vec(), b"world".to_vec(), b"hello".to_vec()];
+        let filter = policy.create_filter(&keys);
+        assert!(policy.may_contain(&filter, b"hello"));
+        assert!(policy.may_contain(&filter, b"world"));
+        assert!(!policy.may_contain(&filter, b"x"));
+        assert!(!policy.may_contain(&filter, b"foo"));
+        // Unknown formats are considered as matches
+        assert!(policy.may_contain(&filter[1..], b"foo"));
+    }
+
+    #[test]
+    fn test_ribbon_filter_false_positive_rate() {
+        for (bits_per_key, max_rate) in [(6, 0.05), (10, 0.006), (16, 0.001)] {
+            let policy = RibbonFilter::new(bits_per_key);
+            for n in [100, 1000, 10000] {
+                let rate = false_positive_rate(&policy, n);
+                assert!(
+                    rate <= max_rate,
+                    "bits per key {}, {} keys: {}",
+                    bits_per_key,
+                    n,
+                    rate
+                );
+                // The space is close to the bits per key for the large sets
+                if n >= 10000 {
+                    let keys = (0..n).map(num_key).collect::<Vec<_>>();
+                    let bits = policy.create_filter(&keys).len() * 8;
+                    assert!(
+                        bits as f64 / (n as f64) < bits_per_key as f64 * 1.1,
+                        "bits per key {}, {} keys: {} bits",
+                        bits_per_key,
+                        n,
+                        bits
+                    );
+                }
+            }
+        }
+    }
+}
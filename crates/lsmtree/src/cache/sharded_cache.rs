@@ -5,7 +5,7 @@ use std::{
     sync::Arc,
 };
 
-use super::CacheSync;
+use super::{CachePriority, CacheSync};
 
 /// A sharded cache container by key hash
 pub struct ShardedCache<K, V, C>
@@ -54,11 +54,27 @@ where
         self.shards[idx].insert(key, value, charge)
     }
 
+    fn insert_with_priority(
+        &self,
+        key: K,
+        value: V,
+        charge: usize,
+        priority: CachePriority,
+    ) -> Option<V> {
+        let idx = self.find_shard(&key);
+        self.shards[idx].insert_with_priority(key, value, charge, priority)
+    }
+
     fn get(&self, key: &K) -> Option<V> {
         let idx = self.find_shard(key);
         self.shards[idx].get(key)
     }
 
+    fn contains(&self, key: &K) -> bool {
+        let idx = self.find_shard(key);
+        self.shards[idx].contains(key)
+    }
+
     fn erase(&self, key: &K) {
         let idx = self.find_shard(key);
         self.shards[idx].erase(key);
@@ -1,8 +1,11 @@
 use std::sync::Arc;
 
+use bytes::Bytes;
+
 use super::lru_cache::LRUCache;
 use crate::{
     cache::CacheSync,
+    error::Operation,
     options::{Options, ReadOptions},
     sstable::{
         block::BlockIterator,
@@ -48,14 +51,27 @@ impl<S: Storage + Clone, C: Comparator + 'static> TableCache<S, C> {
             Ok(v)
         } else {
             let filename = generate_filename(&self.db_path, FileType::Table, file_number);
-            let table_file = self.storage.open(filename)?;
+            let table_file = if self.options.use_direct_reads {
+                self.storage.open_direct(&filename)
+            } else if self.options.allow_mmap_reads {
+                self.storage.open_mmap(&filename)
+            } else {
+                // The sst files are never written once built
+                self.storage.open_read_only(&filename)
+            };
+            let table_file = table_file.map_err(|e| {
+                e.with_operation(Operation::OpenTable)
+                    .with_file_number(file_number)
+                    .with_path(&filename)
+            })?;
             let table = Table::open(
                 table_file,
                 file_number,
                 file_size,
                 self.options.clone(),
                 cmp,
-            )?;
+            )
+            .map_err(|e| e.with_path(&filename))?;
             let value = Arc::new(table);
             let _ = self.cache.insert(file_number, value.clone(), 1);
             Ok(value)
@@ -71,7 +87,7 @@ impl<S: Storage + Clone, C: Comparator + 'static> TableCache<S, C> {
     pub fn get<TC: Comparator>(
         &self,
         cmp: TC,
-        options: ReadOptions,
+        options: &ReadOptions,
         key: &[u8],
         file_number: u64,
         file_size: u64,
@@ -80,6 +96,21 @@ impl<S: Storage + Clone, C: Comparator + 'static> TableCache<S, C> {
         table.internal_get(options, cmp, key)
     }
 
+    /// Returns the results of the seeks to internal keys `keys` in specified file, which are
+    /// looked up in a batch. See `Table::multi_get`.
+    #[allow(clippy::type_complexity)]
+    pub fn multi_get<TC: Comparator>(
+        &self,
+        cmp: TC,
+        options: &ReadOptions,
+        keys: &[&[u8]],
+        file_number: u64,
+        file_size: u64,
+    ) -> TemplateResult<Vec<Option<(Vec<u8>, Bytes)>>> {
+        let table = self.find_table(cmp.clone(), file_number, file_size)?;
+        table.multi_get(options, cmp, keys)
+    }
+
     /// Create an iterator for the specified `file_number` (the corresponding
     /// file length must be exactly `file_size` bytes).
     /// The table referenced by returning Iterator will be released after the Iterator is dropped.
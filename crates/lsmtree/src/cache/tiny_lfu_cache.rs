@@ -0,0 +1,574 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    sync::Mutex,
+};
+
+use crate::{
+    cache::{CachePriority, CacheSync, EvictionListener, linked_slab::LinkedSlab},
+    util::collection::{DefaultHashBuilder, HashMap},
+};
+
+const SKETCH_DEPTH: usize = 4;
+const SKETCH_SEEDS: [u64; SKETCH_DEPTH] = [
+    0x97cb_3127_a56b_73a1,
+    0xc2b2_ae3d_27d4_eb4f,
+    0x1656_67b1_9e37_79f9,
+    0x9e37_79b9_7f4a_7c15,
+];
+// The counters are 4-bit as in TinyLFU, though each takes a byte
+const MAX_FREQUENCY: u8 = 15;
+
+// A count-min sketch estimating the access frequencies of the keys, which halves all the
+// counters after every `10 * width` increments so that the estimates age. Each row has 4
+// counters per entry of the cache, since the keys looked up are many more than the entries.
+struct FrequencySketch {
+    // `SKETCH_DEPTH` rows of `width` counters
+    counters: Vec<u8>,
+    width: usize,
+    additions: usize,
+}
+
+impl FrequencySketch {
+    fn new(entries: usize) -> Self {
+        let width = (entries * 4).max(16).next_power_of_two();
+        Self {
+            counters: vec![0; SKETCH_DEPTH * width],
+            width,
+            additions: 0,
+        }
+    }
+
+    // Widens the sketch if it's too narrow for `entries` keys. A counter is copied to the
+    // ones whose indexes keep its low bits, so the estimates are kept.
+    fn ensure_capacity(&mut self, entries: usize) {
+        if entries * 4 <= self.width {
+            return;
+        }
+        let width = (entries * 4).next_power_of_two();
+        let mut counters = vec![0; SKETCH_DEPTH * width];
+        for row in 0..SKETCH_DEPTH {
+            for j in 0..width {
+                counters[row * width + j] =
+                    self.counters[row * self.width + (j & (self.width - 1))];
+            }
+        }
+        self.counters = counters;
+        self.additions = self.additions * width / self.width;
+        self.width = width;
+    }
+
+    #[inline]
+    fn index(&self, hash: u64, row: usize) -> usize {
+        let h = hash.wrapping_mul(SKETCH_SEEDS[row]);
+        row * self.width + ((h ^ (h >> 32)) as usize & (self.width - 1))
+    }
+
+    fn increment(&mut self, hash: u64) {
+        let mut added = false;
+        for row in 0..SKETCH_DEPTH {
+            let i = self.index(hash, row);
+            if self.counters[i] < MAX_FREQUENCY {
+                self.counters[i] += 1;
+                added = true;
+            }
+        }
+        if added {
+            self.additions += 1;
+            if self.additions >= 10 * self.width {
+                self.counters.iter_mut().for_each(|c| *c >>= 1);
+                self.additions /= 2;
+            }
+        }
+    }
+
+    fn frequency(&self, hash: u64) -> u8 {
+        (0..SKETCH_DEPTH)
+            .map(|row| self.counters[self.index(hash, row)])
+            .min()
+            .unwrap()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Segment {
+    Window,
+    Probation,
+    Protected,
+}
+
+struct Entry<K, V> {
+    key: K,
+    value: V,
+    charge: usize,
+    segment: Segment,
+    high_pri: bool,
+}
+
+struct TinyLfuInner<K, V> {
+    capacity: usize,
+    window_capacity: usize,
+    protected_capacity: usize,
+    high_pri_pool: usize,
+    entries: LinkedSlab<Entry<K, V>>,
+    table: HashMap<K, usize>,
+    // the sentinels of the LRU lists of the segments, whose fronts are the most recent
+    window: usize,
+    probation: usize,
+    protected: usize,
+    window_size: usize,
+    probation_size: usize,
+    protected_size: usize,
+    // the charge of the protected entries inserted with the high priority
+    high_pri_protected: usize,
+    sketch: FrequencySketch,
+    hasher: DefaultHashBuilder,
+    // the entries evicted to pass to the eviction listener once the cache is unlocked, `None`
+    // if there is no listener
+    evicted: Option<Vec<(K, V)>>,
+}
+
+/// `TinyLfuCache` is a scan-resistant cache with the W-TinyLFU admission policy.
+///
+/// A new entry is inserted into a small LRU window, and leaves it for the main segmented
+/// LRU only if it's accessed more often than the entry it would evict, as estimated by a
+/// count-min sketch of the recent accesses. The blocks read once by a scan are seldom
+/// admitted, so they don't evict the frequently read ones. An entry accessed again in the
+/// probation segment of the main LRU moves to the protected segment.
+///
+/// The entries inserted with `CachePriority::High` go to the protected segment directly
+/// while their charge is within the high priority pool, and aren't demoted from it by the
+/// others.
+pub struct TinyLfuCache<K, V> {
+    inner: Mutex<TinyLfuInner<K, V>>,
+    eviction_listener: Option<EvictionListener<K, V>>,
+}
+
+impl<K: Clone + Hash + Eq, V> TinyLfuCache<K, V> {
+    /// Creates a `TinyLfuCache` whose high priority pool is half of `capacity`
+    pub fn new(capacity: usize) -> Self {
+        Self::with_high_pri_pool_ratio(capacity, 0.5)
+    }
+
+    /// Creates a `TinyLfuCache` whose high priority pool is `ratio` of `capacity`
+    pub fn with_high_pri_pool_ratio(capacity: usize, ratio: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&ratio),
+            "invalid high priority pool ratio {ratio}"
+        );
+        // 1% of the capacity for the window and 80% of the rest for the protected segment
+        let window_capacity = (capacity / 100).max(1).min(capacity);
+        let mut entries = LinkedSlab::new();
+        let window = entries.new_sentinel();
+        let probation = entries.new_sentinel();
+        let protected = entries.new_sentinel();
+        Self {
+            inner: Mutex::new(TinyLfuInner {
+                capacity,
+                window_capacity,
+                protected_capacity: (capacity - window_capacity) / 5 * 4,
+                high_pri_pool: (capacity as f64 * ratio) as usize,
+                entries,
+                table: HashMap::default(),
+                window,
+                probation,
+                protected,
+                window_size: 0,
+                probation_size: 0,
+                protected_size: 0,
+                high_pri_protected: 0,
+                sketch: FrequencySketch::new(0),
+                hasher: DefaultHashBuilder::default(),
+                evicted: None,
+            }),
+            eviction_listener: None,
+        }
+    }
+
+    /// Sets the listener called with the evicted entries
+    #[must_use]
+    pub fn with_eviction_listener(mut self, listener: EvictionListener<K, V>) -> Self {
+        self.inner.get_mut().unwrap().evicted = Some(vec![]);
+        self.eviction_listener = Some(listener);
+        self
+    }
+}
+
+impl<K: Clone + Hash + Eq, V> TinyLfuInner<K, V> {
+    #[inline]
+    fn used(&self) -> usize {
+        self.window_size + self.probation_size + self.protected_size
+    }
+
+    #[inline]
+    fn frequency(&self, i: usize) -> u8 {
+        let hash = self.hasher.hash_one(&self.entries.get(i).key);
+        self.sketch.frequency(hash)
+    }
+
+    // Returns the least recent entry of `segment`
+    fn lru(&self, segment: Segment) -> Option<usize> {
+        let sentinel = self.sentinel(segment);
+        if self.entries.is_alone(sentinel) {
+            None
+        } else {
+            Some(self.entries.prev(sentinel))
+        }
+    }
+
+    #[inline]
+    fn sentinel(&self, segment: Segment) -> usize {
+        match segment {
+            Segment::Window => self.window,
+            Segment::Probation => self.probation,
+            Segment::Protected => self.protected,
+        }
+    }
+
+    fn size_mut(&mut self, segment: Segment) -> &mut usize {
+        match segment {
+            Segment::Window => &mut self.window_size,
+            Segment::Probation => &mut self.probation_size,
+            Segment::Protected => &mut self.protected_size,
+        }
+    }
+
+    // Links the unlinked entry `i` as the most recent of `segment`
+    fn link(&mut self, i: usize, segment: Segment) {
+        let sentinel = self.sentinel(segment);
+        self.entries.link_after(i, sentinel);
+        let e = self.entries.get_mut(i);
+        e.segment = segment;
+        let (charge, high_pri) = (e.charge, e.high_pri);
+        *self.size_mut(segment) += charge;
+        if segment == Segment::Protected && high_pri {
+            self.high_pri_protected += charge;
+        }
+    }
+
+    fn unlink(&mut self, i: usize) {
+        self.entries.unlink(i);
+        let e = self.entries.get(i);
+        let (segment, charge, high_pri) = (e.segment, e.charge, e.high_pri);
+        *self.size_mut(segment) -= charge;
+        if segment == Segment::Protected && high_pri {
+            self.high_pri_protected -= charge;
+        }
+    }
+
+    fn remove(&mut self, i: usize) -> Entry<K, V> {
+        self.unlink(i);
+        let e = self.entries.remove(i);
+        self.table.remove(&e.key);
+        e
+    }
+
+    fn evict_entry(&mut self, i: usize) {
+        let e = self.remove(i);
+        if let Some(evicted) = &mut self.evicted {
+            evicted.push((e.key, e.value));
+        }
+    }
+
+    fn on_hit(&mut self, i: usize) {
+        let segment = match self.entries.get(i).segment {
+            Segment::Window => Segment::Window,
+            Segment::Probation | Segment::Protected => Segment::Protected,
+        };
+        self.unlink(i);
+        self.link(i, segment);
+        self.shrink_protected();
+    }
+
+    // Demotes the least recent protected entries to the probation segment until it fits,
+    // skipping the high priority entries within the pool.
+    fn shrink_protected(&mut self) {
+        while self.protected_size > self.protected_capacity {
+            let in_pool = self.high_pri_protected <= self.high_pri_pool;
+            let lru = self.lru(Segment::Protected).unwrap();
+            let mut i = lru;
+            while i != self.protected && in_pool && self.entries.get(i).high_pri {
+                i = self.entries.prev(i);
+            }
+            let victim = if i == self.protected { lru } else { i };
+            self.unlink(victim);
+            self.link(victim, Segment::Probation);
+        }
+    }
+
+    // Moves the overflow of the window to the main segments if they admit it, then evicts
+    // the least recent entries until the cache fits.
+    fn evict(&mut self) {
+        while self.window_size > self.window_capacity {
+            let candidate = self.lru(Segment::Window).unwrap();
+            self.admit(candidate);
+        }
+        while self.used() > self.capacity {
+            let victim = self
+                .lru(Segment::Probation)
+                .or_else(|| self.lru(Segment::Protected))
+                .or_else(|| self.lru(Segment::Window))
+                .unwrap();
+            self.evict_entry(victim);
+        }
+    }
+
+    // Moves `candidate` from the window to the probation segment, evicting the least recent
+    // entries of the main segments that are accessed less often than it. It's evicted
+    // instead if it's not accessed more often than any of them.
+    fn admit(&mut self, candidate: usize) {
+        let frequency = self.frequency(candidate);
+        while self.used() > self.capacity {
+            match self
+                .lru(Segment::Probation)
+                .or_else(|| self.lru(Segment::Protected))
+            {
+                Some(victim) if frequency > self.frequency(victim) => {
+                    self.evict_entry(victim);
+                }
+                _ => {
+                    self.evict_entry(candidate);
+                    return;
+                }
+            }
+        }
+        self.unlink(candidate);
+        self.link(candidate, Segment::Probation);
+    }
+}
+
+impl<K, V> CacheSync<K, V> for TinyLfuCache<K, V>
+where
+    K: Send + Sync + Clone + Hash + Eq,
+    V: Send + Sync + Clone,
+{
+    fn insert(&self, key: K, value: V, charge: usize) -> Option<V> {
+        self.insert_with_priority(key, value, charge, CachePriority::Low)
+    }
+
+    fn insert_with_priority(
+        &self,
+        key: K,
+        value: V,
+        charge: usize,
+        priority: CachePriority,
+    ) -> Option<V> {
+        let mut l = self.inner.lock().unwrap();
+        let high_pri = priority == CachePriority::High;
+        let mut segment = Segment::Window;
+        let mut old = None;
+        if let Some(&i) = l.table.get(&key) {
+            let e = l.remove(i);
+            segment = e.segment;
+            old = Some(e.value);
+        }
+        if charge > l.capacity || l.capacity == 0 {
+            return old;
+        }
+        if high_pri && l.high_pri_protected + charge <= l.high_pri_pool {
+            segment = Segment::Protected;
+        }
+        let i = l.entries.insert(Entry {
+            key: key.clone(),
+            value,
+            charge,
+            segment,
+            high_pri,
+        });
+        l.table.insert(key, i);
+        let len = l.table.len();
+        l.sketch.ensure_capacity(len);
+        l.link(i, segment);
+        l.shrink_protected();
+        l.evict();
+        let evicted = l.evicted.as_mut().map(std::mem::take);
+        drop(l);
+        if let (Some(listener), Some(evicted)) = (&self.eviction_listener, evicted) {
+            for (k, v) in evicted {
+                listener(&k, &v);
+            }
+        }
+        old
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        let mut l = self.inner.lock().unwrap();
+        let hash = l.hasher.hash_one(key);
+        l.sketch.increment(hash);
+        let i = *l.table.get(key)?;
+        l.on_hit(i);
+        Some(l.entries.get(i).value.clone())
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.inner.lock().unwrap().table.contains_key(key)
+    }
+
+    fn erase(&self, key: &K) {
+        let mut l = self.inner.lock().unwrap();
+        if let Some(&i) = l.table.get(key) {
+            l.remove(i);
+        }
+    }
+
+    fn total_charge(&self) -> usize {
+        self.inner.lock().unwrap().used()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_frequency_sketch() {
+        let mut sketch = FrequencySketch::new(100);
+        assert_eq!(sketch.width, 512);
+        for _ in 0..5 {
+            sketch.increment(1);
+        }
+        for _ in 0..20 {
+            sketch.increment(2);
+        }
+        assert!(sketch.frequency(1) >= 5);
+        assert_eq!(sketch.frequency(2), MAX_FREQUENCY);
+
+        // The counts are halved as the increments reach the sample size
+        for i in 0..10 * 512 {
+            sketch.increment(1000 + i);
+        }
+        assert!(sketch.frequency(2) < MAX_FREQUENCY);
+
+        // The counts are kept as the sketch grows
+        let frequency = sketch.frequency(2);
+        sketch.ensure_capacity(1000);
+        assert_eq!(sketch.width, 4096);
+        assert_eq!(sketch.frequency(2), frequency);
+    }
+
+    #[test]
+    fn test_hit_and_miss() {
+        let cache = TinyLfuCache::new(100);
+        assert_eq!(cache.get(&100), None);
+        assert_eq!(cache.insert(100, 101, 1), None);
+        assert_eq!(cache.get(&100), Some(101));
+        assert_eq!(cache.insert(200, 201, 1), None);
+        assert_eq!(cache.insert(100, 102, 1), Some(101));
+        assert_eq!(cache.get(&100), Some(102));
+        assert_eq!(cache.get(&200), Some(201));
+        assert_eq!(cache.total_charge(), 2);
+        cache.erase(&200);
+        assert_eq!(cache.get(&200), None);
+        cache.erase(&300);
+        assert_eq!(cache.total_charge(), 1);
+    }
+
+    #[test]
+    fn test_contains() {
+        let cache = TinyLfuCache::new(100);
+        assert!(!cache.contains(&100));
+        cache.insert(100, 101, 1);
+        let frequency = |cache: &TinyLfuCache<u32, u32>| {
+            let l = cache.inner.lock().unwrap();
+            l.frequency(l.table[&100])
+        };
+        // Unlike `get`, the access is not counted
+        let before = frequency(&cache);
+        for _ in 0..5 {
+            assert!(cache.contains(&100));
+        }
+        assert_eq!(frequency(&cache), before);
+        cache.get(&100);
+        assert!(frequency(&cache) > before);
+    }
+
+    #[test]
+    fn test_charge() {
+        let cache = TinyLfuCache::new(100);
+        for i in 0..100 {
+            cache.insert(i, i, 10);
+            assert!(cache.total_charge() <= 100);
+        }
+        // Too heavy to cache
+        assert_eq!(cache.insert(1000, 1000, 101), None);
+        assert_eq!(cache.get(&1000), None);
+        assert!(cache.total_charge() <= 100);
+
+        let cache = TinyLfuCache::new(0);
+        cache.insert(1, 1, 0);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.total_charge(), 0);
+    }
+
+    #[test]
+    fn test_scan_resistance() {
+        let cache = TinyLfuCache::new(100);
+        for _ in 0..3 {
+            for i in 0..50 {
+                if cache.get(&i).is_none() {
+                    cache.insert(i, i, 1);
+                }
+            }
+        }
+        // The scanned entries are accessed less often than the hot ones
+        for i in 1000..2000 {
+            if cache.get(&i).is_none() {
+                cache.insert(i, i, 1);
+            }
+        }
+        let hits = (0..50).filter(|i| cache.get(i).is_some()).count();
+        assert_eq!(hits, 50);
+        assert!(cache.total_charge() <= 100);
+    }
+
+    #[test]
+    fn test_eviction_listener() {
+        let evicted = Arc::new(Mutex::new(vec![]));
+        let cloned = evicted.clone();
+        let cache = TinyLfuCache::new(10).with_eviction_listener(Box::new(move |k, v| {
+            cloned.lock().unwrap().push((*k, *v));
+        }));
+        for i in 0..20 {
+            cache.insert(i, i * 10, 1);
+        }
+        cache.erase(&19);
+        // The erased entry is not evicted
+        let mut evicted = evicted.lock().unwrap().clone();
+        evicted.sort_unstable();
+        assert_eq!(evicted.len(), 10);
+        assert!(evicted.iter().all(|&(k, v)| k < 19 && v == k * 10));
+        assert!((0..20).filter(|i| cache.get(i).is_some()).count() == 9);
+    }
+
+    #[test]
+    fn test_high_priority() {
+        let cache = TinyLfuCache::with_high_pri_pool_ratio(100, 0.3);
+        for i in 0..30 {
+            cache.insert_with_priority(i, i, 1, CachePriority::High);
+        }
+        // The high priority entries are never accessed but survive the others
+        for _ in 0..3 {
+            for i in 1000..1500 {
+                if cache.get(&i).is_none() {
+                    cache.insert(i, i, 1);
+                }
+            }
+        }
+        for _ in 0..5 {
+            for i in 2000..2070 {
+                if cache.get(&i).is_none() {
+                    cache.insert(i, i, 1);
+                }
+            }
+        }
+        assert!((0..30).all(|i| cache.get(&i).is_some()));
+
+        // Beyond the pool they are inserted like the others
+        for i in 100..1000 {
+            cache.insert_with_priority(i, i, 1, CachePriority::High);
+            assert!(cache.total_charge() <= 100);
+        }
+        assert_eq!(cache.get(&999), Some(999));
+    }
+}
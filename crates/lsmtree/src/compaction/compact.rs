@@ -5,19 +5,20 @@ use std::{cmp::Ordering as CmpOrdering, sync::Arc};
 use crossbeam_channel::Sender;
 
 use crate::{
+    blob::{blob_file::BlobFileBuilder, blob_index::BlobIndex},
     cache::table_cache::TableCache,
     error::TemplateResult,
     iterator::{concatenate_iter::ConcatenateIterator, kmerge_iter::KMergeIter},
     manager::{
         version::{LevelFileNumIterator, Version},
-        version_edit::{FileMetaData, VersionEdit},
+        version_edit::{BlobFileMeta, FileMetaData, VersionEdit},
         version_set::{FileIterFactory, SSTableIters, total_file_size},
     },
     memtable::key_format::{InternalKey, InternalKeyComparator},
     options::{Options, ReadOptions},
     sstable::table::TableBuilder,
     storage::{File, Storage},
-    util::comparator::Comparator,
+    util::{collection::HashMap, comparator::Comparator},
 };
 
 /// Information for a manual compaction
@@ -119,6 +120,13 @@ pub struct Compaction<F: File, C: Comparator> {
 
     // total bytes has been written
     pub total_bytes: u64,
+
+    // current blob file builder for the values separated from the output sst files
+    pub blob_builder: Option<BlobFileBuilder<F>>,
+    // all output blob files information sorted by produced order
+    pub blob_outputs: Vec<BlobFileMeta>,
+    // blob file number -> (count, bytes) of the values dropped or relocated by this compaction
+    pub blob_garbage: HashMap<u64, (u64, u64)>,
 }
 
 impl<O: File, C: Comparator + 'static> Compaction<O, C> {
@@ -139,6 +147,9 @@ impl<O: File, C: Comparator + 'static> Compaction<O, C> {
             outputs: vec![],
             builder: None,
             total_bytes: 0,
+            blob_builder: None,
+            blob_outputs: vec![],
+            blob_garbage: HashMap::default(),
         }
     }
 
@@ -169,6 +180,7 @@ impl<O: File, C: Comparator + 'static> Compaction<O, C> {
             verify_checksums: self.options.paranoid_checks,
             fill_cache: false,
             snapshot: None,
+            ..Default::default()
         };
         // Level-0 files have to be merged together so we generate a merging iterator includes
         // iterators for each level 0 file. For other levels, we will make a concatenating
@@ -183,7 +195,7 @@ impl<O: File, C: Comparator + 'static> Compaction<O, C> {
                 );
                 level_0.push(table_cache.new_iter(
                     icmp.clone(),
-                    read_options,
+                    read_options.clone(),
                     file.number,
                     file.file_size,
                 )?);
@@ -196,7 +208,8 @@ impl<O: File, C: Comparator + 'static> Compaction<O, C> {
                 );
             }
             let origin = LevelFileNumIterator::new(icmp.clone(), self.inputs.base.clone());
-            let factory = FileIterFactory::new(icmp.clone(), read_options, table_cache.clone());
+            let factory =
+                FileIterFactory::new(icmp.clone(), read_options.clone(), table_cache.clone());
             level_n.push(ConcatenateIterator::new(origin, factory));
         }
         if !self.inputs.parent.is_empty() {
@@ -272,6 +285,30 @@ impl<O: File, C: Comparator + 'static> Compaction<O, C> {
         false
     }
 
+    /// Returns the file number before which the values in the blob files are relocated into
+    /// new blob files by this compaction. The oldest `blob_garbage_collection_age_cutoff` of
+    /// the blob files are collected so returns 0 if none is.
+    pub fn blob_gc_cutoff(&self) -> u64 {
+        if !self.options.enable_blob_garbage_collection {
+            return 0;
+        }
+        let blob_files = &self.input_version.as_ref().unwrap().blob_files;
+        let n =
+            (blob_files.len() as f64 * self.options.blob_garbage_collection_age_cutoff) as usize;
+        if n == 0 {
+            return 0;
+        }
+        blob_files.keys().nth(n).copied().unwrap_or(u64::MAX)
+    }
+
+    /// Records the value referred by `index` as garbage since the compaction drops or
+    /// relocates it
+    pub fn add_blob_garbage(&mut self, index: &BlobIndex) {
+        let garbage = self.blob_garbage.entry(index.file_number).or_default();
+        garbage.0 += 1;
+        garbage.1 += index.size;
+    }
+
     /// Apply deletion for current inputs and current output files to the edit
     pub fn apply_to_edit(&mut self) {
         for f in &self.inputs.base {
@@ -286,6 +323,12 @@ impl<O: File, C: Comparator + 'static> Compaction<O, C> {
                 .new_files
                 .push((self.level + 1, output));
         }
+        for blob in self.blob_outputs.drain(..) {
+            self.edit.add_blob_file(blob);
+        }
+        for (number, (count, bytes)) in self.blob_garbage.drain() {
+            self.edit.add_blob_garbage(number, count, bytes);
+        }
     }
 
     /// Calculate the read bytes
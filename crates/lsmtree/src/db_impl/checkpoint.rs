@@ -0,0 +1,143 @@
+use std::{
+    path::{MAIN_SEPARATOR, Path},
+    sync::atomic::Ordering,
+};
+
+use super::template_impl::{DBImpl, TemplateDB};
+use crate::{
+    error::{TemplateKVError, TemplateResult},
+    manager::filename::{FileType, generate_filename, parse_filename},
+    storage::{File, Storage, copy_file, do_write_string_to_file, verify_file_checksum},
+    util::{collection::HashMap, comparator::Comparator},
+};
+
+// Disables the file deletions until it's dropped, so the live files of the current version
+// are kept even if they are compacted meanwhile
+pub(crate) struct FileDeletionsGuard<'a, S: Storage + Clone, C: Comparator> {
+    db: &'a DBImpl<S, C>,
+}
+
+impl<'a, S: Storage + Clone, C: Comparator> FileDeletionsGuard<'a, S, C> {
+    pub(crate) fn new(db: &'a DBImpl<S, C>) -> Self {
+        db.file_deletions_disabled.fetch_add(1, Ordering::AcqRel);
+        Self { db }
+    }
+}
+
+impl<S: Storage + Clone, C: Comparator> Drop for FileDeletionsGuard<'_, S, C> {
+    fn drop(&mut self) {
+        self.db
+            .file_deletions_disabled
+            .fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl<S: Storage + Clone, C: Comparator + 'static> TemplateDB<S, C> {
+    /// Builds an openable snapshot of the db in the directory `checkpoint_dir`, which must not
+    /// exist yet.
+    ///
+    /// The memtable is flushed first so the checkpoint contains all the writes finished before
+    /// this call. The sst files and the blob files are hard linked into `checkpoint_dir` if the
+    /// storage supports it, otherwise they are copied. The result can be opened by
+    /// `TemplateDB::open_db` directly.
+    pub fn create_checkpoint<P: AsRef<Path>>(&self, checkpoint_dir: P) -> TemplateResult<()> {
+        let db = &self.inner;
+        let Some(dir) = checkpoint_dir.as_ref().to_str() else {
+            return Err(TemplateKVError::InvalidArgument(
+                "Invalid checkpoint path. Expect to use Unicode path.".to_owned(),
+            ));
+        };
+        if db.env.exists(dir) {
+            return Err(TemplateKVError::InvalidArgument(format!(
+                "checkpoint directory {} exists",
+                dir
+            )));
+        }
+        if db.mem.read().unwrap().len() > 0 || db.im_mem.read().unwrap().is_some() {
+            db.force_compact_mem_table()?;
+        }
+        // Keep the files we are going to link or copy from being removed by compactions
+        let _guard = FileDeletionsGuard::new(db);
+        let (live_files, checksums, manifest, manifest_size, current) = {
+            let versions = db.versions.lock().unwrap();
+            // The same files as the ones kept by `delete_obsolete_files`
+            let live_files = versions.live_files();
+            let version = versions.current();
+            let mut checksums = HashMap::default();
+            for files in version.files.iter() {
+                for f in files.iter() {
+                    if let Some(checksum) = f.file_checksum {
+                        checksums.insert(f.number, checksum);
+                    }
+                }
+            }
+            for blob in version.blob_files.values() {
+                checksums.insert(blob.number, blob.file_checksum);
+            }
+            let mut f = db
+                .env
+                .open(generate_filename(&db.db_path, FileType::Current, 0))?;
+            let mut buf = vec![];
+            f.read_all(&mut buf)?;
+            let current = String::from_utf8(buf).map_err(TemplateKVError::UTF8Error)?;
+            // The MANIFEST is only appended so a prefix of it is a consistent view
+            let mut manifest = db.db_path.clone();
+            manifest.push(MAIN_SEPARATOR);
+            manifest.push_str(&current);
+            let manifest_size = db.env.open(&manifest)?.len()?;
+            (live_files, checksums, manifest, manifest_size, current)
+        };
+        let live_files = db
+            .env
+            .list(&db.db_path)?
+            .iter()
+            .filter_map(parse_filename)
+            .filter(|(file_type, number)| {
+                matches!(file_type, FileType::Table | FileType::Blob) && live_files.contains(number)
+            })
+            .collect::<Vec<_>>();
+        info!(
+            "Create checkpoint {} with {} files and {}",
+            dir,
+            live_files.len(),
+            &current
+        );
+        let res = (|| {
+            db.env.mkdir_all(dir)?;
+            for (file_type, number) in live_files {
+                let src = generate_filename(&db.db_path, file_type, number);
+                let dst = generate_filename(dir, file_type, number);
+                if db.env.link(&src, &dst).is_err() {
+                    copy_file(&db.env, &src, &db.env, &dst, None)?;
+                }
+                // Refuse to build a checkpoint out of a file differing from the MANIFEST
+                if let Some(&checksum) = checksums.get(&number) {
+                    verify_file_checksum(&db.env, &dst, checksum)
+                        .map_err(|e| e.with_file_number(number).with_path(&dst))?;
+                }
+            }
+            let mut dst_manifest = dir.to_owned();
+            dst_manifest.push(MAIN_SEPARATOR);
+            dst_manifest.push_str(&current);
+            copy_file(
+                &db.env,
+                &manifest,
+                &db.env,
+                &dst_manifest,
+                Some(manifest_size),
+            )?;
+            // CURRENT is written at last so that a checkpoint is openable only if it's complete
+            do_write_string_to_file(
+                &db.env,
+                current,
+                generate_filename(dir, FileType::Current, 0),
+                true,
+            )?;
+            db.env.sync_dir(dir)
+        })();
+        if res.is_err() {
+            let _ = db.env.remove_dir(dir, true);
+        }
+        res
+    }
+}
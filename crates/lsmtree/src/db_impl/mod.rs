@@ -1,4 +1,7 @@
+pub mod checkpoint;
+pub mod repair;
 pub mod template_impl;
+pub mod verify;
 
 #[cfg(test)]
 mod tests {
@@ -6,6 +9,7 @@ mod tests {
         cmp::Ordering as CmpOrdering,
         mem,
         ops::{Deref, DerefMut},
+        path::{Path, PathBuf},
         str,
         sync::{
             Arc, Mutex,
@@ -18,23 +22,33 @@ mod tests {
     use log::LevelFilter;
     use rand::{Rng, distributions::Alphanumeric, thread_rng};
 
-    use super::template_impl::TemplateDB;
+    use super::{repair::repair_db, template_impl::TemplateDB};
     use crate::{
-        cache::{bloom_filter_cache::BloomFilter, lru_cache::LRUCache},
+        cache::{
+            BlockCacheKey, CacheSync, FilterPolicy, SecondaryCache,
+            blocked_bloom_filter::BlockedBloomFilter, bloom_filter_cache::BloomFilter,
+            lru_cache::LRUCache, persistent_cache::PersistentCache, ribbon_filter::RibbonFilter,
+            sharded_cache::ShardedCache,
+        },
         db_trait::DB,
         error::{TemplateKVError, TemplateResult},
         iterator::Iterator,
         manager::{
-            filename::{FileType, parse_filename},
+            filename::{FileType, generate_filename, parse_filename},
             snapshot::Snapshot,
         },
         memtable::{
             key_format::{InternalKey, ParsedInternalKey},
             value_format::ValueType,
+            write_buffer_manager::WriteBufferManager,
         },
         options::{CompressionType, MAX_KEY_SEQUENCE, Options, ReadOptions, WriteOptions},
-        storage::{Storage, mem::MemStorage},
-        util::comparator::{BytewiseComparator, Comparator},
+        sstable::{sst_dump::SstFileDumper, sst_file_writer::SstFileWriter},
+        storage::{File, Storage, file_checksum, mem::MemStorage},
+        util::{
+            comparator::{BytewiseComparator, Comparator},
+            slice_transform::FixedPrefixTransform,
+        },
     };
 
     impl<S: Storage + Clone, C: Comparator + 'static> TemplateDB<S, C> {
@@ -282,6 +296,7 @@ mod tests {
                                     result.push_str(str::from_utf8(iter.value()).unwrap())
                                 }
                                 ValueType::Deletion => result.push_str("DEL"),
+                                ValueType::BlobIndex => result.push_str("BLOB"),
                                 ValueType::Unknown => result.push_str("UNKNOWN"),
                             }
                         }
@@ -544,7 +559,7 @@ mod tests {
             read_opt.snapshot = Some(s.sequence().into());
             t.put("foo", "v1").unwrap();
             t.put("foo", "v2").unwrap();
-            let mut iter = t.iter(read_opt).unwrap();
+            let mut iter = t.iter(read_opt.clone()).unwrap();
             iter.seek_to_first();
             // No entry at this snapshot
             assert!(!iter.valid());
@@ -604,6 +619,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_get_from_last_level() {
+        let mut o = Options::default();
+        o.max_levels = 3;
+        let t = DBTest::new(o);
+        t.put("a", "va").unwrap();
+        t.put("b", "vb").unwrap();
+        t.compact(Some("a"), Some("b"));
+        // The sst file is placed at the last level
+        t.assert_file_num_at_level(2, 1);
+        assert_eq!(t.get("a", None).unwrap(), "va");
+        assert_eq!(t.get("b", None).unwrap(), "vb");
+        assert_eq!(t.get("c", None), None);
+    }
+
     #[test]
     fn test_get_encounters_empty_level() {
         for t in default_cases() {
@@ -1485,13 +1515,56 @@ mod tests {
         let _ = TemplateDB::open_db(opts, dbname, store.clone()).unwrap();
     }
 
+    #[test]
+    fn test_secondary_cache_of_recreated_db() {
+        let store = MemStorage::default();
+        let cache =
+            Arc::new(PersistentCache::open(MemStorage::default(), "cache", 1 << 20).unwrap());
+        let mut opts = Options::<BytewiseComparator>::default();
+        opts.secondary_cache = Some(cache.clone());
+        let dbname = "db_secondary_cache";
+        let mut db = TemplateDB::open_db(opts.clone(), dbname, store.clone()).unwrap();
+        let identity = generate_filename(dbname, FileType::Identity, 0);
+        let mut id = vec![];
+        store.open(&identity).unwrap().read_all(&mut id).unwrap();
+        cache.insert(BlockCacheKey::new(100, 0), b"block");
+        assert_eq!(cache.len(), 1);
+        db.close().unwrap();
+
+        // The same db keeps its cached blocks
+        let mut db = TemplateDB::open_db(opts.clone(), dbname, store.clone()).unwrap();
+        let mut reopened_id = vec![];
+        store
+            .open(&identity)
+            .unwrap()
+            .read_all(&mut reopened_id)
+            .unwrap();
+        assert_eq!(id, reopened_id);
+        assert_eq!(cache.len(), 1);
+        db.destroy().unwrap();
+
+        // The db created again reuses the file numbers
+        let mut db = TemplateDB::open_db(opts.clone(), dbname, store.clone()).unwrap();
+        assert!(cache.is_empty());
+        assert!(cache.lookup(&BlockCacheKey::new(100, 0)).is_none());
+        cache.insert(BlockCacheKey::new(100, 0), b"block");
+
+        // Another db can't use the cache while it's bound
+        let e = TemplateDB::open_db(opts, "db_secondary_cache_other", store.clone())
+            .err()
+            .unwrap();
+        assert!(matches!(e.root(), TemplateKVError::InvalidArgument(_)));
+        assert_eq!(cache.len(), 1);
+        db.close().unwrap();
+    }
+
     #[test]
     fn test_destroy_empty_dir() {
         let store = MemStorage::default();
         let opts = Options::<BytewiseComparator>::default();
         let dbname = "db_empty_dir";
         let mut db = TemplateDB::open_db(opts, dbname, store.clone()).unwrap();
-        assert_eq!(4, store.list(dbname).unwrap().len());
+        assert_eq!(5, store.list(dbname).unwrap().len());
         // clean up dir
         db.destroy().unwrap();
         assert!(!store.exists(dbname));
@@ -1539,6 +1612,1103 @@ mod tests {
         assert_eq!(t.store.list(&t.inner.db_path).unwrap().len(), file_counts);
     }
 
+    #[test]
+    fn test_ingest_external_file() {
+        for t in default_cases() {
+            let build = |name: &str, entries: Vec<(&str, Option<&str>)>| {
+                let mut writer = SstFileWriter::new(t.opt.clone(), t.store.clone());
+                writer.open(name).unwrap();
+                for (k, v) in entries {
+                    match v {
+                        Some(v) => writer.put(k.as_bytes(), v.as_bytes()).unwrap(),
+                        None => writer.delete(k.as_bytes()).unwrap(),
+                    }
+                }
+                writer.finish().unwrap();
+            };
+            // Nothing overlaps so the file goes to the bottommost level
+            build("ext1.sst", vec![("k1", Some("v1")), ("k2", Some("v2"))]);
+            t.ingest_external_file("ext1.sst").unwrap();
+            assert_eq!("0,0,0,0,0,0,1", t.file_count_per_level());
+            t.assert_get("k1", Some("v1"));
+
+            // Overlaps with the memtable
+            t.put("k2", "old").unwrap();
+            t.put("k3", "v3").unwrap();
+            let snapshot = t.snapshot();
+            build("ext2.sst", vec![("k2", Some("new")), ("k3", None)]);
+            t.ingest_external_file("ext2.sst").unwrap();
+            t.assert_get("k2", Some("new"));
+            t.assert_get("k3", None);
+            assert_eq!(
+                t.get("k2", Some(snapshot.sequence().into())),
+                Some("old".to_owned())
+            );
+            assert_eq!(
+                t.get("k3", Some(snapshot.sequence().into())),
+                Some("v3".to_owned())
+            );
+            t.must_release_snapshot(snapshot);
+            assert_eq!("(k1->v1)(k2->new)", t.assert_contents());
+
+            // Survives reopening and compaction
+            let mut t = t;
+            t.reopen().unwrap();
+            t.assert_get("k2", Some("new"));
+            t.compact(None, None);
+            assert_eq!("(k1->v1)(k2->new)", t.assert_contents());
+
+            assert!(t.ingest_external_file("not_exist.sst").is_err());
+        }
+    }
+
+    #[test]
+    fn test_snapshot_during_ingestion() {
+        for t in default_cases() {
+            t.put("k", "old").unwrap();
+            t.inner.force_compact_mem_table().unwrap();
+            let mut writer = SstFileWriter::new(t.opt.clone(), t.store.clone());
+            writer.open("ext.sst").unwrap();
+            for i in 0..2000 {
+                let key = format!("k{:04}", i);
+                writer.put(key.as_bytes(), &[b'v'; 100]).unwrap();
+            }
+            writer.finish().unwrap();
+
+            // Takes snapshots and reads through them while the file is being ingested
+            let stop = Arc::new(AtomicBool::new(false));
+            let reader = {
+                let db = t.db.clone();
+                let stop = stop.clone();
+                thread::spawn(move || {
+                    let mut reads = vec![];
+                    while !stop.load(Ordering::Acquire) {
+                        let snapshot = db.snapshot();
+                        let mut read_opt = ReadOptions::default();
+                        read_opt.snapshot = Some(snapshot.sequence().into());
+                        let value = db.get(read_opt, b"k0000").unwrap();
+                        reads.push((snapshot, value));
+                    }
+                    reads
+                })
+            };
+            t.ingest_external_file("ext.sst").unwrap();
+            stop.store(true, Ordering::Release);
+            let reads = reader.join().unwrap();
+
+            // A snapshot never sees the ingested keys unless it did at the first read
+            for (snapshot, value) in reads {
+                let mut read_opt = ReadOptions::default();
+                read_opt.snapshot = Some(snapshot.sequence().into());
+                assert_eq!(t.db.get(read_opt, b"k0000").unwrap(), value);
+                let _ = t.release_snapshot(snapshot);
+            }
+            t.assert_get("k0000", Some(str::from_utf8(&[b'v'; 100]).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_compaction_during_ingestion() {
+        for t in default_cases() {
+            // level 5: [b...] [y...], level 6: [a] [z]
+            for (k, v) in [("a", "va"), ("z", "vz")] {
+                let mut writer = SstFileWriter::new(t.opt.clone(), t.store.clone());
+                writer.open("ext.sst").unwrap();
+                writer.put(k.as_bytes(), v.as_bytes()).unwrap();
+                writer.finish().unwrap();
+                t.ingest_external_file("ext.sst").unwrap();
+                t.store.remove("ext.sst").unwrap();
+            }
+            for prefix in ["b", "y"] {
+                for i in 0..1000 {
+                    t.put(&format!("{}{:04}", prefix, i), "v").unwrap();
+                }
+                t.inner.force_compact_mem_table().unwrap();
+                for level in 0..5 {
+                    t.compact_range_at(level, None, None).unwrap();
+                }
+            }
+            assert_eq!("0,0,0,0,0,2,2", t.file_count_per_level());
+
+            // The output of the compaction covers the ingested key but the inputs don't
+            let mut writer = SstFileWriter::new(t.opt.clone(), t.store.clone());
+            writer.open("ext.sst").unwrap();
+            writer.put(b"m", b"vm").unwrap();
+            writer.finish().unwrap();
+            let compaction = {
+                let db = t.db.clone();
+                thread::spawn(move || db.compact_range_at(5, None, None))
+            };
+            while t
+                .inner
+                .versions
+                .lock()
+                .unwrap()
+                .running_compaction
+                .is_none()
+                && !compaction.is_finished()
+            {
+                thread::yield_now();
+            }
+            t.ingest_external_file("ext.sst").unwrap();
+            compaction.join().unwrap().unwrap();
+
+            // No files overlap in the levels above 0
+            let current = t.inner.versions.lock().unwrap().current();
+            for files in current.files.iter().skip(1) {
+                for pair in files.windows(2) {
+                    assert!(pair[0].largest.user_key() < pair[1].smallest.user_key());
+                }
+            }
+            t.assert_get("m", Some("vm"));
+            t.assert_get("b0000", Some("v"));
+            t.assert_get("z", Some("vz"));
+        }
+    }
+
+    #[test]
+    fn test_create_checkpoint() {
+        for t in default_cases() {
+            t.put("foo", "v1").unwrap();
+            t.inner.force_compact_mem_table().unwrap();
+            t.put("bar", "v2").unwrap();
+            t.delete("foo").unwrap();
+            t.put("baz", "v3").unwrap();
+            t.create_checkpoint("checkpoint").unwrap();
+            assert!(t.create_checkpoint("checkpoint").is_err());
+
+            // Changes after the checkpoint are not visible in it
+            t.put("bar", "v4").unwrap();
+            t.compact(None, None);
+            let mut cp = TemplateDB::open_db(t.opt.clone(), "checkpoint", t.store.clone()).unwrap();
+            let got = |db: &TemplateDB<MemStorage, BytewiseComparator>, k: &str| {
+                db.get(ReadOptions::default(), k.as_bytes())
+                    .unwrap()
+                    .map(|v| String::from_utf8(v).unwrap())
+            };
+            assert_eq!(got(&cp, "foo"), None);
+            assert_eq!(got(&cp, "bar"), Some("v2".to_owned()));
+            assert_eq!(got(&cp, "baz"), Some("v3".to_owned()));
+            t.assert_get("bar", Some("v4"));
+
+            // The checkpoint is an independent db
+            cp.put(WriteOptions::default(), b"foo", b"v5").unwrap();
+            assert_eq!(got(&cp, "foo"), Some("v5".to_owned()));
+            t.assert_get("foo", None);
+            cp.destroy().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_open_read_only() {
+        for t in default_cases() {
+            t.put("foo", "v1").unwrap();
+            t.inner.force_compact_mem_table().unwrap();
+            t.put("bar", "v2").unwrap();
+            let mut ro =
+                TemplateDB::open_read_only(t.opt.clone(), "db_test", t.store.clone()).unwrap();
+            let got = |db: &TemplateDB<MemStorage, BytewiseComparator>, k: &str| {
+                db.get(ReadOptions::default(), k.as_bytes())
+                    .unwrap()
+                    .map(|v| String::from_utf8(v).unwrap())
+            };
+            // Both the flushed and the logged writes are visible
+            assert_eq!(got(&ro, "foo"), Some("v1".to_owned()));
+            assert_eq!(got(&ro, "bar"), Some("v2".to_owned()));
+            assert!(ro.put(WriteOptions::default(), b"foo", b"v3").is_err());
+            assert!(ro.compact_range(None, None).is_err());
+            assert!(ro.try_catch_up_with_primary().is_err());
+            assert!(ro.destroy().is_err());
+
+            // The primary is not affected by the read-only instance
+            t.put("foo", "v3").unwrap();
+            assert_eq!(got(&ro, "foo"), Some("v1".to_owned()));
+            t.assert_get("foo", Some("v3"));
+            ro.close().unwrap();
+            t.assert_get("bar", Some("v2"));
+        }
+        let opts = Options::<BytewiseComparator>::default();
+        assert!(TemplateDB::open_read_only(opts, "not_exist", MemStorage::default()).is_err());
+    }
+
+    // A storage refusing everything but reading, like one on a read-only mount
+    #[derive(Clone)]
+    struct ReadOnlyStorage(MemStorage);
+
+    impl ReadOnlyStorage {
+        fn denied<T>() -> TemplateResult<T> {
+            Err(TemplateKVError::IO(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "read-only storage",
+            )))
+        }
+    }
+
+    impl Storage for ReadOnlyStorage {
+        type F = <MemStorage as Storage>::F;
+        fn create<P: AsRef<Path>>(&self, _name: P) -> TemplateResult<Self::F> {
+            Self::denied()
+        }
+        fn open<P: AsRef<Path>>(&self, _name: P) -> TemplateResult<Self::F> {
+            Self::denied()
+        }
+        fn open_read_only<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+            self.0.open(name)
+        }
+        fn remove<P: AsRef<Path>>(&self, _name: P) -> TemplateResult<()> {
+            Self::denied()
+        }
+        fn remove_dir<P: AsRef<Path>>(&self, _dir: P, _recursively: bool) -> TemplateResult<()> {
+            Self::denied()
+        }
+        fn exists<P: AsRef<Path>>(&self, name: P) -> bool {
+            self.0.exists(name)
+        }
+        fn rename<P: AsRef<Path>>(&self, _old: P, _new: P) -> TemplateResult<()> {
+            Self::denied()
+        }
+        fn mkdir_all<P: AsRef<Path>>(&self, _dir: P) -> TemplateResult<()> {
+            Self::denied()
+        }
+        fn list<P: AsRef<Path>>(&self, dir: P) -> TemplateResult<Vec<PathBuf>> {
+            self.0.list(dir)
+        }
+    }
+
+    #[test]
+    fn test_open_on_read_only_storage() {
+        for t in default_cases() {
+            t.put("foo", "v1").unwrap();
+            t.inner.force_compact_mem_table().unwrap();
+            t.put("bar", "v2").unwrap();
+            let storage = ReadOnlyStorage(t.store.clone());
+            let ro = TemplateDB::open_read_only(t.opt.clone(), "db_test", storage.clone()).unwrap();
+            let secondary =
+                TemplateDB::open_as_secondary(t.opt.clone(), "db_test", storage.clone()).unwrap();
+            t.put("baz", "v3").unwrap();
+            secondary.try_catch_up_with_primary().unwrap();
+            let got = |db: &TemplateDB<ReadOnlyStorage, BytewiseComparator>, k: &str| {
+                db.get(ReadOptions::default(), k.as_bytes())
+                    .unwrap()
+                    .map(|v| String::from_utf8(v).unwrap())
+            };
+            for db in [&ro, &secondary] {
+                assert_eq!(got(db, "foo"), Some("v1".to_owned()));
+                assert_eq!(got(db, "bar"), Some("v2".to_owned()));
+            }
+            assert_eq!(got(&secondary, "baz"), Some("v3".to_owned()));
+            assert!(TemplateDB::open_db(t.opt.clone(), "db_test", storage).is_err());
+        }
+    }
+
+    #[test]
+    fn test_secondary_catch_up_with_primary() {
+        for t in default_cases() {
+            t.put("foo", "v1").unwrap();
+            let secondary =
+                TemplateDB::open_as_secondary(t.opt.clone(), "db_test", t.store.clone()).unwrap();
+            let got = |k: &str| {
+                secondary
+                    .get(ReadOptions::default(), k.as_bytes())
+                    .unwrap()
+                    .map(|v| String::from_utf8(v).unwrap())
+            };
+            assert_eq!(got("foo"), Some("v1".to_owned()));
+
+            t.put("foo", "v2").unwrap();
+            t.put("bar", "v3").unwrap();
+            t.inner.force_compact_mem_table().unwrap();
+            t.delete("bar").unwrap();
+            t.put("baz", "v4").unwrap();
+            assert_eq!(got("foo"), Some("v1".to_owned()));
+            assert_eq!(got("baz"), None);
+
+            secondary.try_catch_up_with_primary().unwrap();
+            assert_eq!(got("foo"), Some("v2".to_owned()));
+            assert_eq!(got("bar"), None);
+            assert_eq!(got("baz"), Some("v4".to_owned()));
+            // Only the records appended since the last call are replayed
+            let replayed = secondary.inner.mem.read().unwrap().len();
+            assert_eq!(replayed, 2);
+            for (number, offset) in secondary.inner.replayed_logs.lock().unwrap().iter() {
+                let log = generate_filename("db_test", FileType::Log, *number);
+                assert_eq!(*offset, t.store.open(log).unwrap().len().unwrap());
+            }
+            secondary.try_catch_up_with_primary().unwrap();
+            assert_eq!(secondary.inner.mem.read().unwrap().len(), replayed);
+            t.put("baz", "v5").unwrap();
+            secondary.try_catch_up_with_primary().unwrap();
+            assert_eq!(secondary.inner.mem.read().unwrap().len(), replayed + 1);
+            assert_eq!(got("baz"), Some("v5".to_owned()));
+            t.put("baz", "v4").unwrap();
+            secondary.try_catch_up_with_primary().unwrap();
+
+            t.compact(None, None);
+            t.put("qux", "v5").unwrap();
+            secondary.try_catch_up_with_primary().unwrap();
+            assert_eq!(got("foo"), Some("v2".to_owned()));
+            assert_eq!(got("baz"), Some("v4".to_owned()));
+            assert_eq!(got("qux"), Some("v5".to_owned()));
+            assert!(
+                secondary
+                    .put(WriteOptions::default(), b"foo", b"v6")
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_checksums() {
+        for t in default_cases() {
+            for i in 0..100 {
+                t.put(&key(i), &rand_string(100)).unwrap();
+            }
+            t.compact(None, None);
+            t.put("foo", "v1").unwrap();
+            t.inner.force_compact_mem_table().unwrap();
+            let report = t.verify_checksums().unwrap();
+            assert!(report.is_ok(), "{}", report);
+            assert_eq!(report.num_files, t.total_sst_files());
+            assert!(report.num_blocks > report.num_files as u64);
+            assert!(t.verify_file_checksums().unwrap().is_ok());
+
+            // Flip a byte in the first data block of a file
+            let (level, file_number) = {
+                let current = t.inner.versions.lock().unwrap().current();
+                let level = current.files.iter().position(|f| !f.is_empty()).unwrap();
+                (level, current.files[level][0].number)
+            };
+            let file_name = generate_filename(&t.inner.db_path, FileType::Table, file_number);
+            let mut contents = vec![];
+            t.store
+                .open(&file_name)
+                .unwrap()
+                .read_all(&mut contents)
+                .unwrap();
+            contents[1] ^= 0xff;
+            t.store.remove(&file_name).unwrap();
+            t.store
+                .create(&file_name)
+                .unwrap()
+                .write(&contents)
+                .unwrap();
+            for report in [
+                t.verify_checksums().unwrap(),
+                t.verify_file_checksums().unwrap(),
+            ] {
+                assert_eq!(report.corrupted_files.len(), 1, "{}", report);
+                let corruption = &report.corrupted_files[0];
+                assert_eq!(corruption.file_number, file_number);
+                assert_eq!(corruption.level, Some(level));
+                // Both the whole file checksum and the block checksum mismatch
+                assert_eq!(corruption.errors.len(), 2, "{}", report);
+                assert!(corruption.errors.iter().all(|e| e.is_corruption()));
+                assert_eq!(corruption.errors[1].offset(), Some(0));
+            }
+
+            // Missing file
+            t.store.remove(&file_name).unwrap();
+            let report = t.verify_checksums().unwrap();
+            assert_eq!(report.corrupted_files.len(), 1);
+            assert!(!report.corrupted_files[0].errors[0].is_corruption());
+        }
+    }
+
+    #[test]
+    fn test_file_checksums_in_manifest() {
+        for t in default_cases() {
+            for i in 0..100 {
+                t.put(&key(i), &rand_string(100)).unwrap();
+            }
+            t.compact(None, None);
+            t.put("foo", "v1").unwrap();
+            t.inner.force_compact_mem_table().unwrap();
+            let assert_checksums = |t: &DBTest| {
+                let current = t.inner.versions.lock().unwrap().current();
+                for f in current.files.iter().flatten() {
+                    let file_name = generate_filename(&t.inner.db_path, FileType::Table, f.number);
+                    let (checksum, size) = file_checksum(&t.store, &file_name).unwrap();
+                    assert_eq!(size, f.file_size);
+                    assert_eq!(Some(checksum), f.file_checksum, "#{}", f.number);
+                }
+            };
+            assert_checksums(&t);
+            // The checksums are recovered from the MANIFEST
+            let mut t = t;
+            t.reopen().unwrap();
+            assert_checksums(&t);
+
+            // Ingestion is rejected if the external file doesn't match the given checksum
+            let mut writer = SstFileWriter::new(t.opt.clone(), t.store.clone());
+            writer.open("ext.sst").unwrap();
+            writer.put(b"zzz", b"v").unwrap();
+            let info = writer.finish().unwrap();
+            let e = t
+                .ingest_external_file_with_checksum("ext.sst", info.file_checksum ^ 1)
+                .unwrap_err();
+            assert!(e.is_corruption());
+            t.assert_get("zzz", None);
+            t.ingest_external_file_with_checksum("ext.sst", info.file_checksum)
+                .unwrap();
+            t.assert_get("zzz", Some("v"));
+            assert_checksums(&t);
+
+            // A checkpoint is not created out of a file differing from the MANIFEST
+            let file_number = {
+                let current = t.inner.versions.lock().unwrap().current();
+                current.files.iter().flatten().next().unwrap().number
+            };
+            let file_name = generate_filename(&t.inner.db_path, FileType::Table, file_number);
+            let mut contents = vec![];
+            t.store
+                .open(&file_name)
+                .unwrap()
+                .read_all(&mut contents)
+                .unwrap();
+            // Only the footer padding is changed so that the blocks are still readable
+            let last = contents.len() - 9;
+            contents[last] ^= 0xff;
+            t.store.remove(&file_name).unwrap();
+            t.store
+                .create(&file_name)
+                .unwrap()
+                .write(&contents)
+                .unwrap();
+            let e = t.create_checkpoint("checkpoint").unwrap_err();
+            assert!(e.is_corruption());
+            assert_eq!(e.file_number(), Some(file_number));
+            assert!(!t.store.exists("checkpoint"));
+            let report = t.verify_file_checksums().unwrap();
+            assert_eq!(report.corrupted_files.len(), 1, "{}", report);
+        }
+    }
+
+    #[test]
+    fn test_compression_per_level() {
+        let mut opts = Options::<BytewiseComparator>::default();
+        opts.compression_per_level = vec![
+            CompressionType::NoCompression,
+            CompressionType::Lz4Compression,
+            CompressionType::ZstdCompression,
+        ];
+        opts.compression_opts.max_dict_bytes = 4096;
+        let mut t = DBTest::new(opts);
+        assert_eq!(
+            t.opt.compression_for_level(0),
+            CompressionType::NoCompression
+        );
+        assert_eq!(
+            t.opt.compression_for_level(6),
+            CompressionType::ZstdCompression
+        );
+        let value = |i: usize, round: usize| format!("{}-{}-{}", key(i), round, "v".repeat(i % 50));
+        let n = 5000;
+        // The first table is flushed into level 2 and the second one overlapping it stops at
+        // level 1, so that compacting level 1 rewrites all the data into level 2
+        for round in 0..2 {
+            for i in 0..n {
+                t.put(&key(i), &value(i, round)).unwrap();
+            }
+            t.inner.force_compact_mem_table().unwrap();
+        }
+        assert_eq!(t.file_count_per_level(), "0,1,1");
+        t.compact_range_at(1, None, None).unwrap();
+        let current = t.inner.versions.lock().unwrap().current();
+        assert!(current.files[1].is_empty());
+        for f in current.files[2].iter() {
+            let file_name = generate_filename(&t.inner.db_path, FileType::Table, f.number);
+            let dumper = SstFileDumper::open(t.store.open(&file_name).unwrap(), true).unwrap();
+            dumper.verify_checksums().unwrap();
+            assert!(dumper.compression_dict_handle().is_some(), "#{}", f.number);
+        }
+        drop(current);
+        for i in 0..n {
+            t.assert_get(&key(i), Some(&value(i, 1)));
+        }
+        t.reopen().unwrap();
+        for i in (0..n).step_by(7) {
+            t.assert_get(&key(i), Some(&value(i, 1)));
+        }
+        assert!(t.verify_checksums().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_partitioned_index_and_filters() {
+        let mut opts = Options::<BytewiseComparator>::default();
+        opts.partition_index = true;
+        opts.partition_filters = true;
+        opts.metadata_block_size = 128;
+        let mut t = DBTest::new(opts);
+        let n = 5000;
+        for i in 0..n {
+            t.put(&key(i), &format!("value{}", i)).unwrap();
+        }
+        t.inner.force_compact_mem_table().unwrap();
+        let current = t.inner.versions.lock().unwrap().current();
+        for f in current.files.iter().flatten() {
+            let file_name = generate_filename(&t.inner.db_path, FileType::Table, f.number);
+            let dumper = SstFileDumper::open(t.store.open(&file_name).unwrap(), true).unwrap();
+            dumper.verify_checksums().unwrap();
+            assert!(dumper.index_partitions().len() > 1, "#{}", f.number);
+            let filters = dumper.filters().unwrap();
+            assert_eq!(filters[0].partitions.len(), dumper.index_partitions().len());
+        }
+        drop(current);
+        for i in 0..n {
+            t.assert_get(&key(i), Some(&format!("value{}", i)));
+        }
+        t.assert_get("missing", None);
+        t.reopen().unwrap();
+        for i in (0..n).step_by(7) {
+            t.assert_get(&key(i), Some(&format!("value{}", i)));
+        }
+        assert!(t.verify_checksums().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_full_filter() {
+        let mut opts = Options::<BytewiseComparator>::default();
+        opts.full_filter = true;
+        let mut t = DBTest::new(opts);
+        let n = 3000;
+        for i in (0..n).step_by(2) {
+            t.put(&key(i), &format!("value{}", i)).unwrap();
+        }
+        t.inner.force_compact_mem_table().unwrap();
+        let current = t.inner.versions.lock().unwrap().current();
+        for f in current.files.iter().flatten() {
+            let file_name = generate_filename(&t.inner.db_path, FileType::Table, f.number);
+            let dumper = SstFileDumper::open(t.store.open(&file_name).unwrap(), true).unwrap();
+            let filters = dumper.filters().unwrap();
+            assert_eq!(filters.len(), 1);
+            assert_eq!(filters[0].num_filters, 1, "#{}", f.number);
+        }
+        drop(current);
+        t.reopen().unwrap();
+        for i in 0..n {
+            let expect = format!("value{}", i);
+            t.assert_get(&key(i), (i % 2 == 0).then_some(expect.as_str()));
+        }
+    }
+
+    #[test]
+    fn test_filter_policies_with_bits_per_key_per_level() {
+        let policies: Vec<Arc<dyn FilterPolicy>> = vec![
+            Arc::new(BloomFilter::new(10)),
+            Arc::new(BlockedBloomFilter::new(10)),
+            Arc::new(RibbonFilter::new(10)),
+        ];
+        for policy in policies {
+            let name = policy.name().to_owned();
+            let mut opts = Options::<BytewiseComparator>::default();
+            opts.filter_policy = Some(policy);
+            // The memtable is flushed by the bits of level 0 and the compactions into level 3
+            // use more bits
+            opts.filter_bits_per_key_per_level = vec![4.0, 4.0, 4.0, 16.0];
+            let mut t = DBTest::new(opts);
+            let n = 3000;
+            for i in (0..n).step_by(2) {
+                t.put(&key(i), &format!("value{}", i)).unwrap();
+            }
+            t.inner.force_compact_mem_table().unwrap();
+            assert_eq!(t.file_count_per_level(), "0,0,1");
+            let bits_per_key_at = |t: &DBTest, level: usize| {
+                let current = t.inner.versions.lock().unwrap().current();
+                let f = &current.files[level][0];
+                let file_name = generate_filename(&t.inner.db_path, FileType::Table, f.number);
+                let dumper = SstFileDumper::open(t.store.open(&file_name).unwrap(), true).unwrap();
+                let props = dumper.properties().unwrap();
+                assert_eq!(props.filter_policy.as_ref(), Some(&name));
+                props.filter_size as f64 * 8.0 / props.num_entries as f64
+            };
+            let bits_per_key = bits_per_key_at(&t, 2);
+            assert!(bits_per_key < 8.0, "{}: {}", name, bits_per_key);
+            t.compact_range_at(2, None, None).unwrap();
+            assert_eq!(t.file_count_per_level(), "0,0,0,1");
+            let bits_per_key = bits_per_key_at(&t, 3);
+            assert!(bits_per_key > 12.0, "{}: {}", name, bits_per_key);
+            t.reopen().unwrap();
+            for i in 0..n {
+                let expect = format!("value{}", i);
+                t.assert_get(&key(i), (i % 2 == 0).then_some(expect.as_str()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_prefix_seek() {
+        let mut opts = Options::<BytewiseComparator>::default();
+        opts.filter_policy = Some(Arc::new(BlockedBloomFilter::new(10)));
+        opts.prefix_extractor = Some(Arc::new(FixedPrefixTransform::new(4)));
+        opts.full_filter = true;
+        opts.block_size = 256;
+        let cache = Arc::new(ShardedCache::new(vec![LRUCache::new(1 << 20)]));
+        opts.block_cache = Some(cache.clone());
+        let mut t = DBTest::new(opts);
+        // The prefixes a000, a002, ... with 10 keys each
+        let n = 200;
+        for i in (0..n).step_by(2) {
+            for j in 0..10 {
+                t.put(&format!("a{:03}{:04}", i, j), "v").unwrap();
+            }
+        }
+        t.inner.force_compact_mem_table().unwrap();
+        t.reopen().unwrap();
+        t.put("a0040100", "v").unwrap();
+        let read_opt = ReadOptions {
+            prefix_same_as_start: true,
+            ..ReadOptions::default()
+        };
+        let collect = |iter: &mut dyn Iterator| {
+            let mut keys = vec![];
+            while iter.valid() {
+                keys.push(String::from_utf8(iter.key().to_vec()).unwrap());
+                iter.next();
+            }
+            keys
+        };
+
+        // The missing prefixes are mostly ruled out without reading the data blocks
+        let mut iter = t.iter(read_opt).unwrap();
+        let mut block_reads = 0;
+        for i in (1..n).step_by(2) {
+            let charge = cache.total_charge();
+            iter.seek(format!("a{:03}", i).as_bytes());
+            assert!(!iter.valid());
+            if cache.total_charge() > charge {
+                block_reads += 1;
+            }
+        }
+        assert!(block_reads < 5, "{}", block_reads);
+
+        // Stops at the prefix boundary
+        iter.seek(b"a004");
+        let keys = collect(&mut iter);
+        assert_eq!(keys.len(), 11);
+        assert!(keys.iter().all(|k| k.starts_with("a004")));
+        assert_eq!(keys.last().unwrap(), "a0040100");
+        iter.seek(b"a0060005");
+        assert_eq!(collect(&mut iter).len(), 5);
+        iter.seek_to_first();
+        assert_eq!(collect(&mut iter).len(), 1001);
+
+        // The total order seek
+        let mut iter = t.iter(ReadOptions::default()).unwrap();
+        iter.seek(b"a005");
+        assert_eq!(iter_to_string(&iter), "a0060000->v");
+    }
+
+    #[test]
+    fn test_iterate_bounds() {
+        let t = DBTest::new(Options::default());
+        for c in ["a", "b", "c", "d", "e", "f", "g"] {
+            t.put(c, "v").unwrap();
+        }
+        t.inner.force_compact_mem_table().unwrap();
+        for i in 0..10 {
+            t.put(&format!("m{}", i), "v").unwrap();
+        }
+        t.inner.force_compact_mem_table().unwrap();
+        assert_eq!(t.file_count_per_level(), "0,0,2");
+        // Some keys are in the memtable
+        t.put("c1", "v").unwrap();
+        t.delete("d").unwrap();
+        t.put("m", "v").unwrap();
+
+        let read_opt = ReadOptions {
+            iterate_lower_bound: Some(b"b".to_vec()),
+            iterate_upper_bound: Some(b"f".to_vec()),
+            ..ReadOptions::default()
+        };
+        let mut iter = t.iter(read_opt.clone()).unwrap();
+        let mut forward = vec![];
+        iter.seek_to_first();
+        while iter.valid() {
+            forward.push(String::from_utf8(iter.key().to_vec()).unwrap());
+            iter.next();
+        }
+        assert_eq!(forward, vec!["b", "c", "c1", "e"]);
+        let mut backward = vec![];
+        iter.seek_to_last();
+        while iter.valid() {
+            backward.push(String::from_utf8(iter.key().to_vec()).unwrap());
+            iter.prev();
+        }
+        assert_eq!(backward, vec!["e", "c1", "c", "b"]);
+        iter.seek(b"a");
+        assert_eq!(iter_to_string(&iter), "b->v");
+        iter.seek(b"d");
+        assert_eq!(iter_to_string(&iter), "e->v");
+        iter.seek(b"f");
+        assert!(!iter.valid());
+        iter.status().unwrap();
+
+        // The files out of the bounds are never opened
+        let current = t.inner.versions.lock().unwrap().current();
+        let f = current.files[2][1].clone();
+        drop(current);
+        t.inner.table_cache.evict(f.number);
+        let file_name = generate_filename(&t.inner.db_path, FileType::Table, f.number);
+        t.store.remove(&file_name).unwrap();
+        let mut iter = t.iter(read_opt).unwrap();
+        iter.seek_to_first();
+        assert_eq!(iter_to_string(&iter), "b->v");
+        iter.seek_to_last();
+        assert_eq!(iter_to_string(&iter), "e->v");
+        iter.status().unwrap();
+        let read_opt = ReadOptions {
+            iterate_lower_bound: Some(b"m".to_vec()),
+            ..ReadOptions::default()
+        };
+        let mut iter = t.iter(read_opt).unwrap();
+        iter.seek_to_first();
+        assert!(iter.status().is_err());
+    }
+
+    #[test]
+    fn test_multi_get() {
+        let mut opts = Options::<BytewiseComparator>::default();
+        opts.enable_blob_files = true;
+        opts.min_blob_size = 20;
+        let t = DBTest::new(opts);
+        let n = 100;
+        let key = |i: usize| format!("k{:03}", i);
+        for i in 0..n {
+            // Every 4th value is in the blob files
+            t.put(
+                &key(i),
+                &format!("v1-{}", i).repeat(if i % 4 == 0 { 5 } else { 1 }),
+            )
+            .unwrap();
+        }
+        t.inner.force_compact_mem_table().unwrap();
+        for i in (0..n).step_by(3) {
+            t.put(&key(i), &format!("v2-{}", i)).unwrap();
+        }
+        for i in (0..n).step_by(5) {
+            t.delete(&key(i)).unwrap();
+        }
+        t.inner.force_compact_mem_table().unwrap();
+        let snapshot = t.db.snapshot();
+        for i in (0..n).step_by(7) {
+            t.put(&key(i), &format!("v3-{}", i)).unwrap();
+        }
+        for i in (0..n).step_by(11) {
+            t.delete(&key(i)).unwrap();
+        }
+        // The keys in any order with duplicates and missing ones
+        let keys = (0..n + 5).rev().chain(0..10).map(key).collect::<Vec<_>>();
+        let key_refs = keys.iter().map(|k| k.as_bytes()).collect::<Vec<_>>();
+        for snapshot in [None, Some(snapshot.sequence().into())] {
+            let read_opt = ReadOptions {
+                snapshot: snapshot.clone(),
+                ..ReadOptions::default()
+            };
+            let values = t.db.multi_get(read_opt, &key_refs).unwrap();
+            assert_eq!(values.len(), keys.len());
+            for (k, v) in keys.iter().zip(values) {
+                let v = v.map(|v| String::from_utf8(v).unwrap());
+                assert_eq!(v, t.get(k, snapshot.clone()), "{}", k);
+            }
+        }
+        assert_eq!(
+            t.db.multi_get(ReadOptions::default(), &[b"k001", b"k005", b"k007"])
+                .unwrap(),
+            vec![Some(b"v1-1".to_vec()), None, Some(b"v3-7".to_vec())]
+        );
+        assert!(
+            t.db.multi_get(ReadOptions::default(), &[])
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_get_pinned() {
+        let mut opts = Options::<BytewiseComparator>::default();
+        opts.enable_blob_files = true;
+        opts.min_blob_size = 100;
+        let t = DBTest::new(opts);
+        let get_pinned = |k: &str| {
+            t.db.get_pinned(ReadOptions::default(), k.as_bytes())
+                .unwrap()
+        };
+        let large = "v".repeat(200);
+        t.put("k1", "v1").unwrap();
+        t.put("k2", &large).unwrap();
+        t.delete("k3").unwrap();
+        // Pinned in the memtable
+        let v1 = get_pinned("k1").unwrap();
+        assert!(v1.is_pinned());
+        assert_eq!(&v1[..], b"v1");
+        assert!(get_pinned("k3").is_none());
+        t.inner.force_compact_mem_table().unwrap();
+        // The memtable value outlives the memtable
+        assert_eq!(&v1[..], b"v1");
+
+        // Pinned in the data block
+        let v1 = get_pinned("k1").unwrap();
+        assert!(v1.is_pinned());
+        assert_eq!(&v1[..], b"v1");
+        // The values in the blob files are owned
+        let v2 = get_pinned("k2").unwrap();
+        assert!(!v2.is_pinned());
+        assert_eq!(&v2[..], large.as_bytes());
+        assert!(get_pinned("k3").is_none());
+        assert!(get_pinned("k4").is_none());
+        assert_eq!(
+            t.db.get(ReadOptions::default(), b"k1").unwrap(),
+            Some(b"v1".to_vec())
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_direct_io() {
+        use crate::storage::file::FileStorage;
+
+        let mut opts = Options::<BytewiseComparator>::default();
+        opts.use_direct_reads = true;
+        opts.use_direct_io_for_flush_and_compaction = true;
+        opts.enable_blob_files = true;
+        opts.min_blob_size = 100;
+        let name = "test_direct_io_db";
+        let _ = FileStorage.remove_dir(name, true);
+        let mut db = TemplateDB::open_db(opts.clone(), name, FileStorage).unwrap();
+        let value = |i: usize| format!("value{}", i).repeat(if i % 10 == 0 { 20 } else { 1 });
+        for round in 0..3 {
+            for i in (round..1000).step_by(3) {
+                db.put(
+                    WriteOptions::default(),
+                    format!("key{:04}", i).as_bytes(),
+                    value(i).as_bytes(),
+                )
+                .unwrap();
+            }
+            db.inner.force_compact_mem_table().unwrap();
+        }
+        db.compact_range(None, None).unwrap();
+        db.close().unwrap();
+
+        let mut db = TemplateDB::open_db(opts, name, FileStorage).unwrap();
+        for i in 0..1000 {
+            let v = db
+                .get(ReadOptions::default(), format!("key{:04}", i).as_bytes())
+                .unwrap();
+            assert_eq!(v, Some(value(i).into_bytes()));
+        }
+        let mut iter = db.iter(ReadOptions::default()).unwrap();
+        iter.seek_to_first();
+        let mut count = 0;
+        while iter.valid() {
+            count += 1;
+            iter.next();
+        }
+        iter.status().unwrap();
+        assert_eq!(count, 1000);
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_write_buffer_manager() {
+        let manager = Arc::new(WriteBufferManager::new(256 << 10));
+        let mut opts = Options::<BytewiseComparator>::default();
+        opts.write_buffer_size = 8 << 20;
+        opts.write_buffer_manager = Some(manager.clone());
+        let store = MemStorage::default();
+        let dbs = ["db1", "db2"]
+            .map(|name| TemplateDB::open_db(opts.clone(), name, store.clone()).unwrap());
+        let value = "v".repeat(1000);
+        for i in 0..1000 {
+            for db in &dbs {
+                db.put(
+                    WriteOptions::default(),
+                    format!("key{:04}", i).as_bytes(),
+                    value.as_bytes(),
+                )
+                .unwrap();
+            }
+            // The memtables of both DBs are flushed to keep them around the budget, far
+            // below their write buffer sizes
+            assert!(
+                manager.memory_usage() < 3 * manager.buffer_size(),
+                "{}",
+                manager.memory_usage()
+            );
+        }
+        for db in &dbs {
+            assert!(db.total_sst_files() > 0);
+            for i in 0..1000 {
+                let v = db
+                    .get(ReadOptions::default(), format!("key{:04}", i).as_bytes())
+                    .unwrap();
+                assert_eq!(v.as_deref(), Some(value.as_bytes()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_mmap_reads() {
+        use crate::storage::file::FileStorage;
+
+        let mut opts = Options::<BytewiseComparator>::default();
+        opts.allow_mmap_reads = true;
+        opts.compression = CompressionType::NoCompression;
+        let name = "test_mmap_reads_db";
+        let _ = FileStorage.remove_dir(name, true);
+        let mut db = TemplateDB::open_db(opts.clone(), name, FileStorage).unwrap();
+        for round in 0..3 {
+            for i in (round..1000).step_by(3) {
+                db.put(
+                    WriteOptions::default(),
+                    format!("key{:04}", i).as_bytes(),
+                    format!("value{}", i).as_bytes(),
+                )
+                .unwrap();
+            }
+            db.inner.force_compact_mem_table().unwrap();
+        }
+        db.compact_range(None, None).unwrap();
+        db.close().unwrap();
+
+        let mut db = TemplateDB::open_db(opts, name, FileStorage).unwrap();
+        for i in 0..1000 {
+            let v = db
+                .get(ReadOptions::default(), format!("key{:04}", i).as_bytes())
+                .unwrap();
+            assert_eq!(v, Some(format!("value{}", i).into_bytes()));
+        }
+        let mut iter = db.iter(ReadOptions::default()).unwrap();
+        iter.seek_to_first();
+        let mut count = 0;
+        while iter.valid() {
+            count += 1;
+            iter.next();
+        }
+        iter.status().unwrap();
+        assert_eq!(count, 1000);
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_blob_files() {
+        let mut opts = Options::<BytewiseComparator>::default();
+        opts.enable_blob_files = true;
+        opts.min_blob_size = 100;
+        opts.enable_blob_garbage_collection = true;
+        opts.blob_garbage_collection_age_cutoff = 1.0;
+        let mut t = DBTest::new(opts);
+        // Only the values of the even keys are large enough to be separated
+        let value = |i: usize, round: usize| {
+            let len = if i % 2 == 0 { 200 } else { 10 };
+            format!("{}-{}-", key(i), round) + &"v".repeat(len)
+        };
+        let blob_files = |t: &DBTest| -> Vec<(u64, u64)> {
+            let current = t.inner.versions.lock().unwrap().current();
+            current
+                .blob_files
+                .values()
+                .map(|b| (b.number, b.total_count - b.garbage_count))
+                .collect()
+        };
+        let expected_contents = |round: usize| -> String {
+            (0..100)
+                .map(|i| format!("({}->{})", key(i), value(i, round)))
+                .collect()
+        };
+        let n = 100;
+        for round in 0..2 {
+            for i in 0..n {
+                t.put(&key(i), &value(i, round)).unwrap();
+            }
+            t.inner.force_compact_mem_table().unwrap();
+            // The reads might trigger compactions merging the blob files
+            assert!(!blob_files(&t).is_empty());
+            for i in 0..n {
+                t.assert_get(&key(i), Some(&value(i, round)));
+            }
+            assert_eq!(t.assert_contents(), expected_contents(round));
+        }
+
+        // The overwritten values and the relocated ones make the old blob files obsolete
+        let old_files = blob_files(&t);
+        t.compact(None, None);
+        let files = blob_files(&t);
+        assert_eq!(files.iter().map(|(_, live)| live).sum::<u64>(), 50);
+        for (number, _) in old_files {
+            let file_name = generate_filename(&t.inner.db_path, FileType::Blob, number);
+            let live = files.iter().any(|(n, _)| *n == number);
+            assert_eq!(t.store.exists(&file_name), live, "{}", file_name);
+        }
+        assert_eq!(t.assert_contents(), expected_contents(1));
+
+        t.reopen().unwrap();
+        for i in 0..n {
+            t.assert_get(&key(i), Some(&value(i, 1)));
+        }
+        t.create_checkpoint("blob_checkpoint").unwrap();
+        let cp = TemplateDB::open_db(t.opt.clone(), "blob_checkpoint", t.store.clone()).unwrap();
+        for i in (0..n).step_by(3) {
+            let v = cp.get(ReadOptions::default(), key(i).as_bytes()).unwrap();
+            assert_eq!(v, Some(value(i, 1).into_bytes()));
+        }
+        drop(cp);
+
+        // The blob files are verified as a whole
+        let report = t.verify_file_checksums().unwrap();
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(report.num_files, t.total_sst_files() + files.len());
+        let blob_name = generate_filename(&t.inner.db_path, FileType::Blob, files[0].0);
+        let mut contents = vec![];
+        t.store
+            .open(&blob_name)
+            .unwrap()
+            .read_all(&mut contents)
+            .unwrap();
+        contents[0] ^= 0xff;
+        t.store.remove(&blob_name).unwrap();
+        t.store
+            .create(&blob_name)
+            .unwrap()
+            .write(&contents)
+            .unwrap();
+        for report in [
+            t.verify_checksums().unwrap(),
+            t.verify_file_checksums().unwrap(),
+        ] {
+            assert_eq!(report.corrupted_files.len(), 1, "{}", report);
+            assert_eq!(report.corrupted_files[0].level, None);
+            assert_eq!(report.corrupted_files[0].file_number, files[0].0);
+            assert!(report.corrupted_files[0].errors[0].is_corruption());
+        }
+        contents[0] ^= 0xff;
+        t.store.remove(&blob_name).unwrap();
+        t.store
+            .create(&blob_name)
+            .unwrap()
+            .write(&contents)
+            .unwrap();
+
+        // Repairing recovers the blob files referred by the tables
+        t.db.close().unwrap();
+        for file in t.store.list(&t.inner.db_path).unwrap() {
+            if let Some((FileType::Manifest, _)) = parse_filename(&file) {
+                t.store.remove(&file).unwrap();
+            }
+        }
+        repair_db(&t.inner.db_path, t.opt.clone(), t.store.clone()).unwrap();
+        t.db = TemplateDB::open_db(t.opt.clone(), &t.inner.db_path, t.store.clone()).unwrap();
+        assert_eq!(blob_files(&t), files);
+        assert_eq!(t.assert_contents(), expected_contents(1));
+
+        // The values are moved back into the sst files once blob files are disabled
+        t.opt.enable_blob_files = false;
+        t.reopen().unwrap();
+        t.compact(None, None);
+        assert!(blob_files(&t).is_empty());
+        assert_eq!(t.assert_contents(), expected_contents(1));
+    }
+
     #[test]
     fn test_db_reads_using_bloom_filter() {
         let mut store = MemStorage::default();
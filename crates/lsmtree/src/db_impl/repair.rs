@@ -0,0 +1,491 @@
+use std::{
+    path::{MAIN_SEPARATOR, Path},
+    sync::Arc,
+};
+
+use super::template_impl::build_table;
+use crate::{
+    blob::{blob_file::BlobFileReader, blob_index::BlobIndex},
+    cache::table_cache::TableCache,
+    error::{Operation, TemplateKVError, TemplateResult},
+    iterator::Iterator,
+    manager::{
+        filename::{FileType, generate_filename, parse_filename, update_current},
+        version_edit::{BlobFileMeta, FileMetaData, VersionEdit},
+    },
+    memtable::{
+        batch::WriteBatch,
+        key_format::{InternalKey, InternalKeyComparator, ParsedInternalKey},
+        memtable::MemTable,
+        value_format::ValueType,
+    },
+    options::{Options, ReadOptions},
+    storage::{File, Storage, file_checksum},
+    util::{collection::HashMap, comparator::Comparator},
+    wal::{wal_record_format::HEADER_SIZE, wal_record_reader::Reader, wal_record_writer::Writer},
+};
+
+/// The directory in the db where the files that can not be used are moved to
+pub const LOST_DIR: &str = "lost";
+
+/// Rebuilds the MANIFEST of the db at `db_path` from the files left in the directory.
+///
+/// This is the last resort when the db can not be opened because its MANIFEST or CURRENT is
+/// missing or corrupted. The log files are converted into tables, every table is scanned to
+/// recover its key range and largest sequence number, and a fresh MANIFEST with all the tables
+/// at level 0 is written. The blob files referred by the tables are scanned and added as well,
+/// with the values no longer referred counted as garbage. The old MANIFESTs, the converted log
+/// files, the unreadable tables and the unreferred blob files are moved into the `lost`
+/// directory instead of being removed.
+///
+/// Some data might be lost: corrupted log records are dropped and so are the tables that fail
+/// their checksums.
+pub fn repair_db<P: AsRef<Path>, S: Storage + Clone + 'static, C: Comparator + 'static>(
+    db_path: P,
+    mut options: Options<C>,
+    storage: S,
+) -> TemplateResult<()> {
+    let Some(db_path) = db_path.as_ref().to_str().map(|s| s.to_owned()) else {
+        return Err(TemplateKVError::InvalidArgument(
+            "Invalid db path. Expect to use Unicode db path.".to_owned(),
+        )
+        .with_operation(Operation::Repair));
+    };
+    options
+        .initialize(&db_path, &storage)
+        .map_err(|e| e.with_operation(Operation::Repair))?;
+    let mut repairer = Repairer::new(db_path, options, storage);
+    repairer
+        .run()
+        .map_err(|e| e.with_operation(Operation::Repair))
+}
+
+struct Repairer<S: Storage + Clone, C: Comparator> {
+    env: S,
+    db_path: String,
+    options: Arc<Options<C>>,
+    icmp: InternalKeyComparator<C>,
+    table_cache: TableCache<S, C>,
+    next_file_number: u64,
+    manifests: Vec<u64>,
+    logs: Vec<u64>,
+    // Tables found in the directory or converted from the logs
+    table_numbers: Vec<u64>,
+    // Tables successfully scanned
+    tables: Vec<FileMetaData>,
+    // Blob files found in the directory
+    blob_numbers: Vec<u64>,
+    // blob file number -> (count, bytes) of the values referred by the scanned tables
+    blob_refs: HashMap<u64, (u64, u64)>,
+    // Blob files referred by the tables
+    blob_files: Vec<BlobFileMeta>,
+    max_sequence: u64,
+}
+
+impl<S: Storage + Clone + 'static, C: Comparator + 'static> Repairer<S, C> {
+    fn new(db_path: String, options: Options<C>, storage: S) -> Self {
+        let options = Arc::new(options);
+        let table_cache = TableCache::new(
+            db_path.clone(),
+            options.clone(),
+            options.table_cache_size(),
+            storage.clone(),
+        );
+        Self {
+            env: storage,
+            db_path,
+            icmp: InternalKeyComparator::new(options.comparator.clone()),
+            options,
+            table_cache,
+            next_file_number: 1,
+            manifests: vec![],
+            logs: vec![],
+            table_numbers: vec![],
+            tables: vec![],
+            blob_numbers: vec![],
+            blob_refs: HashMap::default(),
+            blob_files: vec![],
+            max_sequence: 0,
+        }
+    }
+
+    fn run(&mut self) -> TemplateResult<()> {
+        if !self.env.exists(&self.db_path) {
+            return Err(TemplateKVError::InvalidArgument(format!(
+                "{} does not exist",
+                &self.db_path
+            )));
+        }
+        // Make sure no instance is using the db while repairing
+        let lock_file = self
+            .env
+            .create(generate_filename(&self.db_path, FileType::Lock, 0))?;
+        lock_file.lock()?;
+        let res = self.find_files().and_then(|_| {
+            self.convert_logs_to_tables();
+            self.scan_tables();
+            self.scan_blob_files();
+            self.write_manifest()
+        });
+        lock_file.unlock()?;
+        if res.is_ok() {
+            let bytes: u64 = self.tables.iter().map(|t| t.file_size).sum();
+            info!(
+                "Repaired db {}: recovered {} files, {} bytes",
+                &self.db_path,
+                self.tables.len(),
+                bytes
+            );
+        }
+        res
+    }
+
+    fn find_files(&mut self) -> TemplateResult<()> {
+        let files = self.env.list(&self.db_path)?;
+        let mut found = false;
+        for file in files {
+            if let Some((file_type, number)) = parse_filename(&file) {
+                match file_type {
+                    FileType::Manifest => self.manifests.push(number),
+                    FileType::Log => self.logs.push(number),
+                    FileType::Table => self.table_numbers.push(number),
+                    FileType::Blob => self.blob_numbers.push(number),
+                    _ => continue,
+                }
+                found = true;
+                if number + 1 > self.next_file_number {
+                    self.next_file_number = number + 1;
+                }
+            }
+        }
+        if !found {
+            return Err(TemplateKVError::InvalidArgument(format!(
+                "no db file found in {}",
+                &self.db_path
+            )));
+        }
+        Ok(())
+    }
+
+    fn convert_logs_to_tables(&mut self) {
+        let mut logs = std::mem::take(&mut self.logs);
+        logs.sort_unstable();
+        for log_number in logs {
+            if let Err(e) = self.convert_log_to_table(log_number) {
+                warn!("Log #{} ignored in repairing: {}", log_number, e);
+            }
+            // The log is not needed any more no matter whether it's converted
+            self.archive_file(&generate_filename(&self.db_path, FileType::Log, log_number));
+        }
+    }
+
+    fn convert_log_to_table(&mut self, log_number: u64) -> TemplateResult<()> {
+        let file_name = generate_filename(&self.db_path, FileType::Log, log_number);
+        let log_file = self.env.open(&file_name)?;
+        // Corrupted records are dropped by the reader without reporting
+        let mut reader = Reader::new(log_file, None, true, 0);
+        let mem = MemTable::new(self.options.write_buffer_size, self.icmp.clone());
+        let mut record_buf = vec![];
+        let mut batch = WriteBatch::default();
+        let mut counter = 0;
+        while reader.read_record(&mut record_buf) {
+            if record_buf.len() < HEADER_SIZE {
+                warn!(
+                    "Log #{}: dropping record at {}: too small",
+                    log_number,
+                    reader.last_record_offset()
+                );
+                continue;
+            }
+            batch.set_contents(&mut record_buf);
+            match batch.insert_into(&mem) {
+                Ok(()) => counter += batch.get_count(),
+                Err(e) => warn!(
+                    "Log #{}: dropping record at {}: {}",
+                    log_number,
+                    reader.last_record_offset(),
+                    e
+                ),
+            }
+        }
+        let mut meta = FileMetaData {
+            number: self.next_file_number,
+            ..Default::default()
+        };
+        self.next_file_number += 1;
+        let mut iter = mem.iter();
+        build_table(
+            self.options.clone(),
+            &self.env,
+            &self.db_path,
+            &self.table_cache,
+            &mut iter,
+            &mut meta,
+            0,
+            // The values are left in the table and separated by the later compactions
+            None,
+        )
+        .map_err(|e| e.with_file_number(meta.number))?;
+        info!(
+            "Log #{}: {} ops saved to table #{}",
+            log_number, counter, meta.number
+        );
+        if meta.file_size > 0 {
+            self.table_numbers.push(meta.number);
+        }
+        Ok(())
+    }
+
+    fn scan_tables(&mut self) {
+        let numbers = std::mem::take(&mut self.table_numbers);
+        for number in numbers {
+            match self.scan_table(number) {
+                Ok(Some(meta)) => self.tables.push(meta),
+                Ok(None) => {
+                    info!("Table #{} has no entry", number);
+                    self.table_cache.evict(number);
+                    self.archive_file(&generate_filename(&self.db_path, FileType::Table, number));
+                }
+                Err(e) => {
+                    warn!("Table #{} ignored in repairing: {}", number, e);
+                    self.table_cache.evict(number);
+                    self.archive_file(&generate_filename(&self.db_path, FileType::Table, number));
+                }
+            }
+        }
+    }
+
+    // Returns the metadata of the table or `None` if it contains no valid entry
+    fn scan_table(&mut self, number: u64) -> TemplateResult<Option<FileMetaData>> {
+        let file_name = generate_filename(&self.db_path, FileType::Table, number);
+        // The blocks are verified below so the checksum of the file as it is can be trusted
+        let (checksum, file_size) = file_checksum(&self.env, &file_name)?;
+        let read_options = ReadOptions {
+            verify_checksums: true,
+            fill_cache: false,
+            ..Default::default()
+        };
+        let mut iter =
+            self.table_cache
+                .new_iter(self.icmp.clone(), read_options, number, file_size)?;
+        let mut meta = FileMetaData {
+            number,
+            file_size,
+            file_checksum: Some(checksum),
+            ..Default::default()
+        };
+        let mut counter = 0;
+        let mut max_sequence = 0;
+        let mut blob_refs: HashMap<u64, (u64, u64)> = HashMap::default();
+        iter.seek_to_first();
+        while iter.valid() {
+            match ParsedInternalKey::decode_from(iter.key()) {
+                Some(key) => {
+                    if counter == 0 {
+                        meta.smallest = InternalKey::decoded_from(iter.key());
+                    }
+                    meta.largest = InternalKey::decoded_from(iter.key());
+                    if key.seq > max_sequence {
+                        max_sequence = key.seq;
+                    }
+                    if key.value_type == ValueType::BlobIndex {
+                        let index = BlobIndex::decode_from(iter.value())?;
+                        let refs = blob_refs.entry(index.file_number).or_default();
+                        refs.0 += 1;
+                        refs.1 += index.size;
+                    }
+                    counter += 1;
+                }
+                None => warn!("Table #{}: unparsable key {:?}", number, iter.key()),
+            }
+            iter.next();
+        }
+        iter.status()?;
+        info!("Table #{}: {} entries", number, counter);
+        if counter == 0 {
+            return Ok(None);
+        }
+        if max_sequence > self.max_sequence {
+            self.max_sequence = max_sequence;
+        }
+        for (number, (count, bytes)) in blob_refs {
+            let refs = self.blob_refs.entry(number).or_default();
+            refs.0 += count;
+            refs.1 += bytes;
+        }
+        Ok(Some(meta))
+    }
+
+    fn scan_blob_files(&mut self) {
+        let numbers = std::mem::take(&mut self.blob_numbers);
+        for number in numbers {
+            let file_name = generate_filename(&self.db_path, FileType::Blob, number);
+            let Some(&(ref_count, ref_bytes)) = self.blob_refs.get(&number) else {
+                info!("Blob file #{} is not referred", number);
+                self.archive_file(&file_name);
+                continue;
+            };
+            match self.scan_blob_file(number, &file_name) {
+                Ok(mut meta) => {
+                    // The values not referred by any table are garbage
+                    meta.garbage_count = meta.total_count.saturating_sub(ref_count);
+                    meta.garbage_bytes = meta.total_bytes.saturating_sub(ref_bytes);
+                    info!(
+                        "Blob file #{}: {} values, {} referred",
+                        number, meta.total_count, ref_count
+                    );
+                    self.blob_files.push(meta);
+                }
+                Err(e) => {
+                    warn!("Blob file #{} ignored in repairing: {}", number, e);
+                    self.archive_file(&file_name);
+                }
+            }
+        }
+    }
+
+    fn scan_blob_file(&self, number: u64, file_name: &str) -> TemplateResult<BlobFileMeta> {
+        let (checksum, file_size) = file_checksum(&self.env, file_name)?;
+        let reader = BlobFileReader::open(self.env.open(file_name)?)?;
+        let mut meta = BlobFileMeta {
+            number,
+            file_size,
+            file_checksum: checksum,
+            ..Default::default()
+        };
+        reader.scan(number, |index| {
+            meta.total_count += 1;
+            meta.total_bytes += index.size;
+        })?;
+        Ok(meta)
+    }
+
+    fn write_manifest(&mut self) -> TemplateResult<()> {
+        let manifest_number = self.next_file_number;
+        self.next_file_number += 1;
+        let mut edit = VersionEdit::new(self.options.max_levels);
+        edit.set_comparator_name(self.options.comparator.name().to_owned());
+        edit.set_log_number(0);
+        edit.set_next_file(self.next_file_number);
+        edit.set_last_sequence(self.max_sequence);
+        // The key ranges of the tables might overlap so they are all placed at level 0
+        for t in &self.tables {
+            edit.add_file(
+                0,
+                t.number,
+                t.file_size,
+                t.smallest.clone(),
+                t.largest.clone(),
+                t.file_checksum,
+            );
+        }
+        for meta in &self.blob_files {
+            edit.add_blob_file(BlobFileMeta {
+                garbage_count: 0,
+                garbage_bytes: 0,
+                ..meta.clone()
+            });
+            if meta.garbage_count > 0 {
+                edit.add_blob_garbage(meta.number, meta.garbage_count, meta.garbage_bytes);
+            }
+        }
+        let manifest_name = generate_filename(&self.db_path, FileType::Manifest, manifest_number);
+        let res = (|| {
+            let mut writer = Writer::new(self.env.create(&manifest_name)?);
+            let mut record = vec![];
+            edit.encode_to(&mut record);
+            writer.add_record(&record)?;
+            writer.sync()
+        })();
+        if let Err(e) = res {
+            let _ = self.env.remove(&manifest_name);
+            return Err(e.with_operation(Operation::WriteManifest));
+        }
+        // The old manifests are useless after the new one is installed
+        for number in std::mem::take(&mut self.manifests) {
+            self.archive_file(&generate_filename(
+                &self.db_path,
+                FileType::Manifest,
+                number,
+            ));
+        }
+        update_current(&self.env, &self.db_path, manifest_number)
+    }
+
+    // Moves the file into the `lost` directory. Errors are ignored on purpose since the file
+    // is not used by the repaired db anyway.
+    fn archive_file(&self, file_name: &str) {
+        let mut lost = self.db_path.clone();
+        lost.push(MAIN_SEPARATOR);
+        lost.push_str(LOST_DIR);
+        let _ = self.env.mkdir_all(&lost);
+        let Some(base) = Path::new(file_name).file_name().and_then(|n| n.to_str()) else {
+            return;
+        };
+        lost.push(MAIN_SEPARATOR);
+        lost.push_str(base);
+        let res = self.env.rename(file_name, &lost);
+        info!("Archiving {}: {:?}", file_name, res);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db_impl::template_impl::TemplateDB, db_trait::DB, options::WriteOptions,
+        storage::mem::MemStorage, util::comparator::BytewiseComparator,
+    };
+
+    fn get(db: &TemplateDB<MemStorage, BytewiseComparator>, k: &str) -> Option<String> {
+        db.get(ReadOptions::default(), k.as_bytes())
+            .unwrap()
+            .map(|v| String::from_utf8(v).unwrap())
+    }
+
+    #[test]
+    fn test_repair_lost_manifest() {
+        let store = MemStorage::default();
+        let opts = Options::<BytewiseComparator>::default();
+        let mut db = TemplateDB::open_db(opts.clone(), "db", store.clone()).unwrap();
+        db.put(WriteOptions::default(), b"foo", b"v1").unwrap();
+        db.put(WriteOptions::default(), b"bar", b"v2").unwrap();
+        db.inner.force_compact_mem_table().unwrap();
+        db.put(WriteOptions::default(), b"foo", b"v3").unwrap();
+        db.delete(WriteOptions::default(), b"bar").unwrap();
+        db.put(WriteOptions::default(), b"baz", b"v4").unwrap();
+        db.close().unwrap();
+
+        // Drop the MANIFEST and put a garbage table in
+        for file in store.list("db").unwrap() {
+            if let Some((FileType::Manifest, _)) = parse_filename(&file) {
+                store.remove(&file).unwrap();
+            }
+        }
+        let mut garbage = store.create("db/000100.sst").unwrap();
+        garbage.write(b"not a table").unwrap();
+        assert!(TemplateDB::open_db(opts.clone(), "db", store.clone()).is_err());
+
+        repair_db("db", opts.clone(), store.clone()).unwrap();
+        assert!(store.exists("db/lost/000100.sst"));
+        let mut db = TemplateDB::open_db(opts.clone(), "db", store.clone()).unwrap();
+        assert_eq!(get(&db, "foo"), Some("v3".to_owned()));
+        assert_eq!(get(&db, "bar"), None);
+        assert_eq!(get(&db, "baz"), Some("v4".to_owned()));
+        // New writes must not be shadowed by the recovered ones
+        db.put(WriteOptions::default(), b"foo", b"v5").unwrap();
+        assert_eq!(get(&db, "foo"), Some("v5".to_owned()));
+        db.close().unwrap();
+        let db = TemplateDB::open_db(opts, "db", store).unwrap();
+        assert_eq!(get(&db, "foo"), Some("v5".to_owned()));
+    }
+
+    #[test]
+    fn test_repair_empty_dir() {
+        let store = MemStorage::default();
+        store.mkdir_all("empty").unwrap();
+        let opts = Options::<BytewiseComparator>::default();
+        assert!(repair_db("empty", opts.clone(), store.clone()).is_err());
+        assert!(repair_db("not_exist", opts, store).is_err());
+    }
+}
@@ -1,24 +1,28 @@
 use std::{
+    borrow::Cow,
     cmp::Ordering as CmpOrdering,
     collections::VecDeque,
     mem,
     path::Path,
     sync::{
         Arc, Condvar, Mutex, MutexGuard, RwLock,
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
     },
     thread,
     time::{Duration, Instant},
 };
 
+use bytes::Bytes;
 use crossbeam::sync::ShardedLock;
 use crossbeam_channel::{Receiver, Sender};
 
 use crate::{
-    cache::table_cache::TableCache,
-    compaction::compact::{Compaction, CompactionStats, ManualCompaction},
+    blob::{blob_file::BlobFileBuilder, blob_index::BlobIndex},
+    cache::{blob_file_cache::BlobFileCache, table_cache::TableCache},
+    compaction::compact::{Compaction, CompactionStats, ManualCompaction, total_range},
+    db_impl::checkpoint::FileDeletionsGuard,
     db_trait::DB,
-    error::{TemplateKVError, TemplateResult},
+    error::{Operation, TemplateKVError, TemplateResult},
     iterator::{
         Iterator,
         db_iter::{DBIterator, DBIteratorCore},
@@ -29,19 +33,24 @@ use crate::{
         filename::{FileType, generate_filename, parse_filename, update_current},
         snapshot::Snapshot,
         version::Version,
-        version_edit::{FileMetaData, VersionEdit},
+        version_edit::{BlobFileMeta, FileMetaData, VersionEdit},
         version_set::{SSTableIters, VersionSet},
     },
     memtable::{
         batch::WriteBatch,
-        key_format::{InternalKey, InternalKeyComparator, LookupKey, ParsedInternalKey},
+        key_format::{
+            InternalKey, InternalKeyComparator, LookupKey, ParsedInternalKey, extract_user_key,
+        },
         memtable::MemTable,
         value_format::{VALUE_TYPE_FOR_SEEK, ValueType},
     },
     options::{MAX_KEY_SEQUENCE, Options, ReadOptions, WriteOptions},
-    sstable::table::TableBuilder,
-    storage::{File, Storage},
-    util::{comparator::Comparator, reporter::LogReporter},
+    sstable::table::{Table, TableBuilder, new_table_iterator},
+    storage::{File, Storage, do_write_string_to_file, verify_file_checksum},
+    util::{
+        collection::HashMap, comparator::Comparator, pinnable_slice::PinnableSlice,
+        reporter::LogReporter,
+    },
     wal::{wal_record_format::HEADER_SIZE, wal_record_reader::Reader, wal_record_writer::Writer},
 };
 
@@ -70,11 +79,28 @@ impl<S: Storage + Clone, C: Comparator + 'static> DB for TemplateDB<S, C> {
     }
 
     fn get(&self, options: ReadOptions, key: &[u8]) -> TemplateResult<Option<Vec<u8>>> {
-        self.inner.get(options, key)
+        Ok(self.inner.get_pinned(options, key)?.map(Vec::from))
+    }
+
+    fn get_pinned(
+        &self,
+        options: ReadOptions,
+        key: &[u8],
+    ) -> TemplateResult<Option<PinnableSlice>> {
+        self.inner.get_pinned(options, key)
+    }
+
+    fn multi_get(
+        &self,
+        options: ReadOptions,
+        keys: &[&[u8]],
+    ) -> TemplateResult<Vec<Option<Vec<u8>>>> {
+        let values = self.inner.multi_get(options, keys)?;
+        Ok(values.into_iter().map(|v| v.map(Vec::from)).collect())
     }
 
     fn iter(&self, read_opt: ReadOptions) -> TemplateResult<Self::Iterator> {
-        let internal_iter = self.internal_iter(read_opt)?;
+        let internal_iter = self.internal_iter(read_opt.clone())?;
         let ucmp = self.inner.internal_comparator.user_comparator.clone();
         let sequence = if let Some(snapshot) = &read_opt.snapshot {
             snapshot.sequence()
@@ -86,6 +112,7 @@ impl<S: Storage + Clone, C: Comparator + 'static> DB for TemplateDB<S, C> {
             self.inner.clone(),
             sequence,
             ucmp,
+            &read_opt,
         ))
     }
 
@@ -103,6 +130,12 @@ impl<S: Storage + Clone, C: Comparator + 'static> DB for TemplateDB<S, C> {
         if self.inner.is_shutting_down.load(Ordering::Acquire) {
             return Ok(());
         }
+        if self.inner.access_mode != AccessMode::ReadWrite {
+            // No background thread is running
+            self.inner.close()?;
+            info!("DB {} closed", &self.inner.db_path);
+            return Ok(());
+        }
         self.inner.is_shutting_down.store(true, Ordering::Relaxed);
         self.inner.schedule_close_batch();
         let _ = self.shutdown_batch_processing_thread.1.recv();
@@ -115,6 +148,7 @@ impl<S: Storage + Clone, C: Comparator + 'static> DB for TemplateDB<S, C> {
     }
 
     fn destroy(&mut self) -> TemplateResult<()> {
+        self.inner.check_writable("destroy")?;
         info!("Start destroying: {}", &self.inner.db_path);
         let db = self.inner.clone();
         self.close()?;
@@ -134,15 +168,17 @@ impl<S: Storage + Clone, C: Comparator + 'static> TemplateDB<S, C> {
         db_path: P,
         storage: S,
     ) -> TemplateResult<Self> {
-        let Ok(db_path) = db_path.as_ref().to_owned().into_os_string().into_string() else {
-            return Err(TemplateKVError::Customized(
-                "Invalid db path. Expect to use Unicode db path.".to_owned(),
-            ));
-        };
-        options.initialize(&db_path, &storage);
+        let db_path = unicode_db_path(db_path.as_ref())?;
+        options
+            .initialize(&db_path, &storage)
+            .map_err(|e| e.with_operation(Operation::OpenDB))?;
         debug!("Open db: '{:?}'", &db_path);
         let mut db = DBImpl::new(options, db_path, storage);
-        let (mut edit, should_save_manifest) = db.recover()?;
+        let (mut edit, should_save_manifest) = db
+            .recover()
+            .map_err(|e| e.with_operation(Operation::Recover))?;
+        db.bind_secondary_cache()
+            .map_err(|e| e.with_operation(Operation::OpenDB))?;
         let mut versions = db.versions.lock().unwrap();
         if versions.record_writer.is_none() {
             let new_log_number = versions.inc_next_file_number();
@@ -176,6 +212,74 @@ impl<S: Storage + Clone, C: Comparator + 'static> TemplateDB<S, C> {
         Ok(template_db)
     }
 
+    /// Opens the db at `db_path` in read-only mode.
+    ///
+    /// The instance serves reads from the state of the db when it's opened, including the
+    /// writes only in the log files. It doesn't take the LOCK file and never modifies any file
+    /// in `db_path`, so it can be opened while another process is writing to the db. All the
+    /// write operations return `InvalidArgument`.
+    pub fn open_read_only<P: AsRef<Path>>(
+        options: Options<C>,
+        db_path: P,
+        storage: S,
+    ) -> TemplateResult<Self> {
+        Self::open_without_lock(options, db_path.as_ref(), storage, AccessMode::ReadOnly)
+    }
+
+    /// Opens the db at `db_path` as a secondary instance of the primary one writing to it.
+    ///
+    /// A secondary instance is a read-only instance which can follow the primary instance by
+    /// `try_catch_up_with_primary`.
+    pub fn open_as_secondary<P: AsRef<Path>>(
+        options: Options<C>,
+        db_path: P,
+        storage: S,
+    ) -> TemplateResult<Self> {
+        Self::open_without_lock(options, db_path.as_ref(), storage, AccessMode::Secondary)
+    }
+
+    /// Makes the secondary instance see the latest state of the primary instance by replaying
+    /// the MANIFEST and the records appended to the live log files since the last call.
+    ///
+    /// This is best effort: if the primary instance switches the MANIFEST or removes a log
+    /// file in the meantime, an error or a slightly older state might be returned, and a
+    /// later call will catch up.
+    pub fn try_catch_up_with_primary(&self) -> TemplateResult<()> {
+        if self.inner.access_mode != AccessMode::Secondary {
+            return Err(TemplateKVError::InvalidArgument(
+                "only a secondary instance can catch up with the primary".to_owned(),
+            ));
+        }
+        self.inner
+            .recover_read_only()
+            .map_err(|e| e.with_operation(Operation::Recover))
+    }
+
+    fn open_without_lock(
+        mut options: Options<C>,
+        db_path: &Path,
+        storage: S,
+        access_mode: AccessMode,
+    ) -> TemplateResult<Self> {
+        let db_path = unicode_db_path(db_path)?;
+        options
+            .initialize_without_info_log(&db_path, &storage)
+            .map_err(|e| e.with_operation(Operation::OpenDB))?;
+        debug!("Open db '{:?}' in {:?} mode", &db_path, access_mode);
+        let mut db = DBImpl::new(options, db_path, storage);
+        db.access_mode = access_mode;
+        db.recover_read_only()
+            .map_err(|e| e.with_operation(Operation::Recover))?;
+        db.bind_secondary_cache()
+            .map_err(|e| e.with_operation(Operation::OpenDB))?;
+        // The background threads are not needed since there is neither write nor compaction
+        Ok(TemplateDB {
+            inner: Arc::new(db),
+            shutdown_batch_processing_thread: crossbeam_channel::bounded(1),
+            shutdown_compaction_thread: crossbeam_channel::bounded(1),
+        })
+    }
+
     /// Schedule a compaction for the key range `[begin, end]`.
     pub fn compact_range(&self, begin: Option<&[u8]>, end: Option<&[u8]>) -> TemplateResult<()> {
         self.inner.compact_range(begin, end)
@@ -191,6 +295,37 @@ impl<S: Storage + Clone, C: Comparator + 'static> TemplateDB<S, C> {
         self.inner.manual_compact_range(level, begin, end)
     }
 
+    /// Bulk loads the sst file at `path` generated by `SstFileWriter` into the db.
+    ///
+    /// The entries in the file are assigned a global sequence number newer than any existing
+    /// entry and the file is placed at the lowest level which has no overlapping files. The
+    /// memtable is flushed first if it overlaps with the file. The writes wait until the file
+    /// is installed while the reads go on without seeing it.
+    pub fn ingest_external_file<P: AsRef<Path>>(&self, path: P) -> TemplateResult<()> {
+        let current = self
+            .inner
+            .ingest_external_file(path.as_ref(), None)
+            .map_err(|e| e.with_path(path))?;
+        self.inner.maybe_schedule_compaction(current);
+        Ok(())
+    }
+
+    /// Same as `ingest_external_file` but the whole file is verified against the given crc32
+    /// first, which is usually the `file_checksum` reported by `SstFileWriter::finish`.
+    /// Nothing is ingested if the checksums mismatch.
+    pub fn ingest_external_file_with_checksum<P: AsRef<Path>>(
+        &self,
+        path: P,
+        checksum: u32,
+    ) -> TemplateResult<()> {
+        let current = self
+            .inner
+            .ingest_external_file(path.as_ref(), Some(checksum))
+            .map_err(|e| e.with_path(path))?;
+        self.inner.maybe_schedule_compaction(current);
+        Ok(())
+    }
+
     /// Returns true if the given snapshot is removed
     #[must_use]
     pub fn release_snapshot(&self, s: Arc<Snapshot>) -> bool {
@@ -234,6 +369,8 @@ impl<S: Storage + Clone, C: Comparator + 'static> TemplateDB<S, C> {
                     break;
                 }
                 let force = first.force_mem_compaction;
+                // No sequence number is taken while an external file is being ingested
+                let _ingestion = db.ingestion_lock.lock().unwrap();
                 match db.make_room_for_write(force) {
                     Ok(mut versions) => {
                         let (mut grouped, signals) = db.group_batches(first);
@@ -255,6 +392,7 @@ impl<S: Storage + Clone, C: Comparator + 'static> TemplateDB<S, C> {
                                 let memtable = db.mem.read().unwrap();
                                 // Might encounter corruption err here
                                 res = grouped.batch.insert_into(&*memtable);
+                                memtable.update_write_buffer_charge();
                             }
                             match res {
                                 Ok(()) => {
@@ -268,10 +406,9 @@ impl<S: Storage + Clone, C: Comparator + 'static> TemplateDB<S, C> {
                                 }
                                 Err(e) => {
                                     warn!("[process batch] write batch failed: {}", e);
+                                    let e = e.with_operation(Operation::Write);
                                     for signal in signals {
-                                        if let Err(e) = signal.send(Err(TemplateKVError::Customized(
-                                            "[process batch] write batch failed".to_owned(),
-                                        ))) {
+                                        if let Err(e) = signal.send(Err(e.clone())) {
                                             error!(
                                                 "[process batch] Fail sending finshing signal to waiting batch: {}", e
                                             )
@@ -298,10 +435,7 @@ impl<S: Storage + Clone, C: Comparator + 'static> TemplateDB<S, C> {
                         }
                     }
                     Err(e) => {
-                        if let Err(e) = first.signal.send(Err(TemplateKVError::Customized(format!(
-                            "[process batch] TemplateKVError making room for write requests: {}",
-                            e
-                        )))) {
+                        if let Err(e) = first.signal.send(Err(e.with_operation(Operation::Write))) {
                             error!(
                                 "[process batch] fail to send finishing signal to waiting batch: {}", e
                             );
@@ -368,6 +502,17 @@ impl<S: Storage + Clone, C: Comparator + 'static> TemplateDB<S, C> {
     }
 }
 
+/// How an instance accesses the db
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessMode {
+    /// The primary instance which holds the LOCK file and writes to the db
+    ReadWrite,
+    /// Serves reads from the state of the db when it's opened
+    ReadOnly,
+    /// Serves reads and follows the primary instance by `try_catch_up_with_primary`
+    Secondary,
+}
+
 pub struct DBImpl<S: Storage + Clone, C: Comparator> {
     pub env: S,
     pub internal_comparator: InternalKeyComparator<C>,
@@ -384,6 +529,8 @@ pub struct DBImpl<S: Storage + Clone, C: Comparator> {
 
     // the table cache
     pub table_cache: TableCache<S, C>,
+    // the cache of the opened blob files
+    pub blob_cache: BlobFileCache<S>,
 
     // The version set
     pub versions: Mutex<VersionSet<S, C>>,
@@ -408,6 +555,17 @@ pub struct DBImpl<S: Storage + Clone, C: Comparator> {
     pub bg_error: RwLock<Option<TemplateKVError>>,
     // Whether the db is closing
     pub is_shutting_down: AtomicBool,
+    // Obsolete files are kept while this is greater than 0
+    pub file_deletions_disabled: AtomicUsize,
+    // Held by the batch processing thread for each write and by an ingestion until the
+    // external file is installed, so the writes never take the sequence number of the file
+    ingestion_lock: Mutex<()>,
+    // The live log files replayed into the memtable by a read-only or secondary instance and
+    // the offsets right after their last replayed records
+    pub(crate) replayed_logs: Mutex<HashMap<u64, u64>>,
+    // Whether the secondary cache is bound to the db and must be unbound on closing
+    secondary_cache_bound: AtomicBool,
+    pub access_mode: AccessMode,
 }
 
 impl<S: Storage + Clone, C: Comparator> Drop for DBImpl<S, C> {
@@ -422,6 +580,11 @@ impl<S: Storage + Clone, C: Comparator> Drop for DBImpl<S, C> {
 impl<S: Storage + Clone, C: Comparator> DBImpl<S, C> {
     fn close(&self) -> TemplateResult<()> {
         self.is_shutting_down.store(true, Ordering::Release);
+        if self.secondary_cache_bound.swap(false, Ordering::AcqRel) {
+            if let Some(sc) = &self.options.secondary_cache {
+                sc.unbind_db();
+            }
+        }
         match &self.db_lock {
             Some(lock) => lock.unlock(),
             None => Ok(()),
@@ -447,22 +610,48 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
                 o.table_cache_size(),
                 storage.clone(),
             ),
+            blob_cache: BlobFileCache::new(db_path.clone(), o.table_cache_size(), storage.clone()),
             versions: Mutex::new(VersionSet::new(db_path, o.clone(), storage)),
             manual_compaction_queue: Mutex::new(VecDeque::new()),
             background_work_finished_signal: Condvar::new(),
             background_compaction_scheduled: AtomicBool::new(false),
             do_compaction: crossbeam_channel::unbounded(),
-            mem: ShardedLock::new(MemTable::new(o.write_buffer_size, icmp)),
+            mem: ShardedLock::new(MemTable::with_write_buffer_manager(
+                o.write_buffer_size,
+                icmp,
+                o.write_buffer_manager.clone(),
+            )),
             im_mem: ShardedLock::new(None),
             bg_error: RwLock::new(None),
             is_shutting_down: AtomicBool::new(false),
+            file_deletions_disabled: AtomicUsize::new(0),
+            ingestion_lock: Mutex::new(()),
+            replayed_logs: Mutex::new(HashMap::default()),
+            secondary_cache_bound: AtomicBool::new(false),
+            access_mode: AccessMode::ReadWrite,
+        }
+    }
+
+    // Returns an error if the instance is not allowed to modify the db
+    fn check_writable(&self, op: &str) -> TemplateResult<()> {
+        if self.access_mode != AccessMode::ReadWrite {
+            return Err(TemplateKVError::InvalidArgument(format!(
+                "{} is not supported in {:?} mode",
+                op, self.access_mode
+            )));
         }
+        Ok(())
     }
     fn snapshot(&self) -> Arc<Snapshot> {
         self.versions.lock().unwrap().new_snapshot()
     }
 
-    fn get(&self, options: ReadOptions, key: &[u8]) -> TemplateResult<Option<Vec<u8>>> {
+    // The value found in a memtable or a data block is pinned without copying
+    fn get_pinned(
+        &self,
+        options: ReadOptions,
+        key: &[u8],
+    ) -> TemplateResult<Option<PinnableSlice>> {
         if self.is_shutting_down.load(Ordering::Acquire) {
             return Err(TemplateKVError::DBClosed("get request".to_owned()));
         }
@@ -472,18 +661,18 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         };
         let lookup_key = LookupKey::new(key, snapshot);
         // search the memtable
-        if let Some(result) = self.mem.read().unwrap().get(&lookup_key) {
+        if let Some(result) = self.mem.read().unwrap().get_pinned(&lookup_key) {
             match result {
-                Ok(value) => return Ok(Some(value.clone())),
+                Ok(value) => return Ok(Some(PinnableSlice::pinned(value))),
                 // mem.get only returns Err() when it get a Deletion of the key
                 Err(_) => return Ok(None),
             }
         }
         // search the immutable memtable
         if let Some(im_mem) = self.im_mem.read().unwrap().as_ref() {
-            if let Some(result) = im_mem.get(&lookup_key) {
+            if let Some(result) = im_mem.get_pinned(&lookup_key) {
                 match result {
-                    Ok(value) => return Ok(Some(value.clone())),
+                    Ok(value) => return Ok(Some(PinnableSlice::pinned(value))),
                     Err(_) => return Ok(None),
                 }
             }
@@ -493,7 +682,80 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         if current.update_stats(seek_stats) {
             self.maybe_schedule_compaction(current);
         }
-        Ok(value)
+        match value {
+            // The blob file is kept until `current` is released
+            Some((index, ValueType::BlobIndex)) => self
+                .get_blob_value(&index)
+                .map(|v| Some(PinnableSlice::owned(v))),
+            Some((value, _)) => Ok(Some(PinnableSlice::pinned(value))),
+            None => Ok(None),
+        }
+    }
+
+    fn multi_get(
+        &self,
+        options: ReadOptions,
+        keys: &[&[u8]],
+    ) -> TemplateResult<Vec<Option<Bytes>>> {
+        if self.is_shutting_down.load(Ordering::Acquire) {
+            return Err(TemplateKVError::DBClosed("multi_get request".to_owned()));
+        }
+        let snapshot = match &options.snapshot {
+            Some(snapshot) => snapshot.sequence(),
+            None => self.versions.lock().unwrap().last_sequence(),
+        };
+        let mut values = vec![None; keys.len()];
+        // the keys not found in the memtables and their indexes
+        let mut pending = vec![];
+        let mut pending_keys = vec![];
+        {
+            let mem = self.mem.read().unwrap();
+            let im_mem = self.im_mem.read().unwrap();
+            for (i, key) in keys.iter().enumerate() {
+                let lookup_key = LookupKey::new(key, snapshot);
+                let result = mem
+                    .get(&lookup_key)
+                    .or_else(|| im_mem.as_ref().and_then(|im_mem| im_mem.get(&lookup_key)));
+                match result {
+                    Some(Ok(value)) => values[i] = Some(Bytes::from(value)),
+                    // A deletion of the key
+                    Some(Err(_)) => {}
+                    None => {
+                        pending.push(i);
+                        pending_keys.push(lookup_key);
+                    }
+                }
+            }
+        }
+        if pending.is_empty() {
+            return Ok(values);
+        }
+        let current = self.versions.lock().unwrap().current();
+        let (found, seek_stats) = current.multi_get(&options, &pending_keys, &self.table_cache)?;
+        let mut schedule_compaction = false;
+        for stats in seek_stats {
+            schedule_compaction |= current.update_stats(Some(stats));
+        }
+        if schedule_compaction {
+            self.maybe_schedule_compaction(current);
+        }
+        for (i, value) in pending.into_iter().zip(found) {
+            values[i] = match value {
+                // The blob files are kept until `current` is released
+                Some((index, ValueType::BlobIndex)) => {
+                    Some(Bytes::from(self.get_blob_value(&index)?))
+                }
+                Some((value, _)) => Some(value),
+                None => None,
+            };
+        }
+        Ok(values)
+    }
+
+    /// Reads the value referred by the encoded `BlobIndex`
+    pub fn get_blob_value(&self, encoded_index: &[u8]) -> TemplateResult<Vec<u8>> {
+        let index = BlobIndex::decode_from(encoded_index)?;
+        self.blob_cache.get(&index)
     }
 
     // Record a sample of bytes read at the specified internal key
@@ -558,6 +820,12 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
                 self.db_path.clone() + " exists (error_if_exists is true)",
             ));
         }
+        // The db created before the IDENTITY file was introduced gets one as well
+        if self.read_identity()?.is_none() {
+            let identity = generate_filename(&self.db_path, FileType::Identity, 0);
+            let id = format!("{:032x}", rand::random::<u128>());
+            do_write_string_to_file(&self.env, id, identity, true)?;
+        }
         let mut versions = self.versions.lock().unwrap();
         let mut should_save_manifest = versions.recover()?;
 
@@ -594,13 +862,18 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         let mut max_sequence = 0;
         let mut edit = VersionEdit::new(self.options.max_levels);
         for (i, log_number) in logs_to_recover.iter().enumerate() {
-            let last_seq = self.replay_log_file(
-                &mut versions,
-                *log_number,
-                i == logs_to_recover.len() - 1,
-                &mut should_save_manifest,
-                &mut edit,
-            )?;
+            let last_seq = self
+                .replay_log_file(
+                    &mut versions,
+                    *log_number,
+                    i == logs_to_recover.len() - 1,
+                    &mut should_save_manifest,
+                    &mut edit,
+                )
+                .map_err(|e| {
+                    e.with_operation(Operation::ReplayLog)
+                        .with_file_number(*log_number)
+                })?;
             if max_sequence < last_seq {
                 max_sequence = last_seq;
             }
@@ -617,6 +890,149 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         Ok((edit, should_save_manifest))
     }
 
+    // Returns the id in the IDENTITY file, or `None` if it's missing or left empty by a crash
+    fn read_identity(&self) -> TemplateResult<Option<String>> {
+        let identity = generate_filename(&self.db_path, FileType::Identity, 0);
+        if !self.env.exists(&identity) {
+            return Ok(None);
+        }
+        let mut id = vec![];
+        self.env
+            .open_read_only(&identity)?
+            .read_all(&mut id)
+            .map_err(|e| e.with_path(&identity))?;
+        Ok(Some(String::from_utf8_lossy(&id).into_owned()).filter(|id| !id.is_empty()))
+    }
+
+    // Binds the secondary cache to the db identified by the IDENTITY file
+    fn bind_secondary_cache(&self) -> TemplateResult<()> {
+        let Some(sc) = &self.options.secondary_cache else {
+            return Ok(());
+        };
+        match self.read_identity()? {
+            Some(id) => {
+                sc.bind_db(&id)?;
+                self.secondary_cache_bound.store(true, Ordering::Release);
+                Ok(())
+            }
+            // Only in read-only modes. The cache stays unbound and unused.
+            None => {
+                warn!("No IDENTITY in {}, skip the secondary cache", &self.db_path);
+                Ok(())
+            }
+        }
+    }
+
+    // Loads the latest state of the db without modifying any file in `db_path`.
+    // The MANIFEST is read into a new current version and the records appended to the live log
+    // files since the last call are replayed into the memtable.
+    fn recover_read_only(&self) -> TemplateResult<()> {
+        if !self
+            .env
+            .exists(generate_filename(&self.db_path, FileType::Current, 0))
+        {
+            return Err(TemplateKVError::InvalidArgument(
+                self.db_path.clone() + " does not exist",
+            ));
+        }
+        let mut versions = self.versions.lock().unwrap();
+        // The replayed entries might be newer than the ones in the MANIFEST
+        let prev_sequence = versions.last_sequence();
+        versions.recover_read_only()?;
+        let min_log = versions.log_number();
+        let prev_log = versions.prev_log_number();
+        let mut logs_to_recover = vec![];
+        for filename in self.env.list(&self.db_path)? {
+            if let Some((FileType::Log, file_number)) = parse_filename(filename) {
+                if file_number >= min_log || file_number == prev_log {
+                    logs_to_recover.push(file_number);
+                }
+            }
+        }
+        logs_to_recover.sort_unstable();
+        let mut replayed_logs = self.replayed_logs.lock().unwrap();
+        // The memtable is rebuilt from the live log files once the primary instance has
+        // flushed a log file replayed into it. Otherwise the records appended to the log
+        // files since the last call are replayed into it.
+        let rebuild = replayed_logs
+            .keys()
+            .any(|n| logs_to_recover.binary_search(n).is_err());
+        let new_mem = rebuild.then(|| {
+            replayed_logs.clear();
+            MemTable::new(
+                self.options.write_buffer_size,
+                self.internal_comparator.clone(),
+            )
+        });
+        let mut max_sequence = versions.last_sequence().max(prev_sequence);
+        {
+            let current_mem = self.mem.read().unwrap();
+            let mem = new_mem.as_ref().unwrap_or(&current_mem);
+            for log_number in logs_to_recover {
+                let offset = replayed_logs.get(&log_number).copied().unwrap_or(0);
+                let (last_seq, end) =
+                    self.replay_log_file_into(mem, log_number, offset)
+                        .map_err(|e| {
+                            e.with_operation(Operation::ReplayLog)
+                                .with_file_number(log_number)
+                        })?;
+                replayed_logs.insert(log_number, end);
+                if max_sequence < last_seq {
+                    max_sequence = last_seq;
+                }
+                versions.mark_file_number_used(log_number);
+            }
+        }
+        versions.set_last_sequence(max_sequence);
+        if let Some(mem) = new_mem {
+            *self.mem.write().unwrap() = mem;
+            *self.im_mem.write().unwrap() = None;
+        }
+        Ok(())
+    }
+
+    // Replays the records from `offset` of the named log file into `mem` without flushing
+    // anything. Returns the last sequence of insertions and the offset right after the last
+    // replayed record. A log file being written by the primary instance might end with a
+    // partial record, which is ignored by the reader and replayed by the next call.
+    fn replay_log_file_into(
+        &self,
+        mem: &MemTable<C>,
+        log_number: u64,
+        offset: u64,
+    ) -> TemplateResult<(u64, u64)> {
+        let file_name = generate_filename(&self.db_path, FileType::Log, log_number);
+        let log_file = self.env.open_read_only(file_name.as_str())?;
+        let reporter = LogReporter::new();
+        let mut reader = Reader::new(log_file, Some(Box::new(reporter.clone())), true, offset);
+        let mut record_buf = vec![];
+        let mut batch = WriteBatch::default();
+        let mut max_sequence = 0;
+        let mut end = offset;
+        while reader.read_record(&mut record_buf) {
+            reporter.result()?;
+            if record_buf.len() < HEADER_SIZE {
+                return Err(
+                    TemplateKVError::Corruption("log record too small".to_owned())
+                        .with_offset(reader.last_record_offset()),
+                );
+            }
+            batch.set_contents(&mut record_buf);
+            let last_seq = batch.get_sequence() + u64::from(batch.get_count()) - 1;
+            if let Err(e) = batch.insert_into(mem) {
+                if self.options.paranoid_checks {
+                    return Err(e);
+                }
+                info!("ignore errors when replaying log file : {:?}", e);
+            }
+            if last_seq > max_sequence {
+                max_sequence = last_seq;
+            }
+            end = reader.end_of_last_record();
+        }
+        Ok((max_sequence, end))
+    }
+
     // Replays the edits in the named log file and returns the last sequence of insertions
     fn replay_log_file(
         &self,
@@ -659,15 +1075,13 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         while reader.read_record(&mut record_buf) {
             reporter.result()?;
             if record_buf.len() < HEADER_SIZE {
-                return Err(TemplateKVError::Corruption(
-                    "log record too small".to_owned(),
-                ));
+                return Err(
+                    TemplateKVError::Corruption("log record too small".to_owned())
+                        .with_offset(reader.last_record_offset()),
+                );
             }
             if mem.is_none() {
-                mem = Some(MemTable::new(
-                    self.options.write_buffer_size,
-                    self.internal_comparator.clone(),
-                ));
+                mem = Some(self.new_mem_table());
             }
             let mem_ref = mem.as_ref().unwrap();
             batch.set_contents(&mut record_buf);
@@ -678,6 +1092,7 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
                 }
                 info!("ignore errors when replaying log file : {:?}", e);
             }
+            mem_ref.update_write_buffer_charge();
             inserted_size += batch.approximate_size();
             if last_seq > max_sequence {
                 max_sequence = last_seq;
@@ -710,10 +1125,7 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
                 *self.mem.write().unwrap() = m;
                 mem = None;
             } else {
-                *self.mem.write().unwrap() = MemTable::new(
-                    self.options.write_buffer_size,
-                    self.internal_comparator.clone(),
-                );
+                *self.mem.write().unwrap() = self.new_mem_table();
             }
         }
         if let Some(m) = &mem {
@@ -738,6 +1150,9 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         &self,
         mut versions: MutexGuard<VersionSet<S, C>>,
     ) -> TemplateResult<()> {
+        if self.file_deletions_disabled.load(Ordering::Acquire) > 0 {
+            return Ok(());
+        }
         versions.lock_live_files();
         // ignore IO error on purpose
         let files = self.env.list(&self.db_path)?;
@@ -750,12 +1165,21 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
                     FileType::Manifest => number >= versions.manifest_number(),
                     // Any temp files that are currently being written to must
                     // be recorded in pending_outputs
-                    FileType::Table | FileType::Temp => versions.pending_outputs.contains(&number),
+                    FileType::Table | FileType::Blob | FileType::Temp => {
+                        versions.pending_outputs.contains(&number)
+                    }
                     _ => true,
                 };
                 if !keep {
-                    if file_type == FileType::Table {
-                        self.table_cache.evict(number);
+                    match file_type {
+                        FileType::Table => {
+                            self.table_cache.evict(number);
+                            if let Some(sc) = &self.options.secondary_cache {
+                                sc.erase_file(number);
+                            }
+                        }
+                        FileType::Blob => self.blob_cache.evict(number),
+                        _ => {}
                     }
                     info!(
                         "Delete type={:?} #{} [filename {:?}]",
@@ -798,6 +1222,7 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         if self.is_shutting_down.load(Ordering::Acquire) {
             return Err(TemplateKVError::DBClosed("schedule WriteBatch".to_owned()));
         }
+        self.check_writable("write")?;
         if batch.is_empty() && !force_mem_compaction {
             return Ok(());
         }
@@ -874,6 +1299,7 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
             } else if !force
                 && self.mem.read().unwrap().approximate_memory_usage()
                     <= self.options.write_buffer_size
+                && !self.exceeds_write_buffer_budget()
             {
                 // There is room in current memtable
                 break;
@@ -898,13 +1324,8 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
                 {
                     let mut mem = self.mem.write().unwrap();
                     if mem.len() > 0 {
-                        let memtable = mem::replace(
-                            &mut *mem,
-                            MemTable::new(
-                                self.options.write_buffer_size,
-                                self.internal_comparator.clone(),
-                            ),
-                        );
+                        let memtable = mem::replace(&mut *mem, self.new_mem_table());
+                        memtable.mark_immutable();
                         let mut im_mem = self.im_mem.write().unwrap();
                         *im_mem = Some(memtable);
                     }
@@ -916,6 +1337,25 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         Ok(versions)
     }
 
+    // Creates an empty memtable charged to the write buffer manager if there is one
+    fn new_mem_table(&self) -> MemTable<C> {
+        MemTable::with_write_buffer_manager(
+            self.options.write_buffer_size,
+            self.internal_comparator.clone(),
+            self.options.write_buffer_manager.clone(),
+        )
+    }
+
+    // Returns true if the memtables sharing the write buffer manager exceed its budget and
+    // the current memtable takes at least 1/8 of it. The small memtables are not flushed, or
+    // a DB would keep flushing while the memory is held by the memtables of the other DBs.
+    fn exceeds_write_buffer_budget(&self) -> bool {
+        self.options.write_buffer_manager.as_ref().is_some_and(|m| {
+            m.should_flush()
+                && self.mem.read().unwrap().approximate_memory_usage() >= m.buffer_size() / 8
+        })
+    }
+
     // Compact immutable memory table to level_0 files
     fn compact_mem_table(&self) -> TemplateResult<()> {
         debug!("Compact memtable");
@@ -923,13 +1363,9 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         let mut edit = VersionEdit::new(self.options.max_levels);
         let mut im_mem = self.im_mem.write().unwrap();
         let mut iter = im_mem.as_ref().unwrap().iter();
-        versions.write_level_0_files(
-            &self.db_path,
-            &self.table_cache,
-            &mut iter,
-            &mut edit,
-            true,
-        )?;
+        versions
+            .write_level_0_files(&self.db_path, &self.table_cache, &mut iter, &mut edit, true)
+            .map_err(|e| e.with_operation(Operation::FlushMemTable))?;
         if self.is_shutting_down.load(Ordering::Acquire) {
             Err(TemplateKVError::DBClosed(
                 "when compacting memory table".to_owned(),
@@ -960,6 +1396,168 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         Ok(())
     }
 
+    // Ingests the external sst file and returns the version containing it
+    fn ingest_external_file(
+        &self,
+        path: &Path,
+        expected_checksum: Option<u32>,
+    ) -> TemplateResult<Arc<Version<C>>> {
+        if self.is_shutting_down.load(Ordering::Acquire) {
+            return Err(TemplateKVError::DBClosed("ingest external file".to_owned()));
+        }
+        self.check_writable("ingest external file")?;
+        if let Some(expected) = expected_checksum {
+            verify_file_checksum(&self.env, path, expected)?;
+        }
+        let file = self.env.open_read_only(path)?;
+        let file_size = file.len()?;
+        // The file is not a part of the db yet so we give it a file number which is never used
+        let table = Table::open(
+            file,
+            0,
+            file_size,
+            self.options.clone(),
+            self.internal_comparator.clone(),
+        )?;
+        let read_opt = ReadOptions {
+            verify_checksums: true,
+            fill_cache: false,
+            snapshot: None,
+            ..Default::default()
+        };
+        let mut iter =
+            new_table_iterator(self.internal_comparator.clone(), Arc::new(table), read_opt);
+        iter.seek_to_first();
+        if !iter.valid() {
+            iter.status()?;
+            return Err(TemplateKVError::InvalidArgument(
+                "can not ingest an empty sst file".to_owned(),
+            ));
+        }
+        let smallest = extract_user_key(iter.key()).to_vec();
+        iter.seek_to_last();
+        let largest = extract_user_key(iter.key()).to_vec();
+        iter.status()?;
+
+        let _ingestion = loop {
+            // Holding the lock blocks all the writes so nothing can be inserted into the
+            // memtable until the file is installed
+            let ingestion = self.ingestion_lock.lock().unwrap();
+            if !self.memtable_overlaps(&smallest, &largest) {
+                break ingestion;
+            }
+            mem::drop(ingestion);
+            // The entries in the memtable are always read before the ones in sst files,
+            // so they must be flushed to avoid hiding the newer ingested entries.
+            self.force_compact_mem_table()?;
+        };
+        // The sequence number is not published until the file is installed, so the snapshots
+        // and reads in the meantime never see the ingested entries
+        let (number, global_seq, level) = {
+            let mut versions = self.versions.lock().unwrap();
+            let level = self.ingestion_level(&versions, &smallest, &largest);
+            (
+                versions.inc_next_file_number(),
+                versions.last_sequence() + 1,
+                level,
+            )
+        };
+
+        // The file is copied without holding the version set so the reads and the background
+        // work go on meanwhile. The file deletions are disabled until it's installed, since
+        // it's not a live file.
+        let _guard = FileDeletionsGuard::new(self);
+        let mut meta = FileMetaData {
+            number,
+            ..Default::default()
+        };
+        let mut stamped = GlobalSeqIterator::new(iter, global_seq);
+        build_table(
+            self.options.clone(),
+            &self.env,
+            &self.db_path,
+            &self.table_cache,
+            &mut stamped,
+            &mut meta,
+            level,
+            None,
+        )
+        .map_err(|e| {
+            e.with_operation(Operation::BuildTable)
+                .with_file_number(meta.number)
+        })?;
+
+        // The level, the sequence number and the file are decided and installed at once
+        let mut versions = self.versions.lock().unwrap();
+        // The files may have been flushed or compacted while copying
+        let level = self.ingestion_level(&versions, &smallest, &largest);
+        info!(
+            "Ingest external file {:?} as table #{} at level {} [seq {}] [key range {:?} ... {:?}]",
+            path, meta.number, level, global_seq, &meta.smallest, &meta.largest,
+        );
+        let mut edit = VersionEdit::new(self.options.max_levels);
+        edit.add_file(
+            level,
+            meta.number,
+            meta.file_size,
+            meta.smallest.clone(),
+            meta.largest.clone(),
+            meta.file_checksum,
+        );
+        versions.set_last_sequence(global_seq);
+        if let Err(e) = versions.log_and_apply(edit) {
+            versions.set_last_sequence(global_seq - 1);
+            return Err(e);
+        }
+        Ok(versions.current())
+    }
+
+    // Returns the deepest level an external file with the user keys in `[smallest, largest]`
+    // can be placed at, which is above all the files overlapping it, including the outputs of
+    // the running compaction
+    fn ingestion_level(
+        &self,
+        versions: &VersionSet<S, C>,
+        smallest: &[u8],
+        largest: &[u8],
+    ) -> usize {
+        let ucmp = &self.internal_comparator.user_comparator;
+        let version = versions.current();
+        let overlaps = |level: usize| {
+            version.overlap_in_level(level, Some(smallest), Some(largest))
+                || versions
+                    .running_compaction
+                    .as_ref()
+                    .is_some_and(|(output_level, begin, end)| {
+                        *output_level == level
+                            && ucmp.compare(begin.user_key(), largest) != CmpOrdering::Greater
+                            && ucmp.compare(end.user_key(), smallest) != CmpOrdering::Less
+                    })
+        };
+        let mut level = 0;
+        while level + 1 < self.options.max_levels && !overlaps(level) && !overlaps(level + 1) {
+            level += 1;
+        }
+        level
+    }
+
+    // Returns true if any entry in the memtable or immutable memtable has a user key in
+    // the range `[smallest, largest]`
+    fn memtable_overlaps(&self, smallest: &[u8], largest: &[u8]) -> bool {
+        let ucmp = &self.internal_comparator.user_comparator;
+        let start = InternalKey::new(smallest, MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK);
+        let overlaps = |mem: &MemTable<C>| {
+            let mut iter = mem.iter();
+            iter.seek(start.data());
+            iter.valid()
+                && ucmp.compare(extract_user_key(iter.key()), largest) != CmpOrdering::Greater
+        };
+        if overlaps(&self.mem.read().unwrap()) {
+            return true;
+        }
+        self.im_mem.read().unwrap().as_ref().is_some_and(overlaps)
+    }
+
     // Compact the underlying storage for the key range `[begin, end]`.
     //
     // In particular, deleted and overwritten versions are discarded,
@@ -997,6 +1595,7 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         begin: Option<&[u8]>,
         end: Option<&[u8]>,
     ) -> TemplateResult<()> {
+        self.check_writable("compaction")?;
         assert!(level + 1 < self.options.max_levels);
         let (sender, finished) = crossbeam_channel::bounded(1);
         {
@@ -1089,6 +1688,7 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
                         f.file_size,
                         f.smallest.clone(),
                         f.largest.clone(),
+                        f.file_checksum,
                     );
                     let res = versions.log_and_apply(compaction.edit);
                     if let Err(e) = res.as_ref() {
@@ -1119,18 +1719,30 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
                             compaction.oldest_snapshot_alive = snapshots.oldest().sequence();
                         }
                     }
+                    // The ingestions must not place a file above the outputs overlapping it
+                    let (smallest, largest) = total_range(
+                        &compaction.inputs.base,
+                        &compaction.inputs.parent,
+                        level,
+                        &self.internal_comparator,
+                    );
+                    versions.running_compaction =
+                        Some((level + 1, smallest.clone(), largest.clone()));
                     // Unlock VersionSet here to avoid dead lock
                     mem::drop(versions);
                     match self.do_compaction(compaction) {
-                        Ok(versions) => {
+                        Ok(mut versions) => {
+                            versions.running_compaction = None;
                             let res = self.delete_obsolete_files(versions);
                             if let Some(done) = done {
                                 done.send(res).unwrap();
                             }
                         }
                         Err(e) => {
+                            let e = e.with_operation(Operation::Compaction);
                             {
-                                let versions = self.versions.lock().unwrap();
+                                let mut versions = self.versions.lock().unwrap();
+                                versions.running_compaction = None;
                                 let _ = self.delete_obsolete_files(versions);
                             }
                             error!("Compaction error: {:?}", &e);
@@ -1156,6 +1768,7 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         let mut input_iter =
             c.new_input_iterator(self.internal_comparator.clone(), self.table_cache.clone())?;
         let mut mem_compaction_duration = 0;
+        let blob_gc_cutoff = c.blob_gc_cutoff();
         input_iter.seek_to_first();
 
         let mut last_sequence_for_key = u64::max_value();
@@ -1202,7 +1815,11 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
                         drop = true
                     }
                     last_sequence_for_key = key.seq;
-                    if !drop {
+                    if drop {
+                        if key.value_type == ValueType::BlobIndex {
+                            c.add_blob_garbage(&BlobIndex::decode_from(input_iter.value())?);
+                        }
+                    } else {
                         // Open output file if necessary
                         if c.builder.is_none() {
                             self.versions
@@ -1210,14 +1827,25 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
                                 .unwrap()
                                 .create_compaction_output_file(&mut c)?;
                         }
+                        let (ikey, value) = match self.separate_compaction_value(
+                            &mut c,
+                            &key,
+                            input_iter.value(),
+                            blob_gc_cutoff,
+                        )? {
+                            Some((new_key, new_value)) => {
+                                (Cow::Owned(new_key.data().to_vec()), Cow::Owned(new_value))
+                            }
+                            None => (Cow::Borrowed(ikey), Cow::Borrowed(input_iter.value())),
+                        };
                         let last = c.outputs.len() - 1;
                         if c.builder.as_ref().unwrap().num_entries() == 0 {
                             // We have a brand new builder so use current key as smallest
-                            c.outputs[last].smallest = InternalKey::decoded_from(ikey);
+                            c.outputs[last].smallest = InternalKey::decoded_from(&ikey);
                         }
                         // Keep updating the largest
-                        c.outputs[last].largest = InternalKey::decoded_from(ikey);
-                        c.builder.as_mut().unwrap().add(ikey, input_iter.value())?;
+                        c.outputs[last].largest = InternalKey::decoded_from(&ikey);
+                        c.builder.as_mut().unwrap().add(&ikey, &value)?;
                         let builder = c.builder.as_ref().unwrap();
                         // Rotate a new output file if the current one is big enough
                         if builder.file_size() >= self.options.max_file_size {
@@ -1238,6 +1866,9 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         if c.builder.is_some() {
             self.finish_output_file(&mut c, input_iter.status())?;
         }
+        if c.blob_builder.is_some() {
+            self.finish_blob_file(&mut c)?;
+        }
         // Close unclosed table builder and remove files in `pending_outputs`
         if let Some(builder) = c.builder.as_mut() {
             builder.close()
@@ -1255,6 +1886,9 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         for output in c.outputs.iter() {
             versions.pending_outputs.remove(&output.number);
         }
+        for output in c.blob_outputs.iter() {
+            versions.pending_outputs.remove(&output.number);
+        }
         if let Ok(()) = input_iter.status() {
             info!(
                 "Compacted {}@{} + {}@{} files => {} bytes",
@@ -1295,7 +1929,9 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
     // 4. there is an immutable table or a manual compaction request or current version needs to be
     //    compacted
     fn maybe_schedule_compaction(&self, version: Arc<Version<C>>) -> bool {
-        if self.background_compaction_scheduled.load(Ordering::Acquire)
+        if self.access_mode != AccessMode::ReadWrite
+            // No compaction thread
+            || self.background_compaction_scheduled.load(Ordering::Acquire)
             // Already scheduled
             || self.is_shutting_down.load(Ordering::Acquire)
             // DB is being shutting down
@@ -1336,7 +1972,11 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         };
         let current_bytes = c.builder.as_ref().unwrap().file_size();
         // update current output
-        c.outputs.last_mut().unwrap().file_size = current_bytes;
+        let output = c.outputs.last_mut().unwrap();
+        output.file_size = current_bytes;
+        if status.is_ok() {
+            output.file_checksum = Some(c.builder.as_ref().unwrap().file_checksum());
+        }
         c.total_bytes += current_bytes;
         c.builder = None;
         if status.is_ok() && current_entries > 0 {
@@ -1360,6 +2000,72 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
         status
     }
 
+    // Returns the new key and value to output for the compaction entry if the value should be
+    // moved between the sst file and the blob files:
+    // - a large value is separated into the blob file of the compaction
+    // - a value in the blob files older than `blob_gc_cutoff` is relocated into the blob file
+    //   of the compaction, or back into the sst file if it's no longer large enough
+    fn separate_compaction_value(
+        &self,
+        c: &mut Compaction<S::F, C>,
+        key: &ParsedInternalKey,
+        value: &[u8],
+        blob_gc_cutoff: u64,
+    ) -> TemplateResult<Option<(InternalKey, Vec<u8>)>> {
+        let blob_value = match key.value_type {
+            ValueType::Value if self.options.enable_blob_files => {
+                if value.len() < self.options.min_blob_size {
+                    return Ok(None);
+                }
+                None
+            }
+            ValueType::BlobIndex => {
+                let index = BlobIndex::decode_from(value)?;
+                if index.file_number >= blob_gc_cutoff {
+                    return Ok(None);
+                }
+                let blob_value = self.blob_cache.get(&index)?;
+                c.add_blob_garbage(&index);
+                if !self.options.enable_blob_files || blob_value.len() < self.options.min_blob_size
+                {
+                    return Ok(Some((
+                        InternalKey::new(key.user_key, key.seq, ValueType::Value),
+                        blob_value,
+                    )));
+                }
+                Some(blob_value)
+            }
+            _ => return Ok(None),
+        };
+        if c.blob_builder.is_none() {
+            self.versions
+                .lock()
+                .unwrap()
+                .create_compaction_blob_file(c)?;
+        }
+        let builder = c.blob_builder.as_mut().unwrap();
+        let index = builder.add(blob_value.as_deref().unwrap_or(value))?;
+        if builder.file_size() >= self.options.blob_file_size {
+            self.finish_blob_file(c)?;
+        }
+        Ok(Some((
+            InternalKey::new(key.user_key, key.seq, ValueType::BlobIndex),
+            index.encode(),
+        )))
+    }
+
+    // Finish the current output blob file of the compaction
+    fn finish_blob_file(&self, c: &mut Compaction<S::F, C>) -> TemplateResult<()> {
+        let mut builder = c.blob_builder.take().unwrap();
+        let meta = builder.finish(true)?;
+        info!(
+            "Compaction output blob file #{}: {} values, {} bytes",
+            meta.number, meta.total_count, meta.file_size,
+        );
+        c.blob_outputs.push(meta);
+        Ok(())
+    }
+
     // Returns the approximate file system space used by keys in "[start .. end)"
     //
     // Note that the returned sizes measure file system space usage, so
@@ -1392,6 +2098,10 @@ struct BatchTask {
 // meta will be filled with metadata about the generated table.
 // If no data is present in iter, `meta.file_size` will be set to
 // zero, and no Table file will be produced.
+// The blocks are compressed by the algorithm configured for `level`.
+// If `blob_file_number` is given, the values not smaller than `options.min_blob_size` are
+// separated into the blob file of that number, whose metadata is returned.
+#[allow(clippy::too_many_arguments)]
 pub fn build_table<S: Storage + Clone, C: Comparator + 'static>(
     options: Arc<Options<C>>,
     storage: &S,
@@ -1399,20 +2109,54 @@ pub fn build_table<S: Storage + Clone, C: Comparator + 'static>(
     table_cache: &TableCache<S, C>,
     iter: &mut dyn Iterator,
     meta: &mut FileMetaData,
-) -> TemplateResult<()> {
+    level: usize,
+    blob_file_number: Option<u64>,
+) -> TemplateResult<Option<BlobFileMeta>> {
     meta.file_size = 0;
     iter.seek_to_first();
     let file_name = generate_filename(db_path, FileType::Table, meta.number);
+    let blob_file_name =
+        blob_file_number.map(|number| generate_filename(db_path, FileType::Blob, number));
+    let mut blob_builder = None;
+    let mut blob_file = None;
     let mut status = Ok(());
+    // Creates the output files with direct I/O if it's enabled
+    let create_file = |name: &str| {
+        if options.use_direct_io_for_flush_and_compaction {
+            storage.create_direct(name)
+        } else {
+            storage.create(name)
+        }
+    };
     if iter.valid() {
-        let file = storage.create(file_name.as_str())?;
+        let file = create_file(file_name.as_str())?;
         let icmp = InternalKeyComparator::new(options.comparator.clone());
-        let mut builder = TableBuilder::new(file, icmp.clone(), &options);
+        let mut builder = TableBuilder::for_level(file, icmp.clone(), &options, level);
         let mut prev_key = vec![];
         meta.smallest = InternalKey::decoded_from(iter.key());
         while iter.valid() {
-            let key = iter.key().to_vec();
-            let s = builder.add(&key, iter.value());
+            let mut key = iter.key().to_vec();
+            let s = match (&blob_file_name, ParsedInternalKey::decode_from(&key)) {
+                (Some(blob_file_name), Some(parsed))
+                    if parsed.value_type == ValueType::Value
+                        && iter.value().len() >= options.min_blob_size =>
+                {
+                    // Create the blob file lazily since there might be no large value at all
+                    if blob_builder.is_none() {
+                        let f = create_file(blob_file_name.as_str())?;
+                        blob_builder = Some(BlobFileBuilder::new(f, blob_file_number.unwrap()));
+                    }
+                    key = InternalKey::new(parsed.user_key, parsed.seq, ValueType::BlobIndex)
+                        .data()
+                        .to_vec();
+                    blob_builder
+                        .as_mut()
+                        .unwrap()
+                        .add(iter.value())
+                        .and_then(|index| builder.add(&key, &index.encode()))
+                }
+                _ => builder.add(&key, iter.value()),
+            };
             if s.is_err() {
                 status = s;
                 break;
@@ -1423,9 +2167,15 @@ pub fn build_table<S: Storage + Clone, C: Comparator + 'static>(
         if !prev_key.is_empty() {
             meta.largest = InternalKey::decoded_from(&prev_key);
         }
+        if status.is_ok() {
+            if let Some(b) = blob_builder.as_mut() {
+                status = b.finish(true).map(|m| blob_file = Some(m));
+            }
+        }
         if status.is_ok() {
             status = builder.finish(true).and_then(|_| {
                 meta.file_size = builder.file_size();
+                meta.file_checksum = Some(builder.file_checksum());
                 assert!(meta.file_size > 0);
                 // make sure that the new file is in the cache
                 let mut it = table_cache.new_iter(
@@ -1444,8 +2194,109 @@ pub fn build_table<S: Storage + Clone, C: Comparator + 'static>(
     };
     if status.is_err() || meta.file_size == 0 {
         storage.remove(file_name.as_str())?;
-        status
+        if let Some(mut b) = blob_builder {
+            b.close();
+            storage.remove(blob_file_name.unwrap().as_str())?;
+        }
+        status.map(|_| None)
     } else {
-        Ok(())
+        Ok(blob_file)
+    }
+}
+
+// An iterator overriding the sequence number of every internal key yielded by the inner one
+// with a global sequence number.
+struct GlobalSeqIterator<I: Iterator> {
+    inner: I,
+    seq: u64,
+    key: Vec<u8>,
+    err: Option<TemplateKVError>,
+}
+
+impl<I: Iterator> GlobalSeqIterator<I> {
+    fn new(inner: I, seq: u64) -> Self {
+        Self {
+            inner,
+            seq,
+            key: vec![],
+            err: None,
+        }
+    }
+
+    fn update_key(&mut self) {
+        self.key.clear();
+        if self.inner.valid() {
+            match ParsedInternalKey::decode_from(self.inner.key()) {
+                Some(parsed) => {
+                    self.key = InternalKey::new(parsed.user_key, self.seq, parsed.value_type)
+                        .data()
+                        .to_vec()
+                }
+                None => {
+                    self.err = Some(TemplateKVError::Corruption(
+                        "bad internal key in external sst file".to_owned(),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl<I: Iterator> Iterator for GlobalSeqIterator<I> {
+    fn valid(&self) -> bool {
+        self.err.is_none() && self.inner.valid()
+    }
+
+    fn seek_to_first(&mut self) {
+        self.inner.seek_to_first();
+        self.update_key();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.inner.seek_to_last();
+        self.update_key();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.inner.seek(target);
+        self.update_key();
+    }
+
+    fn next(&mut self) {
+        self.inner.next();
+        self.update_key();
+    }
+
+    fn prev(&mut self) {
+        self.inner.prev();
+        self.update_key();
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.key
     }
+
+    fn value(&self) -> &[u8] {
+        self.inner.value()
+    }
+
+    fn status(&mut self) -> TemplateResult<()> {
+        if let Some(e) = &self.err {
+            return Err(e.clone());
+        }
+        self.inner.status()
+    }
+}
+
+fn unicode_db_path(db_path: &Path) -> TemplateResult<String> {
+    db_path
+        .to_owned()
+        .into_os_string()
+        .into_string()
+        .map_err(|_| {
+            TemplateKVError::InvalidArgument(
+                "Invalid db path. Expect to use Unicode db path.".to_owned(),
+            )
+            .with_operation(Operation::OpenDB)
+        })
 }
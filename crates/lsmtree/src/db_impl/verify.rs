@@ -0,0 +1,290 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::atomic::Ordering,
+};
+
+use super::{
+    checkpoint::FileDeletionsGuard,
+    template_impl::{DBImpl, TemplateDB},
+};
+use crate::{
+    error::{Operation, TemplateKVError, TemplateResult},
+    manager::{
+        filename::{FileType, generate_filename},
+        version_edit::{BlobFileMeta, FileMetaData},
+    },
+    memtable::key_format::InternalKey,
+    sstable::sst_dump::SstFileDumper,
+    storage::{File, Storage, file_checksum},
+    util::comparator::Comparator,
+};
+
+/// The problems found in a live sst file or blob file
+#[derive(Debug, Clone)]
+pub struct FileCorruption {
+    /// The level of an sst file, or `None` for a blob file
+    pub level: Option<usize>,
+    pub file_number: u64,
+    /// Every error found in the file. The errors of a block carry the offset of the block.
+    pub errors: Vec<TemplateKVError>,
+}
+
+/// The result of verifying the live sst files and blob files of a db
+#[derive(Debug, Clone, Default)]
+pub struct VerificationReport {
+    /// The number of the files checked
+    pub num_files: usize,
+    /// The number of the blocks whose checksums are verified
+    pub num_blocks: u64,
+    pub corrupted_files: Vec<FileCorruption>,
+}
+
+impl VerificationReport {
+    /// Returns true if no problem is found
+    #[inline]
+    pub fn is_ok(&self) -> bool {
+        self.corrupted_files.is_empty()
+    }
+}
+
+impl Display for VerificationReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} files, {} blocks verified, {} corrupted files",
+            self.num_files,
+            self.num_blocks,
+            self.corrupted_files.len()
+        )?;
+        for c in &self.corrupted_files {
+            match c.level {
+                Some(level) => write!(f, "\n  #{} at level {}:", c.file_number, level)?,
+                None => write!(f, "\n  blob #{}:", c.file_number)?,
+            }
+            for e in &c.errors {
+                write!(f, "\n    {}", e)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: Storage + Clone + 'static, C: Comparator + 'static> TemplateDB<S, C> {
+    /// Verifies every block of all the sst files in the current version against its checksum.
+    ///
+    /// Besides the block checksums, the whole file checksum, the size and the footer of each
+    /// file are validated and the first and the last keys in the file are cross-checked with
+    /// the key range recorded in the MANIFEST. All the problems found are collected in the
+    /// returned report instead of failing at the first one. The live blob files are verified
+    /// as `verify_file_checksums` does.
+    pub fn verify_checksums(&self) -> TemplateResult<VerificationReport> {
+        self.inner.verify_live_files(true)
+    }
+
+    /// Verifies all the sst files and blob files in the current version as a whole.
+    ///
+    /// The crc32 and the size of each whole file are compared with the ones recorded in the
+    /// MANIFEST. For the sst files, the footer, the index and meta blocks and the key range are
+    /// checked as well. Only the first and the last data blocks are parsed.
+    pub fn verify_file_checksums(&self) -> TemplateResult<VerificationReport> {
+        self.inner.verify_live_files(false)
+    }
+}
+
+impl<S: Storage + Clone + 'static, C: Comparator + 'static> DBImpl<S, C> {
+    fn verify_live_files(&self, all_blocks: bool) -> TemplateResult<VerificationReport> {
+        if self.is_shutting_down.load(Ordering::Acquire) {
+            return Err(TemplateKVError::DBClosed("verify checksums".to_owned()));
+        }
+        // Keep the files from being removed by compactions while they are being verified
+        let _guard = FileDeletionsGuard::new(self);
+        let current = self.versions.lock().unwrap().current();
+        let mut report = VerificationReport::default();
+        for (level, files) in current.files.iter().enumerate() {
+            for f in files {
+                report.num_files += 1;
+                let errors = self.verify_table(f, all_blocks, &mut report.num_blocks);
+                if !errors.is_empty() {
+                    report.corrupted_files.push(FileCorruption {
+                        level: Some(level),
+                        file_number: f.number,
+                        errors,
+                    });
+                }
+            }
+        }
+        for blob in current.blob_files.values() {
+            report.num_files += 1;
+            let errors = self.verify_blob_file(blob);
+            if !errors.is_empty() {
+                report.corrupted_files.push(FileCorruption {
+                    level: None,
+                    file_number: blob.number,
+                    errors,
+                });
+            }
+        }
+        if report.is_ok() {
+            info!("Verified db {}: {}", &self.db_path, &report);
+        } else {
+            warn!("Verified db {}: {}", &self.db_path, &report);
+        }
+        Ok(report)
+    }
+
+    // Returns all the errors found in the given table
+    fn verify_table(
+        &self,
+        meta: &FileMetaData,
+        all_blocks: bool,
+        num_blocks: &mut u64,
+    ) -> Vec<TemplateKVError> {
+        let file_name = generate_filename(&self.db_path, FileType::Table, meta.number);
+        let mut errors = vec![];
+        let mut on_err = |e: TemplateKVError| {
+            errors.push(
+                e.with_operation(Operation::VerifyTable)
+                    .with_file_number(meta.number)
+                    .with_path(&file_name),
+            )
+        };
+        let file = match self.env.open_read_only(&file_name) {
+            Ok(f) => f,
+            Err(e) => {
+                on_err(e);
+                return errors;
+            }
+        };
+        match file.len() {
+            Ok(len) if len != meta.file_size => on_err(TemplateKVError::Corruption(format!(
+                "file size {} does not match {} in MANIFEST",
+                len, meta.file_size
+            ))),
+            Err(e) => on_err(e),
+            _ => {}
+        }
+        if let Some(expected) = meta.file_checksum {
+            match file_checksum(&self.env, &file_name) {
+                Ok((checksum, _)) if checksum != expected => {
+                    on_err(TemplateKVError::Corruption(format!(
+                        "file checksum {:#010x} does not match {:#010x} in MANIFEST",
+                        checksum, expected
+                    )))
+                }
+                Err(e) => on_err(e),
+                _ => {}
+            }
+        }
+        let dumper = match SstFileDumper::open(file, true) {
+            Ok(d) => d,
+            Err(e) => {
+                on_err(e);
+                return errors;
+            }
+        };
+        match dumper.meta_index() {
+            Ok(entries) => {
+                // An empty meta index block is never written
+                if !entries.is_empty() {
+                    *num_blocks += 1;
+                }
+                for (_, handle) in entries {
+                    match dumper.verify_block(&handle) {
+                        Ok(()) => *num_blocks += 1,
+                        Err(e) => on_err(e),
+                    }
+                }
+            }
+            Err(e) => on_err(e),
+        }
+        let index = match dumper.index() {
+            Ok(index) => {
+                *num_blocks += 1 + dumper.index_partitions().len() as u64;
+                index
+            }
+            Err(e) => {
+                on_err(e);
+                return errors;
+            }
+        };
+        let mut smallest = None;
+        let mut largest = None;
+        let last = index.len().saturating_sub(1);
+        for (i, (_, handle)) in index.iter().enumerate() {
+            if !all_blocks && i != 0 && i != last {
+                continue;
+            }
+            let mut first_key = None;
+            let mut last_key = None;
+            let res = dumper.for_each_in_block(handle, |key, _| {
+                if first_key.is_none() {
+                    first_key = Some(key.to_vec());
+                }
+                last_key = Some(key.to_vec());
+                Ok(())
+            });
+            match res {
+                Ok(()) => {
+                    *num_blocks += 1;
+                    if i == 0 {
+                        smallest = first_key;
+                    }
+                    if i == last {
+                        largest = last_key;
+                    }
+                }
+                Err(e) => on_err(e),
+            }
+        }
+        if let Some(k) = smallest {
+            if k.as_slice() != meta.smallest.data() {
+                on_err(TemplateKVError::Corruption(format!(
+                    "smallest key {:?} does not match {:?} in MANIFEST",
+                    InternalKey::decoded_from(&k),
+                    &meta.smallest
+                )));
+            }
+        }
+        if let Some(k) = largest {
+            if k.as_slice() != meta.largest.data() {
+                on_err(TemplateKVError::Corruption(format!(
+                    "largest key {:?} does not match {:?} in MANIFEST",
+                    InternalKey::decoded_from(&k),
+                    &meta.largest
+                )));
+            }
+        }
+        errors
+    }
+    // Returns all the errors found in the given blob file, whose records are only checked as
+    // a whole
+    fn verify_blob_file(&self, meta: &BlobFileMeta) -> Vec<TemplateKVError> {
+        let file_name = generate_filename(&self.db_path, FileType::Blob, meta.number);
+        let mut errors = vec![];
+        let mut on_err = |e: TemplateKVError| {
+            errors.push(
+                e.with_operation(Operation::VerifyBlob)
+                    .with_file_number(meta.number)
+                    .with_path(&file_name),
+            )
+        };
+        match file_checksum(&self.env, &file_name) {
+            Ok((checksum, len)) => {
+                if len != meta.file_size {
+                    on_err(TemplateKVError::Corruption(format!(
+                        "file size {} does not match {} in MANIFEST",
+                        len, meta.file_size
+                    )));
+                }
+                if checksum != meta.file_checksum {
+                    on_err(TemplateKVError::Corruption(format!(
+                        "file checksum {:#010x} does not match {:#010x} in MANIFEST",
+                        checksum, meta.file_checksum
+                    )));
+                }
+            }
+            Err(e) => on_err(e),
+        }
+        errors
+    }
+}
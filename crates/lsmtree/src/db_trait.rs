@@ -5,6 +5,7 @@ use crate::{
     manager::snapshot::Snapshot,
     memtable::batch::WriteBatch,
     options::{ReadOptions, WriteOptions},
+    util::pinnable_slice::PinnableSlice,
 };
 
 /// A `DB` is a persistent ordered map from keys to values.
@@ -22,6 +23,26 @@ pub trait DB {
     /// does not contain the key.
     fn get(&self, read_opt: ReadOptions, key: &[u8]) -> TemplateResult<Option<Vec<u8>>>;
 
+    /// `get_pinned` is the same as `get` but the value found in a memtable or a data block
+    /// is not copied. The returned `PinnableSlice` refers to the memtable entry or the block
+    /// and keeps it alive while it's held.
+    fn get_pinned(
+        &self,
+        read_opt: ReadOptions,
+        key: &[u8],
+    ) -> TemplateResult<Option<PinnableSlice>>;
+
+    /// `multi_get` gets the values for the given keys from the same consistent view of the
+    /// DB, which is the snapshot in `read_opt` if any. It returns one result per key in the
+    /// order of `keys`, which is `None` if the DB does not contain the key.
+    ///
+    /// The lookups are batched, so it is more efficient than calling `get` for every key.
+    fn multi_get(
+        &self,
+        read_opt: ReadOptions,
+        keys: &[&[u8]],
+    ) -> TemplateResult<Vec<Option<Vec<u8>>>>;
+
     /// Return an iterator over the contents of the database.
     fn iter(&self, read_opt: ReadOptions) -> TemplateResult<Self::Iterator>;
 
@@ -1,3 +1,9 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    io,
+    path::{Path, PathBuf},
+};
+
 use crossbeam_channel::RecvError;
 use quick_error::quick_error;
 
@@ -13,6 +19,7 @@ quick_error! {
         }
         UTF8Error(err: std::string::FromUtf8Error) {
             display("UTF8 error: {:?}", err)
+            source(err)
         }
         InvalidArgument(hint: String) {
             display("invalid argument: {}", hint)
@@ -22,16 +29,255 @@ quick_error! {
         }
         CompressionFailed(err: snap::Error) {
             display("compression failed: {}", err)
+            source(err)
         }
         IO(err: std::io::Error) {
             display("I/O operation error: {}", err)
+            source(err)
         }
         RecvError(err: RecvError) {
             display("{:?}", err)
+            source(err)
         }
         Customized(hint: String) {
             display("{}", hint)
         }
+        /// An error decorated with the operation and the file it happened on
+        Context(context: ErrorContext, err: Box<TemplateKVError>) {
+            display("{} [{}]", err, context)
+            source(&**err)
+        }
+    }
+}
+
+/// The operation during which an error is raised
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Operation {
+    OpenDB,
+    Recover,
+    ReplayLog,
+    Write,
+    Get,
+    Iterate,
+    FlushMemTable,
+    Compaction,
+    OpenTable,
+    ReadBlock,
+    ReadBlob,
+    BuildTable,
+    ReadManifest,
+    WriteManifest,
+    CreateFile,
+    OpenFile,
+    RemoveFile,
+    RenameFile,
+    ListDir,
+    Repair,
+    VerifyTable,
+    VerifyBlob,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let s = match self {
+            Operation::OpenDB => "open db",
+            Operation::Recover => "recover",
+            Operation::ReplayLog => "replay log",
+            Operation::Write => "write",
+            Operation::Get => "get",
+            Operation::Iterate => "iterate",
+            Operation::FlushMemTable => "flush memtable",
+            Operation::Compaction => "compaction",
+            Operation::OpenTable => "open table",
+            Operation::ReadBlock => "read block",
+            Operation::ReadBlob => "read blob",
+            Operation::BuildTable => "build table",
+            Operation::ReadManifest => "read manifest",
+            Operation::WriteManifest => "write manifest",
+            Operation::CreateFile => "create file",
+            Operation::OpenFile => "open file",
+            Operation::RemoveFile => "remove file",
+            Operation::RenameFile => "rename file",
+            Operation::ListDir => "list dir",
+            Operation::Repair => "repair",
+            Operation::VerifyTable => "verify table",
+            Operation::VerifyBlob => "verify blob",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Where an error happened. Every field is optional and filled in by the
+/// layers the error passes through.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ErrorContext {
+    pub operation: Option<Operation>,
+    pub file_number: Option<u64>,
+    pub path: Option<PathBuf>,
+    pub offset: Option<u64>,
+}
+
+impl ErrorContext {
+    // Fill the missing fields by `other`. The fields already set are more
+    // specific since they are recorded closer to where the error is raised.
+    fn merge(&mut self, other: ErrorContext) {
+        if self.operation.is_none() {
+            self.operation = other.operation;
+        }
+        if self.file_number.is_none() {
+            self.file_number = other.file_number;
+        }
+        if self.path.is_none() {
+            self.path = other.path;
+        }
+        if self.offset.is_none() {
+            self.offset = other.offset;
+        }
+    }
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut sep = "";
+        if let Some(op) = &self.operation {
+            write!(f, "op: {}", op)?;
+            sep = ", ";
+        }
+        if let Some(n) = &self.file_number {
+            write!(f, "{}file: #{}", sep, n)?;
+            sep = ", ";
+        }
+        if let Some(p) = &self.path {
+            write!(f, "{}path: {}", sep, p.display())?;
+            sep = ", ";
+        }
+        if let Some(o) = &self.offset {
+            write!(f, "{}offset: {}", sep, o)?;
+        }
+        Ok(())
+    }
+}
+
+impl TemplateKVError {
+    /// Returns the error without any context decoration
+    pub fn root(&self) -> &TemplateKVError {
+        match self {
+            TemplateKVError::Context(_, err) => err.root(),
+            _ => self,
+        }
+    }
+
+    /// Returns the recorded context if there is any
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            TemplateKVError::Context(ctx, _) => Some(ctx),
+            _ => None,
+        }
+    }
+
+    pub fn operation(&self) -> Option<Operation> {
+        self.context().and_then(|c| c.operation)
+    }
+
+    pub fn file_number(&self) -> Option<u64> {
+        self.context().and_then(|c| c.file_number)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.context().and_then(|c| c.path.as_deref())
+    }
+
+    pub fn offset(&self) -> Option<u64> {
+        self.context().and_then(|c| c.offset)
+    }
+
+    /// Returns true if the same request may succeed when it's issued again.
+    /// Transient I/O failures are retriable while corruptions, invalid
+    /// arguments and a closed db are not.
+    pub fn is_retriable(&self) -> bool {
+        match self.root() {
+            TemplateKVError::IO(e) => matches!(
+                e.kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
+
+    /// Returns true if the error is caused by malformed persistent data
+    pub fn is_corruption(&self) -> bool {
+        matches!(
+            self.root(),
+            TemplateKVError::Corruption(_)
+                | TemplateKVError::UTF8Error(_)
+                | TemplateKVError::CompressionFailed(_)
+        )
+    }
+
+    /// Returns true if the error indicates a missing or deleted key
+    pub fn is_not_found(&self) -> bool {
+        matches!(self.root(), TemplateKVError::NotFound(_))
+    }
+
+    pub fn with_operation(self, op: Operation) -> Self {
+        self.with_context(ErrorContext {
+            operation: Some(op),
+            ..Default::default()
+        })
+    }
+
+    pub fn with_file_number(self, file_number: u64) -> Self {
+        self.with_context(ErrorContext {
+            file_number: Some(file_number),
+            ..Default::default()
+        })
+    }
+
+    pub fn with_path<P: AsRef<Path>>(self, path: P) -> Self {
+        self.with_context(ErrorContext {
+            path: Some(path.as_ref().to_owned()),
+            ..Default::default()
+        })
+    }
+
+    pub fn with_offset(self, offset: u64) -> Self {
+        self.with_context(ErrorContext {
+            offset: Some(offset),
+            ..Default::default()
+        })
+    }
+
+    /// Attaches `context` to the error. Fields that have been recorded already are kept.
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        match self {
+            TemplateKVError::Context(mut ctx, err) => {
+                ctx.merge(context);
+                TemplateKVError::Context(ctx, err)
+            }
+            err => TemplateKVError::Context(context, Box::new(err)),
+        }
+    }
+}
+
+impl Clone for TemplateKVError {
+    // `io::Error` is not cloneable so the copy keeps the error kind and message only
+    fn clone(&self) -> Self {
+        match self {
+            TemplateKVError::NotFound(hint) => TemplateKVError::NotFound(hint.clone()),
+            TemplateKVError::Corruption(hint) => TemplateKVError::Corruption(hint.clone()),
+            TemplateKVError::UTF8Error(e) => TemplateKVError::UTF8Error(e.clone()),
+            TemplateKVError::InvalidArgument(hint) => {
+                TemplateKVError::InvalidArgument(hint.clone())
+            }
+            TemplateKVError::DBClosed(hint) => TemplateKVError::DBClosed(hint.clone()),
+            TemplateKVError::CompressionFailed(e) => TemplateKVError::CompressionFailed(e.clone()),
+            TemplateKVError::IO(e) => TemplateKVError::IO(io::Error::new(e.kind(), e.to_string())),
+            TemplateKVError::RecvError(e) => TemplateKVError::RecvError(*e),
+            TemplateKVError::Customized(hint) => TemplateKVError::Customized(hint.clone()),
+            TemplateKVError::Context(ctx, e) => {
+                TemplateKVError::Context(ctx.clone(), Box::new((**e).clone()))
+            }
+        }
     }
 }
 
@@ -45,3 +291,78 @@ macro_rules! map_io_res {
 }
 
 pub type TemplateResult<T> = std::result::Result<T, TemplateKVError>;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::*;
+
+    #[test]
+    fn test_context_accumulation() {
+        let e = TemplateKVError::Corruption("block checksum mismatch".to_owned())
+            .with_offset(4096)
+            .with_operation(Operation::ReadBlock)
+            .with_file_number(12)
+            .with_path("db/000012.sst")
+            // the inner operation is kept
+            .with_operation(Operation::Get);
+        assert_eq!(e.operation(), Some(Operation::ReadBlock));
+        assert_eq!(e.file_number(), Some(12));
+        assert_eq!(e.offset(), Some(4096));
+        assert_eq!(e.path(), Some(Path::new("db/000012.sst")));
+        assert!(e.is_corruption());
+        assert!(!e.is_retriable());
+        assert!(matches!(e.root(), TemplateKVError::Corruption(_)));
+        assert_eq!(
+            e.to_string(),
+            "data corruption: block checksum mismatch [op: read block, file: #12, path: db/000012.sst, offset: 4096]"
+        );
+        // the source chain reaches the undecorated error
+        assert!(
+            e.source()
+                .unwrap()
+                .to_string()
+                .starts_with("data corruption")
+        );
+    }
+
+    #[test]
+    fn test_classification() {
+        let tests = vec![
+            (
+                TemplateKVError::IO(io::Error::new(io::ErrorKind::Interrupted, "")),
+                true,
+                false,
+            ),
+            (
+                TemplateKVError::IO(io::Error::new(io::ErrorKind::TimedOut, "")),
+                true,
+                false,
+            ),
+            (
+                TemplateKVError::IO(io::Error::new(io::ErrorKind::NotFound, "")),
+                false,
+                false,
+            ),
+            (TemplateKVError::Corruption("".to_owned()), false, true),
+            (
+                TemplateKVError::InvalidArgument("".to_owned()),
+                false,
+                false,
+            ),
+            (TemplateKVError::DBClosed("".to_owned()), false, false),
+            (TemplateKVError::Customized("".to_owned()), false, false),
+            (TemplateKVError::NotFound(None), false, false),
+        ];
+        for (e, retriable, corruption) in tests {
+            let e = e.with_operation(Operation::Write);
+            assert_eq!(e.is_retriable(), retriable, "{}", e);
+            assert_eq!(e.is_corruption(), corruption, "{}", e);
+            let cloned = e.clone();
+            assert_eq!(cloned.to_string(), e.to_string());
+            assert_eq!(cloned.is_retriable(), retriable);
+            assert_eq!(cloned.context(), e.context());
+        }
+    }
+}
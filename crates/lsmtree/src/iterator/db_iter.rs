@@ -11,8 +11,9 @@ use crate::{
         key_format::{InternalKey, ParsedInternalKey, extract_user_key},
         value_format::{VALUE_TYPE_FOR_SEEK, ValueType},
     },
+    options::{MAX_KEY_SEQUENCE, ReadOptions},
     storage::Storage,
-    util::comparator::Comparator,
+    util::{comparator::Comparator, slice_transform::SliceTransform},
 };
 
 /// Memtables and sstables that make the DB representation contain
@@ -37,6 +38,16 @@ pub struct DBIterator<I: Iterator, S: Storage + Clone + 'static, C: Comparator>
     saved_key: Vec<u8>,
     // Current value when direction is Reverse
     saved_value: Vec<u8>,
+    // Current value read from the blob file when direction is Forward
+    blob_value: Option<Vec<u8>>,
+    // Set if the iterator stops at the boundary of the prefix of the last seek
+    prefix_extractor: Option<Arc<dyn SliceTransform>>,
+    // The prefix of the target of the last seek
+    prefix: Option<Vec<u8>>,
+    // The inclusive lower bound of the user keys
+    lower_bound: Option<Vec<u8>>,
+    // The exclusive upper bound of the user keys
+    upper_bound: Option<Vec<u8>>,
 }
 
 impl<I: Iterator, S: Storage + Clone, C: Comparator + 'static> Iterator for DBIterator<I, S, C> {
@@ -46,8 +57,15 @@ impl<I: Iterator, S: Storage + Clone, C: Comparator + 'static> Iterator for DBIt
 
     fn seek_to_first(&mut self) {
         self.direction = Direction::Forward;
+        self.prefix = None;
         self.saved_value.clear();
-        self.inner.seek_to_first();
+        match &self.lower_bound {
+            Some(lower) => {
+                let ikey = ParsedInternalKey::new(lower, self.sequence, VALUE_TYPE_FOR_SEEK);
+                self.inner.seek(ikey.encode().data());
+            }
+            None => self.inner.seek_to_first(),
+        }
         if self.inner.valid() {
             self.find_next_user_entry(false);
         } else {
@@ -57,8 +75,21 @@ impl<I: Iterator, S: Storage + Clone, C: Comparator + 'static> Iterator for DBIt
 
     fn seek_to_last(&mut self) {
         self.direction = Direction::Reverse;
+        self.prefix = None;
         self.saved_value.clear();
-        self.inner.seek_to_last();
+        match &self.upper_bound {
+            Some(upper) => {
+                // Position at the last entry before all the entries of `upper`
+                let ikey = ParsedInternalKey::new(upper, MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK);
+                self.inner.seek(ikey.encode().data());
+                if self.inner.valid() {
+                    self.inner.prev();
+                } else {
+                    self.inner.seek_to_last();
+                }
+            }
+            None => self.inner.seek_to_last(),
+        }
         self.find_prev_user_key();
     }
 
@@ -66,6 +97,15 @@ impl<I: Iterator, S: Storage + Clone, C: Comparator + 'static> Iterator for DBIt
         self.direction = Direction::Forward;
         self.saved_value.clear();
         self.saved_key.clear();
+        self.prefix = self
+            .prefix_extractor
+            .as_ref()
+            .filter(|pe| pe.in_domain(target))
+            .map(|pe| pe.transform(target).to_vec());
+        let target = match &self.lower_bound {
+            Some(lower) if self.ucmp.compare(target, lower) == Ordering::Less => lower.as_slice(),
+            _ => target,
+        };
         let ikey = ParsedInternalKey::new(target, self.sequence, VALUE_TYPE_FOR_SEEK).encode();
         self.inner.seek(ikey.data());
         if self.inner.valid() {
@@ -144,7 +184,10 @@ impl<I: Iterator, S: Storage + Clone, C: Comparator + 'static> Iterator for DBIt
     fn value(&self) -> &[u8] {
         self.valid_or_panic();
         match self.direction {
-            Direction::Forward => self.inner.value(),
+            Direction::Forward => match &self.blob_value {
+                Some(v) => v,
+                None => self.inner.value(),
+            },
             Direction::Reverse => &self.saved_value,
         }
     }
@@ -159,7 +202,22 @@ impl<I: Iterator, S: Storage + Clone, C: Comparator + 'static> Iterator for DBIt
 }
 
 impl<I: Iterator, S: Storage + Clone, C: Comparator + 'static> DBIterator<I, S, C> {
-    pub fn new(iter: I, db: Arc<DBImpl<S, C>>, sequence: u64, ucmp: C) -> Self {
+    /// Creates a `DBIterator` yielding the entries visible at `sequence` within the iterate
+    /// bounds of `read_opt`. If `read_opt.prefix_same_as_start` is true and the DB has a prefix
+    /// extractor, the iterator becomes invalid once it leaves the prefix of the target of the
+    /// last seek.
+    pub fn new(
+        iter: I,
+        db: Arc<DBImpl<S, C>>,
+        sequence: u64,
+        ucmp: C,
+        read_opt: &ReadOptions,
+    ) -> Self {
+        let prefix_extractor = db
+            .options
+            .prefix_extractor
+            .clone()
+            .filter(|_| read_opt.prefix_same_as_start);
         Self {
             valid: false,
             db: db.clone(),
@@ -171,6 +229,38 @@ impl<I: Iterator, S: Storage + Clone, C: Comparator + 'static> DBIterator<I, S,
             bytes_util_read_sampling: random_compaction_period(db.options.read_bytes_period),
             saved_key: Default::default(),
             saved_value: Default::default(),
+            blob_value: None,
+            prefix_extractor,
+            prefix: None,
+            lower_bound: read_opt.iterate_lower_bound.clone(),
+            upper_bound: read_opt.iterate_upper_bound.clone(),
+        }
+    }
+
+    // Returns true if the user key is not less than the upper bound
+    #[inline]
+    fn reaches_upper_bound(&self, user_key: &[u8]) -> bool {
+        self.upper_bound
+            .as_ref()
+            .is_some_and(|b| self.ucmp.compare(user_key, b) != Ordering::Less)
+    }
+
+    // Returns true if the user key is less than the lower bound
+    #[inline]
+    fn below_lower_bound(&self, user_key: &[u8]) -> bool {
+        self.lower_bound
+            .as_ref()
+            .is_some_and(|b| self.ucmp.compare(user_key, b) == Ordering::Less)
+    }
+
+    // Returns true if the user key doesn't have the prefix of the last seek
+    #[inline]
+    fn out_of_prefix(&self, user_key: &[u8]) -> bool {
+        match (&self.prefix_extractor, &self.prefix) {
+            (Some(pe), Some(prefix)) => {
+                !pe.in_domain(user_key) || pe.transform(user_key) != prefix.as_slice()
+            }
+            _ => false,
         }
     }
 
@@ -202,9 +292,12 @@ impl<I: Iterator, S: Storage + Clone, C: Comparator + 'static> DBIterator<I, S,
         loop {
             let saved_key = self.saved_key.clone();
             if let Some(pkey) = self.parse_key().parsed() {
+                if self.out_of_prefix(pkey.user_key) || self.reaches_upper_bound(pkey.user_key) {
+                    break;
+                }
                 if pkey.seq <= seq {
                     match pkey.value_type {
-                        ValueType::Value => {
+                        t @ (ValueType::Value | ValueType::BlobIndex) => {
                             if skipping
                                 && ucmp.compare(pkey.user_key, saved_key.as_slice())
                                     != Ordering::Greater
@@ -212,6 +305,16 @@ impl<I: Iterator, S: Storage + Clone, C: Comparator + 'static> DBIterator<I, S,
                                 // not greater than saved_key, so the key is skipped
                             } else {
                                 // Found the next user key
+                                self.blob_value = None;
+                                if t == ValueType::BlobIndex {
+                                    match self.db.get_blob_value(self.inner.value()) {
+                                        Ok(v) => self.blob_value = Some(v),
+                                        Err(e) => {
+                                            self.err = Some(e);
+                                            break;
+                                        }
+                                    }
+                                }
                                 self.valid = true;
                                 if !self.saved_key.is_empty() {
                                     self.saved_key.clear();
@@ -253,8 +356,11 @@ impl<I: Iterator, S: Storage + Clone, C: Comparator + 'static> DBIterator<I, S,
             loop {
                 let saved_key = self.saved_key.clone();
                 if let Some(pkey) = self.parse_key().parsed() {
+                    if self.out_of_prefix(pkey.user_key) || self.below_lower_bound(pkey.user_key) {
+                        break;
+                    }
                     if pkey.seq <= seq {
-                        if value_type == ValueType::Value
+                        if matches!(value_type, ValueType::Value | ValueType::BlobIndex)
                             && ucmp.compare(pkey.user_key, saved_key.as_slice()) == Ordering::Less
                         {
                             // found the key that less than
@@ -266,13 +372,13 @@ impl<I: Iterator, S: Storage + Clone, C: Comparator + 'static> DBIterator<I, S,
                                 self.saved_key.clear();
                                 self.saved_value.clear();
                             }
-                            ValueType::Value => {
+                            ValueType::Value | ValueType::BlobIndex => {
                                 // record the current key for later comparing
                                 self.saved_key = Vec::from(extract_user_key(self.inner.key()));
                                 // record the current value for later yielding
                                 self.saved_value = self.inner.value().to_vec();
                             }
-                            _ => { /* ignore the unknown value type */ }
+                            ValueType::Unknown => { /* ignore the unknown value type */ }
                         }
                     }
                 }
@@ -282,7 +388,16 @@ impl<I: Iterator, S: Storage + Clone, C: Comparator + 'static> DBIterator<I, S,
                 }
             }
         }
-        if value_type != ValueType::Value {
+        if value_type == ValueType::BlobIndex {
+            match self.db.get_blob_value(&self.saved_value) {
+                Ok(v) => self.saved_value = v,
+                Err(e) => {
+                    self.err = Some(e);
+                    value_type = ValueType::Deletion;
+                }
+            }
+        }
+        if !matches!(value_type, ValueType::Value | ValueType::BlobIndex) {
             // We reach the end of inner iter but didn't find a valid user key
             self.valid = false;
             self.saved_key.clear();
@@ -396,18 +511,15 @@ impl<C: Comparator, M: Iterator, T: Iterator> KMergeCore for DBIteratorCore<C, M
     where
         F: FnMut(&mut dyn Iterator, &Self::Cmp),
     {
-        if n < self.mem_iters.len() {
-            for (i, child) in self.mem_iters.iter_mut().enumerate() {
-                if i != n {
-                    f(child as &mut dyn Iterator, &self.cmp)
-                }
+        let mem_len = self.mem_iters.len();
+        for (i, child) in self.mem_iters.iter_mut().enumerate() {
+            if i != n {
+                f(child as &mut dyn Iterator, &self.cmp)
             }
-        } else {
-            let current = n - self.mem_iters.len();
-            for (i, child) in self.table_iters.iter_mut().enumerate() {
-                if i != current {
-                    f(child as &mut dyn Iterator, &self.cmp)
-                }
+        }
+        for (i, child) in self.table_iters.iter_mut().enumerate() {
+            if i + mem_len != n {
+                f(child as &mut dyn Iterator, &self.cmp)
             }
         }
     }
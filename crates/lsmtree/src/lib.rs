@@ -24,6 +24,8 @@ extern crate thiserror;
 #[macro_use]
 pub mod error;
 
+pub mod backup;
+pub mod blob;
 pub mod cache;
 pub mod compaction;
 pub mod db_impl;
@@ -4,6 +4,7 @@ use log::{LevelFilter, Log, Metadata, Record};
 use slog::{Drain, Level, o};
 
 use crate::{
+    error::TemplateResult,
     manager::filename::{FileType, generate_filename},
     storage::{File, Storage},
 };
@@ -28,13 +29,15 @@ impl Logger {
     /// If `inner` is not `None`, use `inner` logger
     /// If `inner` is `None`
     ///     - In dev mode, use a std output
-    ///     - In release mode, use a storage specific file with name `LOG`
+    ///     - In release mode, use a storage specific file with name `LOG` if `create_info_log`
+    ///       is true, or a std error output otherwise
     pub fn new<S: Storage>(
         inner: Option<slog::Logger>,
         level: LevelFilter,
         storage: &S,
         db_path: &str,
-    ) -> Self {
+        create_info_log: bool,
+    ) -> TemplateResult<Self> {
         let inner = match inner {
             Some(l) => l,
             None => {
@@ -43,12 +46,16 @@ impl Logger {
                     let decorator = slog_term::TermDecorator::new().build();
                     let drain = Mutex::new(slog_term::FullFormat::new(decorator).build()).fuse();
                     slog::Logger::root(drain, o!())
+                } else if !create_info_log {
+                    // The `LOG` file belongs to the instance owning the db
+                    let decorator = slog_term::TermDecorator::new().stderr().build();
+                    let drain = Mutex::new(slog_term::FullFormat::new(decorator).build()).fuse();
+                    slog::Logger::root(drain, o!())
                 } else {
                     // Use a file `Log` to record all logs
                     // TODO: add file rotation
                     let file = storage
-                        .create(generate_filename(db_path, FileType::InfoLog, 0).as_str())
-                        .unwrap();
+                        .create(generate_filename(db_path, FileType::InfoLog, 0).as_str())?;
                     let drain = slog_async::Async::new(FileBasedDrain::new(file))
                         .build()
                         .fuse();
@@ -56,7 +63,7 @@ impl Logger {
                 }
             }
         };
-        Self { inner, level }
+        Ok(Self { inner, level })
     }
 }
 
@@ -159,7 +166,7 @@ mod tests {
     fn test_default_logger() {
         let s = MemStorage::default();
         let db_path = "test";
-        let logger = Logger::new(None, LevelFilter::Debug, &s, db_path);
+        let logger = Logger::new(None, LevelFilter::Debug, &s, db_path, true).unwrap();
         // Ignore the error if the logger have been set
         let _ = log::set_logger(Box::leak(Box::new(logger)));
         log::set_max_level(LevelFilter::Debug);
@@ -5,7 +5,7 @@ use crate::{
     storage::{Storage, do_write_string_to_file},
 };
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileType {
     /// `*.log` files guarantee crash consistency for DB.
     Log,
@@ -13,6 +13,8 @@ pub enum FileType {
     Lock,
     /// `*.sst` file.
     Table,
+    /// `*.blob` file holds the large values separated from the sst files.
+    Blob,
     /// `MANIFEST-*` file.
     Manifest,
     /// `CURRENT` file saves the current used manifest filename.
@@ -24,6 +26,8 @@ pub enum FileType {
     InfoLog,
     /// `LOG.old` file records the last runtime logs.
     OldInfoLog,
+    /// `IDENTITY` file holds the unique id of the db generated when it's created.
+    Identity,
 }
 
 /// Returns a filename for a certain `FileType` by given sequence number and a `dirname`.
@@ -44,6 +48,11 @@ pub fn generate_filename(dirname: &str, filetype: FileType, seq: u64) -> String
             .into_os_string()
             .into_string()
             .unwrap(),
+        FileType::Blob => dirname
+            .join(format!("{:06}.blob", seq))
+            .into_os_string()
+            .into_string()
+            .unwrap(),
         FileType::Manifest => dirname
             .join(format!("MANIFEST-{:06}", seq))
             .into_os_string()
@@ -65,6 +74,11 @@ pub fn generate_filename(dirname: &str, filetype: FileType, seq: u64) -> String
             .into_os_string()
             .into_string()
             .unwrap(),
+        FileType::Identity => dirname
+            .join("IDENTITY")
+            .into_os_string()
+            .into_string()
+            .unwrap(),
     }
 }
 
@@ -77,6 +91,7 @@ pub fn parse_filename<P: AsRef<Path>>(filename: P) -> Option<(FileType, u64)> {
     match file_stem.to_str() {
         Some("CURRENT") => Some((FileType::Current, 0)),
         Some("LOCK") => Some((FileType::Lock, 0)),
+        Some("IDENTITY") => Some((FileType::Identity, 0)),
         Some("LOG") => match path.file_name().unwrap_or_else(|| OsStr::new("")).to_str() {
             Some("LOG") => Some((FileType::InfoLog, 0)),
             Some("LOG.old") => Some((FileType::OldInfoLog, 0)),
@@ -105,6 +120,9 @@ pub fn parse_filename<P: AsRef<Path>>(filename: P) -> Option<(FileType, u64)> {
                     Some("sst") => {
                         return Some((FileType::Table, seq));
                     }
+                    Some("blob") => {
+                        return Some((FileType::Blob, seq));
+                    }
                     Some("dbtmp") => {
                         return Some((FileType::Temp, seq));
                     }
@@ -148,22 +166,26 @@ mod tests {
                 (FileType::Log, 10, "test\\000010.log"),
                 (FileType::Lock, 1, "test\\LOCK"),
                 (FileType::Table, 123, "test\\000123.sst"),
+                (FileType::Blob, 124, "test\\000124.blob"),
                 (FileType::Manifest, 9, "test\\MANIFEST-000009"),
                 (FileType::Current, 1, "test\\CURRENT"),
                 (FileType::Temp, 100, "test\\000100.dbtmp"),
                 (FileType::InfoLog, 1, "test\\LOG"),
                 (FileType::OldInfoLog, 1, "test\\LOG.old"),
+                (FileType::Identity, 0, "test\\IDENTITY"),
             ]
         } else {
             vec![
                 (FileType::Log, 10, "test/000010.log"),
                 (FileType::Lock, 1, "test/LOCK"),
                 (FileType::Table, 123, "test/000123.sst"),
+                (FileType::Blob, 124, "test/000124.blob"),
                 (FileType::Manifest, 9, "test/MANIFEST-000009"),
                 (FileType::Current, 1, "test/CURRENT"),
                 (FileType::Temp, 100, "test/000100.dbtmp"),
                 (FileType::InfoLog, 1, "test/LOG"),
                 (FileType::OldInfoLog, 1, "test/LOG.old"),
+                (FileType::Identity, 0, "test/IDENTITY"),
             ]
         };
 
@@ -180,11 +202,13 @@ mod tests {
                 ("a\\b\\c\\000123.log", Some((FileType::Log, 123))),
                 ("a\\b\\c\\LOCK", Some((FileType::Lock, 0))),
                 ("a\\b\\c\\010666.sst", Some((FileType::Table, 10666))),
+                ("a\\b\\c\\000042.blob", Some((FileType::Blob, 42))),
                 ("a\\b\\c\\MANIFEST-000009", Some((FileType::Manifest, 9))),
                 ("a\\b\\c\\000123.dbtmp", Some((FileType::Temp, 123))),
                 ("a\\b\\c\\CURRENT", Some((FileType::Current, 0))),
                 ("a\\b\\c\\LOG", Some((FileType::InfoLog, 0))),
                 ("a\\b\\c\\LOG.old", Some((FileType::OldInfoLog, 0))),
+                ("a\\b\\c\\IDENTITY", Some((FileType::Identity, 0))),
                 ("a\\b\\c\\test.123", None),
                 ("a\\b\\c\\LOG.", None),
                 ("a\\b\\c\\LOG.new", None),
@@ -198,11 +222,13 @@ mod tests {
                 ("a/b/c/000123.log", Some((FileType::Log, 123))),
                 ("a/b/c/LOCK", Some((FileType::Lock, 0))),
                 ("a/b/c/010666.sst", Some((FileType::Table, 10666))),
+                ("a/b/c/000042.blob", Some((FileType::Blob, 42))),
                 ("a/b/c/MANIFEST-000009", Some((FileType::Manifest, 9))),
                 ("a/b/c/000123.dbtmp", Some((FileType::Temp, 123))),
                 ("a/b/c/CURRENT", Some((FileType::Current, 0))),
                 ("a/b/c/LOG", Some((FileType::InfoLog, 0))),
                 ("a/b/c/LOG.old", Some((FileType::OldInfoLog, 0))),
+                ("a/b/c/IDENTITY", Some((FileType::Identity, 0))),
                 // invalid conditions
                 ("a/b/c/test.123", None),
                 ("a/b/c/LOG.", None),
@@ -1,6 +1,7 @@
 use std::{
     cell::{Cell, RefCell},
     cmp::Ordering as CmpOrdering,
+    collections::BTreeMap,
     fmt, mem,
     rc::Rc,
     sync::{
@@ -9,7 +10,12 @@ use std::{
     },
 };
 
-use super::{version_edit::FileMetaData, version_set::total_file_size};
+use bytes::Bytes;
+
+use super::{
+    version_edit::{BlobFileMeta, FileMetaData},
+    version_set::total_file_size,
+};
 use crate::{
     cache::table_cache::TableCache,
     error::{TemplateKVError, TemplateResult},
@@ -59,6 +65,9 @@ pub struct Version<C: Comparator> {
     // files per level in this version
     // sorted by the smallest key in FileMetaData
     pub files: Vec<Vec<Arc<FileMetaData>>>,
+    // live blob files in this version sorted by file number, which is also the order they are
+    // created in
+    pub blob_files: BTreeMap<u64, Arc<BlobFileMeta>>,
 
     // next file to compact based on seek stats
     // TODO: maybe use ShardLock from crossbeam instead.
@@ -87,6 +96,17 @@ impl<C: Comparator> fmt::Debug for Version<C> {
             }
             writeln!(f, " ]")?;
         }
+        if !self.blob_files.is_empty() {
+            write!(f, "blob files: [ ")?;
+            for blob in self.blob_files.values() {
+                write!(
+                    f,
+                    "Blob {}({}): {}/{} garbage, ",
+                    blob.number, blob.file_size, blob.garbage_count, blob.total_count
+                )?;
+            }
+            writeln!(f, " ]")?;
+        }
         Ok(())
     }
 }
@@ -103,6 +123,7 @@ impl<C: Comparator + 'static> Version<C> {
             options,
             icmp,
             files,
+            blob_files: BTreeMap::new(),
             file_to_compact: RwLock::new(None),
             file_to_compact_level: AtomicUsize::new(0),
             compaction_score: 0f32,
@@ -110,17 +131,129 @@ impl<C: Comparator + 'static> Version<C> {
         }
     }
 
-    /// Search the value by the given key in sstables level by level
+    /// Search the value by the given key in sstables level by level.
+    /// The value found is returned with its type, which is `ValueType::BlobIndex` if the value
+    /// is separated into a blob file. The value shares the data block it's in without copying.
+    #[allow(clippy::type_complexity)]
     pub fn get<S: Storage + Clone + 'static>(
         &self,
         options: ReadOptions,
         key: LookupKey,
         table_cache: &TableCache<S, C>,
-    ) -> TemplateResult<(Option<Vec<u8>>, Option<SeekStats>)> {
+    ) -> TemplateResult<(Option<(Bytes, ValueType)>, Option<SeekStats>)> {
+        let ikey = key.internal_key();
+        let mut seek_stats = None;
+        for (file, level) in self.files_to_seek(&key) {
+            if seek_stats.is_none() {
+                // TODO(fullstop000): leveldb only charge the first file for seek compaction
+                seek_stats = Some(SeekStats {
+                    file: file.clone(),
+                    level,
+                });
+            }
+            if let Some(block_iter) = table_cache.get(
+                self.icmp.clone(),
+                &options,
+                ikey,
+                file.number,
+                file.file_size,
+            )? {
+                match self.found_type(key.user_key(), block_iter.key())? {
+                    Some(t @ (ValueType::Value | ValueType::BlobIndex)) => {
+                        return Ok((Some((block_iter.value_bytes(), t)), seek_stats));
+                    }
+                    Some(ValueType::Deletion) => return Ok((None, seek_stats)),
+                    _ => {}
+                }
+            }
+        }
+        Ok((None, seek_stats))
+    }
+
+    /// Same as `get` for every key in `keys` but the lookups are batched. In every round,
+    /// the keys not found yet are grouped by the next file to search, and each file is
+    /// searched once for all the keys in the group by `TableCache::multi_get`.
+    ///
+    /// Returns the values in the order of `keys` along with the seek stats of all the keys.
+    #[allow(clippy::type_complexity)]
+    pub fn multi_get<S: Storage + Clone + 'static>(
+        &self,
+        options: &ReadOptions,
+        keys: &[LookupKey],
+        table_cache: &TableCache<S, C>,
+    ) -> TemplateResult<(Vec<Option<(Bytes, ValueType)>>, Vec<SeekStats>)> {
+        let files = keys
+            .iter()
+            .map(|key| self.files_to_seek(key))
+            .collect::<Vec<_>>();
+        let seek_stats = files
+            .iter()
+            .filter_map(|f| f.first())
+            .map(|(file, level)| SeekStats {
+                file: (*file).clone(),
+                level: *level,
+            })
+            .collect();
+        // The keys are batched in the order of the internal keys
+        let mut order = (0..keys.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| {
+            self.icmp
+                .compare(keys[*a].internal_key(), keys[*b].internal_key())
+        });
+        // the index of the next file to search for each key
+        let mut next = vec![0; keys.len()];
+        let mut values = vec![None; keys.len()];
+        loop {
+            let mut batches: BTreeMap<u64, (&Arc<FileMetaData>, Vec<usize>)> = BTreeMap::new();
+            for &i in &order {
+                if let Some((file, _)) = files[i].get(next[i]) {
+                    batches
+                        .entry(file.number)
+                        .or_insert((file, vec![]))
+                        .1
+                        .push(i);
+                }
+            }
+            if batches.is_empty() {
+                break;
+            }
+            for (file, indexes) in batches.into_values() {
+                let ikeys = indexes
+                    .iter()
+                    .map(|i| keys[*i].internal_key())
+                    .collect::<Vec<_>>();
+                let entries = table_cache.multi_get(
+                    self.icmp.clone(),
+                    options,
+                    &ikeys,
+                    file.number,
+                    file.file_size,
+                )?;
+                for (i, entry) in indexes.into_iter().zip(entries) {
+                    next[i] += 1;
+                    let Some((encoded_key, value)) = entry else {
+                        continue;
+                    };
+                    match self.found_type(keys[i].user_key(), &encoded_key)? {
+                        Some(t @ (ValueType::Value | ValueType::BlobIndex)) => {
+                            values[i] = Some((value, t));
+                        }
+                        Some(ValueType::Deletion) => {}
+                        _ => continue,
+                    }
+                    // Stop searching the key
+                    next[i] = files[i].len();
+                }
+            }
+        }
+        Ok((values, seek_stats))
+    }
+
+    // Returns the files which may contain `key` with their levels, in the order to search
+    fn files_to_seek(&self, key: &LookupKey) -> Vec<(&Arc<FileMetaData>, usize)> {
         let ikey = key.internal_key();
         let ukey = key.user_key();
         let ucmp = &self.icmp.user_comparator;
-        let mut seek_stats = None;
         let mut files_to_seek = vec![];
         for (level, files) in self.files.iter().enumerate() {
             if files.is_empty() {
@@ -148,59 +281,25 @@ impl<C: Comparator + 'static> Version<C> {
                     // `LookupKey` may contains the same `ukey` as the
                     // `smallest` but a bigger `seq` number than it, which is smaller
                     // in a comparison by `icmp`.
-                    if ucmp.compare(ukey, target.smallest.user_key()) != CmpOrdering::Less
-                        && level + 1 < self.options.max_levels
-                    {
+                    if ucmp.compare(ukey, target.smallest.user_key()) != CmpOrdering::Less {
                         files_to_seek.push((target, level));
                     }
                 }
             }
         }
         files_to_seek.sort_by(|(a, _), (b, _)| b.number.cmp(&a.number));
-        for (file, level) in files_to_seek {
-            if seek_stats.is_none() {
-                // TODO(fullstop000): leveldb only charge the first file for seek compaction
-                seek_stats = Some(SeekStats {
-                    file: file.clone(),
-                    level,
-                });
-            }
-            match table_cache.get(
-                self.icmp.clone(),
-                options,
-                ikey,
-                file.number,
-                file.file_size,
-            )? {
-                None => continue,
-                Some(block_iter) => {
-                    let encoded_key = block_iter.key();
-                    let value = block_iter.value();
-                    match ParsedInternalKey::decode_from(encoded_key) {
-                        None => {
-                            return Err(TemplateKVError::Corruption("bad internal key".to_owned()));
-                        }
-                        Some(parsed_key) => {
-                            if self
-                                .options
-                                .comparator
-                                .compare(parsed_key.user_key, key.user_key())
-                                == CmpOrdering::Equal
-                            {
-                                match parsed_key.value_type {
-                                    ValueType::Value => {
-                                        return Ok((Some(value.to_vec()), seek_stats));
-                                    }
-                                    ValueType::Deletion => return Ok((None, seek_stats)),
-                                    _ => {}
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+        files_to_seek
+    }
+
+    // Returns the type of the entry with `encoded_key` found in a table by the search for
+    // `ukey`, or `None` if the entry is of another user key
+    fn found_type(&self, ukey: &[u8], encoded_key: &[u8]) -> TemplateResult<Option<ValueType>> {
+        match ParsedInternalKey::decode_from(encoded_key) {
+            None => Err(TemplateKVError::Corruption("bad internal key".to_owned())),
+            Some(parsed_key) => Ok((self.options.comparator.compare(parsed_key.user_key, ukey)
+                == CmpOrdering::Equal)
+                .then_some(parsed_key.value_type)),
         }
-        Ok((None, seek_stats))
     }
 
     /// Update seek stats for a sstable file. If it runs out of `allow_seek`,
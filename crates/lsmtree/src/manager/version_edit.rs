@@ -24,6 +24,10 @@ enum Tag {
     NewFile = 7,
     // 8 was used for large value refs
     PrevLogNumber = 9,
+    // Not understood by the versions before table checksums were added
+    FileChecksum = 10,
+    BlobFileAddition = 11,
+    BlobFileGarbage = 12,
     Unknown, // unknown tag
 }
 
@@ -38,6 +42,9 @@ impl From<u32> for Tag {
             6 => Tag::DeletedFile,
             7 => Tag::NewFile,
             9 => Tag::PrevLogNumber,
+            10 => Tag::FileChecksum,
+            11 => Tag::BlobFileAddition,
+            12 => Tag::BlobFileGarbage,
             _ => Tag::Unknown,
         }
     }
@@ -64,6 +71,9 @@ pub struct FileMetaData {
     pub smallest: InternalKey,
     // Largest internal key served by table
     pub largest: InternalKey,
+    // crc32 of the whole file. `None` if the file is added by a MANIFEST
+    // written before the checksums are recorded
+    pub file_checksum: Option<u32>,
 }
 
 impl FileMetaData {
@@ -97,10 +107,37 @@ impl Default for FileMetaData {
             number: 0,
             smallest: InternalKey::default(),
             largest: InternalKey::default(),
+            file_checksum: None,
         }
     }
 }
 
+/// Represent a blob file holding the values separated from the sst files.
+/// A blob file is obsolete once all the values in it become garbage.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct BlobFileMeta {
+    // the file number
+    pub number: u64,
+    // The number and the total size of the values in the file
+    pub total_count: u64,
+    pub total_bytes: u64,
+    // The number and the total size of the values no longer referred by any sst file
+    pub garbage_count: u64,
+    pub garbage_bytes: u64,
+    // File size in bytes
+    pub file_size: u64,
+    // crc32 of the whole file
+    pub file_checksum: u32,
+}
+
+impl BlobFileMeta {
+    /// Returns true if no value in the file is referred any more
+    #[inline]
+    pub fn is_obsolete(&self) -> bool {
+        self.garbage_count >= self.total_count
+    }
+}
+
 /// The diff files changes between versions
 #[derive(Default, Debug)]
 pub struct FileDelta {
@@ -110,6 +147,10 @@ pub struct FileDelta {
     pub deleted_files: HashSet<(usize, u64)>,
     // (level, FileMetaData)
     pub new_files: Vec<(usize, FileMetaData)>,
+    // the garbage counters of the new blob files are always zero
+    pub new_blob_files: Vec<BlobFileMeta>,
+    // (blob file number, garbage count, garbage bytes)
+    pub blob_garbage: Vec<(u64, u64, u64)>,
 }
 
 /// A summary for version updating
@@ -141,6 +182,8 @@ impl VersionEdit {
                 deleted_files: HashSet::default(),
                 new_files: Vec::new(),
                 compaction_pointers: Vec::new(),
+                new_blob_files: Vec::new(),
+                blob_garbage: Vec::new(),
             },
         }
     }
@@ -155,6 +198,8 @@ impl VersionEdit {
         self.last_sequence = None;
         self.file_delta.deleted_files.clear();
         self.file_delta.new_files.clear();
+        self.file_delta.new_blob_files.clear();
+        self.file_delta.blob_garbage.clear();
         // NOTICE: compaction pointers are not cleared here
     }
 
@@ -166,6 +211,7 @@ impl VersionEdit {
         file_size: u64,
         smallest: InternalKey,
         largest: InternalKey,
+        file_checksum: Option<u32>,
     ) {
         self.file_delta.new_files.push((level, FileMetaData {
             allowed_seeks: AtomicUsize::new(0),
@@ -173,6 +219,7 @@ impl VersionEdit {
             number: file_number,
             smallest,
             largest,
+            file_checksum,
         }))
     }
 
@@ -182,6 +229,20 @@ impl VersionEdit {
         self.file_delta.deleted_files.insert((level, file_number));
     }
 
+    /// Add the specified blob file
+    #[inline]
+    pub fn add_blob_file(&mut self, meta: BlobFileMeta) {
+        self.file_delta.new_blob_files.push(meta);
+    }
+
+    /// Record `count` values with `bytes` in total in the blob file `file_number` as garbage
+    #[inline]
+    pub fn add_blob_garbage(&mut self, file_number: u64, count: u64, bytes: u64) {
+        self.file_delta
+            .blob_garbage
+            .push((file_number, count, bytes));
+    }
+
     #[inline]
     pub fn set_comparator_name(&mut self, name: String) {
         self.comparator_name = Some(name);
@@ -250,6 +311,31 @@ impl VersionEdit {
             VarintU64::put_varint(dst, file_meta.file_size);
             VarintU32::put_varint_prefixed_slice(dst, file_meta.smallest.data());
             VarintU32::put_varint_prefixed_slice(dst, file_meta.largest.data());
+            // Recorded in a separate tag so the new-file entry keeps its layout. This is a one-way
+            // format change: the versions before the tag was introduced reject the MANIFEST
+            // as corrupted once a file with a checksum is recorded.
+            if let Some(checksum) = file_meta.file_checksum {
+                VarintU32::put_varint(dst, Tag::FileChecksum as u32);
+                VarintU32::put_varint(dst, *level as u32);
+                VarintU64::put_varint(dst, file_meta.number);
+                VarintU32::put_varint(dst, checksum);
+            }
+        }
+
+        for meta in self.file_delta.new_blob_files.iter() {
+            VarintU32::put_varint(dst, Tag::BlobFileAddition as u32);
+            VarintU64::put_varint(dst, meta.number);
+            VarintU64::put_varint(dst, meta.total_count);
+            VarintU64::put_varint(dst, meta.total_bytes);
+            VarintU64::put_varint(dst, meta.file_size);
+            VarintU32::put_varint(dst, meta.file_checksum);
+        }
+
+        for (file_num, count, bytes) in self.file_delta.blob_garbage.iter() {
+            VarintU32::put_varint(dst, Tag::BlobFileGarbage as u32);
+            VarintU64::put_varint(dst, *file_num);
+            VarintU64::put_varint(dst, *count);
+            VarintU64::put_varint(dst, *bytes);
         }
     }
 
@@ -339,6 +425,7 @@ impl VersionEdit {
                                                     number,
                                                     smallest,
                                                     largest,
+                                                    file_checksum: None,
                                                 },
                                             ));
                                             continue;
@@ -350,6 +437,55 @@ impl VersionEdit {
                         msg.push_str("new-file entry");
                         break;
                     }
+                    Tag::FileChecksum => {
+                        // the checksum always follows the new-file entry of the same file
+                        if let Some(level) = get_level(self.max_levels, &mut s) {
+                            if let Some(number) = VarintU64::drain_read(&mut s) {
+                                if let Some(checksum) = VarintU32::drain_read(&mut s) {
+                                    let meta =
+                                        self.file_delta.new_files.iter_mut().rev().find(
+                                            |(l, f)| *l == level as usize && f.number == number,
+                                        );
+                                    if let Some((_, f)) = meta {
+                                        f.file_checksum = Some(checksum);
+                                        continue;
+                                    }
+                                }
+                            }
+                        }
+                        msg.push_str("file checksum");
+                        break;
+                    }
+                    Tag::BlobFileAddition => {
+                        let mut read = || -> Option<BlobFileMeta> {
+                            Some(BlobFileMeta {
+                                number: VarintU64::drain_read(&mut s)?,
+                                total_count: VarintU64::drain_read(&mut s)?,
+                                total_bytes: VarintU64::drain_read(&mut s)?,
+                                file_size: VarintU64::drain_read(&mut s)?,
+                                file_checksum: VarintU32::drain_read(&mut s)?,
+                                ..Default::default()
+                            })
+                        };
+                        if let Some(meta) = read() {
+                            self.file_delta.new_blob_files.push(meta);
+                            continue;
+                        }
+                        msg.push_str("blob file entry");
+                        break;
+                    }
+                    Tag::BlobFileGarbage => {
+                        if let Some(file_num) = VarintU64::drain_read(&mut s) {
+                            if let Some(count) = VarintU64::drain_read(&mut s) {
+                                if let Some(bytes) = VarintU64::drain_read(&mut s) {
+                                    self.file_delta.blob_garbage.push((file_num, count, bytes));
+                                    continue;
+                                }
+                            }
+                        }
+                        msg.push_str("blob garbage");
+                        break;
+                    }
                     Tag::PrevLogNumber => {
                         // decode pre log number
                         if let Some(pre_ln) = VarintU64::drain_read(&mut s) {
@@ -409,6 +545,23 @@ impl Debug for VersionEdit {
                 "\n  AddFile: @{} #{} {}bytes range: [{:?}, {:?}]",
                 level, meta.number, meta.file_size, meta.smallest, meta.largest
             )?;
+            if let Some(checksum) = meta.file_checksum {
+                write!(f, " checksum: {:#010x}", checksum)?;
+            }
+        }
+        for meta in self.file_delta.new_blob_files.iter() {
+            write!(
+                f,
+                "\n  AddBlobFile: #{} {} values {}bytes size: {} checksum: {:#010x}",
+                meta.number, meta.total_count, meta.total_bytes, meta.file_size, meta.file_checksum
+            )?;
+        }
+        for (file_num, count, bytes) in self.file_delta.blob_garbage.iter() {
+            write!(
+                f,
+                "\n  BlobGarbage: #{} {} values {}bytes",
+                file_num, count, bytes
+            )?;
         }
         write!(f, "\n}}\n")?;
         Ok(())
@@ -416,8 +569,7 @@ impl Debug for VersionEdit {
 }
 
 fn get_internal_key(src: &mut &[u8]) -> Option<InternalKey> {
-    // VarintU32::get_varint_prefixed_slice(src).map(|s| InternalKey::decoded_from(s))
-    Some(InternalKey::decoded_from(src))
+    VarintU32::get_varint_prefixed_slice(src).map(InternalKey::decoded_from)
 }
 
 fn get_level(max_levels: usize, src: &mut &[u8]) -> Option<u32> {
@@ -434,8 +586,8 @@ fn get_level(max_levels: usize, src: &mut &[u8]) -> Option<u32> {
 #[cfg(test)]
 mod tests {
     use crate::{
-        manager::version_edit::VersionEdit,
-        // memtable::key_format::InternalKey,
+        manager::version_edit::{BlobFileMeta, VersionEdit},
+        memtable::{key_format::InternalKey, value_format::ValueType},
     };
 
     fn assert_encode_decode(edit: &VersionEdit) {
@@ -480,6 +632,121 @@ mod tests {
     //     assert_encode_decode(&edit);
     // }
 
+    #[test]
+    fn test_encode_decode_new_files() {
+        let mut edit = VersionEdit::new(7);
+        let smallest = InternalKey::new(b"foo", 500, ValueType::Value);
+        let largest = InternalKey::new(b"zoo", 700, ValueType::Deletion);
+        edit.add_file(3, 300, 400, smallest.clone(), largest.clone(), None);
+        edit.add_file(
+            4,
+            301,
+            401,
+            InternalKey::new(b"a", 1, ValueType::Value),
+            InternalKey::new(b"b", 2, ValueType::Value),
+            None,
+        );
+        edit.delete_file(4, 700);
+        assert_encode_decode(&edit);
+        let mut encoded = vec![];
+        edit.encode_to(&mut encoded);
+        let mut parsed = VersionEdit::new(7);
+        parsed.decoded_from(&encoded).unwrap();
+        // The keys are varint-prefixed, so the fields after them are decoded too
+        let files = &parsed.file_delta.new_files;
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            (files[0].0, files[0].1.number, files[0].1.file_size),
+            (3, 300, 400)
+        );
+        assert!(files[0].1.smallest == smallest && files[0].1.largest == largest);
+        assert_eq!((files[1].0, files[1].1.number), (4, 301));
+    }
+
+    #[test]
+    fn test_encode_decode_file_checksum() {
+        let mut edit = VersionEdit::new(7);
+        edit.add_file(
+            1,
+            10,
+            100,
+            InternalKey::new(b"a", 1, ValueType::Value),
+            InternalKey::new(b"b", 2, ValueType::Value),
+            Some(0xdead_beef),
+        );
+        // a file recorded without checksum
+        edit.add_file(
+            2,
+            11,
+            200,
+            InternalKey::new(b"c", 3, ValueType::Value),
+            InternalKey::new(b"d", 4, ValueType::Deletion),
+            None,
+        );
+        assert_encode_decode(&edit);
+        let mut encoded = vec![];
+        edit.encode_to(&mut encoded);
+        let mut parsed = VersionEdit::new(7);
+        parsed.decoded_from(&encoded).unwrap();
+        let files = &parsed.file_delta.new_files;
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].1.file_checksum, Some(0xdead_beef));
+        assert_eq!(files[1].1.file_checksum, None);
+
+        // a checksum without the new-file entry is corrupted
+        let mut orphan = VersionEdit::new(7);
+        orphan.add_file(
+            1,
+            12,
+            100,
+            InternalKey::new(b"a", 1, ValueType::Value),
+            InternalKey::new(b"b", 2, ValueType::Value),
+            Some(1),
+        );
+        let mut encoded = vec![];
+        orphan.encode_to(&mut encoded);
+        let mut partial = VersionEdit::new(7);
+        partial.add_file(
+            1,
+            12,
+            100,
+            InternalKey::new(b"a", 1, ValueType::Value),
+            InternalKey::new(b"b", 2, ValueType::Value),
+            None,
+        );
+        let mut prefix = vec![];
+        partial.encode_to(&mut prefix);
+        assert!(partial.decoded_from(&encoded[prefix.len()..]).is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_blob_files() {
+        let mut edit = VersionEdit::new(7);
+        let meta = BlobFileMeta {
+            number: 12,
+            total_count: 100,
+            total_bytes: 1 << 40,
+            file_size: (1 << 40) + 800,
+            file_checksum: 0xdead_beef,
+            ..Default::default()
+        };
+        edit.add_blob_file(meta.clone());
+        edit.add_blob_garbage(12, 3, 3000);
+        edit.add_blob_garbage(9, 1, 10);
+        assert_encode_decode(&edit);
+        let mut encoded = vec![];
+        edit.encode_to(&mut encoded);
+        let mut parsed = VersionEdit::new(7);
+        parsed.decoded_from(&encoded).unwrap();
+        assert_eq!(parsed.file_delta.new_blob_files, vec![meta]);
+        assert_eq!(parsed.file_delta.blob_garbage, vec![
+            (12, 3, 3000),
+            (9, 1, 10)
+        ]);
+        // truncated entries are corrupted
+        assert!(parsed.decoded_from(&encoded[..encoded.len() - 1]).is_err());
+    }
+
     #[test]
     fn test_set_comparator_name() {
         let mut edit = VersionEdit::new(7);
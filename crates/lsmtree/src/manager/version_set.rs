@@ -14,15 +14,16 @@ use super::{
     filename::{parse_filename, update_current},
     snapshot::{Snapshot, SnapshotList},
     version::{FILE_META_LENGTH, LevelFileNumIterator, Version},
-    version_edit::{FileDelta, FileMetaData, VersionEdit},
+    version_edit::{BlobFileMeta, FileDelta, FileMetaData, VersionEdit},
 };
 use crate::{
+    blob::blob_file::BlobFileBuilder,
     cache::table_cache::TableCache,
     compaction::compact::{
         Compaction, CompactionInputs, CompactionReason, CompactionStats, base_range, total_range,
     },
     db_impl::template_impl::build_table,
-    error::{TemplateKVError, TemplateResult},
+    error::{Operation, TemplateKVError, TemplateResult},
     iterator::{
         Iterator,
         concatenate_iter::{ConcatenateIterator, DerivedIterFactory},
@@ -34,7 +35,10 @@ use crate::{
     sstable::table::{TableBuilder, TableIterator},
     storage::{File, Storage},
     util::{
-        coding::decode_fixed_64, collection::HashSet, comparator::Comparator, reporter::LogReporter,
+        coding::decode_fixed_64,
+        collection::{HashMap, HashSet},
+        comparator::Comparator,
+        reporter::LogReporter,
     },
     wal::{wal_record_reader::Reader, wal_record_writer::Writer},
 };
@@ -50,6 +54,10 @@ struct LevelDiff {
 pub struct VersionBuilder<'a, C: Comparator> {
     // file changes for every level
     levels: Vec<LevelDiff>,
+    // all new added blob files
+    added_blob_files: Vec<BlobFileMeta>,
+    // blob file number -> (garbage count, garbage bytes)
+    blob_garbage: HashMap<u64, (u64, u64)>,
     base: &'a Version<C>,
 }
 
@@ -63,7 +71,12 @@ impl<'a, C: Comparator + 'static> VersionBuilder<'a, C> {
                 added_files: vec![],
             })
         }
-        Self { levels, base }
+        Self {
+            levels,
+            added_blob_files: vec![],
+            blob_garbage: HashMap::default(),
+            base,
+        }
     }
 
     /// Add the given `FileDelta` for later applying
@@ -100,6 +113,12 @@ impl<'a, C: Comparator + 'static> VersionBuilder<'a, C> {
             self.levels[level].deleted_files.remove(&new_file.number);
             self.levels[level].added_files.push(new_file);
         }
+        self.added_blob_files.extend(delta.new_blob_files);
+        for (number, count, bytes) in delta.blob_garbage {
+            let garbage = self.blob_garbage.entry(number).or_default();
+            garbage.0 += count;
+            garbage.1 += bytes;
+        }
     }
 
     // Apply all the changes on the base Version and produce a new Version based on it
@@ -146,6 +165,25 @@ impl<'a, C: Comparator + 'static> VersionBuilder<'a, C> {
                 assert!(!Self::has_overlapping(icmp, &v.files[level]));
             }
         }
+        v.blob_files = self.base.blob_files.clone();
+        for meta in self.added_blob_files {
+            v.blob_files.insert(meta.number, Arc::new(meta));
+        }
+        for (number, (count, bytes)) in self.blob_garbage {
+            match v.blob_files.get_mut(&number) {
+                Some(meta) => {
+                    let mut updated = meta.as_ref().clone();
+                    updated.garbage_count += count;
+                    updated.garbage_bytes += bytes;
+                    if updated.is_obsolete() {
+                        v.blob_files.remove(&number);
+                    } else {
+                        *meta = Arc::new(updated);
+                    }
+                }
+                None => warn!("Garbage recorded for the unknown blob file #{}", number),
+            }
+        }
         v
     }
 
@@ -167,6 +205,8 @@ pub struct VersionSet<S: Storage + Clone, C: Comparator> {
     pub snapshots: SnapshotList,
     // Set of table files to protect them from deletion because they are part of ongoing compaction
     pub pending_outputs: HashSet<u64>,
+    // The output level and the key range of the compaction running without holding the lock
+    pub running_compaction: Option<(usize, InternalKey, InternalKey)>,
     // WAL writer
     pub record_writer: Option<Writer<S::F>>,
 
@@ -209,6 +249,7 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
         Self {
             snapshots: SnapshotList::default(),
             pending_outputs: HashSet::default(),
+            running_compaction: None,
             db_path,
             storage,
             record_writer: None,
@@ -307,12 +348,24 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
         table_cache: TableCache<S, C>,
     ) -> TemplateResult<KMergeIter<SSTableIters<S, C>>> {
         let version = self.current();
+        let ucmp = &self.icmp.user_comparator;
+        // The files entirely out of the iterate bounds are never opened
+        let in_bounds = |f: &&Arc<FileMetaData>| {
+            read_opt
+                .iterate_lower_bound
+                .as_ref()
+                .is_none_or(|b| ucmp.compare(f.largest.user_key(), b) != CmpOrdering::Less)
+                && read_opt
+                    .iterate_upper_bound
+                    .as_ref()
+                    .is_none_or(|b| ucmp.compare(f.smallest.user_key(), b) == CmpOrdering::Less)
+        };
         let mut level_0 = vec![];
         // Merge all level zero files together since they may overlap
-        for file in version.files[0].iter() {
+        for file in version.files[0].iter().filter(in_bounds) {
             level_0.push(table_cache.new_iter(
                 self.icmp.clone(),
-                read_opt,
+                read_opt.clone(),
                 file.number,
                 file.file_size,
             )?);
@@ -323,10 +376,11 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
         // walks through the non-overlapping files in the level, opening them
         // lazily
         for files in version.files.iter().skip(1) {
+            let files = files.iter().filter(in_bounds).cloned().collect::<Vec<_>>();
             if !files.is_empty() {
-                let level_file_iter = LevelFileNumIterator::new(self.icmp.clone(), files.clone());
+                let level_file_iter = LevelFileNumIterator::new(self.icmp.clone(), files);
                 let factory =
-                    FileIterFactory::new(self.icmp.clone(), read_opt, table_cache.clone());
+                    FileIterFactory::new(self.icmp.clone(), read_opt.clone(), table_cache.clone());
                 level_n.push(ConcatenateIterator::new(level_file_iter, factory));
             }
         }
@@ -334,6 +388,7 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
             cmp: self.icmp.clone(),
             level_0,
             level_n,
+            _version: Some(version),
         });
         Ok(iter)
     }
@@ -390,7 +445,10 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
         if self.manifest_writer.is_none() {
             new_manifest_file =
                 generate_filename(&self.db_path, FileType::Manifest, self.manifest_file_number);
-            let f = self.storage.create(&new_manifest_file)?;
+            let f = self
+                .storage
+                .create(&new_manifest_file)
+                .map_err(|e| e.with_operation(Operation::WriteManifest))?;
             debug!("Create new manifest file #{}", self.manifest_file_number);
             let mut writer = Writer::new(f);
             match self.write_snapshot(&mut writer) {
@@ -581,6 +639,7 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
     /// Persistent given memtable into a single sst file to level_0.
     /// If `into_base` is true, the file could be pushed into level1 or level2 if there's no too
     /// much overlapping.
+    /// If blob files are enabled, the large values are separated into a new blob file.
     pub fn write_level_0_files(
         &mut self,
         db_path: &str,
@@ -594,6 +653,11 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
             number: self.inc_next_file_number(),
             ..Default::default()
         };
+        let blob_file_number = if self.options.enable_blob_files {
+            Some(self.inc_next_file_number())
+        } else {
+            None
+        };
         info!("Level-0 table #{} : start building", meta.number);
         let build_result = build_table(
             self.options.clone(),
@@ -602,7 +666,14 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
             table_cache,
             mem_iter,
             &mut meta,
-        );
+            // the level is picked after the file is built
+            0,
+            blob_file_number,
+        )
+        .map_err(|e| {
+            e.with_operation(Operation::BuildTable)
+                .with_file_number(meta.number)
+        });
         let mut level = 0;
 
         // If `file_size` is zero, the file has been deleted and
@@ -628,7 +699,15 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
                 meta.file_size,
                 meta.smallest.clone(),
                 meta.largest.clone(),
+                meta.file_checksum,
             );
+            if let Ok(Some(blob_file)) = &build_result {
+                info!(
+                    "Blob file #{} : add {} values {} bytes for table #{}",
+                    blob_file.number, blob_file.total_count, blob_file.file_size, meta.number
+                );
+                edit.add_blob_file(blob_file.clone());
+            }
         }
         info!(
             "Compactions stats for Level{}: {:?}",
@@ -639,20 +718,15 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
                 bytes_written: meta.file_size,
             }
         );
-        build_result
+        build_result.map(|_| ())
     }
 
     /// Add all living files in all versions into the `pending_outputs` to
     /// prevent them to be deleted
     #[inline]
     pub fn lock_live_files(&mut self) {
-        for version in self.versions.iter() {
-            for files in version.files.iter() {
-                for f in files.iter() {
-                    self.pending_outputs.insert(f.number);
-                }
-            }
-        }
+        let live_files = self.live_files();
+        self.pending_outputs.extend(live_files);
     }
 
     /// Returns the collection of current live files from version metadata
@@ -665,6 +739,7 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
                     set.insert(f.number);
                 }
             }
+            set.extend(version.blob_files.keys());
         }
         set
     }
@@ -682,18 +757,65 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
             ..Default::default()
         };
         let file_name = generate_filename(&self.db_path, FileType::Table, file_number);
-        let file = self.storage.create(file_name.as_str())?;
-        c.builder = Some(TableBuilder::new(file, self.icmp.clone(), &self.options));
+        let file = self.create_output_file(&file_name)?;
+        c.builder = Some(TableBuilder::for_level(
+            file,
+            self.icmp.clone(),
+            &self.options,
+            c.level + 1,
+        ));
         c.outputs.push(output);
         Ok(())
     }
 
+    /// Create new blob file builder for the values separated in Compaction
+    pub fn create_compaction_blob_file(
+        &mut self,
+        c: &mut Compaction<S::F, C>,
+    ) -> TemplateResult<()> {
+        assert!(c.blob_builder.is_none());
+        let file_number = self.inc_next_file_number();
+        self.pending_outputs.insert(file_number);
+        let file_name = generate_filename(&self.db_path, FileType::Blob, file_number);
+        let file = self.create_output_file(&file_name)?;
+        c.blob_builder = Some(BlobFileBuilder::new(file, file_number));
+        Ok(())
+    }
+
+    // Creates an output file of the compaction, with direct I/O if it's enabled
+    fn create_output_file(&self, file_name: &str) -> TemplateResult<S::F> {
+        if self.options.use_direct_io_for_flush_and_compaction {
+            self.storage.create_direct(file_name)
+        } else {
+            self.storage.create(file_name)
+        }
+    }
+
     /// Recover the last saved Version from MANIFEST file.
     /// Returns whether we need a new MANIFEST file for later usage.
     pub fn recover(&mut self) -> TemplateResult<bool> {
+        let (file_name, file_length) = self
+            .recover_manifest()
+            .map_err(|e| e.with_operation(Operation::ReadManifest))?;
+        Ok(!self.should_reuse_manifest(&file_name, file_length))
+    }
+
+    /// Recovers the latest state from the current MANIFEST without taking it over for writing.
+    /// Used by the read-only and secondary instances, which can call this again to catch up
+    /// with the changes made by the primary instance since the last call.
+    pub fn recover_read_only(&mut self) -> TemplateResult<()> {
+        self.recover_manifest()
+            .map(|_| ())
+            .map_err(|e| e.with_operation(Operation::ReadManifest))
+    }
+
+    // Rebuilds the current version from the MANIFEST pointed by CURRENT.
+    // Returns the name and the length of the MANIFEST file.
+    fn recover_manifest(&mut self) -> TemplateResult<(String, u64)> {
         let env = self.storage.clone();
         // Read "CURRENT" file, which contains a pointer to the current manifest file
-        let mut current = env.open(generate_filename(&self.db_path, FileType::Current, 0))?;
+        let mut current =
+            env.open_read_only(generate_filename(&self.db_path, FileType::Current, 0))?;
         let mut buf = vec![];
         current.read_all(&mut buf)?;
         let (current_manifest, file_name) = match String::from_utf8(buf) {
@@ -706,7 +828,7 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
                 let mut file_name = self.db_path.to_owned();
                 file_name.push(MAIN_SEPARATOR);
                 let file_name = file_name.add(&s);
-                (env.open(&file_name)?, file_name)
+                (env.open_read_only(&file_name)?, file_name)
             }
             Err(e) => {
                 return Err(TemplateKVError::Corruption(format!(
@@ -731,9 +853,12 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
         let mut last_sequence = 0;
         let mut has_last_sequence = false;
         while reader.read_record(&mut buf) {
-            reporter.result()?;
+            reporter.result().map_err(|e| e.with_path(&file_name))?;
             let mut edit = VersionEdit::new(self.options.max_levels);
-            edit.decoded_from(&buf)?;
+            edit.decoded_from(&buf).map_err(|e| {
+                e.with_path(&file_name)
+                    .with_offset(reader.last_record_offset())
+            })?;
             debug!("Decoded manifest record: {:?}", &edit);
             if let Some(ref cmp_name) = edit.comparator_name {
                 if cmp_name.as_str() != self.icmp.user_comparator.name() {
@@ -762,7 +887,7 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
         }
 
         if let Err(e) = reporter.result() {
-            return Err(e);
+            return Err(e.with_path(&file_name));
         }
 
         if !has_next_file_number {
@@ -790,13 +915,13 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
 
         let mut new_v = builder.apply_to_new(&self.icmp);
         new_v.finalize();
-        self.versions.push(Arc::new(new_v));
+        self.append_new_version(new_v);
         self.manifest_file_number = next_file_number;
         self.next_file_number = next_file_number + 1;
         self.last_sequence = last_sequence;
         self.log_number = log_number;
         self.prev_log_number = prev_log_number;
-        Ok(!self.should_reuse_manifest(&file_name, file_length))
+        Ok((file_name, file_length))
     }
 
     /// Forward to `num + 1` as the next file number
@@ -860,9 +985,21 @@ impl<S: Storage + Clone + 'static, C: Comparator + 'static> VersionSet<S, C> {
                     file.file_size,
                     file.smallest.clone(),
                     file.largest.clone(),
+                    file.file_checksum,
                 );
             }
         }
+        // Save blob files
+        for meta in self.current().blob_files.values() {
+            edit.add_blob_file(BlobFileMeta {
+                garbage_count: 0,
+                garbage_bytes: 0,
+                ..meta.as_ref().clone()
+            });
+            if meta.garbage_count > 0 {
+                edit.add_blob_garbage(meta.number, meta.garbage_count, meta.garbage_bytes);
+            }
+        }
 
         let mut record = vec![];
         edit.encode_to(&mut record);
@@ -1103,8 +1240,12 @@ impl<S: Storage + Clone, C: Comparator + 'static> DerivedIterFactory for FileIte
         } else {
             let file_number = decode_fixed_64(value);
             let file_size = decode_fixed_64(&value[std::mem::size_of::<u64>()..]);
-            self.table_cache
-                .new_iter(self.icmp.clone(), self.options, file_number, file_size)
+            self.table_cache.new_iter(
+                self.icmp.clone(),
+                self.options.clone(),
+                file_number,
+                file_size,
+            )
         }
     }
 }
@@ -1123,6 +1264,9 @@ pub struct SSTableIters<S: Storage + Clone, C: Comparator + 'static> {
     level_0: Vec<TableIterator<InternalKeyComparator<C>, S::F>>,
     // ConcatenateIterators for opening SST in level n>1 lazily. One iterator for one level
     level_n: Vec<ConcatenateIterator<LevelFileNumIterator<C>, FileIterFactory<S, C>>>,
+    // The version iterated. Holding it keeps the blob files, which are read lazily by the
+    // db iterator, from being deleted.
+    _version: Option<Arc<Version<C>>>,
 }
 
 impl<S: Storage + Clone, C: Comparator> SSTableIters<S, C> {
@@ -1135,6 +1279,7 @@ impl<S: Storage + Clone, C: Comparator> SSTableIters<S, C> {
             cmp,
             level_0,
             level_n,
+            _version: None,
         }
     }
 }
@@ -1440,6 +1585,7 @@ mod add_boundary_tests {
             number,
             smallest: InternalKey::new(number.to_string().as_bytes(), 1, ValueType::Value),
             largest: InternalKey::new(number.to_string().as_bytes(), 2, ValueType::Value),
+            file_checksum: None,
         }
     }
 
@@ -1476,6 +1622,8 @@ mod add_boundary_tests {
             compaction_pointers: vec![],
             deleted_files,
             new_files: added_files,
+            new_blob_files: vec![],
+            blob_garbage: vec![],
         }
     }
 
@@ -101,6 +101,15 @@ impl WriteBatch {
 
     /// Insert all the records in the batch into the given `MemTable`
     pub fn insert_into<C: Comparator>(&self, mem: &MemTable<C>) -> TemplateResult<()> {
+        self.for_each(|seq, value_type, key, value| mem.add(seq, value_type, key, value))
+    }
+
+    /// Calls `f` with the sequence number, the type, the key and the value of every record
+    /// in the batch in order. The value of a deletion is empty.
+    pub fn for_each<F>(&self, mut f: F) -> TemplateResult<()>
+    where
+        F: FnMut(u64, ValueType, &[u8], &[u8]),
+    {
         if self.contents.len() < HEADER_SIZE {
             return Err(TemplateKVError::Corruption(
                 "[batch] malformed WriteBatch (too small)".to_owned(),
@@ -117,7 +126,7 @@ impl WriteBatch {
                 ValueType::Value => {
                     if let Some(key) = VarintU32::get_varint_prefixed_slice(&mut s) {
                         if let Some(value) = VarintU32::get_varint_prefixed_slice(&mut s) {
-                            mem.add(seq, ValueType::Value, key, value);
+                            f(seq, ValueType::Value, key, value);
                             seq += 1;
                             continue;
                         }
@@ -128,7 +137,7 @@ impl WriteBatch {
                 }
                 ValueType::Deletion => {
                     if let Some(key) = VarintU32::get_varint_prefixed_slice(&mut s) {
-                        mem.add(seq, ValueType::Deletion, key, b"");
+                        f(seq, ValueType::Deletion, key, b"");
                         seq += 1;
                         continue;
                     }
@@ -136,7 +145,7 @@ impl WriteBatch {
                         "[batch] bad WriteBatch delete".to_owned(),
                     ));
                 }
-                ValueType::Unknown => {
+                ValueType::BlobIndex | ValueType::Unknown => {
                     return Err(TemplateKVError::Corruption(
                         "[batch] unknown WriteBatch value type".to_owned(),
                     ));
@@ -260,6 +269,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_for_each_record() {
+        let mut b = WriteBatch::default();
+        b.put(b"foo", b"bar");
+        b.delete(b"box");
+        b.set_sequence(100);
+        let mut records = vec![];
+        b.for_each(|seq, t, k, v| records.push((seq, t, k.to_vec(), v.to_vec())))
+            .unwrap();
+        assert_eq!(records, vec![
+            (100, ValueType::Value, b"foo".to_vec(), b"bar".to_vec()),
+            (101, ValueType::Deletion, b"box".to_vec(), vec![]),
+        ]);
+    }
+
     #[test]
     fn test_corrupted_batch() {
         let mut b = WriteBatch::default();
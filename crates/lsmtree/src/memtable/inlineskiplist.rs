@@ -417,6 +417,12 @@ where
     pub fn new(list: InlineSkipList<C, A>) -> Self {
         Self { list, node: null() }
     }
+
+    /// Returns the current key sharing the buffer of the node without copying
+    pub fn key_bytes(&self) -> Bytes {
+        assert!(self.valid());
+        unsafe { (*self.node).key.clone() }
+    }
 }
 
 fn random_height() -> usize {
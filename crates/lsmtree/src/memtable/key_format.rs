@@ -319,6 +319,12 @@ impl FilterPolicy for InternalFilterPolicy {
         }
         self.user_policy.create_filter(user_keys.as_slice())
     }
+
+    fn with_bits_per_key(&self, bits_per_key: f64) -> Option<Arc<dyn FilterPolicy>> {
+        self.user_policy
+            .with_bits_per_key(bits_per_key)
+            .map(|p| Arc::new(Self::new(p)) as Arc<dyn FilterPolicy>)
+    }
 }
 
 /// Returns the encoded user key from encoded internal key
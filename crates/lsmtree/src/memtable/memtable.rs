@@ -1,4 +1,6 @@
-use std::cmp::Ordering;
+use std::{cmp::Ordering, sync::Arc};
+
+use bytes::Bytes;
 
 use super::{
     KeyComparator,
@@ -7,6 +9,7 @@ use super::{
     inlineskiplist::{InlineSkipList, InlineSkiplistIterator},
     key_format::{InternalKeyComparator, LookupKey},
     value_format::ValueType,
+    write_buffer_manager::{MemTableCharge, WriteBufferManager},
 };
 use crate::{
     error::{TemplateKVError, TemplateResult},
@@ -24,15 +27,49 @@ use crate::{
 pub struct MemTable<C: Comparator> {
     cmp: KeyComparator<C>,
     table: InlineSkipList<KeyComparator<C>, OffsetArena>,
+    // the memory charged to the write buffer manager if any
+    charge: Option<Arc<MemTableCharge>>,
 }
 
 impl<C: Comparator> MemTable<C> {
     /// Creates a new memory table
     pub fn new(max_mem_size: usize, icmp: InternalKeyComparator<C>) -> Self {
+        Self::with_write_buffer_manager(max_mem_size, icmp, None)
+    }
+
+    /// Creates a new memory table whose memory is charged to `manager`
+    pub fn with_write_buffer_manager(
+        max_mem_size: usize,
+        icmp: InternalKeyComparator<C>,
+        manager: Option<Arc<WriteBufferManager>>,
+    ) -> Self {
         let arena = OffsetArena::with_capacity(max_mem_size);
         let kcmp = KeyComparator { icmp };
         let table = InlineSkipList::new(kcmp.clone(), arena);
-        Self { cmp: kcmp, table }
+        Self {
+            cmp: kcmp,
+            table,
+            charge: manager.map(|m| Arc::new(MemTableCharge::new(m))),
+        }
+    }
+
+    /// Charges the memory newly used to the write buffer manager, which is the arena
+    /// allocations along with the entries held out of the arena. It's called after the
+    /// insertions.
+    #[inline]
+    pub fn update_write_buffer_charge(&self) {
+        if let Some(charge) = &self.charge {
+            charge.update(self.approximate_memory_usage());
+        }
+    }
+
+    /// Marks the memory table immutable for the write buffer manager, which no longer
+    /// counts its memory as the mutable one.
+    #[inline]
+    pub fn mark_immutable(&self) {
+        if let Some(charge) = &self.charge {
+            charge.mark_immutable();
+        }
     }
 
     /// Returns an estimate of the number of bytes of data in use by this
@@ -98,6 +135,12 @@ impl<C: Comparator> MemTable<C> {
     /// If memtable contains a deletion for key, returns `Some(Err(Status::NotFound))` .
     /// If memtable does not contain the key, return `None`
     pub fn get(&self, key: &LookupKey) -> Option<TemplateResult<Vec<u8>>> {
+        self.get_pinned(key).map(|r| r.map(Vec::from))
+    }
+
+    /// Same as `get` but the value shares the entry in the memtable without copying, which
+    /// keeps the entry alive as long as the value even if the memtable is dropped
+    pub fn get_pinned(&self, key: &LookupKey) -> Option<TemplateResult<Bytes>> {
         let mk = key.mem_key();
         let mut iter = InlineSkiplistIterator::new(self.table.clone());
         iter.seek(mk);
@@ -116,10 +159,12 @@ impl<C: Comparator> MemTable<C> {
                     let tag = decode_fixed_64(&ikey[key_size - INTERNAL_KEY_TAIL..]);
                     match ValueType::from(tag & 0xff_u64) {
                         ValueType::Value => {
-                            return Some(Ok(extract_varint32_encoded_slice(&mut e).to_vec()));
+                            let value = extract_varint32_encoded_slice(&mut e);
+                            return Some(Ok(iter.key_bytes().slice_ref(value)));
                         }
                         ValueType::Deletion => return Some(Err(TemplateKVError::NotFound(None))),
-                        ValueType::Unknown => { /* fallback to None*/ }
+                        // Blob indexes are only created by flushes and compactions
+                        ValueType::BlobIndex | ValueType::Unknown => { /* fallback to None*/ }
                     }
                 }
                 _ => return None,
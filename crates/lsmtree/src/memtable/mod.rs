@@ -10,6 +10,7 @@ pub mod key_format;
 pub mod memtable;
 pub mod skiplist;
 pub mod value_format;
+pub mod write_buffer_manager;
 
 // use crate::{
 //     db::format::{InternalKeyComparator, LookupKey, ValueType, INTERNAL_KEY_TAIL},
@@ -138,6 +139,31 @@ mod tests {
         assert_eq!(b"boo", v.unwrap().unwrap().as_slice());
     }
 
+    #[test]
+    fn test_memtable_get_pinned() {
+        let memtable = new_mem_table();
+        memtable.add(1, ValueType::Value, b"foo", b"val1");
+        memtable.add(2, ValueType::Deletion, b"foo", b"");
+        memtable.add(3, ValueType::Value, b"boo", b"boo");
+
+        let v = memtable.get_pinned(&LookupKey::new(b"foo", 1)).unwrap();
+        assert_eq!(&v.unwrap()[..], b"val1");
+        assert!(
+            memtable
+                .get_pinned(&LookupKey::new(b"foo", 2))
+                .unwrap()
+                .is_err()
+        );
+        assert!(memtable.get_pinned(&LookupKey::new(b"null", 3)).is_none());
+        // The pinned value outlives the memtable
+        let v = memtable
+            .get_pinned(&LookupKey::new(b"boo", 3))
+            .unwrap()
+            .unwrap();
+        drop(memtable);
+        assert_eq!(&v[..], b"boo");
+    }
+
     #[test]
     fn test_memtable_iter() {
         let memtable = new_mem_table();
@@ -4,6 +4,8 @@ pub enum ValueType {
     Deletion = 0,
     /// A normal value
     Value = 1,
+    /// A `BlobIndex` referring to a value stored in a blob file
+    BlobIndex = 2,
 
     /// Unknown type
     Unknown,
@@ -15,11 +17,12 @@ pub enum ValueType {
 /// and the value type is embedded as the low 8 bits in the sequence
 /// number in internal keys, we need to use the highest-numbered
 /// ValueType, not the lowest).
-pub const VALUE_TYPE_FOR_SEEK: ValueType = ValueType::Value;
+pub const VALUE_TYPE_FOR_SEEK: ValueType = ValueType::BlobIndex;
 
 impl From<u64> for ValueType {
     fn from(v: u64) -> Self {
         match v {
+            2 => ValueType::BlobIndex,
             1 => ValueType::Value,
             0 => ValueType::Deletion,
             _ => ValueType::Unknown,
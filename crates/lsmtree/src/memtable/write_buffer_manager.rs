@@ -0,0 +1,290 @@
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use crate::{
+    cache::{BlockCacheKey, CachePriority, CacheSync},
+    sstable::block::Block,
+};
+
+/// The charge of a dummy entry reserving the memory of the memtables in the block cache
+pub const DUMMY_ENTRY_SIZE: usize = 256 << 10;
+
+// The file number of the dummy entries, which no table has
+const DUMMY_FILE_NUMBER: u64 = u64::MAX;
+
+static NEXT_MANAGER_ID: AtomicU64 = AtomicU64::new(0);
+
+// The dummy entries inserted into the block cache for the memory used by the memtables
+struct CacheReservation {
+    cache: Arc<dyn CacheSync<BlockCacheKey, Arc<Block>>>,
+    // distinguishes the dummy entries of the managers sharing a cache
+    id: u64,
+    dummy: Arc<Block>,
+    dummies: u64,
+}
+
+impl CacheReservation {
+    #[inline]
+    fn dummy_key(&self, i: u64) -> BlockCacheKey {
+        BlockCacheKey::new(DUMMY_FILE_NUMBER, (self.id << 32) | i)
+    }
+
+    // Inserts or erases the dummy entries to reserve `memory_used` bytes. The reservation
+    // shrinks lazily, only after the usage drops below 3/4 of it, so that the entries are
+    // not inserted and erased back and forth.
+    fn update(&mut self, memory_used: usize) {
+        let reserved = self.dummies as usize * DUMMY_ENTRY_SIZE;
+        if memory_used > reserved {
+            while (self.dummies as usize) * DUMMY_ENTRY_SIZE < memory_used {
+                let key = self.dummy_key(self.dummies);
+                self.cache.insert_with_priority(
+                    key,
+                    self.dummy.clone(),
+                    DUMMY_ENTRY_SIZE,
+                    CachePriority::High,
+                );
+                self.dummies += 1;
+            }
+        } else if memory_used < reserved / 4 * 3 {
+            while self.dummies > 0 && (self.dummies as usize - 1) * DUMMY_ENTRY_SIZE >= memory_used
+            {
+                self.dummies -= 1;
+                let key = self.dummy_key(self.dummies);
+                self.cache.erase(&key);
+            }
+        }
+    }
+}
+
+impl Drop for CacheReservation {
+    fn drop(&mut self) {
+        self.update(0);
+    }
+}
+
+/// `WriteBufferManager` limits the total memory used by the memtables of one or more DBs
+/// sharing it through `Options::write_buffer_manager`.
+///
+/// The memtables charge the memory allocated in their arenas to the manager, and a DB
+/// flushes its memtable if `should_flush` tells the memory exceeds the budget, before its
+/// memtable reaches `write_buffer_size`.
+///
+/// If a block cache is given, the memory of the memtables is also charged to it by the dummy
+/// entries of `DUMMY_ENTRY_SIZE`, so the block cache and the memtables together stay within
+/// the capacity of the cache. The dummy entries are inserted with the high priority, so
+/// they are kept by a cache with a high priority pool but may be evicted by an LRU cache.
+pub struct WriteBufferManager {
+    buffer_size: usize,
+    // the limit of the memory used by the mutable memtables
+    mutable_limit: usize,
+    memory_used: AtomicUsize,
+    // the memory used by the mutable memtables
+    memory_active: AtomicUsize,
+    cache_reservation: Option<Mutex<CacheReservation>>,
+}
+
+impl WriteBufferManager {
+    /// Creates a `WriteBufferManager` limiting the memtables to `buffer_size` bytes
+    pub fn new(buffer_size: usize) -> Self {
+        Self {
+            buffer_size,
+            mutable_limit: buffer_size / 8 * 7,
+            memory_used: AtomicUsize::new(0),
+            memory_active: AtomicUsize::new(0),
+            cache_reservation: None,
+        }
+    }
+
+    /// Creates a `WriteBufferManager` limiting the memtables to `buffer_size` bytes, whose
+    /// memory is charged to `cache` as well
+    pub fn with_cache(
+        buffer_size: usize,
+        cache: Arc<dyn CacheSync<BlockCacheKey, Arc<Block>>>,
+    ) -> Self {
+        let mut m = Self::new(buffer_size);
+        m.cache_reservation = Some(Mutex::new(CacheReservation {
+            cache,
+            id: NEXT_MANAGER_ID.fetch_add(1, Ordering::Relaxed),
+            dummy: Arc::new(Block::new_raw(vec![])),
+            dummies: 0,
+        }));
+        m
+    }
+
+    /// Returns the budget of the memtables
+    #[inline]
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Returns the memory used by all the memtables charged to the manager
+    #[inline]
+    pub fn memory_usage(&self) -> usize {
+        self.memory_used.load(Ordering::Acquire)
+    }
+
+    /// Returns the memory used by the mutable memtables charged to the manager
+    #[inline]
+    pub fn mutable_memtable_memory_usage(&self) -> usize {
+        self.memory_active.load(Ordering::Acquire)
+    }
+
+    /// Returns the memory charged to the block cache by the dummy entries
+    pub fn cache_charge(&self) -> usize {
+        self.cache_reservation
+            .as_ref()
+            .map_or(0, |r| r.lock().unwrap().dummies as usize * DUMMY_ENTRY_SIZE)
+    }
+
+    /// Returns true if the memtables should be flushed to keep the memory within the budget.
+    /// The mutable memtables may take up to 7/8 of the budget, and half of it once the
+    /// immutable ones awaiting the flushes take the rest.
+    pub fn should_flush(&self) -> bool {
+        let active = self.mutable_memtable_memory_usage();
+        active > self.mutable_limit
+            || (self.memory_usage() >= self.buffer_size && active >= self.buffer_size / 2)
+    }
+
+    /// Charges `mem` bytes allocated by a mutable memtable
+    pub fn reserve_mem(&self, mem: usize) {
+        self.memory_active.fetch_add(mem, Ordering::AcqRel);
+        self.memory_used.fetch_add(mem, Ordering::AcqRel);
+        self.update_cache_reservation();
+    }
+
+    /// Stops counting `mem` bytes as the usage of the mutable memtables, since the memtable
+    /// became immutable and will be flushed
+    pub fn schedule_free_mem(&self, mem: usize) {
+        self.memory_active.fetch_sub(mem, Ordering::AcqRel);
+    }
+
+    /// Releases `mem` bytes of a memtable that's dropped
+    pub fn free_mem(&self, mem: usize) {
+        self.memory_used.fetch_sub(mem, Ordering::AcqRel);
+        self.update_cache_reservation();
+    }
+
+    fn update_cache_reservation(&self) {
+        if let Some(r) = &self.cache_reservation {
+            let mut r = r.lock().unwrap();
+            // The usage might have changed while waiting for the lock
+            r.update(self.memory_usage());
+        }
+    }
+}
+
+/// `MemTableCharge` is the memory of a memtable charged to a `WriteBufferManager`, which is
+/// freed once the memtable and all the iterators over it are dropped.
+pub struct MemTableCharge {
+    manager: Arc<WriteBufferManager>,
+    // (the charged memory, whether the memtable is immutable)
+    state: Mutex<(usize, bool)>,
+}
+
+impl MemTableCharge {
+    pub fn new(manager: Arc<WriteBufferManager>) -> Self {
+        Self {
+            manager,
+            state: Mutex::new((0, false)),
+        }
+    }
+
+    /// Charges the growth of the memory used by the memtable to `memory_used`
+    pub fn update(&self, memory_used: usize) {
+        let mut state = self.state.lock().unwrap();
+        let (charged, immutable) = *state;
+        if memory_used > charged {
+            self.manager.reserve_mem(memory_used - charged);
+            if immutable {
+                self.manager.schedule_free_mem(memory_used - charged);
+            }
+            state.0 = memory_used;
+        }
+    }
+
+    /// Marks the memtable immutable, so its memory no longer counts as the mutable one
+    pub fn mark_immutable(&self) {
+        let mut state = self.state.lock().unwrap();
+        let (charged, immutable) = *state;
+        if !immutable {
+            self.manager.schedule_free_mem(charged);
+            state.1 = true;
+        }
+    }
+}
+
+impl Drop for MemTableCharge {
+    fn drop(&mut self) {
+        let (charged, immutable) = *self.state.get_mut().unwrap();
+        if !immutable {
+            self.manager.schedule_free_mem(charged);
+        }
+        self.manager.free_mem(charged);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{clock_pro_cache::ClockProCache, lru_cache::LRUCache};
+
+    #[test]
+    fn test_should_flush() {
+        let m = Arc::new(WriteBufferManager::new(800));
+        let c1 = MemTableCharge::new(m.clone());
+        c1.update(500);
+        // Charges only the growth
+        c1.update(400);
+        c1.update(600);
+        assert_eq!(m.memory_usage(), 600);
+        assert!(!m.should_flush());
+        let c2 = MemTableCharge::new(m.clone());
+        c2.update(101);
+        assert_eq!(m.mutable_memtable_memory_usage(), 701);
+        assert!(m.should_flush());
+
+        // The immutable memtable is awaiting the flush, which frees its memory
+        c1.mark_immutable();
+        assert_eq!(m.mutable_memtable_memory_usage(), 101);
+        assert!(!m.should_flush());
+        c2.update(400);
+        assert_eq!(m.memory_usage(), 1000);
+        assert!(m.should_flush());
+        drop(c1);
+        assert_eq!(m.memory_usage(), 400);
+        assert!(!m.should_flush());
+        drop(c2);
+        assert_eq!(m.memory_usage(), 0);
+        assert_eq!(m.mutable_memtable_memory_usage(), 0);
+    }
+
+    #[test]
+    fn test_charge_to_cache() {
+        for cache in [
+            Arc::new(LRUCache::new(4 << 20)) as Arc<dyn CacheSync<BlockCacheKey, Arc<Block>>>,
+            Arc::new(ClockProCache::new(4 << 20)),
+        ] {
+            let m = Arc::new(WriteBufferManager::with_cache(2 << 20, cache.clone()));
+            let c = MemTableCharge::new(m.clone());
+            c.update(100);
+            assert_eq!(m.cache_charge(), DUMMY_ENTRY_SIZE);
+            c.update(DUMMY_ENTRY_SIZE * 3 + 1);
+            assert_eq!(m.cache_charge(), DUMMY_ENTRY_SIZE * 4);
+            assert_eq!(cache.total_charge(), DUMMY_ENTRY_SIZE * 4);
+
+            // The reservation shrinks after the usage drops below 3/4 of it
+            let c2 = MemTableCharge::new(m.clone());
+            c2.update(DUMMY_ENTRY_SIZE);
+            c.mark_immutable();
+            drop(c);
+            assert_eq!(m.memory_usage(), DUMMY_ENTRY_SIZE);
+            assert_eq!(m.cache_charge(), DUMMY_ENTRY_SIZE);
+            assert_eq!(cache.total_charge(), DUMMY_ENTRY_SIZE);
+            drop(c2);
+            assert_eq!(m.cache_charge(), 0);
+            assert_eq!(cache.total_charge(), 0);
+        }
+    }
+}
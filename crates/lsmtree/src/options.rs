@@ -4,15 +4,16 @@ use log::{LevelFilter, Log};
 
 use crate::{
     cache::{
-        CacheSync, FilterPolicy, bloom_filter_cache::BloomFilter, lru_cache::LRUCache,
-        sharded_cache::ShardedCache,
+        BlockCacheKey, CacheSync, FilterPolicy, SecondaryCache, bloom_filter_cache::BloomFilter,
+        lru_cache::LRUCache, sharded_cache::ShardedCache, spill_to,
     },
+    error::TemplateResult,
     logger::Logger,
     manager::snapshot::Snapshot,
-    memtable::key_format::InternalFilterPolicy,
+    memtable::{key_format::InternalFilterPolicy, write_buffer_manager::WriteBufferManager},
     sstable::block::Block,
     storage::{File, Storage},
-    util::comparator::Comparator,
+    util::{comparator::Comparator, slice_transform::SliceTransform},
 };
 
 pub const DEFAULT_CACHE_SHARDS: usize = 8;
@@ -25,16 +26,58 @@ pub const MAX_KEY_SEQUENCE: u64 = (1u64 << 56) - 1;
 /// 7bytes sequence number + 1byte type number
 pub const INTERNAL_KEY_TAIL: usize = 8;
 
-#[derive(Clone, Copy, Debug, FromPrimitive)]
+/// The compression algorithm of blocks. The value is stored as the type byte in the block
+/// trailer so existing values must never be changed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, FromPrimitive)]
 pub enum CompressionType {
     NoCompression = 0,
     SnappyCompression = 1,
-    Unknown,
+    Lz4Compression = 4,
+    ZstdCompression = 7,
+    /// Treated as `NoCompression` when writing and rejected when reading
+    Unknown = 0xff,
 }
 
 impl From<u8> for CompressionType {
     fn from(i: u8) -> Self {
-        num_traits::FromPrimitive::from_u8(i).unwrap()
+        num_traits::FromPrimitive::from_u8(i).unwrap_or(CompressionType::Unknown)
+    }
+}
+
+/// Options for the compression algorithms
+#[derive(Clone, Copy, Debug)]
+pub struct CompressionOptions {
+    /// The zstd compression level
+    pub level: i32,
+
+    /// The max size of the zstd dictionary trained for each sst file. The dictionary is
+    /// trained from the first data blocks of the file and stored in the file. Zero disables
+    /// the dictionary.
+    pub max_dict_bytes: usize,
+
+    /// The max bytes of the data blocks sampled for training the dictionary. Defaults to
+    /// `100 * max_dict_bytes` if zero.
+    pub zstd_max_train_bytes: usize,
+}
+
+impl CompressionOptions {
+    /// Returns the bytes of data blocks to be buffered for training the dictionary
+    pub fn max_train_bytes(&self) -> usize {
+        if self.zstd_max_train_bytes > 0 {
+            self.zstd_max_train_bytes
+        } else {
+            self.max_dict_bytes * 100
+        }
+    }
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        CompressionOptions {
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            max_dict_bytes: 0,
+            zstd_max_train_bytes: 0,
+        }
     }
 }
 
@@ -108,17 +151,45 @@ pub struct Options<C: Comparator> {
     /// the next time the database is opened.
     pub write_buffer_size: usize,
 
+    /// If set, the memtables charge their memory to the `WriteBufferManager`, which may be
+    /// shared by several DBs, and a memtable is flushed once the memtables of all of them
+    /// exceed its budget even if it's below `write_buffer_size`. The manager may also
+    /// charge the memory to a block cache so that the block cache and the memtables share
+    /// its capacity.
+    pub write_buffer_manager: Option<Arc<WriteBufferManager>>,
+
     /// Number of open files that can be used by the DB.  You may need to
     /// increase this if your database has a large working set (budget
     /// one open file per 2MB of working set).
     pub max_open_files: usize,
 
+    /// If true, the table files are read with direct I/O, which bypasses the OS page cache
+    /// so that the blocks are not cached twice along with the block cache. See
+    /// `Storage::open_direct`.
+    pub use_direct_reads: bool,
+
+    /// If true, the table files and the blob files are written with direct I/O by the
+    /// flushes and the compactions. See `Storage::create_direct`.
+    pub use_direct_io_for_flush_and_compaction: bool,
+
+    /// If true, the table files are memory-mapped for reading, and the uncompressed blocks are
+    /// served from the mapping without copying. Ignored if `use_direct_reads` is set. See
+    /// `Storage::open_mmap`.
+    pub allow_mmap_reads: bool,
+
     // -------------------
     // Control over blocks (user data is stored in a set of blocks, and
     // a block is the unit of reading from disk).
     /// If non-null, use the specified cache for blocks.
     /// If null, we will automatically create and use an 8MB internal cache.
-    pub block_cache: Option<Arc<dyn CacheSync<Vec<u8>, Arc<Block>>>>,
+    pub block_cache: Option<Arc<dyn CacheSync<BlockCacheKey, Arc<Block>>>>,
+
+    /// If set, the blocks missing in the block cache are looked up in the secondary cache,
+    /// e.g. a `PersistentCache` on a local SSD, before being read from the table files.
+    /// The internal block cache spills the blocks it evicts to the secondary cache. A block
+    /// cache given by `block_cache` should do so with the eviction listener made by
+    /// `cache::spill_to`.
+    pub secondary_cache: Option<Arc<dyn SecondaryCache>>,
 
     /// Number of sstables that remains out of table cache
     pub non_table_cache_files: usize,
@@ -134,6 +205,20 @@ pub struct Options<C: Comparator> {
     /// leave this parameter alone.
     pub block_restart_interval: usize,
 
+    /// If true, the index of a table is cut into partitions of about `metadata_block_size`
+    /// and only the top-level index of the partitions stays in memory once the table is
+    /// opened. The partitions are read through the block cache on demand, which reduces the
+    /// memory used by the opened tables. Default is false.
+    pub partition_index: bool,
+
+    /// If true, the filter of a table is partitioned along with the index, which must be
+    /// partitioned as well. Each partition is a filter over the keys of the data blocks
+    /// indexed by the corresponding index partition. Default is false.
+    pub partition_filters: bool,
+
+    /// The target size of the index and filter partitions. Default is 4KB.
+    pub metadata_block_size: usize,
+
     /// The DB will write up to this amount of bytes to a file before
     /// switching to a new one.
     /// Most clients should leave this parameter alone.  However if your
@@ -148,6 +233,36 @@ pub struct Options<C: Comparator> {
     /// parameter can be changed dynamically. Default is SnappyCompression.
     pub compression: CompressionType,
 
+    /// The compression algorithms for different levels. The files at level `i` are compressed
+    /// by `compression_per_level[i]` and the levels beyond the vector use its last element,
+    /// e.g. `[NoCompression, NoCompression, ZstdCompression]` leaves L0 and L1 uncompressed.
+    /// `compression` is used for all the levels if it's empty.
+    pub compression_per_level: Vec<CompressionType>,
+
+    /// Options for the compression algorithms like the zstd level and dictionary
+    pub compression_opts: CompressionOptions,
+
+    /// If true, the values not smaller than `min_blob_size` are written into separate blob
+    /// files when the memtable is flushed or the tables are compacted, and the sst files only
+    /// keep the references to them. This avoids rewriting the large values in every
+    /// compaction. Default is false.
+    pub enable_blob_files: bool,
+
+    /// The minimum size of the values separated into blob files. Default is 4KB.
+    pub min_blob_size: usize,
+
+    /// A compaction starts a new blob file once the current one reaches this size.
+    /// Default is 256MB.
+    pub blob_file_size: u64,
+
+    /// If true, compactions relocate the values in the oldest blob files into new ones so
+    /// that the old blob files can be deleted once none of their values is referred.
+    /// Default is false.
+    pub enable_blob_garbage_collection: bool,
+
+    /// The fraction of the oldest blob files collected by compactions. Default is 0.25.
+    pub blob_garbage_collection_age_cutoff: f64,
+
     /// If true, append to existing MANIFEST and log files when a database is opened.
     /// This can significantly speed up open.
     pub reuse_logs: bool,
@@ -155,6 +270,28 @@ pub struct Options<C: Comparator> {
     /// If non-null, use the specified filter policy to reduce disk reads.
     pub filter_policy: Option<Arc<dyn FilterPolicy>>,
 
+    /// If true, a table has a single filter over all its keys instead of one filter for every
+    /// 2KB of the data blocks, so a lookup rules out the whole table by one probe without
+    /// reading the index. Ignored if `partition_filters` is set. Default is false.
+    pub full_filter: bool,
+
+    /// If not empty, the filters of the files at level `i` are built with about
+    /// `filter_bits_per_key_per_level[i]` bits per key and the levels beyond the vector use its
+    /// last element. Fewer bits for the last levels, which hold most of the keys, save a lot of
+    /// memory while most lookups are served by the upper levels. Only takes effect for the
+    /// filter policies supporting `FilterPolicy::with_bits_per_key`.
+    pub filter_bits_per_key_per_level: Vec<f64>,
+
+    /// If set, the filters also contain the prefixes of the user keys extracted by it, so
+    /// that a seek with `ReadOptions::prefix_same_as_start` skips the tables without the
+    /// prefix of the target. Default is None.
+    pub prefix_extractor: Option<Arc<dyn SliceTransform>>,
+
+    /// If true, the filters contain the whole keys besides the prefixes. Setting it to false
+    /// makes the filters smaller when `prefix_extractor` is set, but a lookup of a key out of
+    /// the domain of the extractor is not able to use the filters. Default is true.
+    pub whole_key_filtering: bool,
+
     /// The underlying logger
     /// In dev mode, default using a std output
     /// In release mode, default using a file `LOG` for output
@@ -192,13 +329,61 @@ impl<C: Comparator> Options<C> {
         result
     }
 
+    /// Returns the compression algorithm for the files at `level`
+    pub fn compression_for_level(&self, level: usize) -> CompressionType {
+        match self.compression_per_level.last() {
+            Some(last) => *self.compression_per_level.get(level).unwrap_or(last),
+            None => self.compression,
+        }
+    }
+
+    /// Returns the filter policy for building the files at `level`
+    pub fn filter_policy_for_level(&self, level: usize) -> Option<Arc<dyn FilterPolicy>> {
+        let policy = self.filter_policy.as_ref()?;
+        match self.filter_bits_per_key_per_level.last() {
+            Some(last) => {
+                let bits = *self
+                    .filter_bits_per_key_per_level
+                    .get(level)
+                    .unwrap_or(last);
+                policy
+                    .with_bits_per_key(bits)
+                    .or_else(|| Some(policy.clone()))
+            }
+            None => Some(policy.clone()),
+        }
+    }
+
     /// Reserve `non_table_cache_files` files or so for other uses and give the rest to TableCache
     pub fn table_cache_size(&self) -> usize {
         self.max_open_files - self.non_table_cache_files
     }
 
     /// Initialize Options by limiting ranges of some flags, applying customized Logger and etc.
-    pub fn initialize<O: File + 'static, S: Storage<F = O>>(&mut self, db_path: &str, storage: &S) {
+    pub fn initialize<O: File + 'static, S: Storage<F = O>>(
+        &mut self,
+        db_path: &str,
+        storage: &S,
+    ) -> TemplateResult<()> {
+        self.do_initialize(db_path, storage, true)
+    }
+
+    /// Same as `initialize` but never creates the `LOG` file in `db_path`, for the instances
+    /// which don't own the db such as the read-only and the secondary ones.
+    pub(crate) fn initialize_without_info_log<O: File + 'static, S: Storage<F = O>>(
+        &mut self,
+        db_path: &str,
+        storage: &S,
+    ) -> TemplateResult<()> {
+        self.do_initialize(db_path, storage, false)
+    }
+
+    fn do_initialize<O: File + 'static, S: Storage<F = O>>(
+        &mut self,
+        db_path: &str,
+        storage: &S,
+        create_info_log: bool,
+    ) -> TemplateResult<()> {
         if self.max_mem_compact_level < 2 {
             self.max_mem_compact_level = 2
         }
@@ -207,11 +392,15 @@ impl<C: Comparator> Options<C> {
         self.write_buffer_size = Self::clip_range(self.write_buffer_size, 64 << 10, 1 << 30);
         self.max_file_size = Self::clip_range(self.max_file_size, 1 << 20, 1 << 30);
         self.block_size = Self::clip_range(self.block_size, 1 << 10, 4 << 20);
-        self.apply_logger(storage, db_path);
+        self.apply_logger(storage, db_path, create_info_log)?;
         if self.block_cache.is_none() {
             let mut shards = vec![];
             for _ in 0..DEFAULT_CACHE_SHARDS {
-                shards.push(LRUCache::new(8 << 20));
+                let shard = LRUCache::new(8 << 20);
+                shards.push(match &self.secondary_cache {
+                    Some(sc) => shard.with_eviction_listener(spill_to(sc.clone())),
+                    None => shard,
+                });
             }
             self.block_cache = Some(Arc::new(ShardedCache::new(shards)))
         }
@@ -221,15 +410,28 @@ impl<C: Comparator> Options<C> {
             let bf = BloomFilter::new(10);
             self.filter_policy = Some(Arc::new(InternalFilterPolicy::new(Arc::new(bf))))
         }
+        Ok(())
     }
 
-    fn apply_logger<S: Storage>(&mut self, storage: &S, db_path: &str) {
+    fn apply_logger<S: Storage>(
+        &mut self,
+        storage: &S,
+        db_path: &str,
+        create_info_log: bool,
+    ) -> TemplateResult<()> {
         let user_logger = self.logger.take();
-        let logger = Logger::new(user_logger, self.logger_level, storage, db_path);
+        let logger = Logger::new(
+            user_logger,
+            self.logger_level,
+            storage,
+            db_path,
+            create_info_log,
+        )?;
         let static_logger: &'static dyn Log = Box::leak(Box::new(logger));
         let _ = log::set_logger(static_logger); // global logger could be set
         log::set_max_level(self.logger_level);
         info!("Logger initialized: [level {:?}]", &self.logger_level);
+        Ok(())
     }
 
     fn clip_range<N: PartialOrd + Eq + Copy>(n: N, min: N, max: N) -> N {
@@ -259,15 +461,34 @@ impl<C: Comparator> Default for Options<C> {
             max_mem_compact_level: 2,
             read_bytes_period: 1_048_576,
             write_buffer_size: 4 * 1024 * 1024, // 4MB
+            write_buffer_manager: None,
             max_open_files: 500,
+            use_direct_reads: false,
+            use_direct_io_for_flush_and_compaction: false,
+            allow_mmap_reads: false,
             block_cache: None,
+            secondary_cache: None,
             non_table_cache_files: 10,
             block_size: 4 * 1024, // 4KB
             block_restart_interval: 16,
+            partition_index: false,
+            partition_filters: false,
+            metadata_block_size: 4 * 1024,  // 4KB
             max_file_size: 2 * 1024 * 1024, // 2MB
             compression: CompressionType::SnappyCompression,
+            compression_per_level: vec![],
+            compression_opts: CompressionOptions::default(),
+            enable_blob_files: false,
+            min_blob_size: 4 * 1024,           // 4KB
+            blob_file_size: 256 * 1024 * 1024, // 256MB
+            enable_blob_garbage_collection: false,
+            blob_garbage_collection_age_cutoff: 0.25,
             reuse_logs: false,
             filter_policy: None,
+            full_filter: false,
+            filter_bits_per_key_per_level: vec![],
+            prefix_extractor: None,
+            whole_key_filtering: true,
             logger: None,
             logger_level: LevelFilter::Warn,
         }
@@ -275,7 +496,7 @@ impl<C: Comparator> Default for Options<C> {
 }
 
 /// Options that control read operations
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct ReadOptions {
     /// If true, all data read from underlying storage will be
     /// verified against corresponding checksums.
@@ -290,6 +511,26 @@ pub struct ReadOptions {
     /// not have been released).  If `snapshot` is `None`, use an implicit
     /// snapshot of the state at the beginning of this read operation.
     pub snapshot: Option<Snapshot>,
+
+    /// If true, an iterator only yields the keys with the same prefix as the target of
+    /// the last seek by `Options::prefix_extractor`, and the seek skips the tables whose
+    /// filters exclude the prefix. Has no effect without the prefix extractor or if the
+    /// target is out of the domain of the extractor. Iterating backward after such a seek
+    /// is not supported since the skipped tables are not positioned.
+    pub prefix_same_as_start: bool,
+
+    /// If set, an iterator yields no user key less than it. The files entirely below the
+    /// bound are never opened by the iterator.
+    pub iterate_lower_bound: Option<Vec<u8>>,
+
+    /// If set, an iterator yields no user key equal to or greater than it. The files
+    /// entirely at or above the bound are never opened by the iterator.
+    pub iterate_upper_bound: Option<Vec<u8>>,
+
+    /// The number of bytes an iterator reads ahead once it reads the data blocks of a table
+    /// sequentially. If zero, the size starts at 8KB and doubles up to 256KB as a scan goes
+    /// on. Default is zero.
+    pub readahead_size: usize,
 }
 
 impl Default for ReadOptions {
@@ -298,6 +539,10 @@ impl Default for ReadOptions {
             verify_checksums: false,
             fill_cache: true,
             snapshot: None,
+            prefix_same_as_start: false,
+            iterate_lower_bound: None,
+            iterate_upper_bound: None,
+            readahead_size: 0,
         }
     }
 }
@@ -1,7 +1,6 @@
-use std::{
-    cmp::{Ordering, min},
-    sync::Arc,
-};
+use std::cmp::{Ordering, min};
+
+use bytes::Bytes;
 
 use crate::{
     error::{TemplateKVError, TemplateResult},
@@ -31,7 +30,7 @@ const U32_LEN: usize = std::mem::size_of::<u32>();
 /// ```
 #[derive(Clone, Debug)]
 pub struct Block {
-    data: Arc<Vec<u8>>,
+    data: Bytes,
     // restart array starting in `data`
     restart_offset: u32,
     // the lenght of restart array
@@ -44,7 +43,8 @@ impl Block {
     /// # Errors
     ///
     /// If the given `data` is invalid, return an error with `Status::Corruption`
-    pub fn new(data: Vec<u8>) -> TemplateResult<Self> {
+    pub fn new(data: impl Into<Bytes>) -> TemplateResult<Self> {
+        let data = data.into();
         let size = data.len();
         if size >= U32_LEN {
             let max_restarts_allowed = (size - U32_LEN) / U32_LEN;
@@ -52,7 +52,7 @@ impl Block {
             // make sure the size is enough for restarts
             if restarts_len as usize <= max_restarts_allowed {
                 return Ok(Self {
-                    data: Arc::new(data),
+                    data,
                     restart_offset: (size - (1 + restarts_len as usize) * U32_LEN) as u32,
                     restarts_len,
                 });
@@ -63,6 +63,22 @@ impl Block {
         ))
     }
 
+    /// Creates a `Block` over contents not in the block format, like a filter partition, so
+    /// that they can be kept in the block cache. The block yields no entry.
+    pub fn new_raw(data: impl Into<Bytes>) -> Self {
+        Self {
+            data: data.into(),
+            restart_offset: 0,
+            restarts_len: 0,
+        }
+    }
+
+    /// Returns the contents of the block
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
     /// Create a BlockIterator for current block.
     pub fn iter<C: Comparator>(&self, cmp: C) -> BlockIterator<C> {
         BlockIterator::new(
@@ -84,7 +100,7 @@ impl Block {
 impl Default for Block {
     fn default() -> Self {
         Self {
-            data: Arc::new(vec![]),
+            data: Bytes::new(),
             restart_offset: 0,
             restarts_len: 0,
         }
@@ -97,7 +113,7 @@ pub struct BlockIterator<C: Comparator> {
     err: Option<TemplateKVError>,
 
     // underlying block data
-    data: Arc<Vec<u8>>,
+    data: Bytes,
 
     /*
       restarts
@@ -122,7 +138,7 @@ pub struct BlockIterator<C: Comparator> {
 }
 
 impl<C: Comparator> BlockIterator<C> {
-    pub fn new(cmp: C, data: Arc<Vec<u8>>, restarts: u32, restarts_len: u32) -> Self {
+    pub fn new(cmp: C, data: Bytes, restarts: u32, restarts_len: u32) -> Self {
         Self {
             cmp,
             err: None,
@@ -139,6 +155,15 @@ impl<C: Comparator> BlockIterator<C> {
         }
     }
 
+    /// Returns the value of the current entry sharing the block data without copying, so
+    /// that it can outlive the iterator
+    pub fn value_bytes(&self) -> Bytes {
+        self.valid_or_panic();
+        let val_offset = (self.next_entry_offset() - self.value_len) as usize;
+        self.data
+            .slice(val_offset..val_offset + self.value_len as usize)
+    }
+
     // Returns the offset just pasts the end of the current entry
     #[inline]
     fn next_entry_offset(&self) -> u32 {
@@ -543,6 +568,26 @@ mod tests {
         assert!(!iter.valid());
     }
 
+    #[test]
+    fn test_value_bytes() {
+        let ucmp = BytewiseComparator::default();
+        let mut builder = BlockBuilder::new(2, ucmp);
+        for (k, v) in [("a", "v1"), ("b", "value2"), ("c", "")] {
+            builder.add(k.as_bytes(), v.as_bytes());
+        }
+        let block = Block::new(Vec::from(builder.finish())).unwrap();
+        let mut iter = block.iter(ucmp);
+        iter.seek(b"b");
+        let value = iter.value_bytes();
+        assert_eq!(&value[..], b"value2");
+        // The value shares the block data, and outlives the iterator and the block
+        let data = block.data().as_ptr_range();
+        assert!(data.contains(&value.as_ptr()));
+        drop(iter);
+        drop(block);
+        assert_eq!(&value[..], b"value2");
+    }
+
     #[test]
     #[should_panic]
     fn test_add_inconsistent_key() {
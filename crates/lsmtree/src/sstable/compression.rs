@@ -0,0 +1,272 @@
+use std::io;
+
+use bytes::Bytes;
+use snap::raw::max_compress_len;
+use zstd::{bulk, dict::DecoderDictionary};
+
+use crate::{
+    error::{TemplateKVError, TemplateResult},
+    options::CompressionType,
+    util::varint::VarintU32,
+};
+
+/// The key of the zstd dictionary block in the meta index block
+pub const COMPRESSION_DICT_KEY: &str = "compression.zstd_dict";
+
+/// Compresses blocks by the algorithm of a table.
+///
+/// The data blocks can be compressed with a zstd dictionary set by `set_dictionary` while the
+/// index and meta blocks are always compressed without it.
+pub struct BlockCompressor {
+    compression: CompressionType,
+    zstd: Option<bulk::Compressor<'static>>,
+    zstd_with_dict: Option<bulk::Compressor<'static>>,
+    level: i32,
+}
+
+impl BlockCompressor {
+    pub fn new(compression: CompressionType, level: i32) -> Self {
+        Self {
+            compression,
+            zstd: None,
+            zstd_with_dict: None,
+            level,
+        }
+    }
+
+    #[inline]
+    pub fn compression(&self) -> CompressionType {
+        self.compression
+    }
+
+    /// Uses `dict` for compressing the following data blocks. Only takes effect for zstd.
+    pub fn set_dictionary(&mut self, dict: &[u8]) -> TemplateResult<()> {
+        if self.compression == CompressionType::ZstdCompression {
+            self.zstd_with_dict = Some(
+                bulk::Compressor::with_dictionary(self.level, dict).map_err(TemplateKVError::IO)?,
+            );
+        }
+        Ok(())
+    }
+
+    /// Compresses the given raw block. Returns the compressed data and the compression type
+    /// recorded in the block trailer.
+    pub fn compress(
+        &mut self,
+        raw_block: &[u8],
+        use_dict: bool,
+    ) -> TemplateResult<(Vec<u8>, CompressionType)> {
+        match self.compression {
+            CompressionType::SnappyCompression => {
+                let mut enc = snap::raw::Encoder::new();
+                // TODO: avoid this allocation ?
+                let mut buffer = vec![0; max_compress_len(raw_block.len())];
+                match enc.compress(raw_block, buffer.as_mut_slice()) {
+                    Ok(size) => buffer.truncate(size),
+                    Err(e) => return Err(TemplateKVError::CompressionFailed(e)),
+                }
+                Ok((buffer, CompressionType::SnappyCompression))
+            }
+            CompressionType::Lz4Compression => {
+                let mut buffer = vec![];
+                VarintU32::put_varint(&mut buffer, raw_block.len() as u32);
+                buffer.extend_from_slice(&lz4_flex::block::compress(raw_block));
+                Ok((buffer, CompressionType::Lz4Compression))
+            }
+            CompressionType::ZstdCompression => {
+                if self.zstd.is_none() {
+                    self.zstd =
+                        Some(bulk::Compressor::new(self.level).map_err(TemplateKVError::IO)?);
+                }
+                let compressor = match (use_dict, self.zstd_with_dict.as_mut()) {
+                    (true, Some(c)) => c,
+                    _ => self.zstd.as_mut().unwrap(),
+                };
+                let mut buffer = vec![];
+                VarintU32::put_varint(&mut buffer, raw_block.len() as u32);
+                buffer.extend_from_slice(
+                    &compressor
+                        .compress(raw_block)
+                        .map_err(TemplateKVError::IO)?,
+                );
+                Ok((buffer, CompressionType::ZstdCompression))
+            }
+            CompressionType::NoCompression | CompressionType::Unknown => {
+                Ok((Vec::from(raw_block), CompressionType::NoCompression))
+            }
+        }
+    }
+}
+
+/// Decompresses the block `data` stored as `compression`. The zstd dictionary `dict` is
+/// required if the block is compressed with one.
+pub fn decompress_block(
+    data: impl Into<Bytes>,
+    compression: CompressionType,
+    dict: Option<&DecoderDictionary<'static>>,
+) -> TemplateResult<Bytes> {
+    let data = data.into();
+    let decompressed = match compression {
+        // The uncompressed block shares the buffer read
+        CompressionType::NoCompression => return Ok(data),
+        CompressionType::SnappyCompression => {
+            // TODO: use pre-allocated buf
+            let mut decompressed = vec![];
+            match snap::raw::decompress_len(&data) {
+                Ok(len) => decompressed.resize(len, 0u8),
+                Err(e) => return Err(TemplateKVError::CompressionFailed(e)),
+            }
+            let mut dec = snap::raw::Decoder::new();
+            if let Err(e) = dec.decompress(&data, decompressed.as_mut_slice()) {
+                return Err(TemplateKVError::CompressionFailed(e));
+            }
+            Ok(decompressed)
+        }
+        CompressionType::Lz4Compression => {
+            let (len, n) = decode_raw_len(&data)?;
+            let decompressed = lz4_flex::block::decompress(&data[n..], len).map_err(|e| {
+                TemplateKVError::Corruption(format!("lz4 decompression failed: {}", e))
+            })?;
+            check_raw_len(decompressed, len)
+        }
+        CompressionType::ZstdCompression => {
+            let (len, n) = decode_raw_len(&data)?;
+            let on_err = |e: io::Error| {
+                TemplateKVError::Corruption(format!("zstd decompression failed: {}", e))
+            };
+            let mut decompressor = match dict {
+                Some(d) => bulk::Decompressor::with_prepared_dictionary(d),
+                None => bulk::Decompressor::new(),
+            }
+            .map_err(on_err)?;
+            let decompressed = decompressor.decompress(&data[n..], len).map_err(on_err)?;
+            check_raw_len(decompressed, len)
+        }
+        CompressionType::Unknown => Err(TemplateKVError::Corruption(
+            "bad block compression type".to_owned(),
+        )),
+    }?;
+    Ok(Bytes::from(decompressed))
+}
+
+/// Trains a zstd dictionary no larger than `max_size` from the sampled raw blocks
+pub fn train_dictionary<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> TemplateResult<Vec<u8>> {
+    zstd::dict::from_samples(samples, max_size).map_err(TemplateKVError::IO)
+}
+
+// Returns the uncompressed length prefixed to the compressed block and the length of the prefix
+fn decode_raw_len(data: &[u8]) -> TemplateResult<(usize, usize)> {
+    match VarintU32::read(data) {
+        Some((len, n)) => Ok((len as usize, n)),
+        None => Err(TemplateKVError::Corruption(
+            "bad uncompressed block length".to_owned(),
+        )),
+    }
+}
+
+fn check_raw_len(decompressed: Vec<u8>, len: usize) -> TemplateResult<Vec<u8>> {
+    if decompressed.len() != len {
+        return Err(TemplateKVError::Corruption(format!(
+            "decompressed block length {} does not match {}",
+            decompressed.len(),
+            len
+        )));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use zstd::dict::DecoderDictionary;
+
+    use super::*;
+
+    fn sample_block(i: usize, entries: usize) -> Vec<u8> {
+        let mut block = vec![];
+        for j in 0..entries {
+            block.extend_from_slice(
+                format!(
+                    "user{:08}:field{}=value-{}-{};",
+                    i * 64 + j,
+                    j % 7,
+                    i % 3,
+                    j
+                )
+                .as_bytes(),
+            );
+        }
+        block
+    }
+
+    #[test]
+    fn test_compress_and_decompress() {
+        let raw = sample_block(0, 64);
+        for compression in [
+            CompressionType::NoCompression,
+            CompressionType::SnappyCompression,
+            CompressionType::Lz4Compression,
+            CompressionType::ZstdCompression,
+        ] {
+            let mut c = BlockCompressor::new(compression, 3);
+            let (compressed, ct) = c.compress(&raw, true).unwrap();
+            assert_eq!(ct, compression);
+            if compression != CompressionType::NoCompression {
+                assert!(compressed.len() < raw.len(), "{:?}", compression);
+            }
+            assert_eq!(decompress_block(compressed, ct, None).unwrap(), raw);
+        }
+        // Unknown falls back to no compression
+        let mut c = BlockCompressor::new(CompressionType::Unknown, 3);
+        let (compressed, ct) = c.compress(&raw, false).unwrap();
+        assert_eq!(ct, CompressionType::NoCompression);
+        assert_eq!(compressed, raw);
+        assert!(decompress_block(raw, CompressionType::Unknown, None).is_err());
+    }
+
+    #[test]
+    fn test_zstd_dictionary() {
+        // Small blocks compress poorly on their own and benefit from a dictionary
+        let samples = (0..200).map(|i| sample_block(i, 8)).collect::<Vec<_>>();
+        let dict = train_dictionary(&samples, 4096).unwrap();
+        assert!(!dict.is_empty() && dict.len() <= 4096);
+        let mut c = BlockCompressor::new(CompressionType::ZstdCompression, 3);
+        c.set_dictionary(&dict).unwrap();
+        let raw = sample_block(1000, 8);
+        let (with_dict, _) = c.compress(&raw, true).unwrap();
+        let (without_dict, _) = c.compress(&raw, false).unwrap();
+        assert!(with_dict.len() < without_dict.len());
+
+        let d = DecoderDictionary::copy(&dict);
+        assert_eq!(
+            decompress_block(
+                with_dict.clone(),
+                CompressionType::ZstdCompression,
+                Some(&d)
+            )
+            .unwrap(),
+            raw
+        );
+        assert_eq!(
+            decompress_block(without_dict, CompressionType::ZstdCompression, None).unwrap(),
+            raw
+        );
+        // The dictionary is required
+        assert!(decompress_block(with_dict, CompressionType::ZstdCompression, None).is_err());
+    }
+
+    #[test]
+    fn test_decompress_corrupted_block() {
+        let raw = sample_block(0, 64);
+        for compression in [
+            CompressionType::Lz4Compression,
+            CompressionType::ZstdCompression,
+        ] {
+            let mut c = BlockCompressor::new(compression, 3);
+            let (mut compressed, ct) = c.compress(&raw, false).unwrap();
+            // A wrong uncompressed length
+            compressed[0] ^= 0x01;
+            let e = decompress_block(compressed, ct, None).unwrap_err();
+            assert!(e.is_corruption(), "{:?}: {}", compression, e);
+        }
+    }
+}
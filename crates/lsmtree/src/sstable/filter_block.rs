@@ -95,6 +95,71 @@ impl FilterBlockBuilder {
     }
 }
 
+/// A `FullFilterBuilder` builds a single filter over all the keys of a table, so a lookup
+/// can rule out the whole table with one probe before reading its index.
+pub struct FullFilterBuilder {
+    policy: Arc<dyn FilterPolicy>,
+    keys: Vec<Vec<u8>>,
+}
+
+impl FullFilterBuilder {
+    pub fn new(policy: Arc<dyn FilterPolicy>) -> Self {
+        Self {
+            policy,
+            keys: vec![],
+        }
+    }
+
+    /// Adds the given key into the builder
+    pub fn add_key(&mut self, key: &[u8]) {
+        self.keys.push(Vec::from(key));
+    }
+
+    /// Generates the filter over the keys added so far and resets the builder
+    pub fn finish(&mut self) -> Vec<u8> {
+        let filter = self.policy.create_filter(&self.keys);
+        self.keys.clear();
+        filter
+    }
+}
+
+/// A `PartitionedFilterBuilder` builds the filter partitions of a table with the partitioned
+/// index. Each partition is a single filter over the keys of the data blocks indexed by the
+/// corresponding index partition, so the partition to check for a key is found by the same
+/// separator keys as the index partitions.
+pub struct PartitionedFilterBuilder {
+    // the builder of the current partition
+    current: FullFilterBuilder,
+    // the finished partitions
+    partitions: Vec<Vec<u8>>,
+}
+
+impl PartitionedFilterBuilder {
+    pub fn new(policy: Arc<dyn FilterPolicy>) -> Self {
+        Self {
+            current: FullFilterBuilder::new(policy),
+            partitions: vec![],
+        }
+    }
+
+    /// Adds the given key into the current partition
+    #[inline]
+    pub fn add_key(&mut self, key: &[u8]) {
+        self.current.add_key(key);
+    }
+
+    /// Generates the filter of the current partition. Called when an index partition is cut.
+    pub fn finish_partition(&mut self) {
+        let filter = self.current.finish();
+        self.partitions.push(filter);
+    }
+
+    /// Returns all the finished filter partitions in order
+    pub fn take_partitions(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.partitions)
+    }
+}
+
 pub struct FilterBlockReader {
     policy: Arc<dyn FilterPolicy>,
     // all filter block data without filter meta
@@ -158,6 +223,20 @@ impl FilterBlockReader {
     }
 }
 
+/// Returns the number of filters and the base lg in the given filter block, or `None` if the
+/// block is malformed
+pub(crate) fn decode_filter_block_meta(filter_block: &[u8]) -> Option<(usize, usize)> {
+    let n = filter_block.len();
+    if n < FILTER_META_LENGTH {
+        return None;
+    }
+    let num = decode_fixed_32(&filter_block[n - FILTER_META_LENGTH..n - 1]) as usize;
+    if num * FILTER_OFFSET_LEN + FILTER_META_LENGTH > n {
+        return None;
+    }
+    Some((num, filter_block[n - 1] as usize))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -192,6 +271,44 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_full_filter_builder() {
+        let policy = Arc::new(TestHashFilter {});
+        let mut b = FullFilterBuilder::new(policy.clone());
+        for key in [b"foo".as_slice(), b"bar", b"box", b"hello"] {
+            b.add_key(key);
+        }
+        let filter = b.finish();
+        for key in [b"foo".as_slice(), b"bar", b"box", b"hello"] {
+            assert!(policy.may_contain(&filter, key));
+        }
+        assert!(!policy.may_contain(&filter, b"missing"));
+        // The builder is reset
+        assert!(!policy.may_contain(&b.finish(), b"foo"));
+    }
+
+    #[test]
+    fn test_partitioned_filter_builder() {
+        let policy = Arc::new(TestHashFilter {});
+        let mut b = PartitionedFilterBuilder::new(policy.clone());
+        b.add_key(b"foo");
+        b.add_key(b"bar");
+        b.finish_partition();
+        // an empty partition
+        b.finish_partition();
+        b.add_key(b"hello");
+        b.finish_partition();
+        let partitions = b.take_partitions();
+        assert_eq!(partitions.len(), 3);
+        assert!(policy.may_contain(&partitions[0], b"foo"));
+        assert!(policy.may_contain(&partitions[0], b"bar"));
+        assert!(!policy.may_contain(&partitions[0], b"hello"));
+        assert!(!policy.may_contain(&partitions[1], b"foo"));
+        assert!(policy.may_contain(&partitions[2], b"hello"));
+        assert!(!policy.may_contain(&partitions[2], b"foo"));
+        assert!(b.take_partitions().is_empty());
+    }
+
     fn new_test_builder() -> FilterBlockBuilder {
         FilterBlockBuilder::new(Arc::new(TestHashFilter {}))
     }
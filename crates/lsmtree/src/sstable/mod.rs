@@ -182,7 +182,11 @@
 ///
 /// NOTE: All fixed-length integer are little-endian.
 pub mod block;
+pub mod compression;
 mod filter_block;
+pub mod prefetch;
+pub mod sst_dump;
+pub mod sst_file_writer;
 pub mod table;
 
 use crate::{
@@ -249,7 +253,7 @@ impl BlockHandle {
     ///
     /// # Error
     ///
-    /// If varint decoding fails, return `Status::Corruption` with relative messages 
+    /// If varint decoding fails, return `Status::Corruption` with relative messages
     #[inline]
     pub fn decode_from(src: &[u8]) -> TemplateResult<(Self, usize)> {
         if let Some((offset, n)) = VarintU64::read(src) {
@@ -0,0 +1,228 @@
+use bytes::Bytes;
+
+use crate::{
+    error::{TemplateKVError, TemplateResult},
+    storage::File,
+};
+
+/// The readahead size of the first prefetch if the size is adaptive
+pub const INITIAL_READAHEAD_SIZE: usize = 8 * 1024;
+
+/// The max readahead size if the size is adaptive
+pub const MAX_AUTO_READAHEAD_SIZE: usize = 256 * 1024;
+
+// The number of the sequential reads before prefetching. A few point reads of an iterator
+// don't pay for the readahead.
+const SEQUENTIAL_READS_BEFORE_PREFETCH: usize = 2;
+
+// The readahead is split into the reads of this size, which are issued in a batch by
+// `File::read_exact_at_batch` so that they can be served concurrently
+const READAHEAD_CHUNK_SIZE: usize = 32 * 1024;
+
+/// `FilePrefetchBuffer` serves the sequential block reads of a table from a buffer filled by
+/// reading ahead of the blocks.
+///
+/// The readahead starts once the blocks are read sequentially for a few times. With a fixed
+/// readahead size, every prefetch reads that many bytes. Otherwise the size starts at
+/// `INITIAL_READAHEAD_SIZE` and doubles for every prefetch up to `MAX_AUTO_READAHEAD_SIZE`,
+/// and a non-sequential read resets it.
+///
+/// The buffer may also hold several ranges of the file prefetched by `prefetch_batch`.
+pub struct FilePrefetchBuffer {
+    // the prefetched ranges with their file offsets
+    buffers: Vec<(u64, Vec<u8>)>,
+    readahead_size: usize,
+    // the prefetches never read beyond the end of the file
+    file_size: u64,
+    // true if the readahead size is fixed
+    fixed: bool,
+    // the end offset of the last read, to tell whether the reads are sequential
+    prev_end: Option<u64>,
+    num_sequential_reads: usize,
+}
+
+impl FilePrefetchBuffer {
+    /// Creates a `FilePrefetchBuffer` for a file of `file_size` bytes reading ahead
+    /// `readahead_size` bytes at a time, or an adaptive size if it's zero
+    pub fn new(readahead_size: usize, file_size: u64) -> Self {
+        Self {
+            buffers: vec![],
+            readahead_size: if readahead_size == 0 {
+                INITIAL_READAHEAD_SIZE
+            } else {
+                readahead_size
+            },
+            file_size,
+            fixed: readahead_size > 0,
+            prev_end: None,
+            num_sequential_reads: 0,
+        }
+    }
+
+    /// Reads `n` bytes at `offset` of `file`, from the buffer if they are prefetched
+    pub fn read<F: File>(&mut self, file: &F, offset: u64, n: usize) -> TemplateResult<Bytes> {
+        if self.prev_end == Some(offset) {
+            self.num_sequential_reads += 1;
+        } else {
+            self.num_sequential_reads = 0;
+            if !self.fixed {
+                self.readahead_size = INITIAL_READAHEAD_SIZE;
+            }
+        }
+        self.prev_end = Some(offset + n as u64);
+        if let Some(data) = self.try_read_buffer(offset, n) {
+            return Ok(data);
+        }
+        if self.num_sequential_reads < SEQUENTIAL_READS_BEFORE_PREFETCH {
+            let mut data = vec![0; n];
+            file.read_exact_at(&mut data, offset)?;
+            return Ok(Bytes::from(data));
+        }
+        let len = (n.max(self.readahead_size) as u64).min(self.file_size.saturating_sub(offset));
+        self.prefetch(file, offset, len as usize)?;
+        if !self.fixed {
+            self.readahead_size = (self.readahead_size * 2).min(MAX_AUTO_READAHEAD_SIZE);
+        }
+        self.try_read_buffer(offset, n).ok_or_else(|| {
+            TemplateKVError::Corruption(format!(
+                "truncated block read at {}, expect {} bytes",
+                offset, n
+            ))
+        })
+    }
+
+    /// Returns the current readahead size
+    #[inline]
+    pub fn readahead_size(&self) -> usize {
+        self.readahead_size
+    }
+
+    /// Fills the buffer with `n` bytes at `offset`, so that the following reads within them
+    /// are served from the buffer
+    pub fn prefetch<F: File>(&mut self, file: &F, offset: u64, n: usize) -> TemplateResult<()> {
+        self.buffers.clear();
+        let mut buf = vec![0; n];
+        let mut reads = buf
+            .chunks_mut(READAHEAD_CHUNK_SIZE)
+            .enumerate()
+            .map(|(i, chunk)| (offset + (i * READAHEAD_CHUNK_SIZE) as u64, chunk))
+            .collect::<Vec<_>>();
+        file.read_exact_at_batch(&mut reads)?;
+        self.buffers.push((offset, buf));
+        Ok(())
+    }
+
+    /// Fills the buffer with all the `(offset, n)` ranges of the file by one batch of reads,
+    /// so that the following reads within them are served from the buffer
+    pub fn prefetch_batch<F: File>(
+        &mut self,
+        file: &F,
+        ranges: &[(u64, usize)],
+    ) -> TemplateResult<()> {
+        self.buffers.clear();
+        let mut buffers = ranges
+            .iter()
+            .map(|(offset, n)| (*offset, vec![0; *n]))
+            .collect::<Vec<_>>();
+        let mut reads = buffers
+            .iter_mut()
+            .map(|(offset, buf)| (*offset, buf.as_mut_slice()))
+            .collect::<Vec<_>>();
+        file.read_exact_at_batch(&mut reads)?;
+        self.buffers = buffers;
+        Ok(())
+    }
+
+    #[inline]
+    fn try_read_buffer(&self, offset: u64, n: usize) -> Option<Bytes> {
+        self.buffers.iter().find_map(|(buf_offset, buf)| {
+            if offset >= *buf_offset && offset + n as u64 <= buf_offset + buf.len() as u64 {
+                let start = (offset - buf_offset) as usize;
+                // Copied rather than shared, since a cached block sharing the buffer would
+                // keep the whole of it alive
+                Some(Bytes::copy_from_slice(&buf[start..start + n]))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{Storage, mem::MemStorage};
+
+    // A file of `n` bytes whose i-th byte is `i % 251`
+    fn new_file(n: usize) -> <MemStorage as Storage>::F {
+        let s = MemStorage::default();
+        let mut f = s.create("test").unwrap();
+        let data = (0..n).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        f.write(&data).unwrap();
+        f.flush().unwrap();
+        s.open("test").unwrap()
+    }
+
+    fn expected(offset: u64, n: usize) -> Vec<u8> {
+        (offset as usize..offset as usize + n)
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn test_adaptive_readahead() {
+        let file = new_file(1 << 20);
+        let mut buffer = FilePrefetchBuffer::new(0, 1 << 20);
+        let mut offset = 0;
+        let mut sizes = vec![];
+        for _ in 0..200 {
+            let data = buffer.read(&file, offset, 1000).unwrap();
+            assert_eq!(data, expected(offset, 1000));
+            offset += 1000;
+            sizes.push(buffer.readahead_size());
+        }
+        // No prefetch until the reads are sequential for a while
+        assert_eq!(sizes[1], INITIAL_READAHEAD_SIZE);
+        assert_eq!(sizes[2], INITIAL_READAHEAD_SIZE * 2);
+        assert_eq!(*sizes.last().unwrap(), MAX_AUTO_READAHEAD_SIZE);
+        // A random read resets the size
+        let data = buffer.read(&file, 10, 100).unwrap();
+        assert_eq!(data, expected(10, 100));
+        assert_eq!(buffer.readahead_size(), INITIAL_READAHEAD_SIZE);
+    }
+
+    #[test]
+    fn test_fixed_readahead() {
+        let file = new_file(10000);
+        let mut buffer = FilePrefetchBuffer::new(4096, 10000);
+        let mut offset = 0;
+        while offset + 300 <= 10000 {
+            let data = buffer.read(&file, offset, 300).unwrap();
+            assert_eq!(data, expected(offset, 300));
+            assert_eq!(buffer.readahead_size(), 4096);
+            offset += 300;
+        }
+        // Reading beyond the end of the file
+        assert!(buffer.read(&file, 9900, 300).is_err());
+    }
+
+    #[test]
+    fn test_prefetch_batch() {
+        let file = new_file(10000);
+        let mut buffer = FilePrefetchBuffer::new(0, 10000);
+        buffer
+            .prefetch_batch(&file, &[(100, 200), (300, 100), (5000, 1000)])
+            .unwrap();
+        for (offset, n) in [(100, 50), (250, 50), (300, 100), (5500, 500)] {
+            assert_eq!(
+                buffer.try_read_buffer(offset, n).unwrap(),
+                expected(offset, n)
+            );
+        }
+        // The reads across the ranges are not buffered
+        assert!(buffer.try_read_buffer(250, 100).is_none());
+        assert!(buffer.try_read_buffer(4999, 10).is_none());
+        assert!(buffer.prefetch_batch(&file, &[(9000, 1001)]).is_err());
+        assert!(buffer.try_read_buffer(5500, 500).is_none());
+    }
+}
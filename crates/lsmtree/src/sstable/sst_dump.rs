@@ -0,0 +1,518 @@
+use std::fmt::{self, Display, Formatter};
+
+use bytes::Bytes;
+use zstd::dict::DecoderDictionary;
+
+use crate::{
+    error::{Operation, TemplateKVError, TemplateResult},
+    iterator::Iterator,
+    memtable::{
+        key_format::{InternalKey, ParsedInternalKey},
+        value_format::ValueType,
+    },
+    sstable::{
+        BLOCK_TRAILER_SIZE, BlockHandle, FOOTER_ENCODED_LENGTH, Footer,
+        block::Block,
+        compression::COMPRESSION_DICT_KEY,
+        filter_block::decode_filter_block_meta,
+        table::{
+            FULL_FILTER_KEY_PREFIX, PARTITIONED_FILTER_KEY_PREFIX, PARTITIONED_INDEX_KEY,
+            PREFIX_EXTRACTOR_KEY, read_block_with_dict,
+        },
+    },
+    storage::{COPY_BUFFER_SIZE, File},
+    util::{comparator::BytewiseComparator, crc32},
+};
+
+const FILTER_KEY_PREFIX: &str = "filter.";
+
+/// The statistics of a sst file collected by scanning all its blocks
+#[derive(Debug, Default, Clone)]
+pub struct TableProperties {
+    pub file_size: u64,
+    /// The crc32 of the whole file
+    pub file_checksum: u32,
+    pub num_data_blocks: u64,
+    pub num_entries: u64,
+    pub num_deletions: u64,
+    pub raw_key_size: u64,
+    pub raw_value_size: u64,
+    /// The size of all the data blocks including their trailers
+    pub data_size: u64,
+    /// The size of the index block including the index partitions if any
+    pub index_size: u64,
+    /// The size of the filter block or the filter partitions along with their index
+    pub filter_size: u64,
+    /// The size of the zstd dictionary block, zero if there is no dictionary
+    pub compression_dict_size: u64,
+    /// The name of the filter policy the filter block is built by
+    pub filter_policy: Option<String>,
+    /// The name of the prefix extractor whose prefixes are in the filters
+    pub prefix_extractor: Option<String>,
+    /// Whether the whole keys are in the filters
+    pub whole_key_filtering: bool,
+    pub smallest_key: Option<InternalKey>,
+    pub largest_key: Option<InternalKey>,
+    pub smallest_seq: u64,
+    pub largest_seq: u64,
+}
+
+impl Display for TableProperties {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "file size: {}", self.file_size)?;
+        writeln!(f, "file checksum: {:#010x}", self.file_checksum)?;
+        writeln!(f, "# data blocks: {}", self.num_data_blocks)?;
+        writeln!(f, "# entries: {}", self.num_entries)?;
+        writeln!(f, "# deletions: {}", self.num_deletions)?;
+        writeln!(f, "raw key size: {}", self.raw_key_size)?;
+        writeln!(f, "raw value size: {}", self.raw_value_size)?;
+        writeln!(f, "data blocks size: {}", self.data_size)?;
+        writeln!(f, "index block size: {}", self.index_size)?;
+        writeln!(f, "compression dict size: {}", self.compression_dict_size)?;
+        match &self.filter_policy {
+            Some(name) => writeln!(f, "filter block size: {} ({})", self.filter_size, name)?,
+            None => writeln!(f, "filter block size: 0")?,
+        }
+        if let Some(name) = &self.prefix_extractor {
+            writeln!(
+                f,
+                "prefix extractor: {} (whole key filtering: {})",
+                name, self.whole_key_filtering
+            )?;
+        }
+        writeln!(f, "smallest key: {:?}", self.smallest_key)?;
+        writeln!(f, "largest key: {:?}", self.largest_key)?;
+        write!(
+            f,
+            "sequence range: [{}, {}]",
+            self.smallest_seq, self.largest_seq
+        )
+    }
+}
+
+/// The summary of a filter block referenced by the meta index block.
+///
+/// For partitioned filters, `handle` refers to the index of the filter partitions and
+/// `num_filters` is the number of the partitions. A full filter over the whole table counts as
+/// one filter.
+#[derive(Debug, Clone)]
+pub struct FilterBlockInfo {
+    pub policy: String,
+    pub handle: BlockHandle,
+    pub num_filters: usize,
+    pub base_lg: usize,
+    /// The handles of the filter partitions, empty if the filter is not partitioned
+    pub partitions: Vec<BlockHandle>,
+}
+
+/// `SstFileDumper` reads the raw blocks of a sst file for offline inspection.
+///
+/// Unlike `Table`, it doesn't need the options the file is built with and reads every block
+/// directly from the file without any cache.
+pub struct SstFileDumper<F: File> {
+    file: F,
+    file_size: u64,
+    footer: Footer,
+    verify_checksums: bool,
+    compression_dict: Option<(BlockHandle, DecoderDictionary<'static>)>,
+    // The handles in the top-level index if the index is partitioned
+    index_partitions: Vec<BlockHandle>,
+}
+
+impl<F: File> SstFileDumper<F> {
+    /// Reads the footer of `file`. If `verify_checksums` is true, every block read later is
+    /// verified against its checksum.
+    pub fn open(file: F, verify_checksums: bool) -> TemplateResult<Self> {
+        let file_size = file.len()?;
+        if file_size < FOOTER_ENCODED_LENGTH as u64 {
+            return Err(TemplateKVError::Corruption(
+                "file is too short to be an sstable".to_owned(),
+            ));
+        }
+        let mut footer_space = vec![0; FOOTER_ENCODED_LENGTH];
+        file.read_exact_at(&mut footer_space, file_size - FOOTER_ENCODED_LENGTH as u64)?;
+        let (footer, _) = Footer::decode_from(&footer_space)?;
+        let mut dumper = Self {
+            file,
+            file_size,
+            footer,
+            verify_checksums,
+            compression_dict: None,
+            index_partitions: vec![],
+        };
+        // A broken meta index block is reported by the later inspections
+        if let Ok(entries) = dumper.meta_index() {
+            if let Some((_, handle)) = entries.iter().find(|(k, _)| k == COMPRESSION_DICT_KEY) {
+                let dict = dumper.read_block(handle)?;
+                dumper.compression_dict = Some((handle.clone(), DecoderDictionary::copy(&dict)));
+            }
+            if entries.iter().any(|(k, _)| k == PARTITIONED_INDEX_KEY) {
+                let mut partitions = vec![];
+                dumper.for_each_in_block(&dumper.footer.index_handle, |_, value| {
+                    partitions.push(BlockHandle::decode_from(value)?.0);
+                    Ok(())
+                })?;
+                dumper.index_partitions = partitions;
+            }
+        }
+        Ok(dumper)
+    }
+
+    /// Returns the handle of the zstd dictionary block if the data blocks are compressed with
+    /// a dictionary
+    #[inline]
+    pub fn compression_dict_handle(&self) -> Option<&BlockHandle> {
+        self.compression_dict.as_ref().map(|(h, _)| h)
+    }
+
+    #[inline]
+    pub fn footer(&self) -> &Footer {
+        &self.footer
+    }
+
+    /// Returns the entries in the meta index block
+    pub fn meta_index(&self) -> TemplateResult<Vec<(String, BlockHandle)>> {
+        if self.footer.meta_index_handle.size == 0 {
+            return Ok(vec![]);
+        }
+        let mut entries = vec![];
+        self.for_each_in_block(&self.footer.meta_index_handle, |key, value| {
+            let (handle, _) = BlockHandle::decode_from(value)?;
+            entries.push((String::from_utf8_lossy(key).into_owned(), handle));
+            Ok(())
+        })?;
+        Ok(entries)
+    }
+
+    /// Returns the handles of the index partitions, empty if the index is not partitioned
+    #[inline]
+    pub fn index_partitions(&self) -> &[BlockHandle] {
+        &self.index_partitions
+    }
+
+    /// Returns the entries in the index block, or in all the index partitions if the index is
+    /// partitioned. Each key is a separator no less than the last key in the corresponding
+    /// data block.
+    pub fn index(&self) -> TemplateResult<Vec<(InternalKey, BlockHandle)>> {
+        let mut entries = vec![];
+        let mut collect = |key: &[u8], value: &[u8]| {
+            let (handle, _) = BlockHandle::decode_from(value)?;
+            entries.push((InternalKey::decoded_from(key), handle));
+            Ok(())
+        };
+        if self.index_partitions.is_empty() {
+            self.for_each_in_block(&self.footer.index_handle, &mut collect)?;
+        } else {
+            for partition in &self.index_partitions {
+                self.for_each_in_block(partition, &mut collect)?;
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns the summaries of the filter blocks
+    pub fn filters(&self) -> TemplateResult<Vec<FilterBlockInfo>> {
+        let mut filters = vec![];
+        for (key, handle) in self.meta_index()? {
+            if let Some(policy) = key.strip_prefix(PARTITIONED_FILTER_KEY_PREFIX) {
+                let mut partitions = vec![];
+                self.for_each_in_block(&handle, |_, value| {
+                    partitions.push(BlockHandle::decode_from(value)?.0);
+                    Ok(())
+                })?;
+                filters.push(FilterBlockInfo {
+                    policy: policy.to_owned(),
+                    handle,
+                    num_filters: partitions.len(),
+                    base_lg: 0,
+                    partitions,
+                });
+                continue;
+            }
+            if let Some(policy) = key.strip_prefix(FULL_FILTER_KEY_PREFIX) {
+                filters.push(FilterBlockInfo {
+                    policy: policy.to_owned(),
+                    handle,
+                    num_filters: 1,
+                    base_lg: 0,
+                    partitions: vec![],
+                });
+                continue;
+            }
+            let Some(policy) = key.strip_prefix(FILTER_KEY_PREFIX) else {
+                continue;
+            };
+            let block = self.read_block(&handle)?;
+            let Some((num_filters, base_lg)) = decode_filter_block_meta(&block) else {
+                return Err(TemplateKVError::Corruption(format!(
+                    "bad filter block {}",
+                    &key
+                )));
+            };
+            filters.push(FilterBlockInfo {
+                policy: policy.to_owned(),
+                handle,
+                num_filters,
+                base_lg,
+                partitions: vec![],
+            });
+        }
+        Ok(filters)
+    }
+
+    /// Calls `f` with every internal key and value in the data blocks in order
+    pub fn for_each_entry<T>(&self, mut f: T) -> TemplateResult<()>
+    where
+        T: FnMut(&[u8], &[u8]) -> TemplateResult<()>,
+    {
+        for (_, handle) in self.index()? {
+            self.for_each_in_block(&handle, &mut f)?;
+        }
+        Ok(())
+    }
+
+    /// Scans the whole file and collects its statistics
+    pub fn properties(&self) -> TemplateResult<TableProperties> {
+        let mut props = TableProperties {
+            file_size: self.file_size,
+            file_checksum: self.file_checksum()?,
+            index_size: self.footer.index_handle.size + BLOCK_TRAILER_SIZE as u64,
+            smallest_seq: u64::MAX,
+            compression_dict_size: self
+                .compression_dict_handle()
+                .map_or(0, |h| h.size + BLOCK_TRAILER_SIZE as u64),
+            ..Default::default()
+        };
+        for partition in &self.index_partitions {
+            props.index_size += partition.size + BLOCK_TRAILER_SIZE as u64;
+        }
+        for filter in self.filters()? {
+            props.filter_size += filter.handle.size + BLOCK_TRAILER_SIZE as u64;
+            for partition in &filter.partitions {
+                props.filter_size += partition.size + BLOCK_TRAILER_SIZE as u64;
+            }
+            props.filter_policy = Some(filter.policy);
+        }
+        props.whole_key_filtering = props.filter_policy.is_some();
+        if let Some((_, handle)) = self
+            .meta_index()?
+            .into_iter()
+            .find(|(k, _)| k == PREFIX_EXTRACTOR_KEY)
+        {
+            let data = self.read_block(&handle)?;
+            let Some((whole_key_filtering, name)) = data.split_first() else {
+                return Err(TemplateKVError::Corruption(
+                    "bad prefix extractor block".to_owned(),
+                ));
+            };
+            props.whole_key_filtering = *whole_key_filtering != 0;
+            props.prefix_extractor = Some(String::from_utf8_lossy(name).into_owned());
+        }
+        for (_, handle) in self.index()? {
+            props.num_data_blocks += 1;
+            props.data_size += handle.size + BLOCK_TRAILER_SIZE as u64;
+            self.for_each_in_block(&handle, |key, value| {
+                let Some(parsed) = ParsedInternalKey::decode_from(key) else {
+                    return Err(TemplateKVError::Corruption(format!(
+                        "bad internal key {:?} in block at {}",
+                        key, handle.offset
+                    )));
+                };
+                props.num_entries += 1;
+                if parsed.value_type == ValueType::Deletion {
+                    props.num_deletions += 1;
+                }
+                props.raw_key_size += key.len() as u64;
+                props.raw_value_size += value.len() as u64;
+                props.smallest_seq = props.smallest_seq.min(parsed.seq);
+                props.largest_seq = props.largest_seq.max(parsed.seq);
+                if props.smallest_key.is_none() {
+                    props.smallest_key = Some(InternalKey::decoded_from(key));
+                }
+                props.largest_key = Some(InternalKey::decoded_from(key));
+                Ok(())
+            })?;
+        }
+        if props.num_entries == 0 {
+            props.smallest_seq = 0;
+        }
+        Ok(props)
+    }
+
+    /// Reads every block in the file and verifies its checksum
+    pub fn verify_checksums(&self) -> TemplateResult<()> {
+        self.read_block_with(&self.footer.index_handle, true)?;
+        if self.footer.meta_index_handle.size > 0 {
+            self.for_each_in_block_with(&self.footer.meta_index_handle, true, |_, value| {
+                let (handle, _) = BlockHandle::decode_from(value)?;
+                self.read_block_with(&handle, true).map(|_| ())
+            })?;
+        }
+        for partition in &self.index_partitions {
+            self.read_block_with(partition, true)?;
+        }
+        for filter in self.filters()? {
+            for partition in &filter.partitions {
+                self.read_block_with(partition, true)?;
+            }
+        }
+        for (_, handle) in self.index()? {
+            self.for_each_in_block_with(&handle, true, |_, _| Ok(()))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the crc32 of the whole file, which is recorded in the MANIFEST when the file
+    /// is added into a db
+    pub fn file_checksum(&self) -> TemplateResult<u32> {
+        let mut buf = vec![0; COPY_BUFFER_SIZE];
+        let mut crc = 0;
+        let mut offset = 0;
+        while offset < self.file_size {
+            let n = (self.file_size - offset).min(COPY_BUFFER_SIZE as u64) as usize;
+            self.file.read_exact_at(&mut buf[..n], offset)?;
+            crc = crc32::extend(crc, &buf[..n]);
+            offset += n as u64;
+        }
+        Ok(crc)
+    }
+
+    /// Reads the block at `handle` and verifies its checksum without parsing it
+    pub fn verify_block(&self, handle: &BlockHandle) -> TemplateResult<()> {
+        self.read_block_with(handle, true).map(|_| ())
+    }
+
+    #[inline]
+    fn read_block(&self, handle: &BlockHandle) -> TemplateResult<Bytes> {
+        self.read_block_with(handle, self.verify_checksums)
+    }
+
+    fn read_block_with(&self, handle: &BlockHandle, verify: bool) -> TemplateResult<Bytes> {
+        // Only the data blocks are compressed with the dictionary
+        let dict = self.compression_dict.as_ref().map(|(_, d)| d).filter(|_| {
+            *handle != self.footer.index_handle
+                && *handle != self.footer.meta_index_handle
+                && !self.index_partitions.contains(handle)
+        });
+        read_block_with_dict(&self.file, handle, verify, dict).map_err(|e| {
+            e.with_operation(Operation::ReadBlock)
+                .with_offset(handle.offset)
+        })
+    }
+
+    /// Calls `f` with every key and value in the block at `handle` in order
+    #[inline]
+    pub fn for_each_in_block<T>(&self, handle: &BlockHandle, f: T) -> TemplateResult<()>
+    where
+        T: FnMut(&[u8], &[u8]) -> TemplateResult<()>,
+    {
+        self.for_each_in_block_with(handle, self.verify_checksums, f)
+    }
+
+    fn for_each_in_block_with<T>(
+        &self,
+        handle: &BlockHandle,
+        verify: bool,
+        mut f: T,
+    ) -> TemplateResult<()>
+    where
+        T: FnMut(&[u8], &[u8]) -> TemplateResult<()>,
+    {
+        let block = Block::new(self.read_block_with(handle, verify)?).map_err(|e| {
+            e.with_operation(Operation::ReadBlock)
+                .with_offset(handle.offset)
+        })?;
+        let mut iter = block.iter(BytewiseComparator::default());
+        iter.seek_to_first();
+        while iter.valid() {
+            f(iter.key(), iter.value())?;
+            iter.next();
+        }
+        iter.status()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        cache::bloom_filter_cache::BloomFilter,
+        options::Options,
+        sstable::sst_file_writer::{ExternalSstFileInfo, SstFileWriter},
+        storage::{Storage, mem::MemStorage},
+        util::comparator::BytewiseComparator,
+    };
+
+    fn build_sst(storage: &MemStorage, name: &str) -> ExternalSstFileInfo {
+        let mut opts = Options::<BytewiseComparator>::default();
+        opts.filter_policy = Some(Arc::new(BloomFilter::new(10)));
+        opts.block_size = 1024;
+        let mut writer = SstFileWriter::new(opts, storage.clone());
+        writer.open(name).unwrap();
+        for i in 0..1000 {
+            let key = format!("key{:06}", i);
+            if i % 10 == 0 {
+                writer.delete(key.as_bytes()).unwrap();
+            } else {
+                writer.put(key.as_bytes(), b"value").unwrap();
+            }
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_dump_sst_file() {
+        let storage = MemStorage::default();
+        let info = build_sst(&storage, "test.sst");
+        let dumper = SstFileDumper::open(storage.open("test.sst").unwrap(), true).unwrap();
+        assert_eq!(dumper.file_checksum().unwrap(), info.file_checksum);
+        let index = dumper.index().unwrap();
+        assert!(index.len() > 1);
+        let filters = dumper.filters().unwrap();
+        assert_eq!(filters.len(), 1);
+        assert_eq!(filters[0].policy, "TemplateDB.BuiltinBloomFilter");
+        assert!(filters[0].num_filters > 0);
+
+        let props = dumper.properties().unwrap();
+        assert_eq!(props.num_data_blocks, index.len() as u64);
+        assert_eq!(props.num_entries, 1000);
+        assert_eq!(props.num_deletions, 100);
+        assert_eq!(props.raw_value_size, 900 * 5);
+        assert_eq!(props.smallest_key.unwrap().user_key(), b"key000000");
+        assert_eq!(props.largest_key.unwrap().user_key(), b"key000999");
+        assert_eq!((props.smallest_seq, props.largest_seq), (0, 0));
+
+        let mut n = 0;
+        dumper
+            .for_each_entry(|_, _| {
+                n += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(n, 1000);
+        dumper.verify_checksums().unwrap();
+    }
+
+    #[test]
+    fn test_verify_corrupted_sst_file() {
+        let storage = MemStorage::default();
+        build_sst(&storage, "test.sst");
+        let mut contents = vec![];
+        storage
+            .open("test.sst")
+            .unwrap()
+            .read_all(&mut contents)
+            .unwrap();
+        contents[10] ^= 0xff;
+        storage.remove("test.sst").unwrap();
+        storage
+            .create("test.sst")
+            .unwrap()
+            .write(&contents)
+            .unwrap();
+        let dumper = SstFileDumper::open(storage.open("test.sst").unwrap(), false).unwrap();
+        assert!(dumper.verify_checksums().unwrap_err().is_corruption());
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    cmp::Ordering,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    error::{Operation, TemplateKVError, TemplateResult},
+    memtable::{
+        key_format::{InternalFilterPolicy, InternalKey, InternalKeyComparator},
+        value_format::ValueType,
+    },
+    options::Options,
+    sstable::table::TableBuilder,
+    storage::Storage,
+    util::comparator::Comparator,
+};
+
+/// The summary of a sst file generated by `SstFileWriter`
+#[derive(Debug, Clone, Default)]
+pub struct ExternalSstFileInfo {
+    pub file_path: PathBuf,
+    /// The smallest user key in the file
+    pub smallest_key: Vec<u8>,
+    /// The largest user key in the file
+    pub largest_key: Vec<u8>,
+    pub num_entries: usize,
+    pub file_size: u64,
+    /// The crc32 of the whole file, which can be passed to
+    /// `TemplateDB::ingest_external_file_with_checksum`
+    pub file_checksum: u32,
+}
+
+/// `SstFileWriter` builds a sst file out of a db, which can be bulk loaded by
+/// `TemplateDB::ingest_external_file` later without going through the WAL and memtable.
+///
+/// The keys must be added in strictly increasing order according to the comparator in
+/// `Options`. Every entry is stored with sequence number 0 and gets its real sequence
+/// number when the file is ingested.
+pub struct SstFileWriter<S: Storage, C: Comparator> {
+    storage: S,
+    options: Arc<Options<C>>,
+    icmp: InternalKeyComparator<C>,
+    builder: Option<TableBuilder<InternalKeyComparator<C>, S::F>>,
+    info: ExternalSstFileInfo,
+}
+
+impl<S: Storage, C: Comparator> SstFileWriter<S, C> {
+    pub fn new(mut options: Options<C>, storage: S) -> Self {
+        // The table is built on internal keys just like the ones generated by the db
+        if let Some(fp) = options.filter_policy.take() {
+            options.filter_policy = Some(Arc::new(InternalFilterPolicy::new(fp)));
+        }
+        let icmp = InternalKeyComparator::new(options.comparator.clone());
+        Self {
+            storage,
+            options: Arc::new(options),
+            icmp,
+            builder: None,
+            info: ExternalSstFileInfo::default(),
+        }
+    }
+
+    /// Creates the sst file at `path`. Any existing file is truncated.
+    pub fn open<P: AsRef<Path>>(&mut self, path: P) -> TemplateResult<()> {
+        if self.builder.is_some() {
+            return Err(TemplateKVError::InvalidArgument(
+                "sst file writer has been opened".to_owned(),
+            ));
+        }
+        let file = self.storage.create(path.as_ref())?;
+        self.builder = Some(TableBuilder::new(file, self.icmp.clone(), &self.options));
+        self.info = ExternalSstFileInfo {
+            file_path: path.as_ref().to_owned(),
+            ..Default::default()
+        };
+        Ok(())
+    }
+
+    /// Adds a key/value pair into the file
+    #[inline]
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> TemplateResult<()> {
+        self.add(key, value, ValueType::Value)
+    }
+
+    /// Adds a deletion marker of `key` into the file
+    #[inline]
+    pub fn delete(&mut self, key: &[u8]) -> TemplateResult<()> {
+        self.add(key, &[], ValueType::Deletion)
+    }
+
+    /// Returns the current size of the file being built
+    pub fn file_size(&self) -> u64 {
+        self.builder.as_ref().map_or(0, |b| b.file_size())
+    }
+
+    /// Finishes building the file and syncs it. The writer can be opened again with
+    /// another path after this.
+    pub fn finish(&mut self) -> TemplateResult<ExternalSstFileInfo> {
+        let mut builder = match self.builder.take() {
+            Some(b) => b,
+            None => {
+                return Err(TemplateKVError::InvalidArgument(
+                    "sst file writer is not opened".to_owned(),
+                ));
+            }
+        };
+        let path = self.info.file_path.clone();
+        if self.info.num_entries == 0 {
+            builder.close();
+            let _ = self.storage.remove(&path);
+            return Err(TemplateKVError::InvalidArgument(
+                "can not create a sst file with no entries".to_owned(),
+            ));
+        }
+        builder
+            .finish(true)
+            .map_err(|e| e.with_operation(Operation::BuildTable).with_path(&path))?;
+        self.info.file_size = builder.file_size();
+        self.info.file_checksum = builder.file_checksum();
+        Ok(std::mem::take(&mut self.info))
+    }
+
+    fn add(&mut self, key: &[u8], value: &[u8], value_type: ValueType) -> TemplateResult<()> {
+        let builder = match self.builder.as_mut() {
+            Some(b) => b,
+            None => {
+                return Err(TemplateKVError::InvalidArgument(
+                    "sst file writer is not opened".to_owned(),
+                ));
+            }
+        };
+        if self.info.num_entries > 0
+            && self.options.comparator.compare(key, &self.info.largest_key) != Ordering::Greater
+        {
+            return Err(TemplateKVError::InvalidArgument(format!(
+                "keys must be added in strictly increasing order: {:?} after {:?}",
+                key, &self.info.largest_key
+            )));
+        }
+        let ikey = InternalKey::new(key, 0, value_type);
+        builder.add(ikey.data(), value)?;
+        if self.info.num_entries == 0 {
+            self.info.smallest_key = key.to_vec();
+        }
+        self.info.largest_key = key.to_vec();
+        self.info.num_entries += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        iterator::Iterator,
+        memtable::key_format::ParsedInternalKey,
+        options::ReadOptions,
+        sstable::table::{Table, new_table_iterator},
+        storage::{File, mem::MemStorage},
+        util::comparator::BytewiseComparator,
+    };
+
+    #[test]
+    fn test_write_and_read_sst_file() {
+        let storage = MemStorage::default();
+        let opts = Options::<BytewiseComparator>::default();
+        let mut writer = SstFileWriter::new(opts.clone(), storage.clone());
+        writer.open("test.sst").unwrap();
+        writer.put(b"a", b"va").unwrap();
+        writer.delete(b"b").unwrap();
+        writer.put(b"c", b"vc").unwrap();
+        assert!(writer.put(b"c", b"vc2").is_err());
+        assert!(writer.put(b"b", b"vb").is_err());
+        let info = writer.finish().unwrap();
+        assert_eq!(info.smallest_key, b"a");
+        assert_eq!(info.largest_key, b"c");
+        assert_eq!(info.num_entries, 3);
+        assert!(writer.finish().is_err());
+
+        let file = storage.open("test.sst").unwrap();
+        assert_eq!(file.len().unwrap(), info.file_size);
+        let icmp = InternalKeyComparator::new(BytewiseComparator::default());
+        let table = Table::open(file, 0, info.file_size, Arc::new(opts), icmp.clone()).unwrap();
+        let mut iter = new_table_iterator(icmp, Arc::new(table), ReadOptions::default());
+        iter.seek_to_first();
+        let mut got = vec![];
+        while iter.valid() {
+            let k = ParsedInternalKey::decode_from(iter.key()).unwrap();
+            assert_eq!(k.seq, 0);
+            got.push((k.user_key.to_vec(), k.value_type, iter.value().to_vec()));
+            iter.next();
+        }
+        assert_eq!(got, vec![
+            (b"a".to_vec(), ValueType::Value, b"va".to_vec()),
+            (b"b".to_vec(), ValueType::Deletion, vec![]),
+            (b"c".to_vec(), ValueType::Value, b"vc".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn test_finish_empty_file() {
+        let storage = MemStorage::default();
+        let mut writer =
+            SstFileWriter::new(Options::<BytewiseComparator>::default(), storage.clone());
+        assert!(writer.put(b"a", b"va").is_err());
+        writer.open("empty.sst").unwrap();
+        assert!(writer.finish().is_err());
+        assert!(!storage.exists("empty.sst"));
+    }
+}
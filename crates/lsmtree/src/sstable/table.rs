@@ -1,38 +1,97 @@
-use std::{cmp::Ordering, sync::Arc};
+use std::{borrow::Cow, cell::RefCell, cmp::Ordering, sync::Arc};
 
-use snap::raw::max_compress_len;
+use bytes::Bytes;
+use zstd::dict::DecoderDictionary;
 
 use crate::{
-    cache::{CacheSync, FilterPolicy},
-    error::{TemplateKVError, TemplateResult},
+    cache::{BlockCacheKey, CachePriority, CacheSync, FilterPolicy, SecondaryCache},
+    error::{Operation, TemplateKVError, TemplateResult},
     iterator::{
         Iterator,
         concatenate_iter::{ConcatenateIterator, DerivedIterFactory},
     },
+    memtable::key_format::extract_user_key,
     options::{CompressionType, Options, ReadOptions},
     sstable::{
         BLOCK_TRAILER_SIZE, BlockHandle, FOOTER_ENCODED_LENGTH, Footer,
         block::{Block, BlockBuilder, BlockIterator},
-        filter_block::{FilterBlockBuilder, FilterBlockReader},
+        compression::{BlockCompressor, COMPRESSION_DICT_KEY, decompress_block, train_dictionary},
+        filter_block::{
+            FilterBlockBuilder, FilterBlockReader, FullFilterBuilder, PartitionedFilterBuilder,
+        },
+        prefetch::FilePrefetchBuffer,
     },
     storage::File,
     util::{
-        coding::{decode_fixed_32, put_fixed_32, put_fixed_64},
+        coding::{decode_fixed_32, put_fixed_32},
         comparator::Comparator,
         crc32::{extend, hash, mask, unmask},
+        slice_transform::SliceTransform,
     },
 };
 
+/// The key in the meta index block marking the index block is the top-level index of the
+/// index partitions. The value is the handle of the top-level index block.
+pub const PARTITIONED_INDEX_KEY: &str = "index.partitioned";
+
+/// The prefix of the key in the meta index block referring to the index of the filter
+/// partitions, which is followed by the name of the filter policy
+pub const PARTITIONED_FILTER_KEY_PREFIX: &str = "partitionedfilter.";
+
+/// The prefix of the key in the meta index block referring to the filter over all the keys
+/// in the table, which is followed by the name of the filter policy
+pub const FULL_FILTER_KEY_PREFIX: &str = "fullfilter.";
+
+/// The key in the meta index block referring to the block recording the prefix extractor the
+/// filters are built with. The block contains a byte telling whether the whole keys are in
+/// the filters followed by the name of the prefix extractor.
+pub const PREFIX_EXTRACTOR_KEY: &str = "prefixextractor";
+
 /// A `Table` is a sorted map from strings to strings, which must be immutable and persistent.
 /// A `Table` may be safely accessed from multiple threads
 /// without external synchronization.
 pub struct Table<F: File> {
     file: F,
     file_number: u64,
-    filter_reader: Option<FilterBlockReader>,
+    file_len: u64,
+    filter: Option<TableFilter>,
+    // the prefix extractor the filters are built with, which is only set if it's the same as
+    // the one of the options
+    prefix_extractor: Option<Arc<dyn SliceTransform>>,
+    // whether the whole keys are in the filters
+    whole_key_filtering: bool,
     meta_block_handle: Option<BlockHandle>,
+    // the index block, or the top-level index of the partitions if `partitioned_index` is true
     index_block: Block,
-    block_cache: Option<Arc<dyn CacheSync<Vec<u8>, Arc<Block>>>>,
+    partitioned_index: bool,
+    block_cache: Option<Arc<dyn CacheSync<BlockCacheKey, Arc<Block>>>>,
+    secondary_cache: Option<Arc<dyn SecondaryCache>>,
+    // the zstd dictionary the data blocks are compressed with
+    compression_dict: Option<DecoderDictionary<'static>>,
+}
+
+// The filter of a table
+enum TableFilter {
+    // One filter for every 2KB range of the data block offsets
+    Block(FilterBlockReader),
+    // One filter for all the keys in the table
+    Full {
+        policy: Arc<dyn FilterPolicy>,
+        data: Bytes,
+    },
+    // The index of the filter partitions, which are read through the block cache
+    Partitioned {
+        policy: Arc<dyn FilterPolicy>,
+        index: Block,
+    },
+}
+
+// The kinds of the blocks read through the block cache
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    Data,
+    IndexPartition,
+    FilterPartition,
 }
 
 impl<F: File> Table<F> {
@@ -55,51 +114,105 @@ impl<F: File> Table<F> {
         };
         // Read footer
         let mut footer_space = vec![0; FOOTER_ENCODED_LENGTH];
+        let on_open_err = |e: TemplateKVError| {
+            e.with_operation(Operation::OpenTable)
+                .with_file_number(file_number)
+        };
         file.read_exact_at(
             footer_space.as_mut_slice(),
             file_len - FOOTER_ENCODED_LENGTH as u64,
-        )?;
-        let (footer, _) = Footer::decode_from(footer_space.as_slice())?;
+        )
+        .map_err(on_open_err)?;
+        let (footer, _) = Footer::decode_from(footer_space.as_slice()).map_err(on_open_err)?;
         // Read the index block
-        let index_block_contents =
-            read_block(&file, &footer.index_handle, options.paranoid_checks)?;
-        let index_block = Block::new(index_block_contents)?;
+        let index_block_contents = read_block(&file, &footer.index_handle, options.paranoid_checks)
+            .map_err(on_open_err)?;
+        let index_block = Block::new(index_block_contents).map_err(on_open_err)?;
         let mut t = Self {
             block_cache: options.block_cache.clone(),
+            secondary_cache: options.secondary_cache.clone(),
             file,
             file_number,
-            filter_reader: None,
+            file_len,
+            filter: None,
+            prefix_extractor: None,
+            whole_key_filtering: true,
             meta_block_handle: None,
             index_block,
+            partitioned_index: false,
+            compression_dict: None,
         };
         // Read meta block
-        if footer.meta_index_handle.size > 0 && options.filter_policy.is_some() {
-            // ignore the reading errors since meta info is not needed for operation
-            if let Ok(meta_block_contents) =
+        if footer.meta_index_handle.size > 0 {
+            // The meta block tells whether the index is partitioned and holds the compression
+            // dictionary, without which the data blocks fail to be read later. Only the errors
+            // of reading the filters are ignored since they are not needed for operation.
+            let meta_block =
                 read_block(&t.file, &footer.meta_index_handle, options.paranoid_checks)
+                    .and_then(Block::new)
+                    .map_err(on_open_err)?;
+            t.partitioned_index =
+                find_meta_handle(&meta_block, cmp.clone(), PARTITIONED_INDEX_KEY).is_some();
+            if let Some(dict_handle) =
+                find_meta_handle(&meta_block, cmp.clone(), COMPRESSION_DICT_KEY)
             {
-                if let Ok(meta_block) = Block::new(meta_block_contents) {
-                    t.meta_block_handle = Some(footer.meta_index_handle);
-                    let mut iter = meta_block.iter(cmp);
-                    let filter_key = if let Some(fp) = &options.filter_policy {
-                        "filter.".to_owned() + fp.name()
-                    } else {
-                        String::from("")
-                    };
-                    // Read filter block
-                    iter.seek(filter_key.as_bytes());
-                    if iter.valid() && iter.key() == filter_key.as_bytes() {
-                        if let Ok((filter_handle, _)) = BlockHandle::decode_from(iter.value()) {
-                            if let Ok(filter_block) =
-                                read_block(&t.file, &filter_handle, options.paranoid_checks)
-                            {
-                                t.filter_reader = Some(FilterBlockReader::new(
-                                    options.filter_policy.clone().unwrap(),
-                                    filter_block,
-                                ));
-                            }
+                let dict = read_block(&t.file, &dict_handle, options.paranoid_checks)
+                    .map_err(on_open_err)?;
+                t.compression_dict = Some(DecoderDictionary::copy(&dict));
+            }
+            if let Some(fp) = &options.filter_policy {
+                t.meta_block_handle = Some(footer.meta_index_handle);
+                // Read filter block
+                let filter_key = "filter.".to_owned() + fp.name();
+                let full_filter_key = FULL_FILTER_KEY_PREFIX.to_owned() + fp.name();
+                let partitioned_filter_key = PARTITIONED_FILTER_KEY_PREFIX.to_owned() + fp.name();
+                if let Some(filter_handle) = find_meta_handle(&meta_block, cmp.clone(), &filter_key)
+                {
+                    if let Ok(filter_block) =
+                        read_block(&t.file, &filter_handle, options.paranoid_checks)
+                    {
+                        t.filter = Some(TableFilter::Block(FilterBlockReader::new(
+                            fp.clone(),
+                            Vec::from(filter_block),
+                        )));
+                    }
+                } else if let Some(filter_handle) =
+                    find_meta_handle(&meta_block, cmp.clone(), &full_filter_key)
+                {
+                    if let Ok(data) = read_block(&t.file, &filter_handle, options.paranoid_checks) {
+                        t.filter = Some(TableFilter::Full {
+                            policy: fp.clone(),
+                            data,
+                        });
+                    }
+                } else if let Some(index_handle) =
+                    find_meta_handle(&meta_block, cmp.clone(), &partitioned_filter_key)
+                {
+                    // Only the index of the partitions is kept in memory
+                    if let Ok(index) = read_block(&t.file, &index_handle, options.paranoid_checks)
+                        .and_then(Block::new)
+                    {
+                        t.filter = Some(TableFilter::Partitioned {
+                            policy: fp.clone(),
+                            index,
+                        });
+                    }
+                }
+                if let Some(handle) = find_meta_handle(&meta_block, cmp, PREFIX_EXTRACTOR_KEY) {
+                    if let Ok(data) = read_block(&t.file, &handle, options.paranoid_checks) {
+                        if let Some((whole_key_filtering, name)) = data.split_first() {
+                            t.whole_key_filtering = *whole_key_filtering != 0;
+                            t.prefix_extractor = options
+                                .prefix_extractor
+                                .clone()
+                                .filter(|pe| pe.name().as_bytes() == name);
                         }
                     }
+                    // The filters holding only the prefixes of another extractor are
+                    // useless
+                    if !t.whole_key_filtering && t.prefix_extractor.is_none() {
+                        t.filter = None;
+                    }
                 }
             }
         }
@@ -107,35 +220,190 @@ impl<F: File> Table<F> {
     }
 
     // Converts an BlockHandle into an iterator over the contents of the corresponding block.
+    // The block is read through `prefetch` if it's not in the block cache.
     fn block_reader<CC: Comparator>(
         &self,
         cmp: CC,
         data_block_handle: BlockHandle,
-        options: ReadOptions,
+        options: &ReadOptions,
+        prefetch: Option<&mut FilePrefetchBuffer>,
     ) -> TemplateResult<BlockIterator<CC>> {
-        let iter = if let Some(cache) = &self.block_cache {
-            let mut cache_key_buffer = vec![0; 16];
-            put_fixed_64(&mut cache_key_buffer, self.file_number);
-            put_fixed_64(&mut cache_key_buffer, data_block_handle.offset);
-            if let Some(b) = cache.get(&cache_key_buffer) {
-                b.iter(cmp)
-            } else {
-                let data = read_block(&self.file, &data_block_handle, options.verify_checksums)?;
-                let charge = data.len();
-                let new_block = Block::new(data)?;
-                let b = Arc::new(new_block);
-                let iter = b.iter(cmp);
-                if options.fill_cache {
-                    cache.insert(cache_key_buffer, b, charge);
+        self.read_block_cached(data_block_handle, options, BlockKind::Data, prefetch)
+            .map(|b| b.iter(cmp))
+    }
+
+    // Reads the block at `handle` through the block cache and then the secondary cache if
+    // there are. Only the data blocks are compressed with the zstd dictionary and the filter
+    // partitions are not in the block format.
+    fn read_block_cached(
+        &self,
+        handle: BlockHandle,
+        options: &ReadOptions,
+        kind: BlockKind,
+        prefetch: Option<&mut FilePrefetchBuffer>,
+    ) -> TemplateResult<Arc<Block>> {
+        let on_read_err = |e: TemplateKVError| {
+            e.with_operation(Operation::ReadBlock)
+                .with_file_number(self.file_number)
+                .with_offset(handle.offset)
+        };
+        let cache_key = BlockCacheKey::new(self.file_number, handle.offset);
+        if let Some(cache) = &self.block_cache {
+            if let Some(b) = cache.get(&cache_key) {
+                return Ok(b);
+            }
+        }
+        let cached = self
+            .secondary_cache
+            .as_ref()
+            .and_then(|sc| sc.lookup(&cache_key));
+        let data = match cached {
+            Some(data) => data,
+            None => {
+                let dict = self
+                    .compression_dict
+                    .as_ref()
+                    .filter(|_| kind == BlockKind::Data);
+                read_block_prefetched(
+                    &self.file,
+                    &handle,
+                    options.verify_checksums,
+                    dict,
+                    prefetch,
+                )
+                .map_err(on_read_err)?
+            }
+        };
+        let charge = data.len();
+        let b = Arc::new(if kind == BlockKind::FilterPartition {
+            Block::new_raw(data)
+        } else {
+            Block::new(data).map_err(on_read_err)?
+        });
+        if let Some(cache) = &self.block_cache {
+            if options.fill_cache {
+                let priority = if kind == BlockKind::Data {
+                    CachePriority::Low
+                } else {
+                    CachePriority::High
+                };
+                cache.insert_with_priority(cache_key, b.clone(), charge, priority);
+            }
+        }
+        Ok(b)
+    }
+
+    // Returns the index iterator positioned at the first index entry not less than `key`.
+    // With the partitioned index, the iterator is over the index partition holding the entry.
+    fn seek_index<TC: Comparator>(
+        &self,
+        cmp: TC,
+        key: &[u8],
+        options: &ReadOptions,
+    ) -> TemplateResult<BlockIterator<TC>> {
+        let mut index_iter = self.index_block.iter(cmp.clone());
+        index_iter.seek(key);
+        if self.partitioned_index && index_iter.valid() {
+            let (handle, _) = BlockHandle::decode_from(index_iter.value())?;
+            let mut partition_iter = self
+                .read_block_cached(handle, options, BlockKind::IndexPartition, None)?
+                .iter(cmp);
+            partition_iter.seek(key);
+            return Ok(partition_iter);
+        }
+        Ok(index_iter)
+    }
+
+    // Returns false if the filter reports that `probe` is not in the data block at
+    // `block_offset`, or in the filter partition holding `key`. Errors are treated as
+    // potential matches.
+    fn key_may_match<TC: Comparator>(
+        &self,
+        cmp: TC,
+        block_offset: u64,
+        key: &[u8],
+        probe: &[u8],
+        options: &ReadOptions,
+    ) -> bool {
+        match &self.filter {
+            Some(TableFilter::Block(filter)) => filter.key_may_match(block_offset, probe),
+            Some(TableFilter::Full { policy, data }) => policy.may_contain(data, probe),
+            Some(TableFilter::Partitioned { policy, index }) => {
+                // The filter partitions share the separators with the index partitions
+                let mut index_iter = index.iter(cmp);
+                index_iter.seek(key);
+                if !index_iter.valid() {
+                    return true;
+                }
+                match BlockHandle::decode_from(index_iter.value()).and_then(|(handle, _)| {
+                    self.read_block_cached(handle, options, BlockKind::FilterPartition, None)
+                }) {
+                    Ok(partition) => policy.may_contain(partition.data(), probe),
+                    Err(_) => true,
                 }
-                iter
             }
+            None => true,
+        }
+    }
+
+    // Returns the internal key made of the prefix of the user key in `key` and the tail of
+    // `key`, which is how the prefixes are added into the filters. Returns `None` if the
+    // filters have no prefixes or the key is out of the domain of the extractor.
+    fn prefix_key(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let pe = self.prefix_extractor.as_ref()?;
+        let user_key = extract_user_key(key);
+        if !pe.in_domain(user_key) {
+            return None;
+        }
+        let mut prefix_key = pe.transform(user_key).to_vec();
+        prefix_key.extend_from_slice(&key[user_key.len()..]);
+        Some(prefix_key)
+    }
+
+    // Returns the key to probe the filters for a lookup of `key`, or `None` if the filters
+    // can't tell whether the key is in the table
+    fn filter_probe<'a>(&self, key: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        if self.whole_key_filtering {
+            Some(Cow::Borrowed(key))
         } else {
-            let data = read_block(&self.file, &data_block_handle, options.verify_checksums)?;
-            let b = Block::new(data)?;
-            b.iter(cmp)
+            self.prefix_key(key).map(Cow::Owned)
+        }
+    }
+
+    /// Returns false if the filters report that no key in the table has the same prefix as
+    /// the internal key `key` and is not less than it. Always returns true if the filters
+    /// have no prefixes of the same extractor as the options.
+    ///
+    /// Since the keys with the same prefix are contiguous, the first key not less than `key`
+    /// must have the prefix if any key does, so only the filter of its data block is checked.
+    pub fn prefix_may_match<TC: Comparator>(
+        &self,
+        cmp: TC,
+        key: &[u8],
+        options: &ReadOptions,
+    ) -> bool {
+        if self.filter.is_none() {
+            return true;
+        }
+        let Some(prefix_key) = self.prefix_key(key) else {
+            return true;
         };
-        Ok(iter)
+        if let Some(TableFilter::Full { policy, data }) = &self.filter {
+            return policy.may_contain(data, &prefix_key);
+        }
+        match self.seek_index(cmp.clone(), key, options) {
+            Ok(index_iter) if index_iter.valid() => {
+                match BlockHandle::decode_from(index_iter.value()) {
+                    Ok((handle, _)) => {
+                        self.key_may_match(cmp, handle.offset, key, &prefix_key, options)
+                    }
+                    Err(_) => true,
+                }
+            }
+            // All the keys are less than `key`
+            Ok(_) => false,
+            Err(_) => true,
+        }
     }
 
     /// Finds the first entry with the key equal or greater than target and
@@ -144,45 +412,124 @@ impl<F: File> Table<F> {
     /// The given `key` is an internal key so the `cmp` must be a InternalKeyComparator
     pub fn internal_get<TC: Comparator>(
         &self,
-        options: ReadOptions,
+        options: &ReadOptions,
         cmp: TC,
         key: &[u8],
     ) -> TemplateResult<Option<BlockIterator<TC>>> {
-        let mut index_iter = self.index_block.iter(cmp.clone());
-        // seek to the first 'last key' bigger than 'key'
-        index_iter.seek(key);
-        if index_iter.valid() {
-            // It's called 'maybe_contained' not only because the filter policy may report the falsy
-            // result, but also even if we've found a block with the last key bigger
-            // than the target the key may not be contained if the block is the first
-            // block of the sstable.
-            let mut maybe_contained = true;
-
-            let handle_val = index_iter.value();
-            // check the filter block
-            if let Some(filter) = &self.filter_reader {
-                if let Ok((handle, _)) = BlockHandle::decode_from(handle_val) {
-                    if !filter.key_may_match(handle.offset, key) {
-                        maybe_contained = false;
-                    }
+        if let Some(data_block_handle) = self.find_data_block(cmp.clone(), key, options)? {
+            let mut block_iter = self.block_reader(cmp, data_block_handle, options, None)?;
+            block_iter.seek(key);
+            if block_iter.valid() {
+                return Ok(Some(block_iter));
+            }
+            block_iter.seek_to_first();
+            while block_iter.valid() {
+                block_iter.next();
+            }
+            block_iter.status()?;
+        }
+        Ok(None)
+    }
+
+    /// Same as `internal_get` for every key in `keys` but the lookups are batched. The
+    /// filters are checked for all the keys before any data block is read, the keys in the
+    /// same data block share one read of it, and the data blocks not in the block cache are
+    /// read by one batch of reads with the adjacent ones coalesced.
+    ///
+    /// Returns the first entry not less than each key in the data block the key may be in,
+    /// whose value shares the block without copying. The keys are expected to be sorted by
+    /// `cmp` for the best batching.
+    #[allow(clippy::type_complexity)]
+    pub fn multi_get<TC: Comparator>(
+        &self,
+        options: &ReadOptions,
+        cmp: TC,
+        keys: &[&[u8]],
+    ) -> TemplateResult<Vec<Option<(Vec<u8>, Bytes)>>> {
+        // The data blocks to read along with the indexes of the keys in them
+        let mut blocks: Vec<(BlockHandle, Vec<usize>)> = vec![];
+        for (i, key) in keys.iter().enumerate() {
+            if let Some(handle) = self.find_data_block(cmp.clone(), key, options)? {
+                match blocks.last_mut() {
+                    Some((last, indexes)) if last.offset == handle.offset => indexes.push(i),
+                    _ => blocks.push((handle, vec![i])),
                 }
             }
-            if maybe_contained {
-                let (data_block_handle, _) = BlockHandle::decode_from(handle_val)?;
-                let mut block_iter = self.block_reader(cmp, data_block_handle, options)?;
-                block_iter.seek(key);
+        }
+        // Coalesce the reads of the adjacent blocks not in the cache, and issue all the reads
+        // in one batch. The blocks of a memory-mapped file need no read.
+        let mut ranges: Vec<(u64, usize)> = vec![];
+        for (handle, _) in &blocks {
+            let n = handle.size as usize + BLOCK_TRAILER_SIZE;
+            if self.is_block_cached(handle) || self.file.mapped_slice(handle.offset, n).is_some() {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some((offset, len)) if *offset + *len as u64 == handle.offset => *len += n,
+                _ => ranges.push((handle.offset, n)),
+            }
+        }
+        let mut prefetch = FilePrefetchBuffer::new(0, self.file_len);
+        if !ranges.is_empty() {
+            prefetch.prefetch_batch(&self.file, &ranges).map_err(|e| {
+                e.with_operation(Operation::ReadBlock)
+                    .with_file_number(self.file_number)
+            })?;
+        }
+        let mut results = vec![None; keys.len()];
+        for (handle, indexes) in &blocks {
+            let mut block_iter =
+                self.block_reader(cmp.clone(), handle.clone(), options, Some(&mut prefetch))?;
+            for &i in indexes {
+                block_iter.seek(keys[i]);
                 if block_iter.valid() {
-                    return Ok(Some(block_iter));
+                    results[i] = Some((block_iter.key().to_vec(), block_iter.value_bytes()));
                 }
-                block_iter.seek_to_first();
-                while block_iter.valid() {
-                    block_iter.next();
-                }
-                block_iter.status()?;
             }
+            block_iter.status()?;
         }
-        index_iter.status()?;
-        Ok(None)
+        Ok(results)
+    }
+
+    // Returns the handle of the data block that may contain `key`, or `None` if the index or
+    // the filters rule the key out
+    fn find_data_block<TC: Comparator>(
+        &self,
+        cmp: TC,
+        key: &[u8],
+        options: &ReadOptions,
+    ) -> TemplateResult<Option<BlockHandle>> {
+        let probe = self.filter_probe(key);
+        // The full filter rules out the whole table without touching the index
+        if let (Some(TableFilter::Full { policy, data }), Some(probe)) = (&self.filter, &probe) {
+            if !policy.may_contain(data, probe) {
+                return Ok(None);
+            }
+        }
+        // seek to the first 'last key' bigger than 'key'
+        let mut index_iter = self.seek_index(cmp.clone(), key, options)?;
+        if !index_iter.valid() {
+            index_iter.status()?;
+            return Ok(None);
+        }
+        // The key may not be contained even if we've found a block with the last key bigger
+        // than the target, so check the filter of the block
+        let (handle, _) = BlockHandle::decode_from(index_iter.value())?;
+        if let (Some(TableFilter::Block(_) | TableFilter::Partitioned { .. }), Some(probe)) =
+            (&self.filter, &probe)
+        {
+            if !self.key_may_match(cmp, handle.offset, key, probe, options) {
+                return Ok(None);
+            }
+        }
+        Ok(Some(handle))
+    }
+
+    // Returns true if the block at `handle` is in the block cache
+    fn is_block_cached(&self, handle: &BlockHandle) -> bool {
+        self.block_cache.as_ref().is_some_and(|cache| {
+            cache.contains(&BlockCacheKey::new(self.file_number, handle.offset))
+        })
     }
 
     /// Given a key, return an approximate byte offset in the file where
@@ -192,12 +539,12 @@ impl<F: File> Table<F> {
     /// E.g., the approximate offset of the last key in the table will
     /// be close to the file length.
     pub fn approximate_offset_of<TC: Comparator>(&self, cmp: TC, key: &[u8]) -> u64 {
-        let mut index_iter = self.index_block.iter(cmp);
-        index_iter.seek(key);
-        if index_iter.valid() {
-            let val = index_iter.value();
-            if let Ok((h, _)) = BlockHandle::decode_from(val) {
-                return h.offset;
+        if let Ok(index_iter) = self.seek_index(cmp, key, &ReadOptions::default()) {
+            if index_iter.valid() {
+                let val = index_iter.value();
+                if let Ok((h, _)) = BlockHandle::decode_from(val) {
+                    return h.offset;
+                }
             }
         }
         if let Some(meta) = &self.meta_block_handle {
@@ -207,25 +554,171 @@ impl<F: File> Table<F> {
     }
 }
 
+// Returns the block handle stored by `key` in the meta index block
+fn find_meta_handle<C: Comparator>(meta_block: &Block, cmp: C, key: &str) -> Option<BlockHandle> {
+    let mut iter = meta_block.iter(cmp);
+    iter.seek(key.as_bytes());
+    if iter.valid() && iter.key() == key.as_bytes() {
+        BlockHandle::decode_from(iter.value()).ok().map(|(h, _)| h)
+    } else {
+        None
+    }
+}
+
 pub struct TableIterFactory<C: Comparator, F: File> {
     options: ReadOptions,
     table: Arc<Table<F>>,
     cmp: C,
+    // reads ahead of the data blocks for the scans
+    prefetch: RefCell<FilePrefetchBuffer>,
 }
 
 impl<C: Comparator, F: File> DerivedIterFactory for TableIterFactory<C, F> {
+    type Iter = BlockIterator<C>;
+    fn derive(&self, value: &[u8]) -> TemplateResult<Self::Iter> {
+        BlockHandle::decode_from(value).and_then(|(handle, _)| {
+            self.table.block_reader(
+                self.cmp.clone(),
+                handle,
+                &self.options,
+                Some(&mut self.prefetch.borrow_mut()),
+            )
+        })
+    }
+}
+
+// Derives the iterator over an index partition from a top-level index entry
+pub struct IndexPartitionFactory<C: Comparator, F: File> {
+    options: ReadOptions,
+    table: Arc<Table<F>>,
+    cmp: C,
+}
+
+impl<C: Comparator, F: File> DerivedIterFactory for IndexPartitionFactory<C, F> {
     type Iter = BlockIterator<C>;
     fn derive(&self, value: &[u8]) -> TemplateResult<Self::Iter> {
         BlockHandle::decode_from(value).and_then(|(handle, _)| {
             self.table
-                .block_reader(self.cmp.clone(), handle, self.options)
+                .read_block_cached(handle, &self.options, BlockKind::IndexPartition, None)
+                .map(|b| b.iter(self.cmp.clone()))
         })
     }
 }
 
-pub type TableIterator<C, F> = ConcatenateIterator<BlockIterator<C>, TableIterFactory<C, F>>;
+/// An iterator over the index entries of a table, whose values are the handles of the data
+/// blocks
+pub enum IndexIterator<C: Comparator, F: File> {
+    Single(BlockIterator<C>),
+    Partitioned(ConcatenateIterator<BlockIterator<C>, IndexPartitionFactory<C, F>>),
+}
+
+macro_rules! delegate_index_iter {
+    ($self:ident, $iter:ident => $e:expr) => {
+        match $self {
+            IndexIterator::Single($iter) => $e,
+            IndexIterator::Partitioned($iter) => $e,
+        }
+    };
+}
+
+impl<C: Comparator, F: File> Iterator for IndexIterator<C, F> {
+    fn valid(&self) -> bool {
+        delegate_index_iter!(self, iter => iter.valid())
+    }
+
+    fn seek_to_first(&mut self) {
+        delegate_index_iter!(self, iter => iter.seek_to_first())
+    }
+
+    fn seek_to_last(&mut self) {
+        delegate_index_iter!(self, iter => iter.seek_to_last())
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        delegate_index_iter!(self, iter => iter.seek(target))
+    }
+
+    fn next(&mut self) {
+        delegate_index_iter!(self, iter => iter.next())
+    }
+
+    fn prev(&mut self) {
+        delegate_index_iter!(self, iter => iter.prev())
+    }
+
+    fn key(&self) -> &[u8] {
+        delegate_index_iter!(self, iter => iter.key())
+    }
+
+    fn value(&self) -> &[u8] {
+        delegate_index_iter!(self, iter => iter.value())
+    }
+
+    fn status(&mut self) -> TemplateResult<()> {
+        delegate_index_iter!(self, iter => iter.status())
+    }
+}
+
+/// An iterator over the entries of a table. With `ReadOptions::prefix_same_as_start`, a seek
+/// leaves the iterator invalid if the prefix filters exclude the prefix of the target.
+pub struct TableIterator<C: Comparator, F: File> {
+    iter: ConcatenateIterator<IndexIterator<C, F>, TableIterFactory<C, F>>,
+    // set if the seeks check the prefix filters
+    prefix_table: Option<Arc<Table<F>>>,
+    // whether the last seek is ruled out by the prefix filters
+    prefix_excluded: bool,
+    cmp: C,
+    options: ReadOptions,
+}
+
+impl<C: Comparator, F: File> Iterator for TableIterator<C, F> {
+    fn valid(&self) -> bool {
+        !self.prefix_excluded && self.iter.valid()
+    }
+
+    fn seek_to_first(&mut self) {
+        self.prefix_excluded = false;
+        self.iter.seek_to_first()
+    }
+
+    fn seek_to_last(&mut self) {
+        self.prefix_excluded = false;
+        self.iter.seek_to_last()
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        if let Some(table) = &self.prefix_table {
+            if !table.prefix_may_match(self.cmp.clone(), target, &self.options) {
+                self.prefix_excluded = true;
+                return;
+            }
+        }
+        self.prefix_excluded = false;
+        self.iter.seek(target)
+    }
+
+    fn next(&mut self) {
+        self.iter.next()
+    }
+
+    fn prev(&mut self) {
+        self.iter.prev()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iter.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn status(&mut self) -> TemplateResult<()> {
+        self.iter.status()
+    }
+}
 
-/// Create a new `ConcatenateIterator` as table iterator.
+/// Create a new `TableIterator`.
 /// This iterator is able to yield all the key/values in the given `table` file
 ///
 /// Entry format:
@@ -236,13 +729,35 @@ pub fn new_table_iterator<C: Comparator, F: File>(
     table: Arc<Table<F>>,
     options: ReadOptions,
 ) -> TableIterator<C, F> {
-    let index_iter = table.index_block.iter(cmp.clone());
+    let top_level_iter = table.index_block.iter(cmp.clone());
+    let index_iter = if table.partitioned_index {
+        let factory = IndexPartitionFactory {
+            options: options.clone(),
+            table: table.clone(),
+            cmp: cmp.clone(),
+        };
+        IndexIterator::Partitioned(ConcatenateIterator::new(top_level_iter, factory))
+    } else {
+        IndexIterator::Single(top_level_iter)
+    };
+    let prefix_table =
+        (options.prefix_same_as_start && table.prefix_extractor.is_some()).then(|| table.clone());
     let factory = TableIterFactory {
-        options,
+        prefetch: RefCell::new(FilePrefetchBuffer::new(
+            options.readahead_size,
+            table.file_len,
+        )),
+        options: options.clone(),
         table,
-        cmp,
+        cmp: cmp.clone(),
     };
-    ConcatenateIterator::new(index_iter, factory)
+    TableIterator {
+        iter: ConcatenateIterator::new(index_iter, factory),
+        prefix_table,
+        prefix_excluded: false,
+        cmp,
+        options,
+    }
 }
 
 /// Temporarily stores the contents of the table it is
@@ -255,6 +770,8 @@ pub struct TableBuilder<C: Comparator, F: File> {
     // the written data length
     // updated only after the pending_handle is stored in the index block
     offset: u64,
+    // crc32 of all the bytes written into the file so far
+    checksum: u32,
     data_block: BlockBuilder<C>,
     index_block: BlockBuilder<C>,
     // the last added key
@@ -264,6 +781,18 @@ pub struct TableBuilder<C: Comparator, F: File> {
     num_entries: usize,
     closed: bool,
     filter_block: Option<FilterBlockBuilder>,
+    // The current index partition if the index is partitioned. `index_block` is the top-level
+    // index of the partitions then.
+    index_partition: Option<BlockBuilder<C>>,
+    // (last key, contents) of the finished index partitions, which are written when the table
+    // is finished
+    index_partitions: Vec<(Vec<u8>, Vec<u8>)>,
+    // the last key added into the current index partition
+    last_index_key: Vec<u8>,
+    partitioned_filter: Option<PartitionedFilterBuilder>,
+    full_filter: Option<FullFilterBuilder>,
+    // the last prefix added into the filters since the current data block began
+    last_prefix: Option<Vec<u8>>,
     // Indicates whether we have to add a index to index_block
     //
     // We do not emit the index entry for a block until we have seen the
@@ -272,21 +801,108 @@ pub struct TableBuilder<C: Comparator, F: File> {
     pending_index_entry: bool,
     // handle for current block to add to index block
     pending_handle: BlockHandle,
+    compressor: BlockCompressor,
+    // The data blocks kept in memory as the samples for training the zstd dictionary.
+    // They are written out once the samples are enough or the table is finished.
+    buffered_blocks: Vec<BufferedBlock>,
+    buffered_bytes: usize,
+    // Whether the data blocks are being buffered
+    buffering: bool,
+    compression_dict: Option<Vec<u8>>,
 
     // Fields from `Options`
     block_size: usize,
     block_restart_interval: usize,
-    compression: CompressionType,
+    metadata_block_size: usize,
     filter_policy: Option<Arc<dyn FilterPolicy>>,
+    // only set if there is a filter policy
+    prefix_extractor: Option<Arc<dyn SliceTransform>>,
+    whole_key_filtering: bool,
+    max_dict_bytes: usize,
+    max_train_bytes: usize,
+}
+
+// A finished data block not written into the file yet
+struct BufferedBlock {
+    // the uncompressed block contents
+    raw: Vec<u8>,
+    // the key of the index entry for the block, which is known once the next key is added
+    index_key: Option<Vec<u8>>,
 }
 
 impl<C: Comparator, F: File> TableBuilder<C, F> {
+    /// Creates a `TableBuilder` compressing the blocks by `options.compression`
     pub fn new<UC: Comparator>(file: F, cmp: C, options: &Arc<Options<UC>>) -> Self {
+        Self::with_compression(file, cmp, options, options.compression)
+    }
+
+    /// Creates a `TableBuilder` for a table at `level`, which decides the compression algorithm
+    /// and the bits per key of the filter
+    pub fn for_level<UC: Comparator>(
+        file: F,
+        cmp: C,
+        options: &Arc<Options<UC>>,
+        level: usize,
+    ) -> Self {
+        Self::build(
+            file,
+            cmp,
+            options,
+            options.compression_for_level(level),
+            options.filter_policy_for_level(level),
+        )
+    }
+
+    /// Creates a `TableBuilder` compressing the blocks by the given algorithm
+    pub fn with_compression<UC: Comparator>(
+        file: F,
+        cmp: C,
+        options: &Arc<Options<UC>>,
+        compression: CompressionType,
+    ) -> Self {
+        Self::build(
+            file,
+            cmp,
+            options,
+            compression,
+            options.filter_policy.clone(),
+        )
+    }
+
+    fn build<UC: Comparator>(
+        file: F,
+        cmp: C,
+        options: &Arc<Options<UC>>,
+        compression: CompressionType,
+        filter_policy: Option<Arc<dyn FilterPolicy>>,
+    ) -> Self {
         let opt = options.clone();
         let db_builder = BlockBuilder::new(options.block_restart_interval, cmp.clone());
         let ib_builder = BlockBuilder::new(options.block_restart_interval, cmp.clone());
+        let index_partition = opt
+            .partition_index
+            .then(|| BlockBuilder::new(options.block_restart_interval, cmp.clone()));
+        let partitioned_filter = match &filter_policy {
+            Some(policy) if opt.partition_index && opt.partition_filters => {
+                Some(PartitionedFilterBuilder::new(policy.clone()))
+            }
+            _ => None,
+        };
+        let full_filter = match &filter_policy {
+            Some(policy) if opt.full_filter && partitioned_filter.is_none() => {
+                Some(FullFilterBuilder::new(policy.clone()))
+            }
+            _ => None,
+        };
+        let prefix_extractor = opt
+            .prefix_extractor
+            .clone()
+            .filter(|_| filter_policy.is_some());
         let fb = {
-            if let Some(policy) = opt.filter_policy.clone() {
+            if let Some(policy) = filter_policy
+                .clone()
+                .filter(|_| partitioned_filter.is_none() && full_filter.is_none())
+            {
                 let mut f = FilterBlockBuilder::new(policy.clone());
                 f.start_block(0);
                 Some(f)
@@ -298,18 +914,35 @@ impl<C: Comparator, F: File> TableBuilder<C, F> {
             file,
             cmp,
             offset: 0,
+            checksum: 0,
             data_block: db_builder,
             index_block: ib_builder,
             last_key: vec![],
             num_entries: 0,
             closed: false,
             filter_block: fb,
+            index_partition,
+            index_partitions: vec![],
+            last_index_key: vec![],
+            partitioned_filter,
+            full_filter,
+            last_prefix: None,
             pending_index_entry: false,
             pending_handle: BlockHandle::new(0, 0),
-            compression: opt.compression,
+            compressor: BlockCompressor::new(compression, opt.compression_opts.level),
+            buffered_blocks: vec![],
+            buffered_bytes: 0,
+            buffering: compression == CompressionType::ZstdCompression
+                && opt.compression_opts.max_dict_bytes > 0,
+            compression_dict: None,
             block_size: opt.block_size,
             block_restart_interval: opt.block_restart_interval,
-            filter_policy: opt.filter_policy.clone(),
+            metadata_block_size: opt.metadata_block_size,
+            filter_policy,
+            whole_key_filtering: opt.whole_key_filtering || prefix_extractor.is_none(),
+            prefix_extractor,
+            max_dict_bytes: opt.compression_opts.max_dict_bytes,
+            max_train_bytes: opt.compression_opts.max_train_bytes(),
         }
     }
 
@@ -332,9 +965,10 @@ impl<C: Comparator, F: File> TableBuilder<C, F> {
         }
         // Check whether we need to create a new index entry
         self.maybe_append_index_block(Some(key));
-        // Update filter block
-        if let Some(fb) = self.filter_block.as_mut() {
-            fb.add_key(key)
+        // Update filter block. The keys of the buffered blocks are added when they are written
+        // since the filters are partitioned by the block offsets.
+        if !self.buffering {
+            self.add_filter_key(key);
         }
         // TODO: avoid the copy
         self.last_key.resize(key.len(), 0);
@@ -364,18 +998,34 @@ impl<C: Comparator, F: File> TableBuilder<C, F> {
                 !self.pending_index_entry,
                 "[table builder] the index for the previous data block should never remain when flushing current block data"
             );
+            if self.buffering {
+                let raw = self.data_block.finish().to_vec();
+                self.buffered_bytes += raw.len();
+                self.buffered_blocks.push(BufferedBlock {
+                    raw,
+                    index_key: None,
+                });
+                self.data_block.reset();
+                self.pending_index_entry = true;
+                if self.buffered_bytes >= self.max_train_bytes {
+                    self.enter_unbuffered()?;
+                }
+                return Ok(());
+            }
             let data_block = self.data_block.finish();
-            let (compressed, compression) = compress_block(data_block, self.compression)?;
+            let (compressed, compression) = self.compressor.compress(data_block, true)?;
             write_raw_block(
                 &mut self.file,
                 compressed.as_slice(),
                 compression,
                 &mut self.pending_handle,
                 &mut self.offset,
+                &mut self.checksum,
             )?;
             self.data_block.reset();
             self.pending_index_entry = true;
             self.file.flush()?;
+            self.last_prefix = None;
             if let Some(fb) = &mut self.filter_block {
                 fb.start_block(self.offset)
             }
@@ -392,7 +1042,14 @@ impl<C: Comparator, F: File> TableBuilder<C, F> {
     pub fn finish(&mut self, sync: bool) -> TemplateResult<()> {
         self.flush()?;
         self.assert_not_closed();
+        if self.buffering {
+            self.enter_unbuffered()?;
+        }
         self.closed = true;
+        self.maybe_append_index_block(None); // flush the last index first
+        if self.index_partition.is_some() {
+            self.finish_index_partition();
+        }
         // write filter block
         let mut filter_block_handler = BlockHandle::new(0, 0);
         let mut has_filter_block = false;
@@ -404,15 +1061,107 @@ impl<C: Comparator, F: File> TableBuilder<C, F> {
                 CompressionType::NoCompression,
                 &mut filter_block_handler,
                 &mut self.offset,
+                &mut self.checksum,
             )?;
             has_filter_block = true;
         }
+        // write the full filter
+        let mut full_filter_handle = None;
+        if let Some(ff) = &mut self.full_filter {
+            let mut handle = BlockHandle::new(0, 0);
+            write_raw_block(
+                &mut self.file,
+                &ff.finish(),
+                CompressionType::NoCompression,
+                &mut handle,
+                &mut self.offset,
+                &mut self.checksum,
+            )?;
+            full_filter_handle = Some(handle);
+        }
+        // write the filter partitions and their index
+        let mut filter_index_handle = None;
+        if let Some(pf) = &mut self.partitioned_filter {
+            let mut index_builder =
+                BlockBuilder::new(self.block_restart_interval, self.cmp.clone());
+            for ((key, _), filter) in self.index_partitions.iter().zip(pf.take_partitions()) {
+                let mut handle = BlockHandle::new(0, 0);
+                write_raw_block(
+                    &mut self.file,
+                    &filter,
+                    CompressionType::NoCompression,
+                    &mut handle,
+                    &mut self.offset,
+                    &mut self.checksum,
+                )?;
+                index_builder.add(key, &handle.encoded());
+            }
+            let mut handle = BlockHandle::new(0, 0);
+            write_raw_block(
+                &mut self.file,
+                index_builder.finish(),
+                CompressionType::NoCompression,
+                &mut handle,
+                &mut self.offset,
+                &mut self.checksum,
+            )?;
+            filter_index_handle = Some(handle);
+        }
+
+        // record the prefix extractor the filters are built with
+        let mut prefix_extractor_handle = None;
+        if let Some(pe) = &self.prefix_extractor {
+            let mut data = vec![u8::from(self.whole_key_filtering)];
+            data.extend_from_slice(pe.name().as_bytes());
+            let mut handle = BlockHandle::new(0, 0);
+            write_raw_block(
+                &mut self.file,
+                &data,
+                CompressionType::NoCompression,
+                &mut handle,
+                &mut self.offset,
+                &mut self.checksum,
+            )?;
+            prefix_extractor_handle = Some(handle);
+        }
+
+        // write the zstd dictionary
+        let mut dict_block_handle = None;
+        if let Some(dict) = self.compression_dict.take() {
+            let mut handle = BlockHandle::new(0, 0);
+            write_raw_block(
+                &mut self.file,
+                &dict,
+                CompressionType::NoCompression,
+                &mut handle,
+                &mut self.offset,
+                &mut self.checksum,
+            )?;
+            dict_block_handle = Some(handle);
+        }
+
+        // Write the index partitions and the top-level index before the meta block which
+        // refers to the top-level index
+        let mut index_block_handle = BlockHandle::new(0, 0);
+        let partitioned_index = self.index_partition.is_some();
+        if partitioned_index {
+            for (key, partition) in std::mem::take(&mut self.index_partitions) {
+                let mut handle = BlockHandle::new(0, 0);
+                self.write_block(&partition, &mut handle)?;
+                self.index_block.add(&key, &handle.encoded());
+            }
+            self.write_index_block(&mut index_block_handle)?;
+        }
 
         // write meta block
         let mut meta_block_handle = BlockHandle::new(0, 0);
         let mut meta_block_builder =
             BlockBuilder::new(self.block_restart_interval, self.cmp.clone());
         let meta_block = {
+            // the keys must be added in order
+            if let Some(handle) = &dict_block_handle {
+                meta_block_builder.add(COMPRESSION_DICT_KEY.as_bytes(), &handle.encoded());
+            }
             if has_filter_block {
                 let filter_key = if let Some(fp) = &self.filter_policy {
                     "filter.".to_owned() + fp.name()
@@ -421,27 +1170,36 @@ impl<C: Comparator, F: File> TableBuilder<C, F> {
                 };
                 meta_block_builder.add(filter_key.as_bytes(), &filter_block_handler.encoded());
             }
+            if let (Some(handle), Some(fp)) = (&full_filter_handle, &self.filter_policy) {
+                let key = FULL_FILTER_KEY_PREFIX.to_owned() + fp.name();
+                meta_block_builder.add(key.as_bytes(), &handle.encoded());
+            }
+            if partitioned_index {
+                meta_block_builder.add(
+                    PARTITIONED_INDEX_KEY.as_bytes(),
+                    &index_block_handle.encoded(),
+                );
+            }
+            if let (Some(handle), Some(fp)) = (&filter_index_handle, &self.filter_policy) {
+                let key = PARTITIONED_FILTER_KEY_PREFIX.to_owned() + fp.name();
+                meta_block_builder.add(key.as_bytes(), &handle.encoded());
+            }
+            if let Some(handle) = &prefix_extractor_handle {
+                meta_block_builder.add(PREFIX_EXTRACTOR_KEY.as_bytes(), &handle.encoded());
+            }
             meta_block_builder.finish()
         };
         self.write_block(meta_block, &mut meta_block_handle)?;
 
         // Write index block
-        self.maybe_append_index_block(None); // flush the last index first
-        let index_block = self.index_block.finish();
-        let mut index_block_handle = BlockHandle::new(0, 0);
-        let (c_index_block, ct) = compress_block(index_block, self.compression)?;
-        write_raw_block(
-            &mut self.file,
-            c_index_block.as_slice(),
-            ct,
-            &mut index_block_handle,
-            &mut self.offset,
-        )?;
-        self.index_block.reset();
+        if !partitioned_index {
+            self.write_index_block(&mut index_block_handle)?;
+        }
         // write footer
         let footer = Footer::new(meta_block_handle, index_block_handle).encoded();
         self.file.write(footer.as_slice())?;
         self.offset += footer.len() as u64;
+        self.checksum = extend(self.checksum, footer.as_slice());
         if sync {
             self.file.flush()?;
             self.file.close()?;
@@ -469,9 +1227,19 @@ impl<C: Comparator, F: File> TableBuilder<C, F> {
 
     /// Returns size of the file generated so far. If invoked after a successful
     /// `Finish` call, returns the size of the final generated file.
+    ///
+    /// The data blocks buffered for training the zstd dictionary are counted by their
+    /// uncompressed size since they are not written yet.
     #[inline]
     pub fn file_size(&self) -> u64 {
-        self.offset
+        self.offset + self.buffered_bytes as u64
+    }
+
+    /// Returns the crc32 of the whole file generated so far. If invoked after a successful
+    /// `Finish` call, returns the checksum of the final generated file.
+    #[inline]
+    pub fn file_checksum(&self) -> u32 {
+        self.checksum
     }
 
     #[inline]
@@ -496,53 +1264,186 @@ impl<C: Comparator, F: File> TableBuilder<C, F> {
             } else {
                 self.cmp.successor(&self.last_key)
             };
+            self.pending_index_entry = false;
+            if self.buffering {
+                // The handle is not known until the block is written
+                self.buffered_blocks.last_mut().unwrap().index_key = Some(s);
+                return true;
+            }
             // TODO: use a allocted buffer instead
             let mut handle_encoding = vec![];
             self.pending_handle.encoded_to(&mut handle_encoding);
-            self.index_block.add(&s, &handle_encoding);
-            self.pending_index_entry = false;
+            self.add_index_entry(&s, &handle_encoding);
             return true;
         }
         false
     }
 
-    fn write_block(&mut self, raw_block: &[u8], handle: &mut BlockHandle) -> TemplateResult<()> {
-        let (data, compression) = compress_block(raw_block, self.compression)?;
-        write_raw_block(&mut self.file, &data, compression, handle, &mut self.offset)?;
+    // Trains the zstd dictionary from the buffered data blocks and writes them out. The
+    // following data blocks are written directly.
+    fn enter_unbuffered(&mut self) -> TemplateResult<()> {
+        self.buffering = false;
+        let blocks = std::mem::take(&mut self.buffered_blocks);
+        self.buffered_bytes = 0;
+        if !blocks.is_empty() {
+            let samples = blocks.iter().map(|b| b.raw.as_slice()).collect::<Vec<_>>();
+            match train_dictionary(&samples, self.max_dict_bytes) {
+                Ok(dict) => {
+                    self.compressor.set_dictionary(&dict)?;
+                    self.compression_dict = Some(dict);
+                }
+                // The samples might be too few to train a dictionary
+                Err(e) => warn!("Skip the compression dictionary: {}", e),
+            }
+        }
+        for b in blocks {
+            let (compressed, compression) = self.compressor.compress(&b.raw, true)?;
+            self.last_prefix = None;
+            if let Some(fb) = &mut self.filter_block {
+                fb.start_block(self.offset);
+            }
+            if self.filter_block.is_some()
+                || self.partitioned_filter.is_some()
+                || self.full_filter.is_some()
+            {
+                let block = Block::new(b.raw)?;
+                let mut iter = block.iter(self.cmp.clone());
+                iter.seek_to_first();
+                while iter.valid() {
+                    self.add_filter_key(iter.key());
+                    iter.next();
+                }
+            }
+            let mut handle = BlockHandle::new(0, 0);
+            write_raw_block(
+                &mut self.file,
+                &compressed,
+                compression,
+                &mut handle,
+                &mut self.offset,
+                &mut self.checksum,
+            )?;
+            match b.index_key {
+                Some(key) => {
+                    let mut handle_encoding = vec![];
+                    handle.encoded_to(&mut handle_encoding);
+                    self.add_index_entry(&key, &handle_encoding);
+                }
+                // The last block waits for the next key as usual
+                None => self.pending_handle = handle,
+            }
+        }
+        self.file.flush()?;
+        if let Some(fb) = &mut self.filter_block {
+            fb.start_block(self.offset)
+        }
         Ok(())
     }
-}
 
-// Compresses the give raw block by configured compression algorithm.
-// Returns the compressed data and compression data.
-fn compress_block(
-    raw_block: &[u8],
-    compression: CompressionType,
-) -> TemplateResult<(Vec<u8>, CompressionType)> {
-    match compression {
-        CompressionType::SnappyCompression => {
-            let mut enc = snap::raw::Encoder::new();
-            // TODO: avoid this allocation ?
-            let mut buffer = vec![0; max_compress_len(raw_block.len())];
-            match enc.compress(raw_block, buffer.as_mut_slice()) {
-                Ok(size) => buffer.truncate(size),
-                Err(e) => return Err(TemplateKVError::CompressionFailed(e)),
+    // Adds the key into the filters. With the prefix extractor, the prefix of the user key is
+    // added as well along with the tail of the internal key, once for each data block since
+    // the filters are looked up by the data block holding the target of a seek.
+    fn add_filter_key(&mut self, key: &[u8]) {
+        if let Some(pe) = &self.prefix_extractor {
+            let user_key = extract_user_key(key);
+            if pe.in_domain(user_key) {
+                let prefix = pe.transform(user_key);
+                if self.last_prefix.as_deref() != Some(prefix) {
+                    self.last_prefix = Some(prefix.to_vec());
+                    let mut prefix_key = prefix.to_vec();
+                    prefix_key.extend_from_slice(&key[user_key.len()..]);
+                    self.add_to_filters(&prefix_key);
+                }
+            }
+        }
+        if self.whole_key_filtering {
+            self.add_to_filters(key);
+        }
+    }
+
+    #[inline]
+    fn add_to_filters(&mut self, key: &[u8]) {
+        if let Some(fb) = &mut self.filter_block {
+            fb.add_key(key)
+        }
+        if let Some(pf) = &mut self.partitioned_filter {
+            pf.add_key(key)
+        }
+        if let Some(ff) = &mut self.full_filter {
+            ff.add_key(key)
+        }
+    }
+
+    // Adds an entry into the index block, or into the current index partition which is cut
+    // once it's big enough if the index is partitioned
+    fn add_index_entry(&mut self, key: &[u8], handle_encoding: &[u8]) {
+        match &mut self.index_partition {
+            Some(partition) => {
+                partition.add(key, handle_encoding);
+                self.last_index_key.clear();
+                self.last_index_key.extend_from_slice(key);
+                if partition.current_size_estimate() >= self.metadata_block_size {
+                    self.finish_index_partition();
+                }
             }
-            Ok((buffer, CompressionType::SnappyCompression))
+            None => self.index_block.add(key, handle_encoding),
         }
-        CompressionType::NoCompression | CompressionType::Unknown => {
-            Ok((Vec::from(raw_block), CompressionType::NoCompression))
+    }
+
+    // Finishes the current index partition along with the filter partition for the same data
+    // blocks
+    fn finish_index_partition(&mut self) {
+        let partition = self.index_partition.as_mut().unwrap();
+        if partition.is_empty() {
+            return;
         }
+        self.index_partitions
+            .push((self.last_index_key.clone(), partition.finish().to_vec()));
+        partition.reset();
+        if let Some(pf) = &mut self.partitioned_filter {
+            pf.finish_partition();
+        }
+    }
+
+    // Writes the index block, which is the top-level index if the index is partitioned
+    fn write_index_block(&mut self, handle: &mut BlockHandle) -> TemplateResult<()> {
+        let index_block = self.index_block.finish();
+        let (c_index_block, ct) = self.compressor.compress(index_block, false)?;
+        write_raw_block(
+            &mut self.file,
+            c_index_block.as_slice(),
+            ct,
+            handle,
+            &mut self.offset,
+            &mut self.checksum,
+        )?;
+        self.index_block.reset();
+        Ok(())
+    }
+
+    fn write_block(&mut self, raw_block: &[u8], handle: &mut BlockHandle) -> TemplateResult<()> {
+        let (data, compression) = self.compressor.compress(raw_block, false)?;
+        write_raw_block(
+            &mut self.file,
+            &data,
+            compression,
+            handle,
+            &mut self.offset,
+            &mut self.checksum,
+        )?;
+        Ok(())
     }
 }
 
-// Write given block data into the file with block trailer
+// Write given block data into the file with block trailer and fold the written bytes
+// into the whole file `checksum`
 fn write_raw_block<F: File>(
     file: &mut F,
     data: &[u8],
     compression: CompressionType,
     handle: &mut BlockHandle,
     offset: &mut u64,
+    checksum: &mut u32,
 ) -> TemplateResult<()> {
     // write block data
     file.write(data)?;
@@ -558,20 +1459,54 @@ fn write_raw_block<F: File>(
     file.write(trailer.as_slice())?;
     // update offset
     *offset += (data.len() + BLOCK_TRAILER_SIZE) as u64;
+    *checksum = extend(extend(*checksum, data), &trailer);
     Ok(())
 }
 
 // Read the block identified from `file` according to the given `handle`.
 // If the read data does not match the checksum, return a error marked as `Status::Corruption`
-fn read_block<F: File>(
+pub(crate) fn read_block<F: File>(
+    file: &F,
+    handle: &BlockHandle,
+    verify_checksum: bool,
+) -> TemplateResult<Bytes> {
+    read_block_with_dict(file, handle, verify_checksum, None)
+}
+
+// Same as `read_block` but the block is decompressed with the zstd dictionary `dict`
+pub(crate) fn read_block_with_dict<F: File>(
+    file: &F,
+    handle: &BlockHandle,
+    verify_checksum: bool,
+    dict: Option<&DecoderDictionary<'static>>,
+) -> TemplateResult<Bytes> {
+    read_block_prefetched(file, handle, verify_checksum, dict, None)
+}
+
+// Same as `read_block_with_dict` but the block is read through `prefetch` if any. If the file
+// is memory-mapped, the block is read from the mapping instead, and an uncompressed block
+// refers to the mapping without copying.
+fn read_block_prefetched<F: File>(
     file: &F,
     handle: &BlockHandle,
     verify_checksum: bool,
-) -> TemplateResult<Vec<u8>> {
+    dict: Option<&DecoderDictionary<'static>>,
+    prefetch: Option<&mut FilePrefetchBuffer>,
+) -> TemplateResult<Bytes> {
     let n = handle.size as usize;
-    // TODO: use pre-allocated buf
-    let mut buffer = vec![0; n + BLOCK_TRAILER_SIZE];
-    file.read_exact_at(buffer.as_mut_slice(), handle.offset)?;
+    let buffer = match (
+        file.mapped_slice(handle.offset, n + BLOCK_TRAILER_SIZE),
+        prefetch,
+    ) {
+        (Some(mapped), _) => Bytes::from_owner(mapped),
+        (None, Some(prefetch)) => prefetch.read(file, handle.offset, n + BLOCK_TRAILER_SIZE)?,
+        (None, None) => {
+            // TODO: use pre-allocated buf
+            let mut buffer = vec![0; n + BLOCK_TRAILER_SIZE];
+            file.read_exact_at(buffer.as_mut_slice(), handle.offset)?;
+            Bytes::from(buffer)
+        }
+    };
     if verify_checksum {
         let crc = unmask(decode_fixed_32(&buffer[n + 1..]));
         // Compression type is included in CRC checksum
@@ -582,54 +1517,40 @@ fn read_block<F: File>(
             ));
         }
     }
-    let data = {
-        match CompressionType::from(buffer[n]) {
-            CompressionType::NoCompression => {
-                buffer.truncate(buffer.len() - BLOCK_TRAILER_SIZE);
-                buffer
-            }
-            CompressionType::SnappyCompression => {
-                // TODO: use pre-allocated buf
-                let mut decompressed = vec![];
-                match snap::raw::decompress_len(&buffer[..n]) {
-                    Ok(len) => {
-                        decompressed.resize(len, 0u8);
-                    }
-                    Err(e) => {
-                        return Err(TemplateKVError::CompressionFailed(e));
-                    }
-                }
-                let mut dec = snap::raw::Decoder::new();
-                if let Err(e) = dec.decompress(&buffer[..n], decompressed.as_mut_slice()) {
-                    return Err(TemplateKVError::CompressionFailed(e));
-                }
-                decompressed
-            }
-            CompressionType::Unknown => {
-                return Err(TemplateKVError::Corruption(
-                    "bad block compression type".to_owned(),
-                ));
-            }
-        }
-    };
-    Ok(data)
+    let compression = CompressionType::from(buffer[n]);
+    decompress_block(buffer.slice(..n), compression, dict)
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
 
     use crate::{
-        cache::bloom_filter_cache::BloomFilter,
+        cache::{
+            BlockCacheKey, CachePriority, CacheSync, SecondaryCache,
+            blocked_bloom_filter::BlockedBloomFilter, bloom_filter_cache::BloomFilter,
+            lru_cache::LRUCache, persistent_cache::PersistentCache, sharded_cache::ShardedCache,
+            spill_to,
+        },
         iterator::Iterator,
-        options::{Options, ReadOptions},
+        memtable::{
+            key_format::{
+                InternalFilterPolicy, InternalKey, InternalKeyComparator, extract_user_key,
+            },
+            value_format::{VALUE_TYPE_FOR_SEEK, ValueType},
+        },
+        options::{CompressionType, MAX_KEY_SEQUENCE, Options, ReadOptions},
         sstable::{
-            BlockHandle,
+            BlockHandle, FOOTER_ENCODED_LENGTH, Footer,
             block::Block,
-            table::{Table, TableBuilder, read_block},
+            sst_dump::SstFileDumper,
+            table::{BlockKind, Table, TableBuilder, TableFilter, new_table_iterator, read_block},
         },
-        storage::{File, Storage, mem::MemStorage},
-        util::comparator::BytewiseComparator,
+        storage::{File, Storage, file::FileStorage, file_checksum, mem::MemStorage},
+        util::{comparator::BytewiseComparator, slice_transform::FixedPrefixTransform},
     };
 
     #[test]
@@ -646,7 +1567,7 @@ mod tests {
         let file = s.open("test").unwrap();
         let file_len = file.len().unwrap();
         let table = Table::open(file, 0, file_len, opt.clone(), cmp).unwrap();
-        assert!(table.filter_reader.is_some());
+        assert!(table.filter.is_some());
         assert!(table.meta_block_handle.is_some());
     }
 
@@ -662,13 +1583,32 @@ mod tests {
         let file_len = file.len().unwrap();
         let cmp = BytewiseComparator::default();
         let table = Table::open(file, 0, file_len, opt, cmp).unwrap();
-        assert!(table.filter_reader.is_none());
+        assert!(table.filter.is_none());
         assert!(table.meta_block_handle.is_none()); // no filter block means no meta block
         let read_opt = ReadOptions::default();
-        let res = table.internal_get(read_opt, cmp, b"test").unwrap();
+        let res = table.internal_get(&read_opt, cmp, b"test").unwrap();
         assert!(res.is_none());
     }
 
+    #[test]
+    fn test_file_checksum() {
+        let s = MemStorage::default();
+        let new_file = s.create("test").unwrap();
+        let mut o = Options::<BytewiseComparator>::default();
+        o.filter_policy = Some(Arc::new(BloomFilter::new(16)));
+        o.block_size = 64;
+        let opt = Arc::new(o);
+        let mut tb = TableBuilder::new(new_file, BytewiseComparator::default(), &opt);
+        for i in 0..100 {
+            let k = format!("key{:03}", i);
+            tb.add(k.as_bytes(), b"value").unwrap();
+        }
+        tb.finish(false).unwrap();
+        let (crc, size) = file_checksum(&s, "test").unwrap();
+        assert_eq!(size, tb.file_size());
+        assert_eq!(crc, tb.file_checksum());
+    }
+
     #[test]
     #[should_panic]
     fn test_table_add_consistency() {
@@ -731,16 +1671,789 @@ mod tests {
             verify_checksums: true,
             fill_cache: true,
             snapshot: None,
+            ..Default::default()
         };
         for (key, val) in tests.clone().drain(..) {
             assert_eq!(
                 val.as_bytes(),
                 table
-                    .internal_get(read_opt, cmp, key.as_bytes())
+                    .internal_get(&read_opt, cmp, key.as_bytes())
                     .unwrap()
                     .unwrap()
                     .value()
             );
         }
     }
+
+    #[test]
+    fn test_file_size_counts_buffered_blocks() {
+        let s = MemStorage::default();
+        let mut o = Options::<BytewiseComparator>::default();
+        o.block_size = 256;
+        o.compression_opts.max_dict_bytes = 1024;
+        o.compression_opts.zstd_max_train_bytes = 1 << 20;
+        let opt = Arc::new(o);
+        let cmp = BytewiseComparator::default();
+        let mut tb = TableBuilder::with_compression(
+            s.create("test").unwrap(),
+            cmp,
+            &opt,
+            CompressionType::ZstdCompression,
+        );
+        for i in 0..1000 {
+            let k = format!("key{:06}", i);
+            tb.add(k.as_bytes(), b"value").unwrap();
+        }
+        // Nothing is written while the blocks are buffered
+        assert_eq!(tb.offset, 0);
+        assert!(tb.file_size() >= 1000 * 9);
+        tb.finish(false).unwrap();
+        let (_, size) = file_checksum(&s, "test").unwrap();
+        assert_eq!(size, tb.file_size());
+    }
+
+    #[test]
+    fn test_table_compressions() {
+        let tests = vec![
+            (CompressionType::NoCompression, 0),
+            (CompressionType::SnappyCompression, 0),
+            (CompressionType::Lz4Compression, 0),
+            (CompressionType::ZstdCompression, 0),
+            // The dictionary is trained after some blocks are buffered
+            (CompressionType::ZstdCompression, 1024),
+        ];
+        for (compression, max_dict_bytes) in tests {
+            let s = MemStorage::default();
+            let mut o = Options::<BytewiseComparator>::default();
+            o.filter_policy = Some(Arc::new(BloomFilter::new(10)));
+            o.block_size = 256;
+            o.compression_opts.max_dict_bytes = max_dict_bytes;
+            o.compression_opts.zstd_max_train_bytes = 32 * 1024;
+            let opt = Arc::new(o);
+            let cmp = BytewiseComparator::default();
+            let mut tb =
+                TableBuilder::with_compression(s.create("test").unwrap(), cmp, &opt, compression);
+            let n = 2000;
+            for i in 0..n {
+                let k = format!("key{:06}", i);
+                let v = format!("value-{}-{}", i % 13, i);
+                tb.add(k.as_bytes(), v.as_bytes()).unwrap();
+            }
+            tb.finish(false).unwrap();
+            let (checksum, _) = file_checksum(&s, "test").unwrap();
+            assert_eq!(checksum, tb.file_checksum());
+
+            let file = s.open("test").unwrap();
+            let file_len = file.len().unwrap();
+            let table = Arc::new(Table::open(file, 0, file_len, opt.clone(), cmp).unwrap());
+            assert_eq!(table.compression_dict.is_some(), max_dict_bytes > 0);
+            assert!(table.filter.is_some());
+            let read_opt = ReadOptions {
+                verify_checksums: true,
+                fill_cache: false,
+                snapshot: None,
+                ..Default::default()
+            };
+            for i in 0..n {
+                let k = format!("key{:06}", i);
+                let res = table.internal_get(&read_opt, cmp, k.as_bytes()).unwrap();
+                assert_eq!(
+                    res.unwrap().value(),
+                    format!("value-{}-{}", i % 13, i).as_bytes(),
+                    "{:?}",
+                    compression
+                );
+            }
+            let mut iter = new_table_iterator(cmp, table.clone(), read_opt);
+            iter.seek_to_first();
+            let mut count = 0;
+            while iter.valid() {
+                count += 1;
+                iter.next();
+            }
+            iter.status().unwrap();
+            assert_eq!(count, n);
+
+            // The type byte in the trailer of the data blocks
+            let dumper = SstFileDumper::open(s.open("test").unwrap(), true).unwrap();
+            let (_, handle) = dumper.index().unwrap().pop().unwrap();
+            let mut type_byte = [0u8];
+            s.open("test")
+                .unwrap()
+                .read_exact_at(&mut type_byte, handle.offset + handle.size)
+                .unwrap();
+            assert_eq!(CompressionType::from(type_byte[0]), compression);
+            dumper.verify_checksums().unwrap();
+            assert_eq!(
+                dumper.compression_dict_handle().is_some(),
+                max_dict_bytes > 0
+            );
+        }
+    }
+
+    #[test]
+    fn test_partitioned_index_and_filters() {
+        let tests = vec![
+            (false, CompressionType::NoCompression, 0),
+            (true, CompressionType::NoCompression, 0),
+            (true, CompressionType::SnappyCompression, 0),
+            // The index partitions are never compressed with the dictionary
+            (true, CompressionType::ZstdCompression, 1024),
+        ];
+        for (partition_filters, compression, max_dict_bytes) in tests {
+            let s = MemStorage::default();
+            let mut o = Options::<BytewiseComparator>::default();
+            o.filter_policy = Some(Arc::new(BloomFilter::new(10)));
+            o.block_size = 256;
+            o.partition_index = true;
+            o.partition_filters = partition_filters;
+            o.metadata_block_size = 256;
+            o.compression_opts.max_dict_bytes = max_dict_bytes;
+            o.compression_opts.zstd_max_train_bytes = 32 * 1024;
+            o.block_cache = Some(Arc::new(ShardedCache::new(vec![LRUCache::new(1 << 20)])));
+            let opt = Arc::new(o);
+            let cmp = BytewiseComparator::default();
+            let mut tb =
+                TableBuilder::with_compression(s.create("test").unwrap(), cmp, &opt, compression);
+            let n = 2000;
+            for i in 0..n {
+                let k = format!("key{:06}", i * 2);
+                tb.add(k.as_bytes(), format!("value{}", i).as_bytes())
+                    .unwrap();
+            }
+            tb.finish(false).unwrap();
+            let (checksum, _) = file_checksum(&s, "test").unwrap();
+            assert_eq!(checksum, tb.file_checksum());
+
+            let file = s.open("test").unwrap();
+            let file_len = file.len().unwrap();
+            let table = Arc::new(Table::open(file, 0, file_len, opt.clone(), cmp).unwrap());
+            assert!(table.partitioned_index);
+            assert_eq!(
+                matches!(table.filter, Some(TableFilter::Partitioned { .. })),
+                partition_filters
+            );
+            for read_opt in [
+                ReadOptions {
+                    verify_checksums: true,
+                    fill_cache: false,
+                    snapshot: None,
+                    ..Default::default()
+                },
+                // Reads the partitions from the block cache for the second time
+                ReadOptions::default(),
+                ReadOptions::default(),
+            ] {
+                for i in 0..n {
+                    let k = format!("key{:06}", i * 2);
+                    let res = table.internal_get(&read_opt, cmp, k.as_bytes()).unwrap();
+                    assert_eq!(res.unwrap().value(), format!("value{}", i).as_bytes());
+                }
+            }
+            // The keys between the existing ones
+            let mut false_positives = 0;
+            for i in 0..n {
+                let k = format!("key{:06}", i * 2 + 1);
+                if let Some(iter) = table
+                    .internal_get(&ReadOptions::default(), cmp, k.as_bytes())
+                    .unwrap()
+                {
+                    assert_ne!(iter.key(), k.as_bytes());
+                }
+                if table.key_may_match(cmp, 0, k.as_bytes(), k.as_bytes(), &ReadOptions::default())
+                {
+                    false_positives += 1;
+                }
+            }
+            if partition_filters {
+                assert!(false_positives < n / 10, "{}", false_positives);
+            }
+            assert!(
+                table
+                    .internal_get(&ReadOptions::default(), cmp, b"key999999")
+                    .unwrap()
+                    .is_none()
+            );
+
+            let mut iter = new_table_iterator(cmp, table.clone(), ReadOptions::default());
+            iter.seek_to_first();
+            let mut count = 0;
+            while iter.valid() {
+                assert_eq!(iter.key(), format!("key{:06}", count * 2).as_bytes());
+                count += 1;
+                iter.next();
+            }
+            assert_eq!(count, n);
+            iter.seek_to_last();
+            while iter.valid() {
+                count -= 1;
+                assert_eq!(iter.key(), format!("key{:06}", count * 2).as_bytes());
+                iter.prev();
+            }
+            assert_eq!(count, 0);
+            iter.seek(b"key001001");
+            assert_eq!(iter.key(), b"key001002");
+            iter.status().unwrap();
+
+            let dumper = SstFileDumper::open(s.open("test").unwrap(), true).unwrap();
+            let partitions = dumper.index_partitions().len();
+            assert!(partitions > 1, "{}", partitions);
+            let filters = dumper.filters().unwrap();
+            assert_eq!(filters.len(), 1);
+            if partition_filters {
+                assert_eq!(filters[0].partitions.len(), partitions);
+            } else {
+                assert!(filters[0].partitions.is_empty());
+            }
+            // The data blocks from all the partitions
+            let mut count = 0;
+            dumper
+                .for_each_entry(|_, _| {
+                    count += 1;
+                    Ok(())
+                })
+                .unwrap();
+            assert_eq!(count, n);
+            dumper.verify_checksums().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_full_filter() {
+        for partition_index in [false, true] {
+            let s = MemStorage::default();
+            let mut o = Options::<BytewiseComparator>::default();
+            o.filter_policy = Some(Arc::new(BloomFilter::new(10)));
+            o.full_filter = true;
+            o.block_size = 256;
+            o.partition_index = partition_index;
+            o.metadata_block_size = 256;
+            let cache = Arc::new(ShardedCache::new(vec![LRUCache::new(1 << 20)]));
+            o.block_cache = Some(cache.clone());
+            let opt = Arc::new(o);
+            let cmp = BytewiseComparator::default();
+            let mut tb = TableBuilder::new(s.create("test").unwrap(), cmp, &opt);
+            let n = 2000;
+            for i in 0..n {
+                let k = format!("key{:06}", i * 2);
+                tb.add(k.as_bytes(), b"value").unwrap();
+            }
+            tb.finish(false).unwrap();
+
+            let file = s.open("test").unwrap();
+            let file_len = file.len().unwrap();
+            let table = Table::open(file, 0, file_len, opt.clone(), cmp).unwrap();
+            assert!(matches!(table.filter, Some(TableFilter::Full { .. })));
+            let read_opt = ReadOptions::default();
+            // The missing keys are mostly rejected before reading any index partition or
+            // data block
+            let mut false_positives = 0;
+            for i in 0..n {
+                let k = format!("key{:06}", i * 2 + 1);
+                if table.key_may_match(cmp, 0, k.as_bytes(), k.as_bytes(), &read_opt) {
+                    false_positives += 1;
+                }
+                if let Some(iter) = table.internal_get(&read_opt, cmp, k.as_bytes()).unwrap() {
+                    assert_ne!(iter.key(), k.as_bytes());
+                }
+            }
+            assert!(false_positives < n / 10, "{}", false_positives);
+            if false_positives == 0 {
+                assert_eq!(cache.total_charge(), 0);
+            }
+            for i in 0..n {
+                let k = format!("key{:06}", i * 2);
+                let res = table.internal_get(&read_opt, cmp, k.as_bytes()).unwrap();
+                assert_eq!(res.unwrap().key(), k.as_bytes());
+            }
+            assert!(cache.total_charge() > 0);
+
+            let dumper = SstFileDumper::open(s.open("test").unwrap(), true).unwrap();
+            let filters = dumper.filters().unwrap();
+            assert_eq!(filters.len(), 1);
+            assert_eq!(filters[0].num_filters, 1);
+            assert!(filters[0].partitions.is_empty());
+            dumper.verify_checksums().unwrap();
+        }
+    }
+
+    // A block cache that caches nothing but records the charges inserted with each priority
+    #[derive(Default)]
+    struct PriorityRecorder {
+        high: AtomicUsize,
+        low: AtomicUsize,
+    }
+
+    impl CacheSync<BlockCacheKey, Arc<Block>> for PriorityRecorder {
+        fn insert(
+            &self,
+            key: BlockCacheKey,
+            value: Arc<Block>,
+            charge: usize,
+        ) -> Option<Arc<Block>> {
+            self.insert_with_priority(key, value, charge, CachePriority::Low)
+        }
+
+        fn insert_with_priority(
+            &self,
+            _key: BlockCacheKey,
+            _value: Arc<Block>,
+            charge: usize,
+            priority: CachePriority,
+        ) -> Option<Arc<Block>> {
+            match priority {
+                CachePriority::High => self.high.fetch_add(charge, Ordering::Relaxed),
+                CachePriority::Low => self.low.fetch_add(charge, Ordering::Relaxed),
+            };
+            None
+        }
+
+        fn get(&self, _key: &BlockCacheKey) -> Option<Arc<Block>> {
+            None
+        }
+
+        fn contains(&self, _key: &BlockCacheKey) -> bool {
+            false
+        }
+
+        fn erase(&self, _key: &BlockCacheKey) {}
+
+        fn total_charge(&self) -> usize {
+            0
+        }
+    }
+
+    #[test]
+    fn test_block_cache_priority() {
+        for partition_filters in [false, true] {
+            let s = MemStorage::default();
+            let mut o = Options::<BytewiseComparator>::default();
+            o.filter_policy = Some(Arc::new(BloomFilter::new(10)));
+            o.block_size = 256;
+            o.partition_index = true;
+            o.partition_filters = partition_filters;
+            o.metadata_block_size = 256;
+            let cache = Arc::new(PriorityRecorder::default());
+            o.block_cache = Some(cache.clone());
+            let opt = Arc::new(o);
+            let cmp = BytewiseComparator::default();
+            let mut tb = TableBuilder::new(s.create("test").unwrap(), cmp, &opt);
+            let n = 2000;
+            for i in 0..n {
+                let k = format!("key{:06}", i);
+                tb.add(k.as_bytes(), b"value").unwrap();
+            }
+            tb.finish(false).unwrap();
+
+            let file = s.open("test").unwrap();
+            let file_len = file.len().unwrap();
+            let table = Table::open(file, 0, file_len, opt.clone(), cmp).unwrap();
+            let read_opt = ReadOptions::default();
+            let k = format!("key{:06}", n / 2);
+            assert!(
+                table
+                    .internal_get(&read_opt, cmp, k.as_bytes())
+                    .unwrap()
+                    .is_some()
+            );
+            let (high, low) = (
+                cache.high.load(Ordering::Relaxed),
+                cache.low.load(Ordering::Relaxed),
+            );
+            // The index partition, and the filter partition if any, are of the high priority
+            // while the data block is of the low one
+            assert!(high > 0);
+            assert!(low > 0 && low < 512, "{}", low);
+
+            // A scan reads the data blocks of the low priority
+            let mut it = new_table_iterator(cmp, Arc::new(table), read_opt);
+            it.seek_to_first();
+            let mut count = 0;
+            while it.valid() {
+                count += 1;
+                it.next();
+            }
+            assert_eq!(count, n);
+            assert!(cache.low.load(Ordering::Relaxed) > low + n * 10);
+        }
+    }
+
+    #[test]
+    fn test_secondary_cache() {
+        let mut s = MemStorage::default();
+        s.count_random_reads = true;
+        let secondary_cache =
+            Arc::new(PersistentCache::open(MemStorage::default(), "cache", 1 << 20).unwrap());
+        secondary_cache.bind_db("db").unwrap();
+        let mut o = Options::<BytewiseComparator>::default();
+        o.block_size = 256;
+        // Too small to hold all the data blocks
+        let cache =
+            LRUCache::new(2 << 10).with_eviction_listener(spill_to(secondary_cache.clone()));
+        o.block_cache = Some(Arc::new(ShardedCache::new(vec![cache])));
+        o.secondary_cache = Some(secondary_cache.clone());
+        let opt = Arc::new(o);
+        let cmp = BytewiseComparator::default();
+        let mut tb = TableBuilder::new(s.create("test").unwrap(), cmp, &opt);
+        let n = 1000;
+        for i in 0..n {
+            let k = format!("key{:06}", i);
+            tb.add(k.as_bytes(), b"value").unwrap();
+        }
+        tb.finish(false).unwrap();
+
+        let file = s.open("test").unwrap();
+        let file_len = file.len().unwrap();
+        let table = Table::open(file, 1, file_len, opt.clone(), cmp).unwrap();
+        let read_opt = ReadOptions::default();
+        let get_all = || {
+            for i in 0..n {
+                let k = format!("key{:06}", i);
+                assert!(
+                    table
+                        .internal_get(&read_opt, cmp, k.as_bytes())
+                        .unwrap()
+                        .is_some()
+                );
+            }
+        };
+        get_all();
+        // The data blocks evicted from the block cache are spilled to the secondary cache
+        assert!(secondary_cache.len() > 10, "{}", secondary_cache.len());
+        s.random_read_counter.store(0, Ordering::Release);
+        get_all();
+        assert_eq!(s.random_read_counter.load(Ordering::Acquire), 0);
+
+        // The blocks of a deleted table are erased
+        secondary_cache.erase_file(1);
+        assert!(secondary_cache.is_empty());
+        get_all();
+        assert!(s.random_read_counter.load(Ordering::Acquire) > 10);
+    }
+
+    #[test]
+    fn test_prefix_filters() {
+        // (full filter, partitioned filters, whole key filtering)
+        let tests = [
+            (false, false, true),
+            (false, false, false),
+            (true, false, false),
+            (false, true, true),
+            (false, true, false),
+        ];
+        for (full_filter, partition_filters, whole_key_filtering) in tests {
+            let s = MemStorage::default();
+            let mut o = Options::<BytewiseComparator>::default();
+            // The legacy bloom filter tells the short prefixes apart poorly
+            o.filter_policy = Some(Arc::new(InternalFilterPolicy::new(Arc::new(
+                BlockedBloomFilter::new(10),
+            ))));
+            o.prefix_extractor = Some(Arc::new(FixedPrefixTransform::new(4)));
+            o.whole_key_filtering = whole_key_filtering;
+            o.full_filter = full_filter;
+            o.partition_index = partition_filters;
+            o.partition_filters = partition_filters;
+            o.block_size = 256;
+            o.metadata_block_size = 256;
+            let opt = Arc::new(o);
+            let cmp = InternalKeyComparator::new(BytewiseComparator::default());
+            let mut tb = TableBuilder::new(s.create("test").unwrap(), cmp.clone(), &opt);
+            // The prefixes p000, p002, ... with 20 keys each
+            let n = 200;
+            for i in (0..n).step_by(2) {
+                for j in 0..20 {
+                    let k = format!("p{:03}{:04}", i, j);
+                    let ikey = InternalKey::new(k.as_bytes(), 1, ValueType::Value);
+                    tb.add(ikey.data(), b"value").unwrap();
+                }
+            }
+            tb.finish(false).unwrap();
+
+            let file = s.open("test").unwrap();
+            let file_len = file.len().unwrap();
+            let table = Arc::new(Table::open(file, 0, file_len, opt.clone(), cmp.clone()).unwrap());
+            assert!(table.prefix_extractor.is_some());
+            assert_eq!(table.whole_key_filtering, whole_key_filtering);
+            let seek_key =
+                |k: &str| InternalKey::new(k.as_bytes(), MAX_KEY_SEQUENCE, VALUE_TYPE_FOR_SEEK);
+            let read_opt = ReadOptions {
+                prefix_same_as_start: true,
+                ..ReadOptions::default()
+            };
+            let mut false_positives = 0;
+            for i in 0..n {
+                let prefix = format!("p{:03}", i);
+                let target = seek_key(&format!("{}0010", prefix));
+                let may_match = table.prefix_may_match(cmp.clone(), target.data(), &read_opt);
+                let mut iter = new_table_iterator(cmp.clone(), table.clone(), read_opt.clone());
+                iter.seek(target.data());
+                if i % 2 == 0 {
+                    assert!(may_match, "{}", prefix);
+                    assert!(iter.valid());
+                    assert!(iter.key().starts_with(prefix.as_bytes()));
+                    let k =
+                        InternalKey::new(format!("{}0005", prefix).as_bytes(), 1, ValueType::Value);
+                    let res = table
+                        .internal_get(&read_opt, cmp.clone(), k.data())
+                        .unwrap();
+                    assert_eq!(res.unwrap().key(), k.data());
+                } else if may_match {
+                    false_positives += 1;
+                } else {
+                    assert!(!iter.valid());
+                }
+            }
+            assert!(false_positives < n / 10, "{}", false_positives);
+            // The keys out of the domain of the extractor
+            assert!(table.prefix_may_match(cmp.clone(), seek_key("p0").data(), &read_opt));
+
+            let dumper = SstFileDumper::open(s.open("test").unwrap(), true).unwrap();
+            let props = dumper.properties().unwrap();
+            assert_eq!(
+                props.prefix_extractor.as_deref(),
+                Some("TemplateDB.FixedPrefix.4")
+            );
+            assert_eq!(props.whole_key_filtering, whole_key_filtering);
+            dumper.verify_checksums().unwrap();
+
+            // The prefixes are ignored by the options with another extractor
+            let mut o = Options::<BytewiseComparator>::default();
+            o.filter_policy = opt.filter_policy.clone();
+            o.prefix_extractor = Some(Arc::new(FixedPrefixTransform::new(3)));
+            let opt = Arc::new(o);
+            let file = s.open("test").unwrap();
+            let table = Table::open(file, 0, file_len, opt, cmp.clone()).unwrap();
+            assert!(table.prefix_extractor.is_none());
+            assert_eq!(table.filter.is_some(), whole_key_filtering);
+            let target = seek_key("p0010000");
+            assert!(table.prefix_may_match(cmp.clone(), target.data(), &read_opt));
+            let k = InternalKey::new(b"p0020005", 1, ValueType::Value);
+            let res = table
+                .internal_get(&read_opt, cmp.clone(), k.data())
+                .unwrap();
+            assert_eq!(res.unwrap().key(), k.data());
+        }
+    }
+
+    #[test]
+    fn test_multi_get() {
+        for full_filter in [false, true] {
+            let s = MemStorage::default();
+            let mut o = Options::<BytewiseComparator>::default();
+            o.filter_policy = Some(Arc::new(InternalFilterPolicy::new(Arc::new(
+                BlockedBloomFilter::new(10),
+            ))));
+            o.full_filter = full_filter;
+            o.block_size = 256;
+            let cache = Arc::new(ShardedCache::new(vec![LRUCache::new(1 << 20)]));
+            o.block_cache = Some(cache.clone());
+            let opt = Arc::new(o);
+            let cmp = InternalKeyComparator::new(BytewiseComparator::default());
+            let mut tb = TableBuilder::new(s.create("test").unwrap(), cmp.clone(), &opt);
+            // Only the even keys are in the table
+            let n = 2000;
+            let ikey =
+                |i: usize| InternalKey::new(format!("key{:05}", i).as_bytes(), 1, ValueType::Value);
+            for i in (0..n).step_by(2) {
+                tb.add(ikey(i).data(), format!("value{}", i).as_bytes())
+                    .unwrap();
+            }
+            tb.finish(false).unwrap();
+            let file = s.open("test").unwrap();
+            let file_len = file.len().unwrap();
+            let table = Table::open(file, 0, file_len, opt, cmp.clone()).unwrap();
+            let read_opt = ReadOptions::default();
+            // A cached block between the blocks to read
+            assert!(
+                table
+                    .internal_get(&read_opt, cmp.clone(), ikey(500).data())
+                    .unwrap()
+                    .is_some()
+            );
+            let indexes = (0..n + 10).step_by(3).collect::<Vec<_>>();
+            let keys = indexes.iter().map(|i| ikey(*i)).collect::<Vec<_>>();
+            let key_refs = keys.iter().map(|k| k.data()).collect::<Vec<_>>();
+            let results = table.multi_get(&read_opt, cmp.clone(), &key_refs).unwrap();
+            assert_eq!(results.len(), keys.len());
+            for ((i, key), result) in indexes.into_iter().zip(&keys).zip(results) {
+                let expected = table
+                    .internal_get(&read_opt, cmp.clone(), key.data())
+                    .unwrap()
+                    .map(|iter| (iter.key().to_vec(), iter.value_bytes()));
+                assert_eq!(result, expected);
+                if i % 2 == 0 && i < n {
+                    let (k, v) = result.unwrap();
+                    assert_eq!(k, key.data());
+                    assert_eq!(v, format!("value{}", i).as_bytes());
+                } else {
+                    assert!(result.is_none_or(|(k, _)| extract_user_key(&k) != key.user_key()));
+                }
+            }
+            assert!(table.multi_get(&read_opt, cmp, &[]).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_mmap_reads() {
+        for compression in [
+            CompressionType::NoCompression,
+            CompressionType::SnappyCompression,
+        ] {
+            let name = "test_mmap_reads_table";
+            let mut o = Options::<BytewiseComparator>::default();
+            o.compression = compression;
+            o.block_size = 256;
+            let cache = Arc::new(ShardedCache::new(vec![LRUCache::new(1 << 20)]));
+            o.block_cache = Some(cache);
+            let opt = Arc::new(o);
+            let cmp = InternalKeyComparator::new(BytewiseComparator::default());
+            let mut tb = TableBuilder::new(FileStorage.create(name).unwrap(), cmp.clone(), &opt);
+            let n = 1000;
+            let ikey =
+                |i: usize| InternalKey::new(format!("key{:05}", i).as_bytes(), 1, ValueType::Value);
+            for i in 0..n {
+                tb.add(ikey(i).data(), format!("value{}", i).repeat(5).as_bytes())
+                    .unwrap();
+            }
+            tb.finish(false).unwrap();
+            let file = FileStorage.open_mmap(name).unwrap();
+            assert!(file.is_mmap());
+            let file_len = file.len().unwrap();
+            let table = Table::open(file, 0, file_len, opt, cmp.clone()).unwrap();
+            let read_opt = ReadOptions::default();
+            for i in 0..n {
+                let iter = table
+                    .internal_get(&read_opt, cmp.clone(), ikey(i).data())
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(iter.key(), ikey(i).data());
+                assert_eq!(iter.value(), format!("value{}", i).repeat(5).as_bytes());
+                // Only the uncompressed blocks are served from the mapping
+                let handle = table
+                    .find_data_block(cmp.clone(), ikey(i).data(), &read_opt)
+                    .unwrap()
+                    .unwrap();
+                let block = table
+                    .read_block_cached(handle.clone(), &read_opt, BlockKind::Data, None)
+                    .unwrap();
+                let mapped = table
+                    .file
+                    .mapped_slice(handle.offset, handle.size as usize)
+                    .unwrap();
+                assert_eq!(
+                    block.data().as_ptr() == mapped.as_ptr(),
+                    compression == CompressionType::NoCompression
+                );
+            }
+            let keys = (0..n).step_by(7).map(ikey).collect::<Vec<_>>();
+            let key_refs = keys.iter().map(|k| k.data()).collect::<Vec<_>>();
+            let results = table.multi_get(&read_opt, cmp, &key_refs).unwrap();
+            for (key, result) in keys.iter().zip(results) {
+                assert_eq!(result.unwrap().0, key.data());
+            }
+            FileStorage.remove(name).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_scan_with_readahead() {
+        let s = MemStorage::default();
+        let mut o = Options::<BytewiseComparator>::default();
+        o.block_size = 256;
+        let opt = Arc::new(o);
+        let cmp = BytewiseComparator::default();
+        let mut tb = TableBuilder::new(s.create("test").unwrap(), cmp, &opt);
+        let n = 5000;
+        for i in 0..n {
+            let k = format!("key{:06}", i);
+            tb.add(k.as_bytes(), format!("value{}", i).as_bytes())
+                .unwrap();
+        }
+        tb.finish(false).unwrap();
+        let file = s.open("test").unwrap();
+        let file_len = file.len().unwrap();
+        let table = Arc::new(Table::open(file, 0, file_len, opt, cmp).unwrap());
+        for readahead_size in [0, 100, 4096, 1 << 20] {
+            let read_opt = ReadOptions {
+                verify_checksums: true,
+                readahead_size,
+                ..Default::default()
+            };
+            let mut iter = new_table_iterator(cmp, table.clone(), read_opt);
+            iter.seek_to_first();
+            for i in 0..n {
+                assert!(iter.valid());
+                assert_eq!(iter.key(), format!("key{:06}", i).as_bytes());
+                assert_eq!(iter.value(), format!("value{}", i).as_bytes());
+                iter.next();
+            }
+            assert!(!iter.valid());
+            // The random seeks between the sequential reads
+            for i in (0..n).step_by(997) {
+                iter.seek(format!("key{:06}", i).as_bytes());
+                for j in i..(i + 100).min(n) {
+                    assert_eq!(iter.key(), format!("key{:06}", j).as_bytes());
+                    iter.next();
+                }
+            }
+            iter.status().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_unknown_compression_type() {
+        assert_eq!(CompressionType::from(0), CompressionType::NoCompression);
+        assert_eq!(CompressionType::from(1), CompressionType::SnappyCompression);
+        assert_eq!(CompressionType::from(4), CompressionType::Lz4Compression);
+        assert_eq!(CompressionType::from(7), CompressionType::ZstdCompression);
+        assert_eq!(CompressionType::from(2), CompressionType::Unknown);
+
+        let s = MemStorage::default();
+        let opt = Arc::new(Options::<BytewiseComparator>::default());
+        let mut tb = TableBuilder::new(
+            s.create("test").unwrap(),
+            BytewiseComparator::default(),
+            &opt,
+        );
+        tb.data_block.add(b"k", b"v");
+        let block = Vec::from(tb.data_block.finish());
+        let mut bh = BlockHandle::new(0, 0);
+        tb.write_block(&block, &mut bh).unwrap();
+        // Rewrite the type byte with an unsupported one
+        let mut contents = vec![];
+        s.open("test").unwrap().read_all(&mut contents).unwrap();
+        contents[bh.size as usize] = 2;
+        s.remove("test").unwrap();
+        s.create("test").unwrap().write(&contents).unwrap();
+        let e = read_block(&s.open("test").unwrap(), &bh, false).unwrap_err();
+        assert!(e.is_corruption());
+    }
+
+    #[test]
+    fn test_open_with_corrupted_meta_block() {
+        let s = MemStorage::default();
+        let mut o = Options::<BytewiseComparator>::default();
+        o.filter_policy = Some(Arc::new(BloomFilter::new(10)));
+        o.paranoid_checks = true;
+        let opt = Arc::new(o);
+        let cmp = BytewiseComparator::default();
+        let mut tb = TableBuilder::new(s.create("test").unwrap(), cmp, &opt);
+        for i in 0..100 {
+            let k = format!("key{:03}", i);
+            tb.add(k.as_bytes(), b"value").unwrap();
+        }
+        tb.finish(false).unwrap();
+        let mut contents = vec![];
+        s.open("test").unwrap().read_all(&mut contents).unwrap();
+        let (footer, _) =
+            Footer::decode_from(&contents[contents.len() - FOOTER_ENCODED_LENGTH..]).unwrap();
+        assert!(footer.meta_index_handle.size > 0);
+        contents[footer.meta_index_handle.offset as usize] ^= 0xff;
+        s.remove("test").unwrap();
+        s.create("test").unwrap().write(&contents).unwrap();
+        let file = s.open("test").unwrap();
+        let file_len = file.len().unwrap();
+        let e = Table::open(file, 0, file_len, opt, cmp).err().unwrap();
+        assert!(e.is_corruption());
+    }
 }
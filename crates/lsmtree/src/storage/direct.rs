@@ -0,0 +1,421 @@
+use std::{
+    cell::RefCell,
+    fs::{File as SysFile, OpenOptions},
+    io::{self, SeekFrom},
+    os::unix::{
+        fs::{FileExt, OpenOptionsExt},
+        io::{AsRawFd, RawFd},
+    },
+    path::Path,
+};
+
+use crate::{
+    error::{TemplateKVError, TemplateResult},
+    storage::File,
+};
+
+/// The alignment of the file offsets, the lengths and the buffer addresses of direct I/O
+pub const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+// The size of the aligned chunks the writes are buffered into
+const WRITE_BUFFER_SIZE: usize = 1024 * 1024;
+
+// The max size of the buffer kept by every thread for the unaligned reads. The larger reads
+// allocate their own buffers.
+const MAX_READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+thread_local! {
+    // The buffer the unaligned reads of the current thread are done into
+    static READ_BUFFER: RefCell<AlignedBuffer> = RefCell::new(AlignedBuffer::new(0));
+}
+
+#[inline]
+fn align_down(n: u64) -> u64 {
+    n & !(DIRECT_IO_ALIGNMENT as u64 - 1)
+}
+
+#[inline]
+fn align_up(n: u64) -> u64 {
+    align_down(n + DIRECT_IO_ALIGNMENT as u64 - 1)
+}
+
+// A buffer whose address is aligned to `DIRECT_IO_ALIGNMENT`
+struct AlignedBuffer {
+    // over-allocated by `DIRECT_IO_ALIGNMENT` bytes so that an aligned address is always
+    // within it, and never reallocated
+    buf: Vec<u8>,
+    start: usize,
+    capacity: usize,
+}
+
+impl AlignedBuffer {
+    fn new(capacity: usize) -> Self {
+        let buf = vec![0; capacity + DIRECT_IO_ALIGNMENT];
+        let start = buf.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+        Self {
+            buf,
+            start,
+            capacity,
+        }
+    }
+
+    #[inline]
+    fn as_slice(&self) -> &[u8] {
+        &self.buf[self.start..self.start + self.capacity]
+    }
+
+    #[inline]
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.buf[self.start..self.start + self.capacity]
+    }
+}
+
+// The writes not written to the file yet
+struct WriteBuffer {
+    buf: AlignedBuffer,
+    len: usize,
+    // the aligned file offset of `buf`
+    offset: u64,
+    // true if there are writes not written to the file
+    dirty: bool,
+}
+
+/// `DirectFile` is a file opened with `O_DIRECT`, whose I/O bypasses the OS page cache.
+///
+/// Since direct I/O requires the offsets, the lengths and the buffers to be aligned to
+/// `DIRECT_IO_ALIGNMENT`, the reads are done by the aligned reads covering them, and the
+/// writes are buffered into the aligned chunks of `WRITE_BUFFER_SIZE` bytes, which are
+/// written once they are full. `flush` does nothing since the last partial chunk can't be
+/// written without padding. `sync` and `close` write it padded with zeros and then truncate
+/// the file to drop the padding.
+///
+/// The writes always append to the file created by `create`, and the file opened by `open`
+/// is read-only. The reads don't see the writes until the file is synced or closed.
+pub struct DirectFile {
+    file: SysFile,
+    // the length of the file including the writes in `write_buffer`
+    len: u64,
+    // the position of `read`
+    pos: u64,
+    // `None` if the file is read-only
+    write_buffer: Option<WriteBuffer>,
+}
+
+impl DirectFile {
+    /// Creates the file `name` for writing with direct I/O, truncating it if it exists
+    pub fn create<P: AsRef<Path>>(name: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .read(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(name)?;
+        Ok(Self {
+            file,
+            len: 0,
+            pos: 0,
+            write_buffer: Some(WriteBuffer {
+                buf: AlignedBuffer::new(WRITE_BUFFER_SIZE),
+                len: 0,
+                offset: 0,
+                dirty: false,
+            }),
+        })
+    }
+
+    /// Opens the file `name` for reading with direct I/O
+    pub fn open<P: AsRef<Path>>(name: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(name)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            file,
+            len,
+            pos: 0,
+            write_buffer: None,
+        })
+    }
+
+    // Writes the buffered writes padded to the alignment. The last partial block is kept in
+    // the buffer since it will be written again along with the following writes.
+    fn write_out(&mut self) -> io::Result<()> {
+        let Some(wb) = self.write_buffer.as_mut() else {
+            return Ok(());
+        };
+        if wb.len == 0 {
+            return Ok(());
+        }
+        let padded = align_up(wb.len as u64) as usize;
+        wb.buf.as_mut_slice()[wb.len..padded].fill(0);
+        self.file
+            .write_all_at(&wb.buf.as_slice()[..padded], wb.offset)?;
+        let full = align_down(wb.len as u64) as usize;
+        wb.buf.as_mut_slice().copy_within(full..wb.len, 0);
+        wb.len -= full;
+        wb.offset += full as u64;
+        wb.dirty = false;
+        Ok(())
+    }
+
+    // Writes all the buffered writes including the last partial block, and then truncates
+    // the file to drop its padding
+    fn write_all_out(&mut self) -> io::Result<()> {
+        if self.write_buffer.as_ref().is_none_or(|wb| !wb.dirty) {
+            return Ok(());
+        }
+        self.write_out()?;
+        if self.len % DIRECT_IO_ALIGNMENT as u64 != 0 {
+            self.file.set_len(self.len)?;
+        }
+        Ok(())
+    }
+
+    // Reads the aligned range `[offset, offset + buf.len())` into the aligned `buf` until
+    // it's filled or the end of the file is reached
+    fn read_aligned(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            match FileExt::read_at(&self.file, &mut buf[read..], offset + read as u64) {
+                Ok(0) => break,
+                Ok(n) => {
+                    read += n;
+                    // Only the read reaching the end of the file returns an unaligned length
+                    if n % DIRECT_IO_ALIGNMENT != 0 {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(read)
+    }
+
+    // Copies `[offset, offset + buf.len())` from the aligned range read into `aligned`,
+    // which starts at `start`
+    fn read_unaligned(
+        &self,
+        aligned: &mut [u8],
+        start: u64,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> io::Result<usize> {
+        let read = self.read_aligned(aligned, start)?;
+        let skip = (offset - start) as usize;
+        if read <= skip {
+            return Ok(0);
+        }
+        let n = buf.len().min(read - skip);
+        buf[..n].copy_from_slice(&aligned[skip..skip + n]);
+        Ok(n)
+    }
+}
+
+#[inline]
+fn is_aligned(buf: &[u8], offset: u64) -> bool {
+    offset % DIRECT_IO_ALIGNMENT as u64 == 0
+        && buf.len() % DIRECT_IO_ALIGNMENT == 0
+        && buf.as_ptr().align_offset(DIRECT_IO_ALIGNMENT) == 0
+}
+
+impl File for DirectFile {
+    fn write(&mut self, mut buf: &[u8]) -> TemplateResult<usize> {
+        let written = buf.len();
+        while !buf.is_empty() {
+            let Some(wb) = self.write_buffer.as_mut() else {
+                return Err(TemplateKVError::IO(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the direct I/O file is opened for reading",
+                )));
+            };
+            let n = buf.len().min(WRITE_BUFFER_SIZE - wb.len);
+            wb.buf.as_mut_slice()[wb.len..wb.len + n].copy_from_slice(&buf[..n]);
+            wb.len += n;
+            wb.dirty = true;
+            self.len += n as u64;
+            buf = &buf[n..];
+            // A full chunk is aligned so it's written without padding
+            if wb.len == WRITE_BUFFER_SIZE {
+                map_io_res!(self.write_out())?;
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> TemplateResult<()> {
+        // Writing the last partial chunk on every flush would pad and truncate the file again
+        // and again, so it's left to `sync` and `close`
+        Ok(())
+    }
+
+    fn sync(&mut self) -> TemplateResult<()> {
+        map_io_res!(self.write_all_out())?;
+        map_io_res!(self.file.sync_all())
+    }
+
+    fn close(&mut self) -> TemplateResult<()> {
+        map_io_res!(self.write_all_out())
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> TemplateResult<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        match new_pos {
+            Some(n) => {
+                self.pos = n;
+                Ok(n)
+            }
+            None => Err(TemplateKVError::IO(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            ))),
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> TemplateResult<usize> {
+        let n = self.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn read_all(&mut self, buf: &mut Vec<u8>) -> TemplateResult<usize> {
+        let start = buf.len();
+        buf.resize(start + self.len.saturating_sub(self.pos) as usize, 0);
+        let n = self.read_at(&mut buf[start..], self.pos)?;
+        buf.truncate(start + n);
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn len(&self) -> TemplateResult<u64> {
+        Ok(self.len)
+    }
+
+    fn lock(&self) -> TemplateResult<()> {
+        File::lock(&self.file)
+    }
+
+    fn unlock(&self) -> TemplateResult<()> {
+        File::unlock(&self.file)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> TemplateResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if is_aligned(buf, offset) {
+            return map_io_res!(self.read_aligned(buf, offset));
+        }
+        let start = align_down(offset);
+        let len = (align_up(offset + buf.len() as u64) - start) as usize;
+        if len > MAX_READ_BUFFER_SIZE {
+            let mut aligned = AlignedBuffer::new(len);
+            return map_io_res!(self.read_unaligned(aligned.as_mut_slice(), start, buf, offset));
+        }
+        READ_BUFFER.with(|aligned| {
+            let mut aligned = aligned.borrow_mut();
+            if aligned.capacity < len {
+                *aligned = AlignedBuffer::new(len.next_power_of_two());
+            }
+            let aligned = &mut aligned.as_mut_slice()[..len];
+            map_io_res!(self.read_unaligned(aligned, start, buf, offset))
+        })
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> TemplateResult<()> {
+        // `read_at` reads as much as possible by one aligned read
+        if self.read_at(buf, offset)? < buf.len() {
+            let e = io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer");
+            return Err(TemplateKVError::IO(e));
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for DirectFile {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Drop for DirectFile {
+    fn drop(&mut self) {
+        // Don't lose the buffered writes
+        let _ = self.write_all_out();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_file;
+
+    use super::*;
+
+    #[test]
+    fn test_direct_file() {
+        let name = "test_direct_file";
+        let data = (0..3 * WRITE_BUFFER_SIZE + 1234)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let mut f = DirectFile::create(name).unwrap();
+        // The unaligned writes across the chunks
+        let mut written = 0;
+        for n in [1, 4095, 4097, 100_000, WRITE_BUFFER_SIZE, 7] {
+            f.write(&data[written..written + n]).unwrap();
+            written += n;
+            assert_eq!(f.len().unwrap(), written as u64);
+        }
+        // Only the full chunks are written
+        f.flush().unwrap();
+        assert_eq!(
+            std::fs::metadata(name).unwrap().len(),
+            WRITE_BUFFER_SIZE as u64
+        );
+        f.sync().unwrap();
+        assert_eq!(std::fs::metadata(name).unwrap().len(), written as u64);
+        // Rewriting the last partial block by the following writes
+        f.write(&data[written..]).unwrap();
+        f.close().unwrap();
+        drop(f);
+        assert_eq!(std::fs::read(name).unwrap(), data);
+
+        let mut f = DirectFile::open(name).unwrap();
+        assert_eq!(f.len().unwrap(), data.len() as u64);
+        let mut buf = vec![0; 10_000];
+        for offset in [0, 1, 4095, 4096, 123_456] {
+            f.read_exact_at(&mut buf, offset).unwrap();
+            assert_eq!(buf, &data[offset as usize..offset as usize + buf.len()]);
+        }
+        // The reads at the end of the file
+        let offset = data.len() as u64 - 100;
+        assert_eq!(f.read_at(&mut buf, offset).unwrap(), 100);
+        assert_eq!(&buf[..100], &data[data.len() - 100..]);
+        assert!(f.read_exact_at(&mut buf, offset).is_err());
+        assert_eq!(f.read_at(&mut buf, data.len() as u64 + 1).unwrap(), 0);
+        // The aligned reads are done into the buffer directly
+        let mut aligned = AlignedBuffer::new(2 * DIRECT_IO_ALIGNMENT);
+        let n = f.read_at(aligned.as_mut_slice(), 4096).unwrap();
+        assert_eq!(n, aligned.capacity);
+        assert_eq!(aligned.as_slice(), &data[4096..4096 + n]);
+        let offset = align_down(data.len() as u64);
+        let n = f.read_at(aligned.as_mut_slice(), offset).unwrap();
+        assert_eq!(n, data.len() - offset as usize);
+        assert_eq!(&aligned.as_slice()[..n], &data[offset as usize..]);
+        // The reads larger than the buffer kept by the thread
+        let mut large = vec![0; MAX_READ_BUFFER_SIZE + 1];
+        f.read_exact_at(&mut large, 1).unwrap();
+        assert_eq!(large, &data[1..large.len() + 1]);
+        let mut all = vec![];
+        f.seek(SeekFrom::Start(1000)).unwrap();
+        assert_eq!(f.read_all(&mut all).unwrap(), data.len() - 1000);
+        assert_eq!(all, &data[1000..]);
+        assert!(f.write(b"read-only").is_err());
+        remove_file(name).unwrap();
+    }
+}
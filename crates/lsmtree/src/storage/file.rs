@@ -1,7 +1,7 @@
 use std::{
     fs::{
-        File as SysFile, OpenOptions, create_dir_all, read_dir, remove_dir, remove_dir_all,
-        remove_file, rename,
+        File as SysFile, OpenOptions, create_dir_all, hard_link, read_dir, remove_dir,
+        remove_dir_all, remove_file, rename,
     },
     io::{BufReader, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
@@ -9,39 +9,106 @@ use std::{
 
 use fs2::FileExt;
 
+#[cfg(target_os = "linux")]
+use crate::storage::direct::DirectFile;
 use crate::{
-    error::{TemplateKVError, TemplateResult},
-    storage::{File, Storage},
+    error::{Operation, TemplateKVError, TemplateResult},
+    storage::{
+        File, Storage,
+        mmap::{MappedSlice, MmapFile},
+    },
 };
 
 #[derive(Clone, Default)]
 pub struct FileStorage;
 
 impl Storage for FileStorage {
-    type F = SysFile;
+    type F = PosixFile;
     fn create<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
         match OpenOptions::new()
             .write(true)
             .read(true)
             .create(true)
             .truncate(true)
-            .open(name)
+            .open(name.as_ref())
         {
-            Ok(f) => Ok(f),
-            Err(e) => Err(TemplateKVError::IO(e)),
+            Ok(f) => Ok(PosixFile::Buffered(f)),
+            Err(e) => Err(TemplateKVError::IO(e)
+                .with_operation(Operation::CreateFile)
+                .with_path(name)),
         }
     }
 
     fn open<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
-        match OpenOptions::new().write(true).read(true).open(name) {
-            Ok(f) => Ok(f),
-            Err(e) => Err(TemplateKVError::IO(e)),
+        match OpenOptions::new()
+            .write(true)
+            .read(true)
+            .open(name.as_ref())
+        {
+            Ok(f) => Ok(PosixFile::Buffered(f)),
+            Err(e) => Err(TemplateKVError::IO(e)
+                .with_operation(Operation::OpenFile)
+                .with_path(name)),
+        }
+    }
+
+    fn open_read_only<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+        match OpenOptions::new().read(true).open(name.as_ref()) {
+            Ok(f) => Ok(PosixFile::Buffered(f)),
+            Err(e) => Err(TemplateKVError::IO(e)
+                .with_operation(Operation::OpenFile)
+                .with_path(name)),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn open_direct<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+        match DirectFile::open(name.as_ref()) {
+            Ok(f) => Ok(PosixFile::Direct(f)),
+            // The file system doesn't support direct I/O
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                warn!(
+                    "direct I/O is not supported, fall back to buffered I/O: {}",
+                    e
+                );
+                self.open_read_only(name)
+            }
+            Err(e) => Err(TemplateKVError::IO(e)
+                .with_operation(Operation::OpenFile)
+                .with_path(name)),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn create_direct<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+        match DirectFile::create(name.as_ref()) {
+            Ok(f) => Ok(PosixFile::Direct(f)),
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                warn!(
+                    "direct I/O is not supported, fall back to buffered I/O: {}",
+                    e
+                );
+                self.create(name)
+            }
+            Err(e) => Err(TemplateKVError::IO(e)
+                .with_operation(Operation::CreateFile)
+                .with_path(name)),
+        }
+    }
+
+    fn open_mmap<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+        match MmapFile::open(name.as_ref()) {
+            Ok(f) => Ok(PosixFile::Mmap(f)),
+            Err(e) => Err(TemplateKVError::IO(e)
+                .with_operation(Operation::OpenFile)
+                .with_path(name)),
         }
     }
 
     fn remove<P: AsRef<Path>>(&self, name: P) -> TemplateResult<()> {
-        let r = remove_file(name);
+        let r = remove_file(name.as_ref());
         map_io_res!(r)
+            .map_err(|e: TemplateKVError| e.with_operation(Operation::RemoveFile).with_path(name))
     }
 
     fn remove_dir<P: AsRef<Path>>(&self, dir: P, recursively: bool) -> TemplateResult<()> {
@@ -58,7 +125,8 @@ impl Storage for FileStorage {
     }
 
     fn rename<P: AsRef<Path>>(&self, old: P, new: P) -> TemplateResult<()> {
-        map_io_res!(rename(old, new))
+        map_io_res!(rename(old.as_ref(), new))
+            .map_err(|e: TemplateKVError| e.with_operation(Operation::RenameFile).with_path(old))
     }
 
     fn mkdir_all<P: AsRef<Path>>(&self, dir: P) -> TemplateResult<()> {
@@ -66,26 +134,142 @@ impl Storage for FileStorage {
         map_io_res!(r)
     }
 
+    fn link<P: AsRef<Path>>(&self, src: P, dst: P) -> TemplateResult<()> {
+        map_io_res!(hard_link(src.as_ref(), dst))
+            .map_err(|e: TemplateKVError| e.with_operation(Operation::CreateFile).with_path(src))
+    }
+
+    fn sync_dir<P: AsRef<Path>>(&self, dir: P) -> TemplateResult<()> {
+        let r = SysFile::open(dir.as_ref()).and_then(|d| d.sync_all());
+        map_io_res!(r)
+            .map_err(|e: TemplateKVError| e.with_operation(Operation::OpenFile).with_path(dir))
+    }
+
     fn list<P: AsRef<Path>>(&self, dir: P) -> TemplateResult<Vec<PathBuf>> {
         if dir.as_ref().is_dir() {
             let mut v = vec![];
-            match read_dir(dir) {
+            let on_err = |e| {
+                TemplateKVError::IO(e)
+                    .with_operation(Operation::ListDir)
+                    .with_path(dir.as_ref())
+            };
+            match read_dir(dir.as_ref()) {
                 Ok(rd) => {
                     for entry in rd {
                         match entry {
                             Ok(p) => v.push(p.path()),
-                            Err(e) => return Err(TemplateKVError::IO(e)),
+                            Err(e) => return Err(on_err(e)),
                         }
                     }
                     return Ok(v);
                 }
-                Err(e) => return Err(TemplateKVError::IO(e)),
+                Err(e) => return Err(on_err(e)),
             }
         }
         Ok(vec![])
     }
 }
 
+/// A file of `FileStorage`
+pub enum PosixFile {
+    Buffered(SysFile),
+    /// The file opened by `Storage::open_direct` or `Storage::create_direct`
+    #[cfg(target_os = "linux")]
+    Direct(DirectFile),
+    /// The file opened by `Storage::open_mmap`
+    Mmap(MmapFile),
+}
+
+impl PosixFile {
+    /// Returns true if the file is opened with direct I/O
+    #[inline]
+    pub fn is_direct(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        return matches!(self, PosixFile::Direct(_));
+        #[cfg(not(target_os = "linux"))]
+        false
+    }
+
+    /// Returns true if the file is memory-mapped
+    #[inline]
+    pub fn is_mmap(&self) -> bool {
+        matches!(self, PosixFile::Mmap(_))
+    }
+}
+
+// Calls the same method of the underlying file
+macro_rules! delegate {
+    ($file:expr, $f:ident => $call:expr) => {
+        match $file {
+            PosixFile::Buffered($f) => $call,
+            #[cfg(target_os = "linux")]
+            PosixFile::Direct($f) => $call,
+            PosixFile::Mmap($f) => $call,
+        }
+    };
+}
+
+impl File for PosixFile {
+    fn write(&mut self, buf: &[u8]) -> TemplateResult<usize> {
+        delegate!(self, f => File::write(f, buf))
+    }
+
+    fn flush(&mut self) -> TemplateResult<()> {
+        delegate!(self, f => File::flush(f))
+    }
+
+    fn sync(&mut self) -> TemplateResult<()> {
+        delegate!(self, f => File::sync(f))
+    }
+
+    fn close(&mut self) -> TemplateResult<()> {
+        delegate!(self, f => File::close(f))
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> TemplateResult<u64> {
+        delegate!(self, f => File::seek(f, pos))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> TemplateResult<usize> {
+        delegate!(self, f => File::read(f, buf))
+    }
+
+    fn read_all(&mut self, buf: &mut Vec<u8>) -> TemplateResult<usize> {
+        delegate!(self, f => File::read_all(f, buf))
+    }
+
+    fn len(&self) -> TemplateResult<u64> {
+        delegate!(self, f => File::len(f))
+    }
+
+    fn lock(&self) -> TemplateResult<()> {
+        delegate!(self, f => File::lock(f))
+    }
+
+    fn unlock(&self) -> TemplateResult<()> {
+        delegate!(self, f => File::unlock(f))
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> TemplateResult<usize> {
+        delegate!(self, f => File::read_at(f, buf, offset))
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> TemplateResult<()> {
+        delegate!(self, f => File::read_exact_at(f, buf, offset))
+    }
+
+    fn mapped_slice(&self, offset: u64, n: usize) -> Option<MappedSlice> {
+        delegate!(self, f => File::mapped_slice(f, offset, n))
+    }
+}
+
+#[cfg(unix)]
+impl std::os::unix::io::AsRawFd for PosixFile {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        delegate!(self, f => f.as_raw_fd())
+    }
+}
+
 impl File for SysFile {
     fn write(&mut self, buf: &[u8]) -> TemplateResult<usize> {
         map_io_res!(Write::write(self, buf))
@@ -95,6 +279,11 @@ impl File for SysFile {
         map_io_res!(Write::flush(self))
     }
 
+    fn sync(&mut self) -> TemplateResult<()> {
+        map_io_res!(Write::flush(self))?;
+        map_io_res!(self.sync_all())
+    }
+
     fn close(&mut self) -> TemplateResult<()> {
         Ok(())
     }
@@ -171,4 +360,14 @@ mod tests {
             .expect_err("failed to fill whole buffer");
         remove_file("test").unwrap();
     }
+
+    #[test]
+    fn test_sync_dir() {
+        let dir = "test_sync_dir";
+        FileStorage.mkdir_all(dir).unwrap();
+        FileStorage.create(Path::new(dir).join("f")).unwrap();
+        FileStorage.sync_dir(dir).unwrap();
+        FileStorage.remove_dir(dir, true).unwrap();
+        assert!(FileStorage.sync_dir(dir).is_err());
+    }
 }
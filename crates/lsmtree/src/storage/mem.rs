@@ -137,6 +137,21 @@ fn clean<P: AsRef<Path>>(path: P) -> PathBuf {
         })
 }
 
+// Whether `path` is inside the directory `dir`. Both of them must be cleaned.
+fn is_descendant(path: &str, dir: &str) -> bool {
+    path != dir
+        && path.starts_with(dir)
+        && (dir.ends_with(MAIN_SEPARATOR) || path[dir.len()..].starts_with(MAIN_SEPARATOR))
+}
+
+// Whether `path` is directly in the directory `dir`
+fn is_child(path: &str, dir: &str) -> bool {
+    is_descendant(path, dir)
+        && !path[dir.len()..]
+            .trim_start_matches(MAIN_SEPARATOR)
+            .contains(MAIN_SEPARATOR)
+}
+
 impl Storage for MemStorage {
     type F = FileNode;
 
@@ -222,7 +237,7 @@ impl Storage for MemStorage {
             let mut to_delete = if let Some(n) = map.get(&key) {
                 if n.is_dir() {
                     map.keys()
-                        .filter(|k| *k != &key && k.starts_with(&key))
+                        .filter(|k| is_descendant(k, &key))
                         .map(|p| p.into())
                         .collect::<Vec<PathBuf>>()
                 } else {
@@ -250,8 +265,8 @@ impl Storage for MemStorage {
                 match n {
                     Node::Dir => {
                         // Should be an empty dir
-                        for k in map.keys().filter(|k| *k != &key) {
-                            if k.starts_with(&key) {
+                        for k in map.keys() {
+                            if is_descendant(k, &key) {
                                 return Err(TemplateKVError::IO(IOError::new(
                                     ErrorKind::NotFound,
                                     format!("{}: is not an empty dir", &key),
@@ -343,7 +358,7 @@ impl Storage for MemStorage {
         }
         Ok(map
             .keys()
-            .filter(|k| *k != &path && k.starts_with(&path))
+            .filter(|k| is_child(k, &path))
             .map(|p| p.into())
             .collect::<Vec<PathBuf>>())
     }
@@ -884,6 +899,22 @@ mod tests {
             store.assert_file_exists(name);
         }
     }
+    #[test]
+    fn test_storage_list_excludes_siblings() {
+        let store = MemStorage::default();
+        store.mkdir_all("db").unwrap();
+        store.mkdir_all("db.tmp").unwrap();
+        store.create("db/a").unwrap();
+        store.create("db.tmp/b").unwrap();
+        store.mkdir_all("db/sub").unwrap();
+        store.create("db/sub/c").unwrap();
+        let mut list = store.list("db").unwrap();
+        list.sort();
+        assert_eq!(list, vec![clean("db/a"), clean("db/sub")]);
+        store.remove_dir("db", true).unwrap();
+        store.assert_file_exists("db.tmp/b");
+    }
+
     #[test]
     fn test_path_clean() {
         let tests = if cfg!(windows) {
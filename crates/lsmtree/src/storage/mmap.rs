@@ -0,0 +1,232 @@
+use std::{
+    fmt,
+    fs::{File as SysFile, OpenOptions},
+    io::{self, SeekFrom},
+    ops::Deref,
+    path::Path,
+    sync::Arc,
+};
+
+use memmap2::Mmap;
+
+use crate::{
+    error::{TemplateKVError, TemplateResult},
+    storage::File,
+};
+
+/// `MappedSlice` is a range of a memory-mapped file. It keeps the mapping alive so that it
+/// can outlive the file, e.g. as the contents of a cached block.
+#[derive(Clone)]
+pub struct MappedSlice {
+    map: Arc<Mmap>,
+    start: usize,
+    end: usize,
+}
+
+impl MappedSlice {
+    /// Returns the sub-range `[start, end)` of the slice
+    #[must_use]
+    pub fn slice(&self, start: usize, end: usize) -> Self {
+        assert!(start <= end && end <= self.len());
+        Self {
+            map: self.map.clone(),
+            start: self.start + start,
+            end: self.start + end,
+        }
+    }
+}
+
+impl Deref for MappedSlice {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        &self.map[self.start..self.end]
+    }
+}
+
+impl AsRef<[u8]> for MappedSlice {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl fmt::Debug for MappedSlice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedSlice")
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .finish()
+    }
+}
+
+/// `MmapFile` is a read-only file mapped into memory. The reads copy from the mapping, and
+/// `mapped_slice` returns the ranges of it without copying.
+///
+/// The file must not be modified while it's mapped, which holds for the table files since
+/// they are immutable once written.
+pub struct MmapFile {
+    file: SysFile,
+    // `None` if the file is empty, which can't be mapped
+    map: Option<Arc<Mmap>>,
+    // the position of `read`
+    pos: u64,
+}
+
+impl MmapFile {
+    /// Opens the file `name` and maps the whole of it
+    pub fn open<P: AsRef<Path>>(name: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).open(name)?;
+        let map = if file.metadata()?.len() == 0 {
+            None
+        } else {
+            // SAFETY: the file is immutable while it's mapped
+            Some(Arc::new(unsafe { Mmap::map(&file)? }))
+        };
+        Ok(Self { file, map, pos: 0 })
+    }
+
+    #[inline]
+    fn data(&self) -> &[u8] {
+        self.map.as_deref().map_or(&[], |m| &m[..])
+    }
+}
+
+impl File for MmapFile {
+    fn write(&mut self, _buf: &[u8]) -> TemplateResult<usize> {
+        Err(TemplateKVError::IO(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the memory-mapped file is read-only",
+        )))
+    }
+
+    fn flush(&mut self) -> TemplateResult<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> TemplateResult<()> {
+        Ok(())
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> TemplateResult<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => (self.data().len() as u64).checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        match new_pos {
+            Some(n) => {
+                self.pos = n;
+                Ok(n)
+            }
+            None => Err(TemplateKVError::IO(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            ))),
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> TemplateResult<usize> {
+        let n = self.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn read_all(&mut self, buf: &mut Vec<u8>) -> TemplateResult<usize> {
+        let start = (self.pos as usize).min(self.data().len());
+        buf.extend_from_slice(&self.data()[start..]);
+        let n = self.data().len() - start;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn len(&self) -> TemplateResult<u64> {
+        Ok(self.data().len() as u64)
+    }
+
+    fn lock(&self) -> TemplateResult<()> {
+        File::lock(&self.file)
+    }
+
+    fn unlock(&self) -> TemplateResult<()> {
+        File::unlock(&self.file)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> TemplateResult<usize> {
+        let data = self.data();
+        if offset >= data.len() as u64 {
+            return Ok(0);
+        }
+        let start = offset as usize;
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+
+    fn mapped_slice(&self, offset: u64, n: usize) -> Option<MappedSlice> {
+        let map = self.map.as_ref()?;
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(n)?;
+        if end > map.len() {
+            return None;
+        }
+        Some(MappedSlice {
+            map: map.clone(),
+            start,
+            end,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl std::os::unix::io::AsRawFd for MmapFile {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{remove_file, write};
+
+    use super::*;
+
+    #[test]
+    fn test_mmap_file() {
+        let name = "test_mmap_file";
+        let data = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        write(name, &data).unwrap();
+        let mut f = MmapFile::open(name).unwrap();
+        assert_eq!(f.len().unwrap(), data.len() as u64);
+
+        let mut buf = vec![0; 100];
+        f.read_exact_at(&mut buf, 1000).unwrap();
+        assert_eq!(buf, &data[1000..1100]);
+        assert_eq!(f.read_at(&mut buf, 99_950).unwrap(), 50);
+        assert_eq!(&buf[..50], &data[99_950..]);
+        assert_eq!(f.read_at(&mut buf, 200_000).unwrap(), 0);
+        assert!(f.read_exact_at(&mut buf, 99_950).is_err());
+
+        let s = f.mapped_slice(500, 1000).unwrap();
+        assert_eq!(&s[..], &data[500..1500]);
+        assert_eq!(&s.slice(10, 20)[..], &data[510..520]);
+        assert!(f.mapped_slice(99_000, 1001).is_none());
+
+        let mut all = vec![];
+        f.seek(SeekFrom::Start(99_000)).unwrap();
+        assert_eq!(f.read_all(&mut all).unwrap(), 1000);
+        assert_eq!(all, &data[99_000..]);
+        assert!(f.write(b"read-only").is_err());
+        // The mapping outlives the file
+        drop(f);
+        assert_eq!(&s[..10], &data[500..510]);
+        remove_file(name).unwrap();
+
+        write(name, b"").unwrap();
+        let f = MmapFile::open(name).unwrap();
+        assert_eq!(f.len().unwrap(), 0);
+        assert!(f.mapped_slice(0, 0).is_none());
+        remove_file(name).unwrap();
+    }
+}
@@ -1,5 +1,10 @@
+#[cfg(target_os = "linux")]
+pub mod direct;
 pub mod file;
 pub mod mem;
+pub mod mmap;
+#[cfg(target_os = "linux")]
+pub mod uring;
 
 use std::{
     io,
@@ -7,7 +12,11 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::error::{TemplateKVError, TemplateResult};
+use crate::{
+    error::{TemplateKVError, TemplateResult},
+    storage::mmap::MappedSlice,
+    util::crc32,
+};
 
 /// `Storage` is a namespace for files.
 ///
@@ -23,6 +32,12 @@ pub trait Storage: Send + Sync {
     /// Open a file for writing and reading
     fn open<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F>;
 
+    /// Open a file for reading only, which works without the write permission of the file.
+    /// The storages without such permissions open the file as `open` does.
+    fn open_read_only<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+        self.open(name)
+    }
+
     /// Delete the named file
     fn remove<P: AsRef<Path>>(&self, name: P) -> TemplateResult<()>;
 
@@ -42,12 +57,52 @@ pub trait Storage: Send + Sync {
 
     /// Returns a list of the full-path to each file in given directory
     fn list<P: AsRef<Path>>(&self, dir: P) -> TemplateResult<Vec<PathBuf>>;
+
+    /// Opens a file for reading with direct I/O, which bypasses the OS page cache.
+    /// The storages without direct I/O open the file as `open` does.
+    fn open_direct<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+        self.open(name)
+    }
+
+    /// Creates a file for writing with direct I/O, which bypasses the OS page cache.
+    /// The storages without direct I/O create the file as `create` does.
+    fn create_direct<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+        self.create(name)
+    }
+
+    /// Opens a file for reading by mapping it into memory, so that its ranges can be read
+    /// without copying by `File::mapped_slice`. The storages without memory-mapped files open
+    /// the file as `open` does.
+    fn open_mmap<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+        self.open(name)
+    }
+
+    /// Makes the entries of the directory `dir`, e.g. the files just created in it, durable
+    /// on the device. The storages not backed by a device do nothing.
+    fn sync_dir<P: AsRef<Path>>(&self, _dir: P) -> TemplateResult<()> {
+        Ok(())
+    }
+
+    /// Creates a new hard link `dst` to the file `src`.
+    /// The storages without hard links return an `Unsupported` error so that the caller
+    /// can fall back to `copy_file`.
+    fn link<P: AsRef<Path>>(&self, _src: P, _dst: P) -> TemplateResult<()> {
+        Err(TemplateKVError::IO(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Hard link is not supported",
+        )))
+    }
 }
 
 /// A file abstraction for IO operations
 pub trait File: Send + Sync {
     fn write(&mut self, buf: &[u8]) -> TemplateResult<usize>;
     fn flush(&mut self) -> TemplateResult<()>;
+    /// Flushes the file and makes the written data durable on the device. The files not
+    /// backed by a device only flush.
+    fn sync(&mut self) -> TemplateResult<()> {
+        self.flush()
+    }
     fn close(&mut self) -> TemplateResult<()>;
     fn seek(&mut self, pos: SeekFrom) -> TemplateResult<u64>;
     fn read(&mut self, buf: &mut [u8]) -> TemplateResult<usize>;
@@ -107,6 +162,25 @@ pub trait File: Send + Sync {
             Ok(())
         }
     }
+
+    /// Reads the exact number of bytes required to fill every buffer in `reads` from the
+    /// offset paired with it.
+    ///
+    /// The implementations are free to issue the reads concurrently, e.g. by submitting all
+    /// of them to an io_uring at once. The default implementation calls `read_exact_at` for
+    /// the reads one by one.
+    fn read_exact_at_batch(&self, reads: &mut [(u64, &mut [u8])]) -> TemplateResult<()> {
+        for (offset, buf) in reads.iter_mut() {
+            self.read_exact_at(buf, *offset)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the `n` bytes at `offset` without copying if the file is memory-mapped and
+    /// contains all of them. Returns `None` otherwise, and the caller should read them.
+    fn mapped_slice(&self, _offset: u64, _n: usize) -> Option<MappedSlice> {
+        None
+    }
 }
 
 /// Write given `data` into underlying `env` file and flush file iff `should_sync` is true
@@ -119,10 +193,78 @@ pub fn do_write_string_to_file<S: Storage, P: AsRef<Path>>(
     let mut file = env.create(&file_name)?;
     file.write(data.as_bytes())?;
     if should_sync {
-        file.flush()?;
+        file.sync()?;
     }
     if file.close().is_err() {
         env.remove(&file_name)?;
     }
     Ok(())
 }
+
+/// Copy the first `size` bytes of file `src` in `src_env` into a newly created file `dst` in
+/// `dst_env`, which is synced before returning. The whole file is copied if `size` is `None`.
+///
+/// Returns the crc32 checksum and the length of the copied data.
+pub fn copy_file<S: Storage, T: Storage, P: AsRef<Path>, Q: AsRef<Path>>(
+    src_env: &S,
+    src: P,
+    dst_env: &T,
+    dst: Q,
+    size: Option<u64>,
+) -> TemplateResult<(u32, u64)> {
+    let src_file = src_env.open_read_only(&src)?;
+    let size = match size {
+        Some(s) => s,
+        None => src_file.len()?,
+    };
+    let mut dst_file = dst_env.create(&dst)?;
+    let mut buf = vec![0; COPY_BUFFER_SIZE];
+    let mut crc = 0;
+    let mut offset = 0;
+    while offset < size {
+        let n = (size - offset).min(COPY_BUFFER_SIZE as u64) as usize;
+        src_file.read_exact_at(&mut buf[..n], offset)?;
+        crc = crc32::extend(crc, &buf[..n]);
+        dst_file.write(&buf[..n])?;
+        offset += n as u64;
+    }
+    // The file must be durable before anything, e.g. a CURRENT or a backup meta file,
+    // refers to it
+    dst_file.sync()?;
+    dst_file.close()?;
+    Ok((crc, size))
+}
+
+/// Returns the crc32 checksum and the length of the whole file `name`
+pub fn file_checksum<S: Storage, P: AsRef<Path>>(env: &S, name: P) -> TemplateResult<(u32, u64)> {
+    let file = env.open_read_only(name)?;
+    let size = file.len()?;
+    let mut buf = vec![0; COPY_BUFFER_SIZE];
+    let mut crc = 0;
+    let mut offset = 0;
+    while offset < size {
+        let n = (size - offset).min(COPY_BUFFER_SIZE as u64) as usize;
+        file.read_exact_at(&mut buf[..n], offset)?;
+        crc = crc32::extend(crc, &buf[..n]);
+        offset += n as u64;
+    }
+    Ok((crc, size))
+}
+
+/// Checks the crc32 checksum of the whole file `name` against `expected`
+pub fn verify_file_checksum<S: Storage, P: AsRef<Path>>(
+    env: &S,
+    name: P,
+    expected: u32,
+) -> TemplateResult<()> {
+    let (checksum, _) = file_checksum(env, name)?;
+    if checksum != expected {
+        return Err(TemplateKVError::Corruption(format!(
+            "file checksum {:#010x} does not match the expected {:#010x}",
+            checksum, expected
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) const COPY_BUFFER_SIZE: usize = 64 * 1024;
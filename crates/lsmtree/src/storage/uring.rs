@@ -0,0 +1,474 @@
+use std::{
+    cell::RefCell,
+    io::{self, SeekFrom},
+    os::unix::io::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use io_uring::{IoUring, opcode, types};
+
+use crate::{
+    error::{TemplateKVError, TemplateResult},
+    storage::{
+        File, Storage,
+        file::{FileStorage, PosixFile},
+        mmap::MappedSlice,
+    },
+};
+
+// The number of the entries of the submission queue, which is also the max number of the
+// reads submitted at a time
+const QUEUE_DEPTH: u32 = 64;
+
+// The times to retry a failed submission before giving up waiting for the reads in flight
+const MAX_SUBMIT_RETRIES: usize = 1000;
+
+enum RingState {
+    Uninitialized,
+    Ready(Box<IoUring>),
+    // io_uring is not available, e.g. forbidden by seccomp
+    Unavailable,
+}
+
+thread_local! {
+    // Every thread submits the reads to its own io_uring so that no lock is needed
+    static RING: RefCell<RingState> = const { RefCell::new(RingState::Uninitialized) };
+}
+
+#[cfg(test)]
+thread_local! {
+    // The number of the next submissions to fail, for testing the error handling
+    static FAILING_SUBMITS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// `UringStorage` is the same as `FileStorage` except that the reads of the files are
+/// submitted to io_uring. A batch of reads by `File::read_exact_at_batch` is submitted at
+/// once, so that they are served concurrently.
+///
+/// It falls back to the blocking reads of `FileStorage` if io_uring is not available. The files
+/// opened with direct I/O are always read by the blocking reads, which align the buffers.
+#[derive(Clone)]
+pub struct UringStorage {
+    use_io_uring: bool,
+}
+
+impl UringStorage {
+    /// Creates a `UringStorage` using io_uring if it's available
+    #[must_use]
+    pub fn new() -> Self {
+        let use_io_uring = match IoUring::new(QUEUE_DEPTH) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!(
+                    "io_uring is not available, fall back to blocking reads: {}",
+                    e
+                );
+                false
+            }
+        };
+        Self { use_io_uring }
+    }
+
+    /// Creates a `UringStorage` always using the blocking reads
+    #[must_use]
+    pub fn blocking() -> Self {
+        Self {
+            use_io_uring: false,
+        }
+    }
+
+    /// Returns true if the reads are submitted to io_uring
+    #[inline]
+    pub fn is_io_uring_enabled(&self) -> bool {
+        self.use_io_uring
+    }
+
+    #[inline]
+    fn wrap(&self, file: PosixFile) -> UringFile {
+        // The direct files are read by the blocking reads with their aligned buffers
+        let use_io_uring = self.use_io_uring && !file.is_direct();
+        UringFile { file, use_io_uring }
+    }
+}
+
+impl Default for UringStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Storage for UringStorage {
+    type F = UringFile;
+    fn create<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+        FileStorage.create(name).map(|f| self.wrap(f))
+    }
+
+    fn open<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+        FileStorage.open(name).map(|f| self.wrap(f))
+    }
+
+    fn open_read_only<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+        FileStorage.open_read_only(name).map(|f| self.wrap(f))
+    }
+
+    fn open_direct<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+        FileStorage.open_direct(name).map(|f| self.wrap(f))
+    }
+
+    fn create_direct<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+        FileStorage.create_direct(name).map(|f| self.wrap(f))
+    }
+
+    fn open_mmap<P: AsRef<Path>>(&self, name: P) -> TemplateResult<Self::F> {
+        FileStorage.open_mmap(name).map(|f| self.wrap(f))
+    }
+
+    fn remove<P: AsRef<Path>>(&self, name: P) -> TemplateResult<()> {
+        FileStorage.remove(name)
+    }
+
+    fn remove_dir<P: AsRef<Path>>(&self, dir: P, recursively: bool) -> TemplateResult<()> {
+        FileStorage.remove_dir(dir, recursively)
+    }
+
+    fn exists<P: AsRef<Path>>(&self, name: P) -> bool {
+        FileStorage.exists(name)
+    }
+
+    fn rename<P: AsRef<Path>>(&self, old: P, new: P) -> TemplateResult<()> {
+        FileStorage.rename(old, new)
+    }
+
+    fn mkdir_all<P: AsRef<Path>>(&self, dir: P) -> TemplateResult<()> {
+        FileStorage.mkdir_all(dir)
+    }
+
+    fn list<P: AsRef<Path>>(&self, dir: P) -> TemplateResult<Vec<PathBuf>> {
+        FileStorage.list(dir)
+    }
+
+    fn link<P: AsRef<Path>>(&self, src: P, dst: P) -> TemplateResult<()> {
+        FileStorage.link(src, dst)
+    }
+
+    fn sync_dir<P: AsRef<Path>>(&self, dir: P) -> TemplateResult<()> {
+        FileStorage.sync_dir(dir)
+    }
+}
+
+/// A file of `UringStorage`
+pub struct UringFile {
+    file: PosixFile,
+    use_io_uring: bool,
+}
+
+impl File for UringFile {
+    fn write(&mut self, buf: &[u8]) -> TemplateResult<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> TemplateResult<()> {
+        File::flush(&mut self.file)
+    }
+
+    fn sync(&mut self) -> TemplateResult<()> {
+        File::sync(&mut self.file)
+    }
+
+    fn close(&mut self) -> TemplateResult<()> {
+        self.file.close()
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> TemplateResult<u64> {
+        File::seek(&mut self.file, pos)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> TemplateResult<usize> {
+        File::read(&mut self.file, buf)
+    }
+
+    fn read_all(&mut self, buf: &mut Vec<u8>) -> TemplateResult<usize> {
+        self.file.read_all(buf)
+    }
+
+    fn len(&self) -> TemplateResult<u64> {
+        File::len(&self.file)
+    }
+
+    fn lock(&self) -> TemplateResult<()> {
+        File::lock(&self.file)
+    }
+
+    fn unlock(&self) -> TemplateResult<()> {
+        File::unlock(&self.file)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> TemplateResult<usize> {
+        if self.use_io_uring {
+            let mut reads = [(offset, buf)];
+            if let Some(r) = submit_reads(self.file.as_raw_fd(), &mut reads) {
+                return map_io_res!(r.and_then(|mut results| results.pop().unwrap()));
+            }
+            let [(_, buf)] = reads;
+            return File::read_at(&self.file, buf, offset);
+        }
+        File::read_at(&self.file, buf, offset)
+    }
+
+    fn read_exact_at_batch(&self, reads: &mut [(u64, &mut [u8])]) -> TemplateResult<()> {
+        let submitted = if self.use_io_uring {
+            submit_reads(self.file.as_raw_fd(), reads)
+        } else {
+            None
+        };
+        let results = match submitted {
+            Some(r) => map_io_res!(r)?,
+            None => reads.iter().map(|_| Ok(0)).collect(),
+        };
+        for ((offset, buf), result) in reads.iter_mut().zip(results) {
+            let n = match result {
+                Ok(n) => n,
+                Err(e)
+                    if e.kind() == io::ErrorKind::Interrupted
+                        || e.kind() == io::ErrorKind::WouldBlock =>
+                {
+                    0
+                }
+                Err(e) => return Err(TemplateKVError::IO(e)),
+            };
+            // Finish the short reads and the reads not submitted by the blocking reads
+            if n < buf.len() {
+                self.file.read_exact_at(&mut buf[n..], *offset + n as u64)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn mapped_slice(&self, offset: u64, n: usize) -> Option<MappedSlice> {
+        self.file.mapped_slice(offset, n)
+    }
+}
+
+// Submits the reads to the io_uring of the current thread and waits for all of them.
+// Returns the number of bytes read into every buffer, or `None` if io_uring is not available
+// on the thread.
+fn submit_reads(
+    fd: RawFd,
+    reads: &mut [(u64, &mut [u8])],
+) -> Option<io::Result<Vec<io::Result<usize>>>> {
+    RING.with(|state| {
+        let mut state = state.borrow_mut();
+        if let RingState::Uninitialized = *state {
+            *state = match IoUring::new(QUEUE_DEPTH) {
+                Ok(ring) => RingState::Ready(Box::new(ring)),
+                Err(e) => {
+                    warn!(
+                        "io_uring is not available, fall back to blocking reads: {}",
+                        e
+                    );
+                    RingState::Unavailable
+                }
+            };
+        }
+        let RingState::Ready(ring) = &mut *state else {
+            return None;
+        };
+        let mut results = Vec::with_capacity(reads.len());
+        for batch in reads.chunks_mut(QUEUE_DEPTH as usize) {
+            // All the reads of the batch are completed even if it fails, so the ring is left
+            // empty and can be reused
+            match submit_batch(ring, fd, batch) {
+                Ok(r) => results.extend(r),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(Ok(results))
+    })
+}
+
+// Submits at most `QUEUE_DEPTH` reads and waits for all of them
+fn submit_batch(
+    ring: &mut IoUring,
+    fd: RawFd,
+    batch: &mut [(u64, &mut [u8])],
+) -> io::Result<Vec<io::Result<usize>>> {
+    let mut sq = ring.submission();
+    // Nothing is pushed unless the whole batch fits so that no read is left in the queue
+    if sq.capacity() - sq.len() < batch.len() {
+        return Err(io::Error::other("io_uring submission queue is full"));
+    }
+    for (i, (offset, buf)) in batch.iter_mut().enumerate() {
+        let entry = opcode::Read::new(types::Fd(fd), buf.as_mut_ptr(), buf.len() as u32)
+            .offset(*offset)
+            .build()
+            .user_data(i as u64);
+        // SAFETY: the buffer outlives the read since `wait_for_reads` doesn't return until all
+        // the pushed reads complete, even if the submission fails
+        unsafe { sq.push(&entry) }.expect("the free entries are checked above");
+    }
+    drop(sq);
+    let mut results = batch.iter().map(|_| Ok(0)).collect::<Vec<_>>();
+    wait_for_reads(ring, &mut results)?;
+    Ok(results)
+}
+
+// Submits the pushed reads and reaps the completions of all of them, so that the kernel no
+// longer writes into their buffers when it returns. If a submission fails, it's retried to
+// drain the reads and the error is returned after that. The process is aborted if the reads
+// can't be drained since their buffers would be released while the kernel writes into them.
+fn wait_for_reads(ring: &mut IoUring, results: &mut [io::Result<usize>]) -> io::Result<()> {
+    let mut completed = 0;
+    let mut error = None;
+    let mut retries = 0;
+    while completed < results.len() {
+        match enter(ring, results.len() - completed) {
+            Ok(_) => retries = 0,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                retries += 1;
+                if retries > MAX_SUBMIT_RETRIES {
+                    error!(
+                        "Failed to drain {} io_uring reads in flight: {}",
+                        results.len() - completed,
+                        e
+                    );
+                    std::process::abort();
+                }
+                error.get_or_insert(e);
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        for cqe in ring.completion() {
+            results[cqe.user_data() as usize] = if cqe.result() < 0 {
+                Err(io::Error::from_raw_os_error(-cqe.result()))
+            } else {
+                Ok(cqe.result() as usize)
+            };
+            completed += 1;
+        }
+    }
+    error.map_or(Ok(()), Err)
+}
+
+// Submits the entries in the submission queue and waits for at least `want` completions
+fn enter(ring: &mut IoUring, want: usize) -> io::Result<usize> {
+    #[cfg(test)]
+    if FAILING_SUBMITS.with(|n| n.replace(n.get().saturating_sub(1))) > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "injected submission failure",
+        ));
+    }
+    ring.submit_and_wait(want)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uring_file_reads() {
+        let name = "test_uring_file_reads";
+        let data = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        for storage in [UringStorage::new(), UringStorage::blocking()] {
+            let mut f = storage.create(name).unwrap();
+            f.write(&data).unwrap();
+            f.flush().unwrap();
+            let f = storage.open(name).unwrap();
+            assert_eq!(f.len().unwrap(), data.len() as u64);
+
+            let mut buf = vec![0; 100];
+            assert_eq!(f.read_at(&mut buf, 1000).unwrap(), 100);
+            assert_eq!(buf, &data[1000..1100]);
+            // Short read at the end of the file
+            assert_eq!(f.read_at(&mut buf, 99_950).unwrap(), 50);
+            assert_eq!(&buf[..50], &data[99_950..]);
+            assert_eq!(f.read_at(&mut buf, 200_000).unwrap(), 0);
+
+            // More reads than the queue depth
+            let mut bufs = (0..200).map(|i| vec![0; i * 3 + 1]).collect::<Vec<_>>();
+            let mut reads = bufs
+                .iter_mut()
+                .enumerate()
+                .map(|(i, buf)| ((i * 499) as u64, buf.as_mut_slice()))
+                .collect::<Vec<_>>();
+            f.read_exact_at_batch(&mut reads).unwrap();
+            for (i, buf) in bufs.iter().enumerate() {
+                assert_eq!(buf.as_slice(), &data[i * 499..i * 499 + buf.len()]);
+            }
+            // A read beyond the end of the file fails the batch
+            let (mut a, mut b) = (vec![0; 10], vec![0; 10]);
+            let mut reads = [(0, a.as_mut_slice()), (99_995, b.as_mut_slice())];
+            assert!(f.read_exact_at_batch(&mut reads).is_err());
+            storage.remove(name).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_uring_direct_io() {
+        let name = "test_uring_direct_io";
+        let data = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let storage = UringStorage::new();
+        let mut f = storage.create_direct(name).unwrap();
+        // Falls back to buffered I/O only where `FileStorage` does
+        let direct = FileStorage.open_direct(name).unwrap().is_direct();
+        assert_eq!(f.file.is_direct(), direct);
+        f.write(&data).unwrap();
+        f.sync().unwrap();
+        f.close().unwrap();
+
+        let f = storage.open_direct(name).unwrap();
+        assert_eq!(f.file.is_direct(), direct);
+        assert!(!direct || !f.use_io_uring);
+        let mut buf = vec![0; 100];
+        assert_eq!(f.read_at(&mut buf, 1001).unwrap(), 100);
+        assert_eq!(buf, &data[1001..1101]);
+        let (mut a, mut b) = (vec![0; 10], vec![0; 5000]);
+        let mut reads = [(3, a.as_mut_slice()), (90_000, b.as_mut_slice())];
+        f.read_exact_at_batch(&mut reads).unwrap();
+        assert_eq!(a, &data[3..13]);
+        assert_eq!(b, &data[90_000..95_000]);
+        storage.remove(name).unwrap();
+    }
+
+    #[test]
+    fn test_uring_submission_failure() {
+        let storage = UringStorage::new();
+        if !storage.is_io_uring_enabled() {
+            return;
+        }
+        let name = "test_uring_submission_failure";
+        let data = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut f = storage.create(name).unwrap();
+        f.write(&data).unwrap();
+        f.flush().unwrap();
+        let f = storage.open(name).unwrap();
+
+        let mut bufs = (0..QUEUE_DEPTH as usize)
+            .map(|_| vec![0; 100])
+            .collect::<Vec<_>>();
+        let mut reads = bufs
+            .iter_mut()
+            .enumerate()
+            .map(|(i, buf)| ((i * 1000) as u64, buf.as_mut_slice()))
+            .collect::<Vec<_>>();
+        FAILING_SUBMITS.with(|n| n.set(1));
+        let e = f.read_exact_at_batch(&mut reads).unwrap_err();
+        assert!(e.to_string().contains("injected"), "{}", e);
+        // The reads are completed before the error is returned
+        for (i, buf) in bufs.iter().enumerate() {
+            assert_eq!(buf.as_slice(), &data[i * 1000..i * 1000 + 100]);
+        }
+        // No completion is left behind in the ring, which is still used by the later reads
+        assert!(RING.with(|state| match &mut *state.borrow_mut() {
+            RingState::Ready(ring) => ring.submission().is_empty() && ring.completion().is_empty(),
+            _ => false,
+        }));
+        let mut buf = vec![0; 100];
+        assert_eq!(f.read_at(&mut buf, 500).unwrap(), 100);
+        assert_eq!(buf, &data[500..600]);
+        storage.remove(name).unwrap();
+    }
+}
@@ -33,6 +33,15 @@ pub fn hash(data: &[u8], seed: u32) -> u32 {
     h
 }
 
+/// Scrambles the bits of `z` by the finalizer of splitmix64, so that every output bit depends
+/// on every input bit
+#[inline]
+pub fn mix64(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
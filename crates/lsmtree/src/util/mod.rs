@@ -3,6 +3,8 @@ pub mod collection;
 pub mod comparator;
 pub mod crc32;
 pub mod hash;
+pub mod pinnable_slice;
 pub mod reporter;
 pub mod slice;
+pub mod slice_transform;
 pub mod varint;
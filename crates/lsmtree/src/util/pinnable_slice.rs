@@ -0,0 +1,98 @@
+use std::{fmt, ops::Deref};
+
+use bytes::Bytes;
+
+/// `PinnableSlice` is a value returned by `DB::get_pinned`. A value found in a memtable or a
+/// data block is pinned: it refers to the memtable entry or the block without copying, and
+/// keeps it alive until the `PinnableSlice` is dropped. Other values, like the ones read from
+/// the blob files, are owned by the `PinnableSlice`.
+///
+/// A pinned value holds the memory of the whole entry or block, so it should not be kept for
+/// long.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PinnableSlice {
+    data: Bytes,
+    pinned: bool,
+}
+
+impl PinnableSlice {
+    /// Creates a `PinnableSlice` referring to `data` shared with a memtable or a block
+    #[inline]
+    pub fn pinned(data: Bytes) -> Self {
+        Self { data, pinned: true }
+    }
+
+    /// Creates a `PinnableSlice` owning `data`
+    #[inline]
+    pub fn owned(data: Vec<u8>) -> Self {
+        Self {
+            data: Bytes::from(data),
+            pinned: false,
+        }
+    }
+
+    /// Returns true if the value refers to a memtable entry or a block without copying
+    #[inline]
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+
+    /// Converts the value into `Bytes`, which still shares the pinned memory
+    #[inline]
+    pub fn into_bytes(self) -> Bytes {
+        self.data
+    }
+}
+
+impl Deref for PinnableSlice {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl AsRef<[u8]> for PinnableSlice {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl From<PinnableSlice> for Vec<u8> {
+    fn from(s: PinnableSlice) -> Self {
+        Vec::from(s.data)
+    }
+}
+
+impl fmt::Debug for PinnableSlice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PinnableSlice")
+            .field("data", &self.data)
+            .field("pinned", &self.pinned)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pinnable_slice() {
+        let block = Bytes::from(b"key1value1key2value2".to_vec());
+        let pinned = PinnableSlice::pinned(block.slice(4..10));
+        assert!(pinned.is_pinned());
+        assert_eq!(&pinned[..], b"value1");
+        // The value shares the block
+        assert_eq!(pinned.as_ptr(), block[4..].as_ptr());
+        drop(block);
+        assert_eq!(Vec::from(pinned), b"value1".to_vec());
+
+        let owned = PinnableSlice::owned(b"value".to_vec());
+        assert!(!owned.is_pinned());
+        assert_eq!(owned.as_ref(), b"value");
+        assert_eq!(owned.into_bytes(), Bytes::from_static(b"value"));
+    }
+}
@@ -0,0 +1,106 @@
+/// A `SliceTransform` extracts a prefix from a key. It's used to build the filters over the
+/// prefixes of the keys, so that a seek for a prefix is able to skip the tables without it.
+///
+/// The keys with the same prefix must be contiguous in the order of the comparator, that is,
+/// for any keys `a <= b <= c`, `transform(a) == transform(c)` implies
+/// `transform(a) == transform(b)`.
+pub trait SliceTransform: Send + Sync {
+    /// The name of the transform. It's recorded in the tables so that the prefix filters are
+    /// only used by a DB opened with the same transform.
+    fn name(&self) -> &str;
+
+    /// Returns the prefix of the key. Only called on the keys in the domain.
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8];
+
+    /// Returns true if the key has a prefix. The keys out of the domain are not added to
+    /// the prefix filters.
+    fn in_domain(&self, key: &[u8]) -> bool;
+}
+
+/// `FixedPrefixTransform` extracts the first `len` bytes of the keys. The keys shorter than
+/// `len` are not in the domain.
+pub struct FixedPrefixTransform {
+    len: usize,
+    name: String,
+}
+
+impl FixedPrefixTransform {
+    #[must_use]
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            name: format!("TemplateDB.FixedPrefix.{}", len),
+        }
+    }
+}
+
+impl SliceTransform for FixedPrefixTransform {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[..self.len]
+    }
+
+    fn in_domain(&self, key: &[u8]) -> bool {
+        key.len() >= self.len
+    }
+}
+
+/// `CappedPrefixTransform` extracts the first `len` bytes of the keys or the whole key if it's
+/// shorter. All the keys are in the domain.
+pub struct CappedPrefixTransform {
+    len: usize,
+    name: String,
+}
+
+impl CappedPrefixTransform {
+    #[must_use]
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            name: format!("TemplateDB.CappedPrefix.{}", len),
+        }
+    }
+}
+
+impl SliceTransform for CappedPrefixTransform {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[..key.len().min(self.len)]
+    }
+
+    fn in_domain(&self, _key: &[u8]) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_prefix_transform() {
+        let t = FixedPrefixTransform::new(3);
+        assert_eq!(t.name(), "TemplateDB.FixedPrefix.3");
+        assert!(t.in_domain(b"abc"));
+        assert!(t.in_domain(b"abcdef"));
+        assert!(!t.in_domain(b"ab"));
+        assert_eq!(t.transform(b"abc"), b"abc");
+        assert_eq!(t.transform(b"abcdef"), b"abc");
+    }
+
+    #[test]
+    fn test_capped_prefix_transform() {
+        let t = CappedPrefixTransform::new(3);
+        assert_eq!(t.name(), "TemplateDB.CappedPrefix.3");
+        assert!(t.in_domain(b""));
+        assert!(t.in_domain(b"ab"));
+        assert_eq!(t.transform(b"ab"), b"ab");
+        assert_eq!(t.transform(b"abcdef"), b"abc");
+    }
+}
@@ -706,6 +706,26 @@ mod tests {
         test_check_read_initial_offset_into_block_padding: (3 * BLOCK_SIZE as u64 - 3, 5),
     );
 
+    #[test]
+    fn test_resume_at_end_of_last_record() {
+        let mut log = new_record_test();
+        log.write_initial_offset_log();
+        // Every reader reads its own copy of the log
+        let file = || StringFile::new(Rc::new(RefCell::new(log.source.borrow().clone())));
+        let mut offset = 0;
+        let mut record = vec![];
+        for (i, size) in INITIAL_OFFSET_RECORD_SIZES.iter().enumerate() {
+            let mut reader = Reader::new(file(), None, true, offset);
+            assert!(reader.read_record(&mut record));
+            assert_eq!(record.len(), *size);
+            assert_eq!(b'a' + u8::try_from(i).unwrap(), record[0]);
+            offset = reader.end_of_last_record();
+        }
+        assert_eq!(offset, log.written_bytes() as u64);
+        let mut reader = Reader::new(file(), None, true, offset);
+        assert!(!reader.read_record(&mut record));
+    }
+
     #[test]
     fn test_check_read_end() {
         let mut log = new_record_test();
@@ -218,14 +218,19 @@ impl<F: File> Reader<F> {
         }
     }
 
-    // Returns the last_record_offset.
-    // Temporary for test.
+    /// Returns the physical offset of the last record returned by `read_record`
     #[inline]
-    #[allow(dead_code)]
-    pub(super) fn last_record_offset(&self) -> u64 {
+    pub fn last_record_offset(&self) -> u64 {
         self.last_record_offset
     }
 
+    /// Returns the physical offset right after the last record returned by `read_record`,
+    /// where a new `Reader` can start to read the records appended later
+    #[inline]
+    pub fn end_of_last_record(&self) -> u64 {
+        self.end_of_buffer_offset - self.buf_length as u64
+    }
+
     fn read_physical_record(&mut self) -> Result<Record, ReaderError> {
         loop {
             // we've reached the end of a block and do not have a valid header